@@ -0,0 +1,59 @@
+use alloy::primitives::Address;
+
+/// Canonical display form of `address`: EIP-55 checksummed for EVM addresses,
+/// unchanged otherwise (e.g. Solana's base58 is already case-sensitive)
+pub fn normalize_address(address: &str) -> String {
+    match address.parse::<Address>() {
+        Ok(parsed) => parsed.to_checksum(None),
+        Err(_) => address.to_string(),
+    }
+}
+
+/// Abbreviate the normalized `address` to its first and last `visible`
+/// characters, e.g. `0x78697a…3b10`, for output that may be shared. The `0x`
+/// prefix isn't counted; addresses too short to shorten are returned whole.
+pub fn mask_address(address: &str, visible: usize) -> String {
+    let normalized = normalize_address(address);
+    let (prefix, body) = match normalized.strip_prefix("0x") {
+        Some(body) => ("0x", body),
+        None => ("", normalized.as_str()),
+    };
+
+    let chars: Vec<char> = body.chars().collect();
+    if chars.len() <= visible * 2 {
+        return normalized;
+    }
+    let head: String = chars[..visible].iter().collect();
+    let tail: String = chars[chars.len() - visible..].iter().collect();
+    format!("{}{}…{}", prefix, head, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_address_checksums_evm() {
+        assert_eq!(
+            normalize_address("0x78697a9cfc48c1e9d1040172d51833ef78083b10"),
+            "0x78697a9cfc48C1e9d1040172d51833EF78083b10"
+        );
+        assert_eq!(
+            normalize_address("8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4"),
+            "8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4"
+        );
+    }
+
+    #[test]
+    fn test_mask_address() {
+        assert_eq!(
+            mask_address("0x78697a9cfc48c1e9d1040172d51833ef78083b10", 4),
+            "0x7869…3b10"
+        );
+        assert_eq!(
+            mask_address("8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4", 6),
+            "8vJ1EE…kPsCF4"
+        );
+        assert_eq!(mask_address("abcdef", 3), "abcdef");
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::{anyhow, Result};
+
+use crate::types::Balance;
+
+/// Sum balances of the same token symbol across chains into one balance per
+/// symbol, in first-seen order.
+///
+/// Each amount is rescaled to `decimals` before summing, or to the largest
+/// decimals of that symbol when `None`, so a 6-decimal USDC and an 18-decimal
+/// bridged USDC add up exactly. Fails rather than rounding if an amount can't
+/// be expressed with the requested decimals.
+pub fn total_by_token(per_chain: &[Vec<Balance>], decimals: Option<u8>) -> Result<Vec<Balance>> {
+    let mut symbols: Vec<&str> = Vec::new();
+    for balance in per_chain.iter().flatten() {
+        if !symbols.contains(&balance.token.as_str()) {
+            symbols.push(&balance.token);
+        }
+    }
+
+    let mut totals = Vec::with_capacity(symbols.len());
+    for symbol in symbols {
+        let members: Vec<&Balance> = per_chain
+            .iter()
+            .flatten()
+            .filter(|balance| balance.token == symbol)
+            .collect();
+        if members
+            .iter()
+            .any(|balance| balance.kind != members[0].kind)
+        {
+            return Err(anyhow!(
+                "{} is both fungible and an NFT across chains",
+                symbol
+            ));
+        }
+
+        let basis = decimals.unwrap_or_else(|| {
+            members
+                .iter()
+                .map(|balance| balance.decimals)
+                .max()
+                .unwrap_or_default()
+        });
+
+        let mut sum = 0u128;
+        for balance in &members {
+            let units = balance.rescale(basis)?.to_minor_units()?;
+            sum = sum
+                .checked_add(units)
+                .ok_or_else(|| anyhow!("Total of {} overflows", symbol))?;
+        }
+
+        // A total only belongs to a chain (or endpoint, or address) if every
+        // part came from it
+        let chain = members[0].chain.clone().filter(|chain| {
+            members
+                .iter()
+                .all(|balance| balance.chain.as_ref() == Some(chain))
+        });
+        let address = members[0].address.clone().filter(|address| {
+            members
+                .iter()
+                .all(|balance| balance.address.as_ref() == Some(address))
+        });
+        let rpc = members[0].rpc.clone().filter(|rpc| {
+            members
+                .iter()
+                .all(|balance| balance.rpc.as_ref() == Some(rpc))
+        });
+        totals.push(Balance {
+            kind: members[0].kind,
+            chain,
+            address,
+            rpc,
+            ..Balance::from_minor_units(symbol.to_string(), sum, basis)
+        });
+    }
+
+    Ok(totals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balance(token: &str, amount: &str, decimals: u8) -> Balance {
+        Balance::new(token.to_string(), amount.to_string(), decimals).unwrap()
+    }
+
+    #[test]
+    fn test_mixed_decimals_usdc_total() {
+        let ethereum = vec![
+            balance("ETH", "1000000000000000000", 18),
+            balance("USDC", "1500000", 6),
+        ];
+        // Bridged USDC with 18 decimals
+        let bsc = vec![balance("USDC", "2250000000000000000", 18)];
+
+        let totals = total_by_token(&[ethereum, bsc], None).unwrap();
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals[1].token, "USDC");
+        assert_eq!(totals[1].decimals, 18);
+        assert_eq!(totals[1].amount, "3750000000000000000");
+        assert_eq!(totals[1].formatted, "3.75");
+    }
+
+    #[test]
+    fn test_total_keeps_chain_only_when_shared() {
+        let on = |chain: &str, token: &str| Balance {
+            chain: Some(chain.to_string()),
+            ..balance(token, "1", 0)
+        };
+        let chains = vec![
+            vec![on("sepolia", "ETH"), on("sepolia", "USDC")],
+            vec![on("base-sepolia", "USDC")],
+        ];
+
+        let totals = total_by_token(&chains, None).unwrap();
+        assert_eq!(totals[0].chain.as_deref(), Some("sepolia"));
+        assert_eq!(totals[1].chain, None);
+    }
+
+    #[test]
+    fn test_total_with_explicit_decimals() {
+        let chains = vec![
+            vec![balance("USDC", "1500000", 6)],
+            vec![balance("USDC", "2250000000000000000", 18)],
+        ];
+        let totals = total_by_token(&chains, Some(6)).unwrap();
+        assert_eq!(totals[0].amount, "3750000");
+
+        // 1 wei of 18-decimal USDC can't be expressed with 6 decimals
+        let dust = vec![
+            vec![balance("USDC", "1500000", 6)],
+            vec![balance("USDC", "1", 18)],
+        ];
+        assert!(total_by_token(&dust, Some(6)).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use alloy::primitives::{U256, U512};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+
+use crate::snapshot::{diff_balances, TokenDelta};
+use crate::types::Balance;
+
+/// A token whose balance moved by more than the alert threshold between two runs
+#[derive(Debug, Clone)]
+pub struct BalanceChange {
+    pub token: String,
+    pub previous: String,
+    pub current: String,
+    /// Signed percent change, `None` when the previous balance was zero
+    pub percent: Option<f64>,
+}
+
+/// Compare two sets of balances and return the tokens whose raw amount changed
+/// by more than `threshold_pct` percent (e.g. 10 or 2.5), in token order.
+/// Built on `diff_balances`, so tokens are matched the same way, a token whose
+/// decimals changed is compared at the larger ones and one missing on a side
+/// counts as a zero balance there. The threshold test is done in exact integer
+/// arithmetic on any 256-bit raw amount; `percent` is only for display.
+pub fn changes_exceeding(
+    previous: &[Balance],
+    current: &[Balance],
+    threshold_pct: Decimal,
+) -> Result<Vec<BalanceChange>> {
+    let threshold_pct = threshold_pct.normalize();
+    let pct_scale = threshold_pct.scale();
+    let threshold = u128::try_from(threshold_pct.mantissa())
+        .map_err(|_| anyhow!("Invalid percentage '{}'", threshold_pct))?;
+    // Scale at most 28, so both fit in a u128
+    let threshold = U512::from(threshold);
+    let hundred = U512::from(100u128 * 10u128.pow(pct_scale));
+
+    let diff = diff_balances(previous, current)?;
+    let mut deltas: Vec<&TokenDelta> = diff
+        .added
+        .iter()
+        .chain(&diff.removed)
+        .chain(&diff.changed)
+        .collect();
+    deltas.sort_by(|a, b| a.token.cmp(&b.token));
+
+    let mut changes = Vec::new();
+    for delta in deltas {
+        let old = units(&delta.previous)?;
+        let (decrease, change) = match delta.delta.strip_prefix('-') {
+            Some(magnitude) => (true, units(magnitude)?),
+            None => (false, units(&delta.delta)?),
+        };
+
+        // change / old * 100 > threshold, without division; a 256-bit amount
+        // times a 128-bit factor can't overflow 512 bits
+        if !old.is_zero() && U512::from(change) * hundred <= U512::from(old) * threshold {
+            continue;
+        }
+
+        let percent = (!old.is_zero()).then(|| {
+            let percent = f64::from(change) / f64::from(old) * 100.0;
+            if decrease {
+                -percent
+            } else {
+                percent
+            }
+        });
+        changes.push(BalanceChange {
+            token: delta.token.clone(),
+            previous: formatted(delta, &delta.previous)?,
+            current: formatted(delta, &delta.current)?,
+            percent,
+        });
+    }
+
+    Ok(changes)
+}
+
+fn units(amount: &str) -> Result<U256> {
+    U256::from_str_radix(amount, 10).map_err(|_| anyhow!("Invalid raw amount '{}'", amount))
+}
+
+/// `amount` of `delta`'s token as a whole-token string, e.g. "1.25"
+fn formatted(delta: &TokenDelta, amount: &str) -> Result<String> {
+    Ok(Balance::new(delta.token.clone(), amount.to_string(), delta.decimals)?.formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pct(threshold: &str) -> Decimal {
+        crate::amount::AmountParser::parse(threshold).unwrap()
+    }
+
+    fn usdc(amount: &str) -> Balance {
+        Balance::new("USDC".to_string(), amount.to_string(), 6).unwrap()
+    }
+
+    #[test]
+    fn test_change_above_threshold_is_flagged() {
+        let changes = changes_exceeding(&[usdc("1000000")], &[usdc("1250000")], pct("20")).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].previous, "1");
+        assert_eq!(changes[0].current, "1.25");
+        assert_eq!(changes[0].percent, Some(25.0));
+    }
+
+    #[test]
+    fn test_change_at_or_below_threshold_is_ignored() {
+        assert!(
+            changes_exceeding(&[usdc("1000000")], &[usdc("1200000")], pct("20"))
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            changes_exceeding(&[usdc("1000000")], &[usdc("975000")], pct("2.5"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_fractional_threshold_decrease() {
+        let changes = changes_exceeding(&[usdc("1000000")], &[usdc("974999")], pct("2.5")).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].percent.unwrap() < 0.0);
+    }
+
+    #[test]
+    fn test_amounts_beyond_u128_are_compared_exactly() {
+        let wei = |amount: String| Balance::new("ETH".to_string(), amount, 18).unwrap();
+        let old = U256::from(u128::MAX) * U256::from(1000);
+        // Exactly 10% up isn't more than 10%, one wei more is
+        let exact = old + old / U256::from(10);
+        let changes = changes_exceeding(
+            &[wei(old.to_string())],
+            &[wei(exact.to_string())],
+            pct("10"),
+        )
+        .unwrap();
+        assert!(changes.is_empty());
+
+        let over = exact + U256::from(1);
+        let changes =
+            changes_exceeding(&[wei(old.to_string())], &[wei(over.to_string())], pct("10"))
+                .unwrap();
+        assert_eq!(changes.len(), 1);
+    }
+
+    #[test]
+    fn test_appearing_and_disappearing_tokens() {
+        let eth = Balance::new("ETH".to_string(), "5".to_string(), 18).unwrap();
+        let changes = changes_exceeding(&[eth], &[usdc("1")], pct("50")).unwrap();
+        let tokens: Vec<&str> = changes.iter().map(|c| c.token.as_str()).collect();
+        assert_eq!(tokens, vec!["ETH", "USDC"]);
+        assert_eq!(changes[0].current, "0");
+        assert_eq!(changes[1].percent, None);
+    }
+
+    #[test]
+    fn test_changed_decimals_are_compared_at_the_larger_ones() {
+        let wide = Balance::new("USDC".to_string(), "1250000000000000000".to_string(), 18).unwrap();
+        let changes = changes_exceeding(&[usdc("1000000")], &[wide], pct("20")).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].previous, "1");
+        assert_eq!(changes[0].current, "1.25");
+        assert_eq!(changes[0].percent, Some(25.0));
+    }
+}
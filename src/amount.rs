@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+
+use crate::types::{parse_units, rescale};
+
+/// Parser for amounts typed by people: plain decimals (`0.01`, `250`) with an
+/// optional `k`, `m` or `b` suffix for thousand, million and billion (`1.5k`
+/// is exactly 1500). Used by every flag that takes an amount or threshold.
+/// Returns a `rust_decimal::Decimal` with trailing zeros stripped.
+pub struct AmountParser;
+
+impl AmountParser {
+    pub fn parse(input: &str) -> Result<Decimal> {
+        let input = input.trim();
+        let invalid = |reason: &str| {
+            anyhow!(
+                "Invalid amount '{}': {} (expected e.g. 0.01, 250 or 1.5k)",
+                input,
+                reason
+            )
+        };
+
+        if input.starts_with('-') {
+            return Err(invalid("amounts can't be negative"));
+        }
+        if input.contains(',') || input.contains('_') {
+            return Err(invalid("digit separators are ambiguous, write 1000 or 1k"));
+        }
+        if input.contains(char::is_whitespace) {
+            return Err(invalid("spaces aren't allowed, write 1.5k"));
+        }
+        if input.contains(['e', 'E']) {
+            return Err(invalid("scientific notation isn't supported"));
+        }
+
+        let (number, exponent) = match input.char_indices().last() {
+            Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+                let exponent = match suffix.to_ascii_lowercase() {
+                    'k' => 3,
+                    'm' => 6,
+                    'b' => 9,
+                    _ => return Err(invalid("unknown suffix, use k, m or b")),
+                };
+                (&input[..index], exponent)
+            }
+            _ => (input, 0),
+        };
+        if number.ends_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(invalid("only one suffix is allowed"));
+        }
+        if number.is_empty() {
+            return Err(invalid("missing number"));
+        }
+
+        let places = number
+            .split_once('.')
+            .map(|(_, fraction)| fraction.len())
+            .unwrap_or(0);
+        // A Decimal holds at most 28 decimal places
+        if places > 28 {
+            return Err(invalid("too many decimal places"));
+        }
+        let places = places as u8;
+        let units = parse_units(number, places).map_err(|_| invalid("not a decimal number"))?;
+
+        // Move the suffix into the scale first, so `1.5k` stays exact
+        let shift = exponent.min(places);
+        let units = rescale(units, 0, exponent - shift).map_err(|_| invalid("too large"))?;
+        // Fails beyond the 96-bit mantissa
+        let amount = i128::try_from(units)
+            .ok()
+            .and_then(|units| {
+                Decimal::try_from_i128_with_scale(units, (places - shift) as u32).ok()
+            })
+            .ok_or_else(|| invalid("too large"))?;
+        Ok(amount.normalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> String {
+        AmountParser::parse(input).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse_plain_and_suffixed_amounts() {
+        assert_eq!(parse("0.01"), "0.01");
+        assert_eq!(parse("250"), "250");
+        assert_eq!(parse("1.5k"), "1500");
+        assert_eq!(parse("2m"), "2000000");
+        assert_eq!(parse("0.25B"), "250000000");
+        assert_eq!(parse("1.23456k"), "1234.56");
+        assert_eq!(parse(" 3K "), "3000");
+        assert_eq!(parse("0.0000001"), "0.0000001");
+    }
+
+    #[test]
+    fn test_trailing_zeros_are_stripped() {
+        let amount = |input| AmountParser::parse(input).unwrap();
+        assert_eq!(amount("1.50").scale(), 1);
+        assert_eq!(amount("1500.0").scale(), 0);
+        assert_eq!(amount("1.5k"), Decimal::from(1500));
+        assert_eq!(amount("1.5"), Decimal::from_i128_with_scale(1_500_000, 6));
+    }
+
+    #[test]
+    fn test_rejects_amounts_beyond_the_mantissa() {
+        let max = (1u128 << 96) - 1;
+        assert_eq!(parse(&max.to_string()), max.to_string());
+        assert!(AmountParser::parse(&(max + 1).to_string()).is_err());
+        assert!(AmountParser::parse("100000000000000000000b").is_err());
+        assert!(AmountParser::parse(&format!("0.{}1", "0".repeat(28))).is_err());
+    }
+
+    #[test]
+    fn test_rejects_ambiguous_or_invalid_input() {
+        for input in [
+            "", "k", "-1", "1,000", "1_000", "1e6", "1.5kk", "2x", "1.2.3", "abc", "1 k",
+        ] {
+            assert!(AmountParser::parse(input).is_err(), "{} should fail", input);
+        }
+        let error = AmountParser::parse("1,000").unwrap_err().to_string();
+        assert!(error.contains("write 1000 or 1k"));
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Result};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::amount::AmountParser;
+use crate::types::{parse_units, Balance};
+
+/// Comparison operator of a balance expectation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Greater => ordering == Ordering::Greater,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::NotEqual => ordering != Ordering::Equal,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        }
+    }
+}
+
+/// A balance expectation such as `USDC>=0.1` or `ETH>0`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expectation {
+    pub token: String,
+    pub comparison: Comparison,
+    pub threshold: String,
+}
+
+/// Outcome of checking one expectation against queried balances
+#[derive(Debug, Clone)]
+pub struct AssertionResult {
+    pub expectation: Expectation,
+    /// Formatted balance of the token, `None` if it wasn't returned
+    pub actual: Option<String>,
+    pub passed: bool,
+}
+
+impl FromStr for Expectation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Two-character operators first so ">=" isn't read as ">"
+        const OPERATORS: [(&str, Comparison); 7] = [
+            (">=", Comparison::GreaterOrEqual),
+            ("<=", Comparison::LessOrEqual),
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            (">", Comparison::Greater),
+            ("<", Comparison::Less),
+            ("=", Comparison::Equal),
+        ];
+
+        for (operator, comparison) in OPERATORS {
+            if let Some((token, threshold)) = s.split_once(operator) {
+                let token = token.trim();
+                // Normalized to a plain decimal, so `1.5k` is stored as "1500"
+                let threshold = AmountParser::parse(threshold)?;
+                if token.is_empty() {
+                    return Err(anyhow!("Missing token in expectation '{}'", s));
+                }
+                return Ok(Self {
+                    token: token.to_string(),
+                    comparison,
+                    threshold: threshold.to_string(),
+                });
+            }
+        }
+
+        Err(anyhow!(
+            "Invalid expectation '{}', expected e.g. USDC>=0.1",
+            s
+        ))
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.token,
+            self.comparison.as_str(),
+            self.threshold
+        )
+    }
+}
+
+impl Expectation {
+    /// Check the expectation against the balance for its token (matched
+    /// case-insensitively), comparing exact integer amounts rather than floats
+    pub fn evaluate(&self, balances: &[Balance]) -> Result<AssertionResult> {
+        let Some(balance) = balances
+            .iter()
+            .find(|balance| balance.token.eq_ignore_ascii_case(&self.token))
+        else {
+            return Ok(AssertionResult {
+                expectation: self.clone(),
+                actual: None,
+                passed: false,
+            });
+        };
+
+        // Compare both sides at whichever precision is finer
+        let scale = balance.decimals.max(fraction_digits(&self.threshold));
+        let amount: u128 = balance.amount.parse().map_err(|_| {
+            anyhow!(
+                "Invalid raw amount '{}' for {}",
+                balance.amount,
+                balance.token
+            )
+        })?;
+        let amount = 10u128
+            .checked_pow((scale - balance.decimals) as u32)
+            .and_then(|factor| amount.checked_mul(factor))
+            .ok_or_else(|| anyhow!("Amount of {} is too large to compare", balance.token))?;
+        let threshold = parse_units(&self.threshold, scale)?;
+
+        Ok(AssertionResult {
+            expectation: self.clone(),
+            actual: Some(balance.formatted.clone()),
+            passed: self.comparison.holds(amount.cmp(&threshold)),
+        })
+    }
+}
+
+/// Check every expectation against the same set of balances
+pub fn evaluate_all(
+    expectations: &[Expectation],
+    balances: &[Balance],
+) -> Result<Vec<AssertionResult>> {
+    expectations
+        .iter()
+        .map(|expectation| expectation.evaluate(balances))
+        .collect()
+}
+
+fn fraction_digits(value: &str) -> u8 {
+    value
+        .split_once('.')
+        .map(|(_, fraction)| fraction.len().min(u8::MAX as usize) as u8)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balances() -> Vec<Balance> {
+        vec![
+            Balance::new("ETH".to_string(), "7000000000000000".to_string(), 18).unwrap(),
+            Balance::new("USDC".to_string(), "100000".to_string(), 6).unwrap(),
+            Balance::new("EURC".to_string(), "0".to_string(), 6).unwrap(),
+        ]
+    }
+
+    fn passes(expectation: &str) -> bool {
+        expectation
+            .parse::<Expectation>()
+            .unwrap()
+            .evaluate(&balances())
+            .unwrap()
+            .passed
+    }
+
+    #[test]
+    fn test_parse_expectation() {
+        let expectation: Expectation = "USDC >= 0.1".parse().unwrap();
+        assert_eq!(expectation.token, "USDC");
+        assert_eq!(expectation.comparison, Comparison::GreaterOrEqual);
+        assert_eq!(expectation.threshold, "0.1");
+        assert_eq!(expectation.to_string(), "USDC>=0.1");
+
+        let suffixed: Expectation = "USDC<1.5k".parse().unwrap();
+        assert_eq!(suffixed.threshold, "1500");
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_expectations() {
+        assert!("USDC".parse::<Expectation>().is_err());
+        assert!(">=0.1".parse::<Expectation>().is_err());
+        assert!("USDC>=abc".parse::<Expectation>().is_err());
+        assert!("USDC>=-1".parse::<Expectation>().is_err());
+        assert!("USDC>=1,000".parse::<Expectation>().is_err());
+    }
+
+    #[test]
+    fn test_evaluate_thresholds_exactly() {
+        assert!(passes("USDC>=0.1"));
+        assert!(!passes("USDC>0.1"));
+        assert!(passes("USDC==0.100000"));
+        assert!(passes("ETH>0"));
+        assert!(passes("eth<0.0070000000000000001"));
+        assert!(passes("EURC=0"));
+        assert!(!passes("EURC!=0"));
+    }
+
+    #[test]
+    fn test_missing_token_fails() {
+        let result = "DAI>0"
+            .parse::<Expectation>()
+            .unwrap()
+            .evaluate(&balances())
+            .unwrap();
+        assert!(!result.passed);
+        assert!(result.actual.is_none());
+    }
+}
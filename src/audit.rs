@@ -0,0 +1,139 @@
+use anyhow::{anyhow, Result};
+
+use crate::config::{ChainConfig, Config, TokenInfo};
+use crate::doctor::Check;
+use crate::ethereum::EthereumProvider;
+use crate::solana::SolanaProvider;
+
+/// Compare every configured token of `chain` against the chain itself: the
+/// contract (or mint) must exist and its `symbol()`/`decimals()` must match
+/// the configured values. One check per token, in symbol order.
+pub async fn audit_chain(config: &Config, chain: &str) -> Result<Vec<Check>> {
+    let chain_config = config
+        .get_chain(chain)
+        .ok_or_else(|| anyhow!("Chain {} not found in config", chain))?;
+    // Audits read the configured endpoints only, never the public fallbacks
+    chain_config.validate_rpc_endpoint(chain, chain_config.primary_rpc())?;
+    chain_config.validate_rpc(chain)?;
+
+    let mut symbols: Vec<&String> = chain_config.tokens.keys().collect();
+    symbols.sort();
+
+    let mut checks = Vec::new();
+    for symbol in symbols {
+        let info = &chain_config.tokens[symbol];
+        let name = format!("{}: token {}", chain, symbol);
+        let Some(address) = info.address.as_deref() else {
+            checks.push(Check::fail(name, "no address configured".to_string()));
+            continue;
+        };
+
+        let result = match chain_config.chain_type.as_str() {
+            "evm" => audit_evm_token(chain_config, symbol, info, address).await,
+            "solana" => audit_spl_token(chain_config, info, address).await,
+            other => return Err(anyhow!("Unsupported chain type: {}", other)),
+        };
+        checks.push(match result {
+            Ok(mismatches) if mismatches.is_empty() => {
+                Check::pass(name, format!("matches contract at {}", address))
+            }
+            Ok(mismatches) => Check::fail(name, mismatches.join("; ")),
+            Err(e) => Check::fail(name, e.to_string()),
+        });
+    }
+    Ok(checks)
+}
+
+/// Mismatches between an ERC-20 entry and its contract; NFTs and LP pairs
+/// only need the contract to exist
+async fn audit_evm_token(
+    chain: &ChainConfig,
+    symbol: &str,
+    info: &TokenInfo,
+    address: &str,
+) -> Result<Vec<String>> {
+    let provider = EthereumProvider::new(chain.primary_rpc().to_string())
+        .with_fallback_rpcs(chain.rpc[1..].to_vec())
+        .with_rpc_methods(chain.rpc_methods.clone());
+    if !provider.has_code(address).await? {
+        return Ok(vec![format!("dead contract: no code at {}", address)]);
+    }
+    if !matches!(info.kind.as_deref(), None | Some("erc20")) {
+        return Ok(Vec::new());
+    }
+
+    let (onchain_symbol, onchain_decimals) = provider.fetch_token_metadata(address).await?;
+    let configured_symbol = info.symbol_or(symbol);
+    Ok(metadata_mismatches(
+        (configured_symbol, info.decimals),
+        (Some(&onchain_symbol), onchain_decimals),
+    ))
+}
+
+/// Mismatches between an SPL entry and its mint; mints carry no symbol
+async fn audit_spl_token(chain: &ChainConfig, info: &TokenInfo, mint: &str) -> Result<Vec<String>> {
+    let provider = SolanaProvider::new(chain.primary_rpc().to_string())
+        .with_fallback_rpcs(chain.rpc[1..].to_vec())?;
+    if !provider.account_exists(mint).await? {
+        return Ok(vec![format!("dead mint: no account at {}", mint)]);
+    }
+    let decimals = provider.fetch_mint_decimals(mint).await?;
+    Ok(metadata_mismatches(("", info.decimals), (None, decimals)))
+}
+
+/// Differences between configured `(symbol, decimals)` and what the chain
+/// reports; symbols compare case-insensitively, unset decimals always match
+fn metadata_mismatches(configured: (&str, Option<u8>), onchain: (Option<&str>, u8)) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    if let Some(onchain_symbol) = onchain.0 {
+        if !onchain_symbol.eq_ignore_ascii_case(configured.0) {
+            mismatches.push(format!(
+                "wrong symbol: config {}, chain {}",
+                configured.0, onchain_symbol
+            ));
+        }
+    }
+    if let Some(decimals) = configured.1.filter(|decimals| *decimals != onchain.1) {
+        mismatches.push(format!(
+            "wrong decimals: config {}, chain {}",
+            decimals, onchain.1
+        ));
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_mismatches() {
+        assert!(metadata_mismatches(("USDC", Some(6)), (Some("usdc"), 6)).is_empty());
+        assert_eq!(
+            metadata_mismatches(("USDC", Some(18)), (Some("USDT"), 6)),
+            [
+                "wrong symbol: config USDC, chain USDT",
+                "wrong decimals: config 18, chain 6"
+            ]
+        );
+        // SPL mints have no on-chain symbol to compare
+        assert!(metadata_mismatches(("", Some(6)), (None, 6)).is_empty());
+        // Decimals left to the chain can't be wrong
+        assert!(metadata_mismatches(("USDC", None), (Some("USDC"), 6)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_audit_unknown_chain() {
+        let config = Config::load().unwrap();
+        assert!(audit_chain(&config, "no-such-chain").await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_audit_sepolia() {
+        let config = Config::load().unwrap();
+        let checks = audit_chain(&config, "sepolia").await.unwrap();
+        assert!(!checks.is_empty());
+        assert!(checks.iter().all(|check| check.passed), "{:?}", checks);
+    }
+}
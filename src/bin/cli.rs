@@ -1,39 +1,767 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use balance_checker::{
+    AccountState, AmountParser, Balance, BalanceError, BalanceKind, BlockTag, ChainReader, Config,
+    Decimal, Expectation, HistoryPoint, HttpConfig, OnError, PriceSource, QueryOptions, RequestId,
+    Snapshot, SqliteStore, TokenSpec, DEFAULT_REQUEST_ID_HEADER,
+};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Flags that read or write one chain's results, so they can't be combined
+/// with --chains, --all-chains or a comma-separated --chain
+const SINGLE_CHAIN_FLAGS: [&str; 5] = ["save_snapshot", "since", "sqlite", "explain_rpc", "watch"];
 
 #[derive(Parser, Debug)]
 #[command(name = "balance-checker")]
 #[command(about = "Query blockchain balances for multiple chains and tokens", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// The blockchain address to query
-    #[arg(short, long)]
-    address: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The blockchain address to query. Repeat the flag to query several
+    /// addresses on one chain, each printed in its own section.
+    #[arg(short, long, env = "BALANCE_CHECKER_ADDRESS", action = ArgAction::Append)]
+    address: Vec<String>,
+
+    /// Chain to query (sepolia, solana-devnet, etc.); defaults to the config's
+    /// `defaultChain`, then sepolia. A comma-separated list is the same as --chains.
+    #[arg(short, long, env = "BALANCE_CHECKER_CHAIN")]
+    chain: Option<String>,
+
+    /// Query several chains (comma-separated), printed in the order given
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "CHAIN,...",
+        conflicts_with = "chain",
+        conflicts_with_all = SINGLE_CHAIN_FLAGS
+    )]
+    chains: Vec<String>,
+
+    /// Query every configured chain whose type one of the addresses is valid
+    /// for; pass addresses of several types comma-separated in --address or by
+    /// repeating it (e.g. an EVM and a Solana one). Chains that fail are listed
+    /// at the end.
+    #[arg(
+        long,
+        conflicts_with_all = ["chain", "chains"],
+        conflicts_with_all = SINGLE_CHAIN_FLAGS
+    )]
+    all_chains: bool,
+
+    /// With several chains, query them all concurrently; output keeps the requested order
+    #[arg(long)]
+    parallel_chains: bool,
+
+    /// What a query of several chains does when one fails: skip it and list the
+    /// failures at the end, exiting non-zero only if all failed (default), or abort
+    /// the run
+    #[arg(long, value_name = "MODE")]
+    on_error: Option<OnError>,
+
+    #[command(flatten)]
+    query: QueryArgs,
+
+    /// Show each token's current USD unit price (e.g. to spot an off-peg stablecoin)
+    #[arg(long)]
+    show_price: bool,
+
+    /// Show each balance's USD value (amount times unit price); balances whose
+    /// token has no price are left blank
+    #[arg(long)]
+    with_prices: bool,
+
+    /// Where --show-price and --with-prices read prices from: coingecko,
+    /// coinmarketcap or chainlink-onchain (overrides config `price.source`)
+    #[arg(long, value_name = "SOURCE")]
+    price_source: Option<PriceSource>,
+
+    /// Print only the raw integer amounts, one per line (native first, then
+    /// tokens by symbol), for scripts that do their own decimal handling
+    #[arg(long, conflicts_with_all = ["show_price", "with_prices"])]
+    raw_only: bool,
+
+    /// Exit with CODE when every balance read is zero (with --chains, on every
+    /// chain that answered), e.g. to tell an empty wallet from a failure
+    #[arg(long, value_name = "CODE")]
+    empty_exit_code: Option<i32>,
+
+    /// Leave out tokens whose raw amount is zero (the native balance is always shown)
+    #[arg(long, conflicts_with = "raw_only")]
+    hide_zero: bool,
+
+    /// Output format: a human-readable table, or a JSON object with the chain,
+    /// the queried address and its balances (an array of them with --chains or --all-chains)
+    #[arg(
+        long,
+        default_value = "table",
+        value_parser = ["table", "json"],
+        conflicts_with_all = ["raw_only", "show_price", "with_prices"]
+    )]
+    format: String,
+
+    /// Abbreviate the queried address in human-readable output (e.g. 0x1234…abcd),
+    /// keeping N characters on each side; snapshot and SQLite output keep it whole
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "4")]
+    mask_address: Option<usize>,
+
+    /// Redraw the balance table every SECS seconds until Ctrl-C, reusing one
+    /// provider; each refresh reads what a single query with the same flags reads
+    #[arg(
+        long,
+        value_name = "SECS",
+        conflicts_with_all = [
+            "chains", "all_chains", "format", "raw_only", "save_snapshot", "sqlite", "since",
+            "explain_rpc"
+        ]
+    )]
+    watch: Option<u64>,
+
+    /// Write the queried balances as a JSON snapshot (with the block, time and
+    /// USD total) to this file
+    #[arg(long, value_name = "PATH")]
+    save_snapshot: Option<PathBuf>,
+
+    /// Append the queried balances to a SQLite database (created on first use)
+    #[arg(long, value_name = "PATH")]
+    sqlite: Option<PathBuf>,
+
+    /// Snapshot previously written with --save-snapshot that --alert-change
+    /// compares the balances against
+    #[arg(long, value_name = "PATH", requires = "alert_change")]
+    since: Option<PathBuf>,
+
+    /// Flag tokens whose balance changed by more than this percentage since
+    /// the --since snapshot, and exit non-zero if any did
+    #[arg(long, value_name = "PCT", requires = "since", value_parser = AmountParser::parse)]
+    alert_change: Option<Decimal>,
+
+    /// Print the equivalent curl command of every RPC request to stderr
+    /// (credentials in the URL and auth headers are redacted)
+    #[arg(long)]
+    explain_rpc: bool,
+
+    /// With --explain-rpc, only print the commands without sending any request
+    #[arg(long, requires = "explain_rpc")]
+    dry_run: bool,
+
+    /// With --explain-rpc, don't redact credentials
+    #[arg(long, requires = "explain_rpc")]
+    show_secrets: bool,
+}
+
+/// Flags that shape a balance query, shared by the top-level query and `assert`
+#[derive(clap::Args, Debug)]
+struct QueryArgs {
+    /// Network to query: picks the mainnet/testnet entry of the chain's family
+    #[arg(long, value_parser = ["mainnet", "testnet"])]
+    network: Option<String>,
+
+    /// Extra token to query as ADDRESS[:SYMBOL[:DECIMALS]] (repeatable);
+    /// missing symbol/decimals are read from chain
+    #[arg(long = "token-address", value_name = "ADDRESS[:SYMBOL[:DECIMALS]]")]
+    token_addresses: Vec<TokenSpec>,
+
+    /// Also report wrapped SOL (wSOL) separately from native SOL on Solana chains
+    #[arg(long)]
+    include_wrapped: bool,
+
+    /// Don't fall back to built-in public RPC endpoints when the configured one fails
+    #[arg(long)]
+    no_fallback_rpc: bool,
+
+    /// Block to read EVM balances at: latest (default), pending, safe, finalized
+    /// or a block number. `pending` includes mempool transactions where the node
+    /// supports it, so results vary between runs and nodes.
+    #[arg(long, value_name = "TAG")]
+    block_tag: Option<BlockTag>,
+
+    /// Commitment Solana balances are read at: processed (fastest, may be rolled
+    /// back), confirmed (default) or finalized
+    #[arg(long, value_name = "LEVEL", value_parser = ["processed", "confirmed", "finalized"])]
+    commitment: Option<String>,
+
+    /// Fail if the block (or slot) balances are read at is more than SECS old,
+    /// e.g. because the node is syncing or stuck
+    #[arg(long, value_name = "SECS")]
+    max_staleness: Option<u64>,
 
-    /// Chain to query (sepolia, solana-devnet, etc.)
-    #[arg(short, long, default_value = "sepolia")]
-    chain: String,
+    /// Report ERC-20 tokens whose balanceOf reverts as zero instead of failing
+    #[arg(long)]
+    treat_revert_as_zero: bool,
+
+    /// Max idle pooled connections kept per RPC host (overrides config `http`)
+    #[arg(long)]
+    pool_max_idle_per_host: Option<usize>,
+
+    /// Seconds an idle pooled connection is kept open (overrides config `http`)
+    #[arg(long, value_name = "SECS")]
+    pool_idle_timeout: Option<u64>,
+
+    /// Most RPC requests a provider sends at once, to stay under public RPC
+    /// rate limits (default 5; overrides config `http.maxInFlight`)
+    #[arg(long, value_name = "N")]
+    max_in_flight: Option<usize>,
+
+    /// Send If-None-Match for repeated EVM requests so a caching RPC proxy can
+    /// answer 304 Not Modified (overrides config `http.etagCache`)
+    #[arg(long)]
+    etag_cache: bool,
+
+    /// Attach a correlation ID header to every RPC request
+    #[arg(long)]
+    request_id: bool,
+
+    /// Header name used for the correlation ID
+    #[arg(long, default_value = DEFAULT_REQUEST_ID_HEADER)]
+    request_id_header: String,
+}
+
+impl QueryArgs {
+    /// The `QueryOptions` these flags ask for, with `on_error` as the caller
+    /// resolved it
+    fn options(&self, on_error: OnError) -> Result<QueryOptions> {
+        Ok(QueryOptions {
+            include_wrapped: self.include_wrapped,
+            disable_fallback_rpc: self.no_fallback_rpc,
+            treat_revert_as_zero: self.treat_revert_as_zero,
+            extra_tokens: self.token_addresses.clone(),
+            network: self.network.clone(),
+            block_tag: self.block_tag.unwrap_or_default(),
+            solana_commitment: self.commitment.as_deref().map(str::parse).transpose()?,
+            max_staleness_secs: self.max_staleness,
+            on_error,
+            http: HttpConfig {
+                pool_max_idle_per_host: self.pool_max_idle_per_host,
+                pool_idle_timeout_secs: self.pool_idle_timeout,
+                etag_cache: self.etag_cache.then_some(true),
+                max_in_flight: self.max_in_flight,
+                ..Default::default()
+            },
+            // Each chain, address and --watch refresh gets its own ID
+            request_id_header: self.request_id.then(|| self.request_id_header.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate the config and check every configured chain's RPC and contracts
+    Doctor,
+
+    /// Compare every configured token's contract, symbol and decimals with the chain
+    AuditConfig {
+        /// Chain whose tokens to audit
+        #[arg(short, long, env = "BALANCE_CHECKER_CHAIN")]
+        chain: Option<String>,
+    },
+
+    /// Balances at every Nth block of a range, as a JSON or CSV time series
+    History {
+        /// The blockchain address to query
+        #[arg(short, long, env = "BALANCE_CHECKER_ADDRESS")]
+        address: String,
+
+        /// EVM chain to query (sepolia, etc.)
+        #[arg(short, long, env = "BALANCE_CHECKER_CHAIN")]
+        chain: Option<String>,
+
+        /// First block of the range
+        #[arg(long)]
+        from_block: u64,
+
+        /// Last block of the range (inclusive)
+        #[arg(long)]
+        to_block: u64,
+
+        /// Query every Nth block
+        #[arg(long, default_value_t = 1)]
+        step: u64,
+
+        /// Output format
+        #[arg(long, default_value = "json", value_parser = ["json", "csv"])]
+        format: String,
+
+        /// What to do when a block fails: abort the series (default), or skip it
+        /// and list the failures at the end, exiting non-zero only if all failed
+        #[arg(long, value_name = "MODE")]
+        on_error: Option<OnError>,
+    },
+
+    /// Query balances and check them against expectations, exiting non-zero on failure
+    Assert {
+        /// The blockchain address to query
+        #[arg(short, long, env = "BALANCE_CHECKER_ADDRESS")]
+        address: String,
+
+        /// Chain to query (sepolia, solana-devnet, etc.)
+        #[arg(short, long, env = "BALANCE_CHECKER_CHAIN")]
+        chain: Option<String>,
+
+        /// Expected balance such as "USDC>=0.1" or "ETH>0" (repeatable)
+        #[arg(long = "expect", required = true)]
+        expectations: Vec<Expectation>,
+
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+
+    /// Find the SPL tokens an owner holds from its token accounts, configured or not
+    Discover {
+        /// The owner address to query
+        #[arg(short, long, env = "BALANCE_CHECKER_ADDRESS")]
+        address: String,
+
+        /// Solana chain to query (solana-devnet, etc.)
+        #[arg(short, long, env = "BALANCE_CHECKER_CHAIN")]
+        chain: Option<String>,
+
+        /// Also list tokens the owner has an account for but now holds none of
+        #[arg(long)]
+        include_zero_discovered: bool,
+
+        /// Output format
+        #[arg(long, default_value = "table", value_parser = ["table", "json"])]
+        format: String,
+    },
+
+    /// List every token account the owner has for a mint, with its state and the total
+    TokenAccounts {
+        /// The owner address to query
+        #[arg(short, long, env = "BALANCE_CHECKER_ADDRESS")]
+        address: String,
+
+        /// Solana chain to query (solana-devnet, etc.)
+        #[arg(short, long, env = "BALANCE_CHECKER_CHAIN")]
+        chain: Option<String>,
+
+        /// SPL token mint address
+        #[arg(long)]
+        mint: String,
+
+        /// Output format
+        #[arg(long, default_value = "table", value_parser = ["table", "json"])]
+        format: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let args = parse_args(std::env::args_os()).unwrap_or_else(|e| e.exit());
+
+    // Warnings only unless RUST_LOG is set, e.g. RUST_LOG=balance_checker=debug;
+    // --request-id also shows the IDs it generates, logged at info level
+    let mut filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(tracing::level_filters::LevelFilter::WARN.into())
+        .from_env_lossy();
+    let query = match &args.command {
+        Some(Command::Assert { query, .. }) => query,
+        _ => &args.query,
+    };
+    if query.request_id && std::env::var_os("RUST_LOG").is_none() {
+        filter = filter.add_directive("balance_checker=info".parse()?);
+    }
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    match args.command {
+        Some(Command::Doctor) => return run_doctor().await,
+        Some(Command::AuditConfig { chain }) => {
+            return run_audit_config(&chain_or_default(chain)?).await
+        }
+        Some(Command::History {
+            address,
+            chain,
+            from_block,
+            to_block,
+            step,
+            format,
+            on_error,
+        }) => {
+            let chain = chain_or_default(chain)?;
+            let options = QueryOptions {
+                on_error: on_error.unwrap_or_default(),
+                ..Default::default()
+            };
+            return run_history(
+                &chain, &address, from_block, to_block, step, &format, &options,
+            )
+            .await;
+        }
+        Some(Command::Assert {
+            address,
+            chain,
+            expectations,
+            query,
+        }) => {
+            let options = query.options(OnError::Abort)?;
+            return run_assert(&chain_or_default(chain)?, &address, &expectations, &options).await;
+        }
+        Some(Command::Discover {
+            address,
+            chain,
+            include_zero_discovered,
+            format,
+        }) => {
+            let chain = chain_or_default(chain)?;
+            let balances = balance_checker::discover_tokens(
+                &chain,
+                &address,
+                include_zero_discovered,
+                &QueryOptions::default(),
+            )
+            .await?;
+            if format == "json" {
+                println!("{}", serde_json::to_string_pretty(&balances)?);
+                return Ok(());
+            }
+            return print_table(&chain, &balances, false, false, None).await;
+        }
+        Some(Command::TokenAccounts {
+            address,
+            chain,
+            mint,
+            format,
+        }) => return run_token_accounts(&chain_or_default(chain)?, &address, &mint, &format).await,
+        None => {}
+    }
+
+    let chain = chain_or_default(args.chain)?;
+    let chains = args.chains;
+
+    let addresses = args.address;
+    let address = match addresses.as_slice() {
+        [] => return Err(anyhow!("--address or BALANCE_CHECKER_ADDRESS is required")),
+        [address] => address.clone(),
+        _ if args.all_chains => addresses.join(","),
+        _ if !chains.is_empty()
+            || args.save_snapshot.is_some()
+            || args.since.is_some()
+            || args.sqlite.is_some()
+            || args.explain_rpc
+            || args.watch.is_some() =>
+        {
+            return Err(anyhow!(
+                "Several --address take a single --chain and no --save-snapshot, --since, --sqlite, --explain-rpc or --watch"
+            ))
+        }
+        _ => String::new(),
+    };
+
+    // Several chains skip a failed one by default, so the others are still printed
+    let options = args.query.options(args.on_error.unwrap_or(OnError::Skip))?;
+
+    if args.explain_rpc {
+        // The explained reads are an operation of their own, with their own ID
+        let options = QueryOptions {
+            request_id: options
+                .request_id_header
+                .as_deref()
+                .map(RequestId::generate)
+                .transpose()?,
+            ..options.clone()
+        };
+        let headers: Vec<(String, String)> = options
+            .request_id
+            .iter()
+            .map(|id| (id.header().to_string(), id.value().to_string()))
+            .collect();
+        for call in balance_checker::explain_rpc(&chain, &address, &options).await? {
+            eprintln!("{}", call.to_curl(&headers, !args.show_secrets));
+        }
+        if args.dry_run {
+            return Ok(());
+        }
+    }
+
+    let json = args.format == "json";
+    let show = |address: &str| match args.mask_address {
+        Some(visible) => balance_checker::mask_address(address, visible),
+        None => balance_checker::normalize_address(address),
+    };
+
+    if addresses.len() > 1 && !args.all_chains {
+        // One set of providers for the chain, shared by every address
+        let results: Vec<_> =
+            balance_checker::get_balances_for_addresses(&chain, &addresses, &options)
+                .await
+                .into_iter()
+                .map(|result| result.map(|balances| shown_balances(balances, args.hide_zero)))
+                .collect();
+        for (i, (address, result)) in addresses.iter().zip(&results).enumerate() {
+            if json {
+                continue;
+            }
+            if i > 0 {
+                println!();
+            }
+            if !args.raw_only {
+                println!("Querying balances for address: {}\n", show(address));
+            }
+            match result {
+                Ok(balances) if args.raw_only => {
+                    for balance in balances {
+                        println!("{}", balance.amount);
+                    }
+                }
+                Ok(balances) => {
+                    print_balances(
+                        &chain,
+                        &show(address),
+                        balances,
+                        args.show_price,
+                        args.with_prices,
+                        args.price_source,
+                    )
+                    .await?
+                }
+                // The other addresses are still queried and printed
+                Err(e) => eprintln!("Error: {}: {}", show(address), e),
+            }
+        }
+        if json {
+            let document = addresses_json(&chain, &addresses, &results);
+            println!("{}", serde_json::to_string_pretty(&document)?);
+        }
+        if let Some(code) = addresses_exit_code(&results, args.empty_exit_code) {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
+
+    let shown = match args.all_chains {
+        true => address
+            .split(',')
+            .map(|a| show(a.trim()))
+            .collect::<Vec<_>>()
+            .join(", "),
+        false => show(&address),
+    };
+    if !args.raw_only && !json {
+        println!("Querying balances for address: {}\n", shown);
+    }
+
+    if let Some(secs) = args.watch {
+        let config = Config::shared()?;
+        // Built once, so every refresh reuses its connections, unless each
+        // refresh needs a reader of its own for its generated request ID
+        let shared = match options.request_id_header {
+            Some(_) => None,
+            None => Some(ChainReader::new(&config, &chain, &options)?),
+        };
+
+        let refresh = async {
+            let mut ticks = tokio::time::interval(Duration::from_secs(secs.max(1)));
+            let mut last_refresh = None;
+            let mut last_balances = None;
+            loop {
+                ticks.tick().await;
+                let fresh;
+                let reader = match &shared {
+                    Some(reader) => reader,
+                    None => {
+                        fresh = ChainReader::new(&config, &chain, &options)?;
+                        &fresh
+                    }
+                };
+                let failure = match reader.read(&address).await {
+                    Ok(balances) => {
+                        last_refresh = Some(SystemTime::now().duration_since(UNIX_EPOCH)?);
+                        last_balances = Some(shown_balances(balances, args.hide_zero));
+                        None
+                    }
+                    // Retrying can't fix the address
+                    Err(e @ BalanceError::InvalidAddress { .. }) => return Err(e.into()),
+                    Err(e) => Some(e),
+                };
+
+                // Clear the screen and move the cursor to the top left
+                print!("\x1b[2J\x1b[H");
+                println!("Querying balances for address: {}\n", shown);
+                // Keep the last table on screen; its time shows it is stale
+                if let Some(balances) = &last_balances {
+                    print_balances(
+                        &chain,
+                        &shown,
+                        balances,
+                        args.show_price,
+                        args.with_prices,
+                        args.price_source,
+                    )
+                    .await?;
+                }
+                if let Some(e) = failure {
+                    println!("Refresh failed: {}", e);
+                }
+                match last_refresh {
+                    Some(at) => println!(
+                        "Last refresh: {} UTC (every {}s, Ctrl-C to exit)",
+                        utc_time(at.as_secs()),
+                        secs
+                    ),
+                    None => println!("No successful refresh yet (Ctrl-C to exit)"),
+                }
+            }
+        };
+        return tokio::select! {
+            result = refresh => result,
+            _ = tokio::signal::ctrl_c() => Ok(()),
+        };
+    }
 
-    println!("Querying balances for address: {}\n", args.address);
+    if !chains.is_empty() || args.all_chains {
+        // (chain, address it was queried with, balances)
+        let results: Vec<(String, String, Result<Vec<Balance>>)> = match args.all_chains {
+            true => portfolio(&address, &options).await?,
+            false => {
+                let results = balance_checker::get_balances_for_chains(
+                    &chains,
+                    &address,
+                    &options,
+                    args.parallel_chains,
+                )
+                .await;
+                chains
+                    .iter()
+                    .zip(results)
+                    .map(|(chain, result)| (chain.clone(), address.clone(), result))
+                    .collect()
+            }
+        };
+        let queried = results.len();
+        let mut failures = Vec::new();
+        let mut documents = Vec::new();
+        let mut all_empty = true;
+        for (chain, address, result) in results {
+            if let Ok(balances) = &result {
+                all_empty &= all_zero(balances);
+            }
+            match result.map(|balances| shown_balances(balances, args.hide_zero)) {
+                Ok(balances) if json => documents.push(balances_json(&chain, &address, &balances)),
+                Ok(balances) if args.raw_only => {
+                    for balance in &balances {
+                        println!("{}", balance.amount);
+                    }
+                }
+                Ok(balances) => {
+                    print_balances(
+                        &chain,
+                        &show(&address),
+                        &balances,
+                        args.show_price,
+                        args.with_prices,
+                        args.price_source,
+                    )
+                    .await?
+                }
+                Err(e) if options.on_error == OnError::Abort && !args.all_chains => {
+                    eprintln!("Error: {}: {}", chain, e);
+                    std::process::exit(1);
+                }
+                Err(e) => failures.push((chain, e)),
+            }
+        }
+        if json {
+            println!("{}", serde_json::to_string_pretty(&documents)?);
+        }
+        if !failures.is_empty() {
+            eprintln!("\nSkipped {} of {} chains:", failures.len(), queried);
+            for (chain, e) in &failures {
+                eprintln!("  {}: {}", chain, e);
+            }
+            if failures.len() == queried {
+                std::process::exit(1);
+            }
+        }
+        if let Some(code) = args.empty_exit_code.filter(|_| all_empty) {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
 
     // Use the library API
-    match balance_checker::get_balances(&args.chain, &args.address).await {
+    match balance_checker::get_balances_with_options(&chain, &address, &options).await {
         Ok(balances) => {
-            println!("Chain: {}", args.chain);
-            println!("{}", "=".repeat(60));
+            // Snapshots and SQLite keep zero balances, so later diffs see them
+            let kept = shown_balances(balances.clone(), args.hide_zero);
+            if json {
+                let document = balances_json(&chain, &address, &kept);
+                println!("{}", serde_json::to_string_pretty(&document)?);
+            } else if args.raw_only {
+                for balance in &kept {
+                    println!("{}", balance.amount);
+                }
+            } else {
+                print_balances(
+                    &chain,
+                    &shown,
+                    &kept,
+                    args.show_price,
+                    args.with_prices,
+                    args.price_source,
+                )
+                .await?;
+            }
+
+            if let Some(path) = &args.save_snapshot {
+                let snapshot = balance_checker::snapshot_of_balances(
+                    &chain,
+                    &address,
+                    balances.clone(),
+                    &options,
+                )
+                .await?;
+                std::fs::write(path, serde_json::to_string_pretty(&snapshot)?)?;
+            }
+
+            if let Some(path) = &args.sqlite {
+                let chain = Config::shared()?.resolve_chain(&chain, options.network.as_deref())?;
+                // Rows keep the block their balance was read at; the head, read
+                // after the balances as an upper bound, only stands in for
+                // balances that don't record one
+                let block = match balances.iter().all(|balance| balance.block.is_some()) {
+                    true => None,
+                    false => balance_checker::latest_block(&chain, &options).await.ok(),
+                };
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                let mut store = SqliteStore::open(path)?;
+                store.insert(&chain, &address, block, timestamp, &balances)?;
+            }
 
-            for balance in balances {
-                println!(
-                    "{:6} | {:>20} (raw: {})",
-                    balance.token, balance.formatted, balance.amount
+            let mut alerts = Vec::new();
+            if let (Some(path), Some(threshold)) = (&args.since, &args.alert_change) {
+                let previous: Snapshot = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+                alerts =
+                    balance_checker::changes_exceeding(&previous.balances, &balances, *threshold)?;
+            }
+            // On stderr, so they don't break --format json or --raw-only output
+            for change in &alerts {
+                let percent = change
+                    .percent
+                    .map(|percent| format!("{:+.2}%", percent))
+                    .unwrap_or_else(|| "new".to_string());
+                eprintln!(
+                    "ALERT {} changed by {} ({} -> {})",
+                    change.token, percent, change.previous, change.current
                 );
             }
 
-            println!("{}", "=".repeat(60));
+            // Only exit once everything is printed and written
+            if !alerts.is_empty() {
+                std::process::exit(2);
+            }
+            if let Some(code) = args.empty_exit_code.filter(|_| all_zero(&balances)) {
+                std::process::exit(code);
+            }
         }
         Err(e) => {
             eprintln!("Error: {}", e);
@@ -43,3 +771,675 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// `Args` from `argv`, with a comma-separated --chain (or BALANCE_CHECKER_CHAIN)
+/// moved to --chains, so both are validated, defaulted and queried the same way
+fn parse_args<I, T>(argv: I) -> Result<Args, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = Args::command().try_get_matches_from(argv)?;
+    let mut args = Args::from_arg_matches(&matches)?;
+    let Some(list) = args.chain.take_if(|chain| chain.contains(',')) else {
+        return Ok(args);
+    };
+    let single_chain_flag = SINGLE_CHAIN_FLAGS
+        .into_iter()
+        .find(|id| matches.value_source(id) == Some(ValueSource::CommandLine));
+    if let Some(flag) = single_chain_flag {
+        return Err(Args::command().error(
+            ErrorKind::ArgumentConflict,
+            format!(
+                "a comma-separated '--chain' cannot be used with '--{}'",
+                flag.replace('_', "-")
+            ),
+        ));
+    }
+    args.chains = list
+        .split(',')
+        .map(str::trim)
+        .filter(|chain| !chain.is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok(args)
+}
+
+/// `--chain` or `BALANCE_CHECKER_CHAIN` if given, else the config's default chain
+fn chain_or_default(chain: Option<String>) -> Result<String> {
+    match chain {
+        Some(chain) => Ok(chain),
+        None => Ok(Config::shared()?.default_chain().to_string()),
+    }
+}
+
+/// `HH:MM:SS` of the Unix time `secs`, in UTC
+fn utc_time(secs: u64) -> String {
+    let day_secs = secs % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        day_secs / 3_600,
+        day_secs % 3_600 / 60,
+        day_secs % 60
+    )
+}
+
+/// `get_portfolio` for the comma-separated `addresses`, each used for every
+/// chain type it is valid for (the first one listed wins), as (chain,
+/// address, balances)
+async fn portfolio(
+    addresses: &str,
+    options: &QueryOptions,
+) -> Result<Vec<(String, String, Result<Vec<Balance>>)>> {
+    let mut by_type = HashMap::new();
+    for address in addresses.split(',').map(str::trim) {
+        let types: Vec<&str> = ["bitcoin", "evm", "icp", "solana"]
+            .into_iter()
+            .filter(|chain_type| balance_checker::validate_address(chain_type, address).is_ok())
+            .collect();
+        if types.is_empty() {
+            return Err(anyhow!(
+                "'{}' is not a valid address on any chain type",
+                address
+            ));
+        }
+        for chain_type in types {
+            by_type
+                .entry(chain_type.to_string())
+                .or_insert_with(|| address.to_string());
+        }
+    }
+
+    let config = Config::shared()?;
+    let results = balance_checker::get_portfolio(&config, &by_type, options).await;
+    if results.is_empty() {
+        return Err(anyhow!("No configured chain matches the given addresses"));
+    }
+    Ok(results
+        .into_iter()
+        .map(|(chain, result)| {
+            let chain_type = &config.chains[&chain].chain_type;
+            (chain, by_type[chain_type].clone(), result)
+        })
+        .collect())
+}
+
+/// The `--format json` object for one chain: context plus the balances
+fn balances_json(chain: &str, address: &str, balances: &[Balance]) -> serde_json::Value {
+    serde_json::json!({
+        "chain": chain,
+        "address": balance_checker::normalize_address(address),
+        "balances": balances,
+    })
+}
+
+/// The `--format json` array of a multi-address run: one object per address,
+/// in the order given, with its balances or the error it failed with
+fn addresses_json(
+    chain: &str,
+    addresses: &[String],
+    results: &[Result<Vec<Balance>, BalanceError>],
+) -> serde_json::Value {
+    let documents = addresses
+        .iter()
+        .zip(results)
+        .map(|(address, result)| match result {
+            Ok(balances) => balances_json(chain, address, balances),
+            Err(e) => serde_json::json!({
+                "chain": chain,
+                "address": balance_checker::normalize_address(address),
+                "error": e.to_string(),
+            }),
+        });
+    serde_json::Value::Array(documents.collect())
+}
+
+/// Exit code of a multi-address run, `None` to exit normally: 1 when every
+/// address failed, else `empty_exit_code` when every address that answered
+/// holds nothing
+fn addresses_exit_code(
+    results: &[Result<Vec<Balance>, BalanceError>],
+    empty_exit_code: Option<i32>,
+) -> Option<i32> {
+    let answered: Vec<&Vec<Balance>> = results.iter().filter_map(|r| r.as_ref().ok()).collect();
+    if answered.is_empty() {
+        return Some(1);
+    }
+    empty_exit_code.filter(|_| answered.iter().all(|balances| all_zero(balances)))
+}
+
+/// Whether an address holds nothing at all, native balance included
+fn all_zero(balances: &[Balance]) -> bool {
+    balances.iter().all(Balance::is_zero)
+}
+
+/// `print_table`, or one line saying so when every balance is zero, as a
+/// table of zeros reads like a failed query
+async fn print_balances(
+    chain: &str,
+    address: &str,
+    balances: &[Balance],
+    show_price: bool,
+    with_prices: bool,
+    price_source: Option<PriceSource>,
+) -> Result<()> {
+    if all_zero(balances) {
+        println!("No balances found for {} on {}", address, chain);
+        return Ok(());
+    }
+    print_table(chain, balances, show_price, with_prices, price_source).await
+}
+
+fn shown_balances(balances: Vec<Balance>, hide_zero: bool) -> Vec<Balance> {
+    match hide_zero {
+        true => balance_checker::filter_nonzero(balances),
+        false => balances,
+    }
+}
+
+async fn print_table(
+    chain: &str,
+    balances: &[Balance],
+    show_price: bool,
+    with_prices: bool,
+    price_source: Option<PriceSource>,
+) -> Result<()> {
+    println!("Chain: {}", chain);
+    println!("{}", "=".repeat(60));
+
+    let config = Config::shared()?;
+    let prices = if show_price || with_prices {
+        let mut price_config = config.price.clone();
+        if price_source.is_some() {
+            price_config.source = price_source;
+        }
+        let symbols: Vec<String> = balances.iter().map(|b| b.token.clone()).collect();
+        // A failed batch leaves every price blank rather than failing the query
+        let provider = balance_checker::price_provider(&price_config)?;
+        Some(provider.get_prices(&symbols).await.unwrap_or_default())
+    } else {
+        None
+    };
+    let read_at = match config.get_chain(chain).map(|c| c.chain_type.as_str()) {
+        Some("solana") => "slot",
+        _ => "block",
+    };
+    for balance in balances {
+        // Unknown symbols leave the price and value blank
+        let price = prices
+            .as_ref()
+            .and_then(|prices| prices.get(&balance.token));
+        println!(
+            "{}",
+            table_row(balance, price, show_price, with_prices, read_at)
+        );
+    }
+
+    println!("{}", "=".repeat(60));
+    if let Some(rpc) = balances.first().and_then(|balance| balance.rpc.as_ref()) {
+        println!("Served by: {}", rpc);
+    }
+    Ok(())
+}
+
+/// One balance line of the table; `read_at` names what `balance.block` is
+/// ("block" or "slot")
+fn table_row(
+    balance: &Balance,
+    price: Option<&f64>,
+    show_price: bool,
+    with_prices: bool,
+    read_at: &str,
+) -> String {
+    let mut line = format!(
+        "{:6} | {:>20} (raw: {})",
+        balance.token, balance.formatted, balance.amount
+    );
+    if show_price {
+        match price {
+            Some(price) => line.push_str(&format!(" @ ${}", price)),
+            None => line.push_str(" @ -"),
+        }
+    }
+    if with_prices {
+        match price {
+            Some(price) if balance.kind == BalanceKind::Fungible => {
+                line.push_str(&format!(" = ${:.2}", balance.usd_value(*price)))
+            }
+            _ => line.push_str(" = -"),
+        }
+    }
+    if let Some(block) = balance.block {
+        line.push_str(&format!(" [{} {}]", read_at, block));
+    }
+    line
+}
+
+async fn run_doctor() -> Result<()> {
+    let config = match Config::load() {
+        Ok(config) => {
+            println!("[PASS] config: loaded {} chains", config.chains.len());
+            config
+        }
+        Err(e) => {
+            println!("[FAIL] config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let problems = config.validate().err().unwrap_or_default();
+    for problem in &problems {
+        println!("[FAIL] config: {}", problem);
+    }
+
+    let checks = balance_checker::diagnose(&config).await;
+    for check in &checks {
+        let status = if check.passed { "PASS" } else { "FAIL" };
+        println!("[{}] {}: {}", status, check.name, check.detail);
+    }
+
+    let failed = checks.iter().filter(|check| !check.passed).count() + problems.len();
+    println!(
+        "\n{} checks, {} failed",
+        checks.len() + problems.len() + 1,
+        failed
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_audit_config(chain: &str) -> Result<()> {
+    let checks = balance_checker::audit_chain(&Config::load()?, chain).await?;
+    for check in &checks {
+        let status = if check.passed { "PASS" } else { "FAIL" };
+        println!("[{}] {}: {}", status, check.name, check.detail);
+    }
+
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    println!("\n{} tokens, {} mismatched", checks.len(), failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_history(
+    chain: &str,
+    address: &str,
+    from: u64,
+    to: u64,
+    step: u64,
+    format: &str,
+    options: &QueryOptions,
+) -> Result<()> {
+    let report =
+        balance_checker::balance_history_report(chain, address, from, to, step, options).await?;
+    let points = &report.points;
+
+    if format == "csv" {
+        for line in history_csv(points) {
+            println!("{}", line);
+        }
+    } else {
+        println!("{}", serde_json::to_string_pretty(points)?);
+    }
+
+    if !report.failures.is_empty() {
+        let total = report.failures.len() + points.len();
+        eprintln!("\nSkipped {} of {} blocks:", report.failures.len(), total);
+        for failure in &report.failures {
+            eprintln!("  {}: {}", failure.block, failure.error);
+        }
+        if points.is_empty() {
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// `history --format csv` lines, header first, one per balance of each point
+fn history_csv(points: &[HistoryPoint]) -> Vec<String> {
+    let rows = points.iter().flat_map(|point| {
+        point.balances.iter().map(move |balance| {
+            format!(
+                "{},{},{},{}",
+                point.block, balance.token, balance.amount, balance.formatted
+            )
+        })
+    });
+    std::iter::once("block,token,amount,formatted".to_string())
+        .chain(rows)
+        .collect()
+}
+
+/// Exit code of `assert` when the balances couldn't be read, so CI can tell
+/// a failed query from a failed expectation (exit code 1)
+const ASSERT_QUERY_FAILED: i32 = 3;
+
+async fn run_assert(
+    chain: &str,
+    address: &str,
+    expectations: &[Expectation],
+    options: &QueryOptions,
+) -> Result<()> {
+    let balances = match balance_checker::get_balances_with_options(chain, address, options).await {
+        Ok(balances) => balances,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(ASSERT_QUERY_FAILED);
+        }
+    };
+
+    let results = balance_checker::evaluate_all(expectations, &balances)?;
+    for result in &results {
+        let status = if result.passed { "PASS" } else { "FAIL" };
+        let actual = result.actual.as_deref().unwrap_or("not found");
+        println!("[{}] {} (actual: {})", status, result.expectation, actual);
+    }
+
+    let failed = results.iter().filter(|result| !result.passed).count();
+    println!("\n{} assertions, {} failed", results.len(), failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_token_accounts(chain: &str, address: &str, mint: &str, format: &str) -> Result<()> {
+    let accounts =
+        balance_checker::get_token_accounts(chain, address, mint, &QueryOptions::default()).await?;
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&accounts)?);
+        return Ok(());
+    }
+
+    println!("Chain: {}", chain);
+    println!("Mint: {} ({})", accounts.mint, accounts.total.token);
+    println!("{}", "=".repeat(60));
+    for account in &accounts.accounts {
+        let state = match account.state {
+            AccountState::Initialized => "initialized",
+            AccountState::Frozen => "frozen (can't be spent)",
+        };
+        println!(
+            "{} | {:>20} | {}",
+            account.address, account.formatted, state
+        );
+    }
+    println!("{}", "=".repeat(60));
+    println!(
+        "Total: {} {} in {} accounts",
+        accounts.total.formatted,
+        accounts.total.token,
+        accounts.accounts.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use balance_checker::{ChainConfig, ChainProvider, Token};
+
+    #[test]
+    fn test_comma_separated_chain_is_a_chain_list() {
+        let address = "0x78697a9cfc48c1e9d1040172d51833ef78083b10";
+        let args = parse_args([
+            "balance-checker",
+            "-a",
+            address,
+            "--chain",
+            "sepolia, base-sepolia",
+        ])
+        .unwrap();
+        assert_eq!(args.chain, None);
+        assert_eq!(args.chains, ["sepolia", "base-sepolia"]);
+
+        for flag in [["--sqlite", "balances.db"], ["--watch", "30"]] {
+            let chains = [
+                "balance-checker",
+                "-a",
+                address,
+                "--chains",
+                "sepolia,base-sepolia",
+            ];
+            let chain = [
+                "balance-checker",
+                "-a",
+                address,
+                "--chain",
+                "sepolia,base-sepolia",
+            ];
+            let error =
+                |argv: [&str; 5]| parse_args(argv.into_iter().chain(flag)).unwrap_err().kind();
+            assert_eq!(error(chains), ErrorKind::ArgumentConflict);
+            assert_eq!(error(chain), ErrorKind::ArgumentConflict);
+        }
+
+        let single = parse_args(["balance-checker", "-a", address, "--chain", "sepolia"]).unwrap();
+        assert_eq!(single.chain.as_deref(), Some("sepolia"));
+        assert!(single.chains.is_empty());
+    }
+
+    #[test]
+    fn test_on_error_is_only_taken_where_it_applies() {
+        let address = "0x78697a9cfc48c1e9d1040172d51833ef78083b10";
+        let history = parse_args([
+            "balance-checker",
+            "history",
+            "-a",
+            address,
+            "--from-block",
+            "1",
+            "--to-block",
+            "2",
+            "--on-error",
+            "skip",
+        ])
+        .unwrap();
+        assert!(matches!(
+            history.command,
+            Some(Command::History {
+                on_error: Some(OnError::Skip),
+                ..
+            })
+        ));
+
+        let assert = parse_args([
+            "balance-checker",
+            "assert",
+            "-a",
+            address,
+            "--expect",
+            "ETH>0",
+            "--on-error",
+            "skip",
+        ]);
+        assert_eq!(assert.unwrap_err().kind(), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn test_assert_takes_the_query_flags() {
+        let args = parse_args([
+            "balance-checker",
+            "assert",
+            "--address",
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+            "--expect",
+            "ETH>0",
+            "--network",
+            "testnet",
+            "--block-tag",
+            "7000000",
+            "--max-in-flight",
+            "2",
+        ])
+        .unwrap();
+        let Some(Command::Assert { query, .. }) = args.command else {
+            panic!("expected the assert subcommand");
+        };
+        let options = query.options(OnError::Abort).unwrap();
+        assert_eq!(options.network.as_deref(), Some("testnet"));
+        assert_eq!(options.block_tag, BlockTag::Number(7_000_000));
+        assert_eq!(options.http.max_in_flight, Some(2));
+    }
+
+    fn results() -> Vec<Result<Vec<Balance>, BalanceError>> {
+        vec![
+            Ok(vec![Balance::from_minor_units("ETH".to_string(), 0, 18)]),
+            Err(BalanceError::InvalidAddress {
+                address: "not-an-address".to_string(),
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_addresses_json_keeps_errors_in_order() {
+        let addresses = [
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+            "not-an-address",
+        ]
+        .map(String::from);
+        let document = addresses_json("sepolia", &addresses, &results());
+
+        assert_eq!(
+            document[0]["address"],
+            balance_checker::normalize_address(&addresses[0])
+        );
+        assert_eq!(document[0]["balances"][0]["token"], "ETH");
+        assert!(document[0].get("error").is_none());
+        assert_eq!(document[1]["chain"], "sepolia");
+        assert_eq!(document[1]["address"], "not-an-address");
+        assert!(document[1]["error"]
+            .as_str()
+            .unwrap()
+            .contains("not-an-address"));
+        assert!(document[1].get("balances").is_none());
+    }
+
+    #[test]
+    fn test_addresses_exit_code() {
+        let mut results = results();
+        // One address failed, the other is empty
+        assert_eq!(addresses_exit_code(&results, None), None);
+        assert_eq!(addresses_exit_code(&results, Some(3)), Some(3));
+
+        results.push(Ok(vec![Balance::from_minor_units(
+            "ETH".to_string(),
+            5,
+            18,
+        )]));
+        assert_eq!(addresses_exit_code(&results, Some(3)), None);
+
+        // Every address failed
+        results.retain(Result::is_err);
+        assert_eq!(addresses_exit_code(&results, None), Some(1));
+        assert_eq!(addresses_exit_code(&results, Some(3)), Some(1));
+    }
+
+    /// Provider whose query returns `self.0` as is: the native balance, then
+    /// the tokens in whatever order a config `HashMap` iterates them
+    struct Unordered(Vec<Balance>);
+
+    #[async_trait::async_trait]
+    impl ChainProvider for Unordered {
+        fn chain_name(&self) -> &str {
+            "Unordered"
+        }
+
+        fn native_symbol(&self) -> &str {
+            "ETH"
+        }
+
+        async fn get_native_balance(&self, _address: &str) -> Result<Balance, BalanceError> {
+            Ok(self.0[0].clone())
+        }
+
+        async fn get_token_balance(
+            &self,
+            _address: &str,
+            token: &Token,
+        ) -> Result<Balance, BalanceError> {
+            Err(BalanceError::Config {
+                message: format!("unexpected read of {}", token.symbol()),
+            })
+        }
+
+        async fn get_balances(
+            &self,
+            _config: &ChainConfig,
+            _address: &str,
+            _options: &QueryOptions,
+        ) -> Result<Vec<Balance>, BalanceError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    /// The table rows, `--format json` document and history CSV lines of the
+    /// balances a query reading `balances` returns
+    async fn outputs(balances: Vec<Balance>) -> (Vec<String>, serde_json::Value, Vec<String>) {
+        let config = Config::load().unwrap();
+        let address = "0x78697a9cfc48c1e9d1040172d51833ef78083b10";
+        let balances = balance_checker::get_balances_with_provider(
+            &config,
+            "sepolia",
+            address,
+            &QueryOptions::default(),
+            Box::new(Unordered(balances)),
+        )
+        .await
+        .unwrap();
+
+        let table = balances
+            .iter()
+            .map(|balance| table_row(balance, None, false, false, "block"))
+            .collect();
+        let json = balances_json("sepolia", address, &balances);
+        let csv = history_csv(&[HistoryPoint { block: 1, balances }]);
+        (table, json, csv)
+    }
+
+    #[tokio::test]
+    async fn test_output_formats_share_one_order() {
+        let native = Balance::from_minor_units("ETH".to_string(), 1, 18);
+        // Two ERC-1155 IDs of one collection share the symbol ITEM
+        let tokens = [
+            ("USDC", 2, 6),
+            ("ITEM", 20, 0),
+            ("EURC", 3, 6),
+            ("ITEM", 4, 0),
+        ]
+        .map(|(token, units, decimals)| {
+            Balance::from_minor_units(token.to_string(), units, decimals)
+        });
+        let forward = std::iter::once(native.clone()).chain(tokens.iter().cloned());
+        let reversed = std::iter::once(native).chain(tokens.iter().rev().cloned());
+
+        let first = outputs(forward.collect()).await;
+        assert_eq!(first, outputs(reversed.collect()).await);
+
+        let (table, json, csv) = first;
+        let expected = [
+            ("ETH", "1"),
+            ("EURC", "3"),
+            ("ITEM", "4"),
+            ("ITEM", "20"),
+            ("USDC", "2"),
+        ];
+        assert_eq!(table.len(), expected.len());
+        assert_eq!(csv.len(), expected.len() + 1);
+        for (i, (token, amount)) in expected.into_iter().enumerate() {
+            assert!(table[i].starts_with(&format!("{:6} |", token)));
+            assert!(table[i].ends_with(&format!("(raw: {})", amount)));
+            assert_eq!(json["balances"][i]["token"], token);
+            assert_eq!(json["balances"][i]["amount"], amount);
+            assert!(csv[i + 1].starts_with(&format!("1,{},{},", token, amount)));
+        }
+    }
+}
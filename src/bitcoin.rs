@@ -0,0 +1,476 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use serde::Deserialize;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::chain::ChainProvider;
+use crate::config::ChainConfig;
+use crate::error::BalanceError;
+use crate::fallback::{rpc_host, RpcEndpoints};
+use crate::http::{self, HttpConfig, RequestId, DEFAULT_RPC_TIMEOUT};
+use crate::options::QueryOptions;
+use crate::retry::RetryPolicy;
+use crate::telemetry;
+use crate::types::{Balance, Token};
+
+/// BTC has 8 decimals: one bitcoin is 10^8 satoshis
+const BTC_DECIMALS: u8 = 8;
+
+/// Characters of the bech32 data part
+const BECH32_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// `/address/:address` response; `chain_stats` only counts confirmed
+/// transactions, unconfirmed ones are under `mempool_stats`
+#[derive(Deserialize)]
+struct AddressInfo {
+    chain_stats: TxoStats,
+}
+
+#[derive(Deserialize)]
+struct TxoStats {
+    funded_txo_sum: u64,
+    spent_txo_sum: u64,
+}
+
+/// `/block/:hash` response, the fields needed for staleness checks
+#[derive(Deserialize)]
+struct BlockInfo {
+    height: u64,
+    timestamp: u64,
+}
+
+/// Bitcoin chain provider using an Esplora REST API, as served by
+/// mempool.space and blockstream.info. The base URL picks the network, e.g.
+/// `https://mempool.space/api` or `https://mempool.space/testnet4/api`.
+#[derive(Clone)]
+pub struct BitcoinProvider {
+    endpoints: Arc<RpcEndpoints>,
+    request_id: Option<RequestId>,
+    http: HttpConfig,
+    retry: RetryPolicy,
+    timeout: Duration,
+    // Built on first use and shared by every call so connections are pooled
+    client: OnceLock<reqwest::Client>,
+    /// Limits requests in flight, shared by clones
+    in_flight: Arc<Semaphore>,
+    chain_name: String,
+}
+
+impl BitcoinProvider {
+    pub fn new(base_url: String) -> Self {
+        Self {
+            endpoints: Arc::new(RpcEndpoints::new(vec![base_url])),
+            request_id: None,
+            http: HttpConfig::default(),
+            retry: RetryPolicy::default(),
+            timeout: DEFAULT_RPC_TIMEOUT,
+            client: OnceLock::new(),
+            in_flight: HttpConfig::default().in_flight_limiter(),
+            chain_name: "Bitcoin".to_string(),
+        }
+    }
+
+    pub fn new_testnet() -> Self {
+        Self::new("https://mempool.space/testnet4/api".to_string())
+            .with_chain_name("Bitcoin Testnet4".to_string())
+    }
+
+    /// Name `chain_name` reports; "Bitcoin" by default
+    pub fn with_chain_name(mut self, name: String) -> Self {
+        self.chain_name = name;
+        self
+    }
+
+    /// Base URLs to move on to, in order, when the current one is down
+    /// (unreachable, erroring, timing out or rate limiting after retries).
+    /// The one that answered stays in use for later calls.
+    pub fn with_fallback_rpcs(mut self, rpcs: Vec<String>) -> Self {
+        let primary = self.endpoints.urls()[0].clone();
+        let urls = std::iter::once(primary).chain(rpcs).collect();
+        self.endpoints = Arc::new(RpcEndpoints::new(urls));
+        self
+    }
+
+    /// The base URL and fallbacks, in the order they are tried
+    pub fn rpc_urls(&self) -> &[String] {
+        self.endpoints.urls()
+    }
+
+    /// The base URL calls currently go to
+    pub fn active_rpc(&self) -> &str {
+        self.endpoints.active()
+    }
+
+    /// Send the given correlation ID header with every request
+    pub fn with_request_id(mut self, request_id: RequestId) -> Self {
+        self.request_id = Some(request_id);
+        self.client = OnceLock::new();
+        self
+    }
+
+    /// Tune the connection pool of the underlying HTTP client
+    pub fn with_http_config(mut self, http: HttpConfig) -> Self {
+        self.in_flight = http.in_flight_limiter();
+        self.http = http;
+        self.client = OnceLock::new();
+        self
+    }
+
+    /// Retry policy for transient failures (rate limits, timeouts, connection
+    /// and server errors); three attempts by default
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Give up on a request to an endpoint, retries included, after `timeout`
+    /// with `BalanceError::Timeout` (then moving on to the next fallback, if
+    /// any); `DEFAULT_RPC_TIMEOUT` by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Height of the latest block, used as a connectivity check
+    pub async fn tip_height(&self) -> Result<u64> {
+        let height = self.get("blocks/tip/height").await?;
+        height
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid block height '{}'", height.trim()))
+    }
+
+    /// Height and Unix timestamp of the latest block
+    pub async fn tip_time(&self) -> Result<(u64, u64)> {
+        let hash = self.get("blocks/tip/hash").await?;
+        let block: BlockInfo =
+            serde_json::from_str(&self.get(&format!("block/{}", hash.trim())).await?)?;
+        Ok((block.height, block.timestamp))
+    }
+
+    /// GET `path` below the base URL under the retry policy and the timeout,
+    /// on each endpoint in turn while they are down
+    async fn get(&self, path: &str) -> Result<String> {
+        let client = match self.client.get() {
+            Some(client) => client.clone(),
+            None => {
+                let client = http::client_builder(self.request_id.as_ref(), &self.http)?
+                    .timeout(self.timeout)
+                    .build()?;
+                self.client.get_or_init(|| client).clone()
+            }
+        };
+
+        let (client, in_flight) = (&client, &self.in_flight);
+        let mut last_error = None;
+        for index in self.endpoints.order() {
+            let url = &format!(
+                "{}/{}",
+                self.endpoints.urls()[index].trim_end_matches('/'),
+                path
+            );
+            // Wait for a slot per attempt, so backoff between retries holds none
+            let attempts = self.retry.run_async(
+                || async move {
+                    let _permit = in_flight.acquire().await;
+                    telemetry::rpc_call(url, fetch(client, url)).await
+                },
+                is_transient,
+            );
+            let error = match tokio::time::timeout(self.timeout, attempts).await {
+                Ok(Ok(body)) => return Ok(body),
+                Ok(Err(error)) if is_transient(&error) => error,
+                Ok(Err(error)) => return Err(error),
+                Err(_) => BalanceError::Timeout.into(),
+            };
+            self.endpoints.fail_over(index, &error);
+            last_error = Some(error);
+        }
+        Err(last_error.expect("a provider has at least one endpoint"))
+    }
+}
+
+/// One GET request, with rate limits, server errors and connection trouble
+/// reported as `BalanceError`s
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<String> {
+    let response = client.get(url).send().await.map_err(request_error)?;
+    let status = response.status();
+    let body = response.text().await.map_err(request_error)?;
+    match status {
+        reqwest::StatusCode::TOO_MANY_REQUESTS => Err(BalanceError::RateLimited.into()),
+        status if status.is_server_error() => Err(BalanceError::Network {
+            message: format!("{} answered {}", rpc_host(url), status),
+        }
+        .into()),
+        status if !status.is_success() => Err(anyhow!(
+            "{} answered {}: {}",
+            rpc_host(url),
+            status,
+            body.trim()
+        )),
+        _ => Ok(body),
+    }
+}
+
+fn request_error(error: reqwest::Error) -> anyhow::Error {
+    match error.is_timeout() {
+        true => BalanceError::Timeout.into(),
+        false => BalanceError::Network {
+            message: error.to_string(),
+        }
+        .into(),
+    }
+}
+
+/// Failures that go away on their own or on another endpoint
+fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<BalanceError>()
+        .is_some_and(BalanceError::is_retryable)
+}
+
+/// A Bitcoin address by its format: base58 legacy and P2SH addresses (`1`,
+/// `3`, or `m`, `n`, `2` on test networks) and bech32 SegWit addresses
+/// (`bc1`, `tb1`, `bcrt1`). Checksums are left to the explorer to verify.
+pub(crate) fn is_bitcoin_address(address: &str) -> bool {
+    let lower = address.to_lowercase();
+    let bech32 = ["bc1", "tb1", "bcrt1"]
+        .iter()
+        .find_map(|hrp| lower.strip_prefix(hrp));
+    if let Some(data) = bech32 {
+        // bech32 is either all lowercase or all uppercase
+        let single_case = address == lower || address == address.to_uppercase();
+        return single_case
+            && (11..=71).contains(&data.len())
+            && data.chars().all(|c| BECH32_CHARSET.contains(c));
+    }
+    address.starts_with(['1', '3', 'm', 'n', '2'])
+        && (26..=35).contains(&address.len())
+        && address
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() && !"0OIl".contains(c))
+}
+
+fn not_a_bitcoin_token(token: &Token) -> BalanceError {
+    BalanceError::Config {
+        message: format!(
+            "{} is not a Bitcoin token: Bitcoin has no tokens, only the native BTC balance",
+            token.symbol()
+        ),
+    }
+}
+
+impl BitcoinProvider {
+    /// Confirmed balance: received minus spent in mined transactions
+    async fn confirmed_balance(&self, address: &str) -> Result<Balance> {
+        let info: AddressInfo =
+            serde_json::from_str(&self.get(&format!("address/{}", address)).await?)?;
+        let stats = info.chain_stats;
+        let satoshis = stats
+            .funded_txo_sum
+            .checked_sub(stats.spent_txo_sum)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Explorer reports more BTC spent than received by {}",
+                    address
+                )
+            })?;
+        Ok(Balance::from_minor_units(
+            "BTC".to_string(),
+            satoshis.into(),
+            BTC_DECIMALS,
+        ))
+    }
+}
+
+#[async_trait]
+impl ChainProvider for BitcoinProvider {
+    fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    fn native_symbol(&self) -> &str {
+        "BTC"
+    }
+
+    async fn get_balances(
+        &self,
+        _config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        Ok(crate::get_bitcoin_balances(self, address, options).await?)
+    }
+
+    fn balance_stream<'a>(
+        &'a self,
+        _config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        crate::stream_reads(crate::bitcoin_balance_reads(self, address, options))
+    }
+
+    fn active_endpoint(&self) -> Option<&str> {
+        Some(self.active_rpc())
+    }
+
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        let read = self.confirmed_balance(address);
+        Ok(telemetry::balance_read(
+            &self.chain_name,
+            address,
+            "BTC",
+            self.request_id.as_ref(),
+            read,
+        )
+        .await?)
+    }
+
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        match token {
+            Token::Native { .. } => {
+                let native = self.get_native_balance(address).await?;
+                Ok(Balance::for_token(token, native.amount)?)
+            }
+            other => Err(not_a_bitcoin_token(other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    const ADDRESS: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+    /// Local Esplora endpoint answering GETs of `path` with `(status, body)`
+    /// and everything else with 404
+    async fn serve(routes: Vec<(&'static str, u16, &'static str)>) -> String {
+        test_server::serve(move |request| {
+            routes
+                .iter()
+                .find(|(route, _, _)| *route == request.path)
+                .map_or((404, "Not found".to_string()), |(_, status, body)| {
+                    (*status, body.to_string())
+                })
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_balance_ignores_mempool() {
+        let url = serve(vec![(
+            "/api/address/tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+            200,
+            r#"{
+                "address": "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+                "chain_stats": { "funded_txo_sum": 250000000, "spent_txo_sum": 100000000, "tx_count": 3 },
+                "mempool_stats": { "funded_txo_sum": 5000, "spent_txo_sum": 0, "tx_count": 1 }
+            }"#,
+        )])
+        .await;
+        let provider = BitcoinProvider::new(format!("{}/api/", url));
+
+        let balance = provider.get_native_balance(ADDRESS).await.unwrap();
+        assert_eq!(balance.token, "BTC");
+        assert_eq!(balance.decimals, 8);
+        assert_eq!(balance.amount, "150000000");
+        assert_eq!(balance.formatted, "1.5");
+    }
+
+    #[tokio::test]
+    async fn test_tip_time() {
+        let url = serve(vec![
+            ("/blocks/tip/hash", 200, "00000000000000000001\n"),
+            (
+                "/block/00000000000000000001",
+                200,
+                r#"{ "id": "00000000000000000001", "height": 840000, "timestamp": 1713571767 }"#,
+            ),
+            ("/blocks/tip/height", 200, "840000"),
+        ])
+        .await;
+        let provider = BitcoinProvider::new(url);
+
+        assert_eq!(provider.tip_height().await.unwrap(), 840_000);
+        assert_eq!(provider.tip_time().await.unwrap(), (840_000, 1_713_571_767));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let url = serve(vec![
+            ("/address/busy", 429, "Too many requests"),
+            ("/address/bogus", 400, "Invalid Bitcoin address"),
+        ])
+        .await;
+        let provider = BitcoinProvider::new(url).with_retry_policy(RetryPolicy::none());
+
+        let error = provider.get_native_balance("busy").await.unwrap_err();
+        assert_eq!(error, BalanceError::RateLimited);
+        let error = provider.get_native_balance("bogus").await.unwrap_err();
+        assert!(error.to_string().contains("Invalid Bitcoin address"));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_unsupported() {
+        let provider = BitcoinProvider::new("http://127.0.0.1:1".to_string());
+        let usdc = Token::Erc20 {
+            address: "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238".to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+
+        let error = provider
+            .get_token_balance(ADDRESS, &usdc)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Bitcoin has no tokens"));
+    }
+
+    #[test]
+    fn test_is_bitcoin_address() {
+        for valid in [
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn",
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+            ADDRESS,
+        ] {
+            assert!(is_bitcoin_address(valid), "{} should be valid", valid);
+        }
+        for invalid in [
+            "",
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+            "8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4",
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfN0",
+            "bc1qW508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "bc1qb",
+        ] {
+            assert!(
+                !is_bitcoin_address(invalid),
+                "{} should be invalid",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_testnet_balance() {
+        let provider = BitcoinProvider::new_testnet();
+        let balance = provider.get_native_balance(ADDRESS).await.unwrap();
+        assert_eq!(balance.token, "BTC");
+        assert!(provider.tip_height().await.unwrap() > 0);
+    }
+}
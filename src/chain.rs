@@ -1,29 +1,418 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
 
+use crate::bitcoin::{self, BitcoinProvider};
+use crate::config::ChainConfig;
+use crate::error::BalanceError;
+use crate::ethereum::{self, EthereumProvider};
+use crate::fallback::rpc_candidates;
+use crate::icp::{self, IcpProvider};
+use crate::middleware::ProviderStack;
+use crate::options::QueryOptions;
+use crate::solana::SolanaProvider;
 use crate::types::{Balance, Token};
 
-/// Trait for chain providers - implement this for each blockchain
+/// Trait for chain providers - implement this for each blockchain. Reads
+/// fail with a `BalanceError`; `?` turns an `anyhow::Error` into one.
 #[async_trait]
 pub trait ChainProvider: Send + Sync {
+    /// Name of the chain the provider reads, e.g. "Solana Devnet"
+    fn chain_name(&self) -> &str;
+
+    /// Symbol of the chain's native coin, e.g. "ETH" or "SOL"
+    fn native_symbol(&self) -> &str;
+
     /// Get the native token balance for an address
-    async fn get_native_balance(&self, address: &str) -> Result<Balance>;
-    
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError>;
+
     /// Get the balance of a specific token for an address
-    async fn get_token_balance(&self, address: &str, token: &Token) -> Result<Balance>;
-    
-    /// Get all balances (native + specified tokens) for an address
-    async fn get_all_balances(&self, address: &str, tokens: &[Token]) -> Result<Vec<Balance>> {
-        let mut balances = Vec::new();
-        
-        // Get native balance
-        balances.push(self.get_native_balance(address).await?);
-        
-        // Get token balances
-        for token in tokens {
-            balances.push(self.get_token_balance(address, token).await?);
-        }
-        
-        Ok(balances)
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError>;
+
+    /// Get all balances (native + specified tokens) for an address. Every
+    /// read runs concurrently; the native balance comes first, then one per
+    /// token in `tokens` order, and any failed read fails the whole call.
+    async fn get_all_balances(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Balance>, BalanceError> {
+        let native = self.get_native_balance(address);
+        let tokens = futures::future::try_join_all(
+            tokens
+                .iter()
+                .map(|token| self.get_token_balance(address, token)),
+        );
+        let (native, tokens) = futures::future::try_join(native, tokens).await?;
+        Ok(std::iter::once(native).chain(tokens).collect())
+    }
+
+    /// Every balance of `address` on the chain `config` describes, read the
+    /// way `get_balances_with_options` reads it: the native balance first,
+    /// then the configured and ad-hoc tokens, with whatever the chain adds
+    /// (one multicall, confirmation depths, vesting and LP positions on EVM
+    /// chains, wrapped SOL on Solana). The default reads the native balance
+    /// and `config.tokens()` with `get_all_balances`.
+    async fn get_balances(
+        &self,
+        config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        if let Some(spec) = options.extra_tokens.first() {
+            return Err(BalanceError::Config {
+                message: format!(
+                    "{} provider can't read ad-hoc token {}",
+                    self.chain_name(),
+                    spec.address
+                ),
+            });
+        }
+        self.get_all_balances(address, &config.tokens()?).await
+    }
+
+    /// `get_balances` as a stream, yielding the balances of each of its reads
+    /// (one multicall, one token account query, ...) as that read completes.
+    /// A failed read is yielded without ending the stream. The default
+    /// yields the whole `get_balances` result as one item.
+    fn balance_stream<'a>(
+        &'a self,
+        config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        futures::stream::once(self.get_balances(config, address, options)).boxed()
+    }
+
+    /// The RPC endpoint calls currently go to, if the provider reads one
+    fn active_endpoint(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Provider for a configured chain, picked by its `type`: an `EthereumProvider`,
+/// `SolanaProvider` or `BitcoinProvider` on its `rpc` endpoints, then the
+/// public fallbacks, in order, or an `IcpProvider` on the first of them,
+/// wrapped in the chain's `middleware` layers
+pub fn provider_for(config: &ChainConfig) -> Result<Box<dyn ChainProvider>> {
+    provider_with_options(config, &QueryOptions::default())
+}
+
+/// `provider_for` with the HTTP, block tag, commitment, request ID and
+/// fallback settings of `options`, for callers that keep one provider across
+/// queries, e.g. to reuse its connections
+pub fn provider_with_options(
+    config: &ChainConfig,
+    options: &QueryOptions,
+) -> Result<Box<dyn ChainProvider>> {
+    config.validate_rpc(&config.name)?;
+    let mut rpcs = rpc_candidates(config, !options.disable_fallback_rpc);
+    if rpcs.is_empty() {
+        return Err(anyhow!(
+            "No RPC endpoint configured for chain '{}'",
+            config.name
+        ));
+    }
+    let rpc = rpcs.remove(0);
+
+    let provider: Box<dyn ChainProvider> = match config.chain_type.as_str() {
+        "evm" => Box::new(ethereum_provider(config, &rpc, options).with_fallback_rpcs(rpcs)),
+        "solana" => Box::new(
+            solana_provider(&rpc, options)?
+                .with_fallback_rpcs(rpcs)?
+                .with_chain_name(config.name.clone()),
+        ),
+        "icp" => Box::new(
+            icp_provider(config, &rpc, options)?.with_chain_name(config.name.clone()),
+        ),
+        "bitcoin" => Box::new(
+            bitcoin_provider(&rpc, options)
+                .with_fallback_rpcs(rpcs)
+                .with_chain_name(config.name.clone()),
+        ),
+        other => {
+            return Err(BalanceError::UnsupportedChainType {
+                chain_type: other.to_string(),
+            }
+            .into())
+        }
+    };
+    Ok(ProviderStack::from_config(provider, &config.middleware).build())
+}
+
+/// `EthereumProvider` on `rpc` with the chain's name, native symbol and method
+/// names and the query's HTTP, block tag and request ID settings
+pub(crate) fn ethereum_provider(
+    config: &ChainConfig,
+    rpc: &str,
+    options: &QueryOptions,
+) -> EthereumProvider {
+    let mut provider = EthereumProvider::new(rpc.to_string())
+        .with_chain_name(config.name.clone())
+        .with_http_config(options.http.clone())
+        .with_block_tag(options.block_tag)
+        .with_rpc_methods(config.rpc_methods.clone());
+    if let Some(symbol) = &config.native_token.symbol {
+        provider = provider.with_native_symbol(symbol.clone());
+    }
+    match &options.request_id {
+        Some(request_id) => provider.with_request_id(request_id.clone()),
+        None => provider,
+    }
+}
+
+/// `SolanaProvider` on `rpc` with the query's HTTP, commitment and request ID settings
+pub(crate) fn solana_provider(rpc: &str, options: &QueryOptions) -> Result<SolanaProvider> {
+    let mut provider =
+        SolanaProvider::new(rpc.to_string()).with_http_config(options.http.clone())?;
+    if let Some(commitment) = options.solana_commitment {
+        provider = provider.with_commitment(commitment);
+    }
+    match &options.request_id {
+        Some(request_id) => provider.with_request_id(request_id.clone()),
+        None => Ok(provider),
+    }
+}
+
+/// ICP provider reading the chain's `canisterId` ledger through gateway `rpc`,
+/// with the query's HTTP and request ID settings
+pub(crate) fn icp_provider(
+    config: &ChainConfig,
+    rpc: &str,
+    options: &QueryOptions,
+) -> Result<IcpProvider> {
+    let ledger = config.canister_id.clone().ok_or_else(|| {
+        anyhow!(
+            "Chain '{}' has no canisterId, which ICP chains require",
+            config.name
+        )
+    })?;
+    let provider =
+        IcpProvider::new(rpc.to_string(), ledger).with_http_config(options.http.clone())?;
+    match &options.request_id {
+        Some(request_id) => provider.with_request_id(request_id.clone()),
+        None => Ok(provider),
+    }
+}
+
+/// `BitcoinProvider` on the Esplora base URL `rpc` with the query's HTTP and
+/// request ID settings
+pub(crate) fn bitcoin_provider(rpc: &str, options: &QueryOptions) -> BitcoinProvider {
+    let provider = BitcoinProvider::new(rpc.to_string()).with_http_config(options.http.clone());
+    match &options.request_id {
+        Some(request_id) => provider.with_request_id(request_id.clone()),
+        None => provider,
+    }
+}
+
+/// `BalanceError::InvalidAddress` unless `address` is valid for `chain_type`:
+/// on EVM chains a 0x-prefixed 20-byte hex address, whose EIP-55 checksum must
+/// match when it is mixed-case, or an ENS name; on Solana a base58 public key;
+/// on ICP a principal with a valid checksum; on Bitcoin a base58 or bech32
+/// address. Unknown chain types are left to the caller to report.
+pub fn validate_address(chain_type: &str, address: &str) -> Result<()> {
+    let valid = match chain_type {
+        "evm" => is_evm_address(address) || ethereum::is_ens_name(address),
+        "solana" => address.parse::<solana_sdk::pubkey::Pubkey>().is_ok(),
+        "icp" => icp::is_principal(address),
+        "bitcoin" => bitcoin::is_bitcoin_address(address),
+        _ => true,
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(BalanceError::InvalidAddress {
+            address: address.to_string(),
+        }
+        .into()),
+    }
+}
+
+/// A 0x-prefixed 20-byte hex address; all-lowercase and all-uppercase
+/// addresses carry no EIP-55 checksum, mixed-case ones must match theirs
+pub(crate) fn is_evm_address(address: &str) -> bool {
+    let Some(hex) = address.strip_prefix("0x") else {
+        return false;
+    };
+    if hex.len() != 40 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return false;
+    }
+    let mixed_case = hex.bytes().any(|byte| byte.is_ascii_lowercase())
+        && hex.bytes().any(|byte| byte.is_ascii_uppercase());
+    !mixed_case || alloy::primitives::Address::parse_checksummed(address, None).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Provider whose reads each wait until all of them are running
+    struct Rendezvous {
+        barrier: tokio::sync::Barrier,
+    }
+
+    #[async_trait]
+    impl ChainProvider for Rendezvous {
+        fn chain_name(&self) -> &str {
+            "Rendezvous"
+        }
+
+        fn native_symbol(&self) -> &str {
+            "ETH"
+        }
+
+        async fn get_native_balance(&self, _address: &str) -> Result<Balance, BalanceError> {
+            self.barrier.wait().await;
+            Ok(Balance::from_minor_units("ETH".to_string(), 1, 18))
+        }
+
+        async fn get_token_balance(
+            &self,
+            _address: &str,
+            token: &Token,
+        ) -> Result<Balance, BalanceError> {
+            self.barrier.wait().await;
+            Ok(Balance::from_minor_units(token.symbol().to_string(), 2, 6))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_all_balances_reads_concurrently_in_token_order() {
+        let token = |symbol: &str| Token::Erc20 {
+            address: String::new(),
+            symbol: symbol.to_string(),
+            decimals: 6,
+        };
+        let tokens = [token("USDC"), token("EURC"), token("DAI")];
+        let provider = Rendezvous {
+            barrier: tokio::sync::Barrier::new(1 + tokens.len()),
+        };
+
+        // One read at a time would wait at the barrier forever
+        let balances = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            provider.get_all_balances("0xabc", &tokens),
+        )
+        .await
+        .expect("reads ran one at a time")
+        .unwrap();
+        let symbols: Vec<&str> = balances.iter().map(|b| b.token.as_str()).collect();
+        assert_eq!(symbols, ["ETH", "USDC", "EURC", "DAI"]);
+    }
+
+    fn chain_config(chain_type: &str, rpc: &str) -> ChainConfig {
+        serde_json::from_value(json!({
+            "type": chain_type,
+            "name": "Test",
+            "rpc": rpc,
+            "nativeToken": { "symbol": "ETH", "decimals": 18 },
+            "tokens": {}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate_address() {
+        let valid = [
+            ("evm", "0x78697a9cfc48c1e9d1040172d51833ef78083b10"),
+            ("evm", "0x78697a9cfc48C1e9d1040172d51833EF78083b10"),
+            ("evm", "0x78697A9CFC48C1E9D1040172D51833EF78083B10"),
+            ("evm", "vitalik.eth"),
+            ("solana", "8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4"),
+            ("solana", "So11111111111111111111111111111111111111112"),
+            ("icp", "ryjl3-tyaaa-aaaaa-aaaba-cai"),
+            ("bitcoin", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"),
+            ("bitcoin", "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"),
+        ];
+        for (chain_type, address) in valid {
+            assert!(
+                validate_address(chain_type, address).is_ok(),
+                "{} should be a valid {} address",
+                address,
+                chain_type
+            );
+        }
+
+        let invalid = [
+            // Bad EIP-55 checksum
+            ("evm", "0x78697A9cfc48C1e9d1040172d51833EF78083b10"),
+            ("evm", "78697a9cfc48c1e9d1040172d51833ef78083b10"),
+            ("evm", "0x78697a9cfc48c1e9d1040172d51833ef78083b1"),
+            ("evm", "0x78697a9cfc48c1e9d1040172d51833ef78083b10ff"),
+            ("evm", "0x78697a9cfc48c1e9d1040172d51833ef78083bzz"),
+            ("evm", ""),
+            ("evm", "8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4"),
+            ("solana", "0x78697a9cfc48c1e9d1040172d51833ef78083b10"),
+            ("solana", "vitalik.eth"),
+            // Base58 has no 0, O, I or l
+            ("solana", "0vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4"),
+            ("solana", "8vJ1EEeJBSX8UZetuHY7d2SiGjdw2Ahfamzfxok"),
+            ("solana", ""),
+            // Checksum mismatch
+            ("icp", "ryjl3-tyaaa-aaaaa-aaaba-caa"),
+            ("bitcoin", "0x78697a9cfc48c1e9d1040172d51833ef78083b10"),
+            ("bitcoin", "8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4"),
+        ];
+        for (chain_type, address) in invalid {
+            let error = validate_address(chain_type, address).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<BalanceError>(),
+                Some(&BalanceError::InvalidAddress {
+                    address: address.to_string()
+                }),
+                "{} should be rejected on {}",
+                address,
+                chain_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_provider_for_chain_types() {
+        assert!(provider_for(&chain_config("evm", "https://rpc.example.com")).is_ok());
+        assert!(provider_for(&chain_config("solana", "https://api.devnet.solana.com")).is_ok());
+        assert!(provider_for(&chain_config("bitcoin", "https://mempool.space/api")).is_ok());
+
+        let error = provider_for(&chain_config("cosmos", "https://rpc.example.com"))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Unsupported chain type"));
+    }
+
+    #[test]
+    fn test_provider_for_reports_chain_and_native_symbol() {
+        let mut polygon = chain_config("evm", "https://rpc.example.com");
+        polygon.native_token.symbol = Some("POL".to_string());
+        let provider = provider_for(&polygon).unwrap();
+        assert_eq!(provider.chain_name(), "Test");
+        assert_eq!(provider.native_symbol(), "POL");
+
+        let provider =
+            provider_for(&chain_config("solana", "https://api.devnet.solana.com")).unwrap();
+        assert_eq!(provider.chain_name(), "Test");
+        assert_eq!(provider.native_symbol(), "SOL");
+
+        let provider = provider_for(&chain_config("bitcoin", "https://mempool.space/api")).unwrap();
+        assert_eq!(provider.native_symbol(), "BTC");
+
+        let mut icp = chain_config("icp", "https://icp-api.io");
+        icp.canister_id = Some("ryjl3-tyaaa-aaaaa-aaaba-cai".to_string());
+        let provider = provider_for(&icp).unwrap();
+        assert_eq!(provider.chain_name(), "Test");
+        assert_eq!(provider.native_symbol(), "ICP");
+    }
+
+    #[test]
+    fn test_provider_for_rejects_missing_rpc() {
+        let error = provider_for(&chain_config("evm", "")).err().unwrap();
+        assert!(matches!(
+            error.downcast_ref::<BalanceError>(),
+            Some(BalanceError::InvalidRpcUrl { .. })
+        ));
     }
 }
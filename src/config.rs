@@ -1,11 +1,51 @@
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+
+use crate::chain::is_evm_address;
+use crate::error::BalanceError;
+use crate::ethereum::is_ws_url;
+use crate::fallback::public_rpcs;
+use crate::http::HttpConfig;
+use crate::icp::is_principal;
+use crate::middleware::MiddlewareConfig;
+use crate::price::PriceConfig;
+use crate::types::Token;
+
+/// Chain queried when neither the caller nor the config picks one
+pub const DEFAULT_CHAIN: &str = "sepolia";
+
+/// Prefix of the environment variables overriding a chain's `rpc`, see
+/// `rpc_env_var`
+pub const RPC_ENV_PREFIX: &str = "BALANCE_CHECKER_RPC_";
+
+/// Parsed configuration shared by `get_balances` and friends
+static SHARED_CONFIG: OnceLock<RwLock<Arc<Config>>> = OnceLock::new();
 
 /// Configuration for all supported chains
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub chains: HashMap<String, ChainConfig>,
+    /// Chain the CLI queries when neither `--chain` nor `BALANCE_CHECKER_CHAIN` is set
+    #[serde(rename = "defaultChain", skip_serializing_if = "Option::is_none")]
+    pub default_chain: Option<String>,
+    /// HTTP client tuning shared by all chains
+    #[serde(default, skip_serializing_if = "is_default_http")]
+    pub http: HttpConfig,
+    /// Fiat price source for `--show-price`
+    #[serde(default, skip_serializing_if = "is_default_price")]
+    pub price: PriceConfig,
+}
+
+fn is_default_http(http: &HttpConfig) -> bool {
+    *http == HttpConfig::default()
+}
+
+fn is_default_price(price: &PriceConfig) -> bool {
+    *price == PriceConfig::default()
 }
 
 /// Configuration for a single chain
@@ -14,14 +54,78 @@ pub struct ChainConfig {
     #[serde(rename = "type")]
     pub chain_type: String,
     pub name: String,
-    pub rpc: String,
+    /// One RPC URL or a list of them, tried in order until one answers.
+    /// May be omitted for chains with built-in public fallback endpoints.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_rpcs",
+        serialize_with = "serialize_rpcs",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub rpc: Vec<String>,
     #[serde(rename = "chainId", skip_serializing_if = "Option::is_none")]
     pub chain_id: Option<u64>,
     #[serde(rename = "canisterId", skip_serializing_if = "Option::is_none")]
     pub canister_id: Option<String>,
+    /// Chain family shared by the mainnet/testnet entries of one chain (e.g. "ethereum")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+    /// "mainnet" or "testnet", used by `--network` to pick between family members
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
     #[serde(rename = "nativeToken")]
     pub native_token: TokenInfo,
     pub tokens: HashMap<String, TokenInfo>,
+    /// GraphQL indexer used for token balances instead of per-token RPC calls
+    #[serde(rename = "graphqlUrl", skip_serializing_if = "Option::is_none")]
+    pub graphql_url: Option<String>,
+    /// Query sent to `graphql_url`, see `GraphQlProvider` for the expected shape
+    #[serde(rename = "graphqlQuery", skip_serializing_if = "Option::is_none")]
+    pub graphql_query: Option<String>,
+    /// Chain-specific names of standard EVM JSON-RPC methods, e.g.
+    /// `{"eth_getBalance": "klay_getBalance"}`; unlisted methods keep their name
+    #[serde(
+        rename = "rpcMethods",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub rpc_methods: HashMap<String, String>,
+    /// Retry, cache and rate limit layers for `ProviderStack::from_config`
+    #[serde(default, skip_serializing_if = "is_default_middleware")]
+    pub middleware: MiddlewareConfig,
+}
+
+/// `rpc` as written in config
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Rpcs {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn deserialize_rpcs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    Ok(match Rpcs::deserialize(deserializer)? {
+        Rpcs::One(rpc) if rpc.trim().is_empty() => Vec::new(),
+        Rpcs::One(rpc) => vec![rpc],
+        Rpcs::Many(rpcs) => rpcs,
+    })
+}
+
+/// A single endpoint is written back as a plain string
+fn serialize_rpcs<S: Serializer>(
+    rpcs: &[String],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match rpcs {
+        [rpc] => serializer.serialize_str(rpc),
+        rpcs => rpcs.serialize(serializer),
+    }
+}
+
+fn is_default_middleware(middleware: &MiddlewareConfig) -> bool {
+    *middleware == MiddlewareConfig::default()
 }
 
 /// Token information from config
@@ -29,22 +133,417 @@ pub struct ChainConfig {
 pub struct TokenInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub address: Option<String>,
+    /// Symbol balances are reported under; the entry's key when unset
     pub symbol: Option<String>,
-    pub decimals: u8,
+    /// Read from chain (`decimals()` on ERC-20s, the mint on Solana) when
+    /// left out, so a mistyped value can't skew `formatted`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u8>,
+    /// Report a reverted `balanceOf` as a zero balance instead of an error.
+    /// Only set this for tokens known to revert for never-seen holders: a
+    /// broken or wrong contract will then silently read as empty.
+    #[serde(rename = "treatRevertAsZero", skip_serializing_if = "Option::is_none")]
+    pub treat_revert_as_zero: Option<bool>,
+    /// Token standard on EVM chains: `"erc20"` (the default when unset),
+    /// `"erc721"`, `"erc1155"` (with `tokenId`) or `"univ2-lp"` for a Uniswap V2
+    /// pair whose LP position is reported as its two underlying balances
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// ERC-1155 token ID (decimal)
+    #[serde(rename = "tokenId", skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+    /// Vesting or escrow contract holding this ERC-20 for the queried address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vesting: Option<VestingContract>,
+    /// EVM only: read this token's balance this many blocks below the latest
+    /// one, for asset-specific finality; ignored with an explicit block tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmations: Option<u64>,
+}
+
+/// Supported vesting and escrow contract interfaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum VestingKind {
+    /// OpenZeppelin `VestingWallet`: `releasable(token)` is available, the rest locked
+    #[serde(rename = "vesting-wallet")]
+    VestingWallet,
+    /// OpenZeppelin `TokenTimelock`: everything is locked until `releaseTime`
+    #[serde(rename = "token-timelock")]
+    TokenTimelock,
+}
+
+/// A vesting contract configured on a token, e.g.
+/// `{ "contract": "0x...", "type": "vesting-wallet" }`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VestingContract {
+    pub contract: String,
+    #[serde(rename = "type")]
+    pub kind: VestingKind,
+}
+
+/// Check that `url` is a non-empty, well-formed http(s) URL
+pub fn validate_rpc_url(chain: &str, url: &str) -> std::result::Result<(), BalanceError> {
+    let invalid = || BalanceError::InvalidRpcUrl {
+        chain: chain.to_string(),
+        url: url.to_string(),
+    };
+    let parsed = reqwest::Url::parse(url.trim()).map_err(|_| invalid())?;
+    match parsed.scheme() {
+        "http" | "https" if parsed.host().is_some() => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+impl TokenInfo {
+    /// The symbol to report for this entry, keyed by `key` in config: its
+    /// `symbol` when set, so a short alias can key a canonical symbol
+    pub fn symbol_or<'a>(&'a self, key: &'a str) -> &'a str {
+        self.symbol.as_deref().unwrap_or(key)
+    }
+
+    /// The EVM token to query for this entry (keyed by `key` in config),
+    /// or `None` when it has no address. An ERC-20 without configured
+    /// decimals is an error; `needs_decimals` tells when to read them first.
+    pub fn evm_token(&self, key: &str) -> Result<Option<Token>> {
+        let Some(address) = self.address.clone() else {
+            return Ok(None);
+        };
+        let symbol = self.symbol_or(key).to_string();
+
+        let token = match self.kind.as_deref() {
+            None | Some("erc20") => Token::Erc20 {
+                decimals: self
+                    .decimals
+                    .ok_or_else(|| anyhow!("Token {} has no configured decimals", symbol))?,
+                address,
+                symbol,
+            },
+            Some("erc721") => Token::Erc721 { address, symbol },
+            Some("erc1155") => Token::Erc1155 {
+                id: self
+                    .token_id
+                    .clone()
+                    .ok_or_else(|| anyhow!("ERC-1155 token {} needs a tokenId", symbol))?,
+                address,
+                symbol,
+            },
+            Some("univ2-lp") => Token::UniV2Lp {
+                pair: address,
+                symbol,
+            },
+            Some(other) => return Err(anyhow!("Unknown token kind '{}' for {}", other, symbol)),
+        };
+        Ok(Some(token))
+    }
+
+    /// Whether this is an ERC-20 or SPL entry whose decimals must be read from chain
+    pub fn needs_decimals(&self) -> bool {
+        self.decimals.is_none() && matches!(self.kind.as_deref(), None | Some("erc20"))
+    }
+}
+
+impl ChainConfig {
+    /// Check every configured `rpc`; there may only be none when the chain
+    /// has built-in public fallback endpoints
+    pub fn validate_rpc(&self, chain: &str) -> std::result::Result<(), BalanceError> {
+        let has_fallback = self
+            .chain_id
+            .is_some_and(|chain_id| !public_rpcs(chain_id).is_empty());
+        match self.rpc.as_slice() {
+            [] if has_fallback => Ok(()),
+            [] => validate_rpc_url(chain, ""),
+            rpcs => rpcs
+                .iter()
+                .try_for_each(|rpc| self.validate_rpc_endpoint(chain, rpc)),
+        }
+    }
+
+    /// `validate_rpc_url` for one of this chain's endpoints; EVM chains also
+    /// take ws:// and wss:// URLs, which `EthereumProvider` reaches over WebSocket
+    pub fn validate_rpc_endpoint(
+        &self,
+        chain: &str,
+        url: &str,
+    ) -> std::result::Result<(), BalanceError> {
+        match self.chain_type == "evm" && is_ws_url(url.trim()) {
+            true => Ok(()),
+            false => validate_rpc_url(chain, url),
+        }
+    }
+
+    /// Everything `Config::validate` reports for this chain
+    fn problems(&self, chain: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        match self.chain_type.as_str() {
+            "evm" if self.chain_id.is_none() => problems.push(format!(
+                "Chain '{}' has no chainId, which EVM chains require",
+                chain
+            )),
+            "icp" => match &self.canister_id {
+                None => problems.push(format!(
+                    "Chain '{}' has no canisterId, which ICP chains require",
+                    chain
+                )),
+                Some(canister_id) if !is_principal(canister_id) => problems.push(format!(
+                    "Chain '{}' has an invalid canisterId '{}'",
+                    chain, canister_id
+                )),
+                Some(_) => {}
+            },
+            "evm" | "solana" | "bitcoin" => {}
+            other => problems.push(format!(
+                "Chain '{}' has unsupported type '{}'",
+                chain, other
+            )),
+        }
+
+        let rpc_errors: Vec<BalanceError> = match self.rpc.as_slice() {
+            [] => self.validate_rpc(chain).err().into_iter().collect(),
+            rpcs => rpcs
+                .iter()
+                .filter_map(|rpc| self.validate_rpc_endpoint(chain, rpc).err())
+                .collect(),
+        };
+        problems.extend(rpc_errors.iter().map(BalanceError::to_string));
+
+        let mut symbols: Vec<&String> = self.tokens.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            let Some(address) = &self.tokens[symbol].address else {
+                continue;
+            };
+            if self.chain_type == "bitcoin" {
+                problems.push(format!(
+                    "Chain '{}' token {} can't be queried, Bitcoin has no tokens",
+                    chain, symbol
+                ));
+                continue;
+            }
+            let valid = match self.chain_type.as_str() {
+                "evm" => is_evm_address(address),
+                "solana" => address.parse::<solana_sdk::pubkey::Pubkey>().is_ok(),
+                "icp" => is_principal(address),
+                _ => true,
+            };
+            if !valid {
+                problems.push(format!(
+                    "Chain '{}' token {} has an invalid address '{}'",
+                    chain, symbol, address
+                ));
+            }
+        }
+        problems
+    }
+
+    /// First configured RPC URL, or "" when there is none
+    pub fn primary_rpc(&self) -> &str {
+        self.rpc.first().map_or("", String::as_str)
+    }
+
+    /// The configured tokens as passed to `ChainProvider::get_all_balances`,
+    /// sorted by symbol. Unlike `get_balances`, this can't read missing
+    /// decimals from chain, so an ERC-20, SPL or ICRC-1 entry without them is
+    /// an error.
+    pub fn tokens(&self) -> Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        for (key, info) in &self.tokens {
+            let symbol = info.symbol_or(key);
+            if info.address.is_some() && info.needs_decimals() {
+                return Err(anyhow!("Token {} has no configured decimals", symbol));
+            }
+            let token = match self.chain_type.as_str() {
+                "evm" => info.evm_token(key)?,
+                "solana" => info.address.clone().map(|mint| Token::Spl {
+                    mint,
+                    symbol: symbol.to_string(),
+                    decimals: info.decimals.unwrap_or_default(),
+                }),
+                "icp" => info.address.clone().map(|ledger| Token::Icrc1 {
+                    ledger,
+                    symbol: symbol.to_string(),
+                    decimals: info.decimals.unwrap_or_default(),
+                }),
+                _ => None,
+            };
+            tokens.extend(token);
+        }
+        tokens.sort_by(|a, b| a.symbol().cmp(b.symbol()));
+        Ok(tokens)
+    }
+}
+
+/// Environment variable overriding the `rpc` of chain `chain`: its key
+/// uppercased, with every non-alphanumeric character replaced by `_`, so
+/// `solana-devnet` is read from `BALANCE_CHECKER_RPC_SOLANA_DEVNET`
+pub fn rpc_env_var(chain: &str) -> String {
+    let suffix: String = chain
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    format!("{}{}", RPC_ENV_PREFIX, suffix)
+}
+
+/// Re-parse the configuration and replace the copy cached by `Config::shared`.
+/// Queries already running keep the configuration they started with.
+pub fn reload_config() -> Result<()> {
+    let config = Arc::new(Config::load()?);
+    let lock = SHARED_CONFIG.get_or_init(|| RwLock::new(config.clone()));
+    *lock.write().unwrap_or_else(PoisonError::into_inner) = config;
+    Ok(())
+}
+
+/// Parses config JSON, failing with `BalanceError::Config`
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        serde_json::from_str(s).map_err(|e| {
+            BalanceError::Config {
+                message: format!("Invalid config: {}", e),
+            }
+            .into()
+        })
+    }
 }
 
 impl Config {
-    /// Load configuration from embedded JSON
+    /// Check every chain's settings and return all problems found, in chain
+    /// order: unsupported types, EVM chains without `chainId`, invalid `rpc`
+    /// URLs and token addresses that don't parse for the chain type
+    pub fn validate(&self) -> std::result::Result<(), Vec<String>> {
+        let mut names: Vec<&String> = self.chains.keys().collect();
+        names.sort();
+        let problems: Vec<String> = names
+            .into_iter()
+            .flat_map(|name| self.chains[name].problems(name))
+            .collect();
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems),
+        }
+    }
+
+    /// Load configuration from embedded JSON, with each chain's `rpc`
+    /// replaced by its `rpc_env_var` when that is set
     pub fn load() -> Result<Self> {
-        let config_str = include_str!("../config.json");
-        let config: Config = serde_json::from_str(config_str)?;
-        Ok(config)
+        let config: Config = include_str!("../config.json").parse()?;
+        Ok(config.with_rpc_overrides(|name| std::env::var(name).ok()))
+    }
+
+    /// Load configuration from the JSON file at `path` instead of the
+    /// embedded one, e.g. a `config.json` shipped next to the binary; RPC
+    /// overrides apply as in `load`
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let config_str = std::fs::read_to_string(path).map_err(|e| BalanceError::Config {
+            message: format!("Can't read config {}: {}", path.display(), e),
+        })?;
+        let config = Config::from_str(&config_str).map_err(|e| BalanceError::Config {
+            message: format!("{}: {}", path.display(), e),
+        })?;
+        Ok(config.with_rpc_overrides(|name| std::env::var(name).ok()))
+    }
+
+    /// Replace the `rpc` of every chain for which `lookup` returns a
+    /// non-empty URL under the chain's `rpc_env_var`
+    pub fn with_rpc_overrides(mut self, lookup: impl Fn(&str) -> Option<String>) -> Self {
+        for (key, chain) in self.chains.iter_mut() {
+            if let Some(rpc) = lookup(&rpc_env_var(key)).filter(|rpc| !rpc.trim().is_empty()) {
+                chain.rpc = vec![rpc.trim().to_string()];
+            }
+        }
+        self
+    }
+
+    /// The configuration used by `get_balances`, `snapshot` and the other
+    /// top-level functions. It is parsed on first use and then cached for the
+    /// life of the process, so repeated queries don't re-parse the JSON; call
+    /// `reload_config` to refresh it.
+    pub fn shared() -> Result<Arc<Config>> {
+        if let Some(lock) = SHARED_CONFIG.get() {
+            return Ok(lock.read().unwrap_or_else(PoisonError::into_inner).clone());
+        }
+        let config = Arc::new(Config::load()?);
+        let lock = SHARED_CONFIG.get_or_init(|| RwLock::new(config));
+        Ok(lock.read().unwrap_or_else(PoisonError::into_inner).clone())
+    }
+
+    /// `default_chain`, or `DEFAULT_CHAIN` when the config doesn't set one
+    pub fn default_chain(&self) -> &str {
+        self.default_chain.as_deref().unwrap_or(DEFAULT_CHAIN)
     }
 
     /// Get a specific chain configuration
     pub fn get_chain(&self, chain_name: &str) -> Option<&ChainConfig> {
         self.chains.get(chain_name)
     }
+
+    /// `resolve_chain` followed by the lookup of the resolved chain's config
+    pub fn resolve_chain_config(
+        &self,
+        name: &str,
+        network: Option<&str>,
+    ) -> Result<(String, &ChainConfig)> {
+        let key = self.resolve_chain(name, network)?;
+        let chain = self
+            .chains
+            .get(&key)
+            .ok_or_else(|| BalanceError::ChainNotFound { chain: key.clone() })?;
+        Ok((key, chain))
+    }
+
+    /// Resolve the chain key to query for `name` on `network`.
+    ///
+    /// `name` may be a chain key or a family name. Without a network the name
+    /// must be a chain key. With one, the chain in the same family whose
+    /// `network` matches is returned, so `sepolia` + `mainnet` resolves to the
+    /// `ethereum` entry when both declare `"family": "ethereum"`.
+    pub fn resolve_chain(&self, name: &str, network: Option<&str>) -> Result<String> {
+        let Some(network) = network else {
+            return match self.chains.contains_key(name) {
+                true => Ok(name.to_string()),
+                false => Err(BalanceError::ChainNotFound {
+                    chain: name.to_string(),
+                }
+                .into()),
+            };
+        };
+
+        let family = match self.chains.get(name) {
+            Some(chain) if chain.network.as_deref() == Some(network) => return Ok(name.to_string()),
+            Some(chain) => chain.family.as_deref().unwrap_or(name),
+            None => name,
+        };
+
+        let mut matches: Vec<&String> = self
+            .chains
+            .iter()
+            .filter(|(_, chain)| {
+                chain.family.as_deref() == Some(family) && chain.network.as_deref() == Some(network)
+            })
+            .map(|(key, _)| key)
+            .collect();
+        matches.sort();
+
+        match matches.as_slice() {
+            [key] => Ok(key.to_string()),
+            [] => Err(anyhow!(
+                "No {} chain found for '{}' (family '{}')",
+                network,
+                name,
+                family
+            )),
+            _ => Err(anyhow!(
+                "Multiple {} chains in family '{}': {:?}",
+                network,
+                family,
+                matches
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -56,6 +555,42 @@ mod tests {
         let config = Config::load().unwrap();
         assert!(config.chains.contains_key("sepolia"));
         assert!(config.chains.contains_key("solana-devnet"));
+        assert!(config.chains.contains_key("bitcoin-testnet"));
+    }
+
+    #[test]
+    fn test_config_from_str_and_path() {
+        let json = r#"{
+            "chains": {
+                "local": {
+                    "type": "evm",
+                    "name": "Local",
+                    "rpc": "http://127.0.0.1:8545",
+                    "chainId": 31337,
+                    "nativeToken": { "symbol": "ETH", "decimals": 18 },
+                    "tokens": {}
+                }
+            }
+        }"#;
+        let config = Config::from_str(json).unwrap();
+        assert_eq!(
+            config.get_chain("local").unwrap().primary_rpc(),
+            "http://127.0.0.1:8545"
+        );
+
+        let path = std::env::temp_dir().join(format!("config-{}.json", std::process::id()));
+        std::fs::write(&path, json).unwrap();
+        let loaded = Config::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.unwrap().chains.contains_key("local"));
+
+        let error = Config::from_str("{ \"chains\": [] }").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<BalanceError>(),
+            Some(BalanceError::Config { .. })
+        ));
+        let error = Config::from_path(path).unwrap_err();
+        assert!(error.to_string().starts_with("Can't read config"));
     }
 
     #[test]
@@ -63,17 +598,368 @@ mod tests {
         let config = Config::load().unwrap();
         let sepolia = config.get_chain("sepolia").unwrap();
         assert_eq!(sepolia.chain_type, "evm");
-        assert_eq!(sepolia.native_token.decimals, 18);
+        assert_eq!(sepolia.native_token.decimals, Some(18));
         assert!(sepolia.tokens.contains_key("USDC"));
         assert!(sepolia.tokens.contains_key("EURC"));
     }
 
+    fn network_config() -> Config {
+        serde_json::from_str(
+            r#"{
+                "chains": {
+                    "ethereum": {
+                        "type": "evm", "name": "Ethereum", "rpc": "https://eth.example",
+                        "chainId": 1, "family": "ethereum", "network": "mainnet",
+                        "nativeToken": { "symbol": "ETH", "decimals": 18 }, "tokens": {}
+                    },
+                    "sepolia": {
+                        "type": "evm", "name": "Sepolia", "rpc": "https://sepolia.example",
+                        "chainId": 11155111, "family": "ethereum", "network": "testnet",
+                        "nativeToken": { "symbol": "ETH", "decimals": 18 }, "tokens": {}
+                    },
+                    "solana-devnet": {
+                        "type": "solana", "name": "Devnet", "rpc": "https://devnet.example",
+                        "family": "solana", "network": "testnet",
+                        "nativeToken": { "symbol": "SOL", "decimals": 9 }, "tokens": {}
+                    }
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_chain_by_network() {
+        let config = network_config();
+        assert_eq!(config.resolve_chain("sepolia", None).unwrap(), "sepolia");
+        assert_eq!(
+            config.resolve_chain("sepolia", Some("mainnet")).unwrap(),
+            "ethereum"
+        );
+        assert_eq!(
+            config.resolve_chain("ethereum", Some("testnet")).unwrap(),
+            "sepolia"
+        );
+        assert_eq!(
+            config.resolve_chain("sepolia", Some("testnet")).unwrap(),
+            "sepolia"
+        );
+        // Family names work too
+        assert_eq!(
+            config.resolve_chain("solana", Some("testnet")).unwrap(),
+            "solana-devnet"
+        );
+    }
+
+    #[test]
+    fn test_resolve_chain_missing_network() {
+        let config = network_config();
+        assert!(config
+            .resolve_chain("solana-devnet", Some("mainnet"))
+            .is_err());
+        let error = config.resolve_chain("polygon", None).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<BalanceError>(),
+            Some(&BalanceError::ChainNotFound {
+                chain: "polygon".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_shared_config_is_cached_until_reload() {
+        let first = Config::shared().unwrap();
+        assert!(Arc::ptr_eq(&first, &Config::shared().unwrap()));
+
+        reload_config().unwrap();
+        let reloaded = Config::shared().unwrap();
+        assert!(!Arc::ptr_eq(&first, &reloaded));
+        assert_eq!(first.chains.len(), reloaded.chains.len());
+    }
+
+    #[test]
+    fn test_bundled_config_is_valid() {
+        Config::load().unwrap().validate().unwrap();
+    }
+
+    #[test]
+    fn test_default_chain() {
+        let mut config = network_config();
+        assert_eq!(config.default_chain(), DEFAULT_CHAIN);
+
+        config.default_chain = Some("solana-devnet".to_string());
+        assert_eq!(config.default_chain(), "solana-devnet");
+    }
+
+    #[test]
+    fn test_token_vesting_from_json() {
+        let info: TokenInfo = serde_json::from_str(
+            r#"{
+                "address": "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238",
+                "symbol": "USDC",
+                "decimals": 6,
+                "vesting": { "contract": "0x000000000000000000000000000000000000dEaD", "type": "token-timelock" }
+            }"#,
+        )
+        .unwrap();
+        let vesting = info.vesting.unwrap();
+        assert_eq!(vesting.kind, VestingKind::TokenTimelock);
+        assert_eq!(
+            vesting.contract,
+            "0x000000000000000000000000000000000000dEaD"
+        );
+    }
+
+    #[test]
+    fn test_token_symbol_overrides_key() {
+        let chain: ChainConfig = serde_json::from_value(serde_json::json!({
+            "type": "evm",
+            "name": "Test",
+            "rpc": "https://rpc.example.com",
+            "nativeToken": { "symbol": "ETH", "decimals": 18 },
+            "tokens": {
+                "usdc-bridged": {
+                    "address": "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238",
+                    "symbol": "USDC.e",
+                    "decimals": 6
+                },
+                "EURC": {
+                    "address": "0x08210F9170F89Ab7658F0B5E3fF39b0E03C594D4",
+                    "decimals": 6
+                }
+            }
+        }))
+        .unwrap();
+
+        let aliased = &chain.tokens["usdc-bridged"];
+        assert_eq!(aliased.symbol_or("usdc-bridged"), "USDC.e");
+        let token = aliased.evm_token("usdc-bridged").unwrap().unwrap();
+        assert_eq!(token.symbol(), "USDC.e");
+
+        let token = chain.tokens["EURC"].evm_token("EURC").unwrap().unwrap();
+        assert_eq!(token.symbol(), "EURC");
+
+        // Decimals to read from chain aren't made up as 0
+        let mut unknown = chain.tokens["EURC"].clone();
+        unknown.decimals = None;
+        let error = unknown.evm_token("EURC").unwrap_err().to_string();
+        assert!(error.contains("no configured decimals"));
+    }
+
+    fn config_with_rpc(rpc: &str) -> Config {
+        let mut config = network_config();
+        let json = serde_json::json!({ "rpc": rpc });
+        config.chains.get_mut("solana-devnet").unwrap().rpc =
+            deserialize_rpcs(&json["rpc"]).unwrap();
+        config
+    }
+
+    #[test]
+    fn test_validate_rejects_empty_rpc() {
+        let config = config_with_rpc("");
+        assert_eq!(
+            config.chains["solana-devnet"].validate_rpc("solana-devnet"),
+            Err(BalanceError::InvalidRpcUrl {
+                chain: "solana-devnet".to_string(),
+                url: String::new(),
+            })
+        );
+        assert_eq!(
+            config.validate().unwrap_err(),
+            ["Chain 'solana-devnet' has an empty RPC URL"]
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_malformed_rpc() {
+        for rpc in ["api.devnet.solana.com", "https://", "ftp://devnet.example"] {
+            let config = config_with_rpc(rpc);
+            assert!(
+                matches!(
+                    config.chains["solana-devnet"].validate_rpc("solana-devnet"),
+                    Err(BalanceError::InvalidRpcUrl { chain, url })
+                        if chain == "solana-devnet" && url == rpc
+                ),
+                "{} should be rejected",
+                rpc
+            );
+            assert_eq!(config.validate().unwrap_err().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_ws_rpc_only_valid_for_evm() {
+        let mut config = network_config();
+        config.chains.get_mut("sepolia").unwrap().rpc =
+            vec!["wss://ethereum-sepolia-rpc.publicnode.com".to_string()];
+        assert!(config.chains["sepolia"].validate_rpc("sepolia").is_ok());
+
+        config.chains.get_mut("solana-devnet").unwrap().rpc =
+            vec!["wss://api.devnet.solana.com".to_string()];
+        assert!(matches!(
+            config.chains["solana-devnet"].validate_rpc("solana-devnet"),
+            Err(BalanceError::InvalidRpcUrl { .. })
+        ));
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn test_validate_rejects_bitcoin_tokens() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "chains": {
+                "bitcoin": {
+                    "type": "bitcoin", "name": "Bitcoin", "rpc": "https://mempool.space/api",
+                    "nativeToken": { "symbol": "BTC", "decimals": 8 },
+                    "tokens": { "USDT": { "address": "31" } }
+                }
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err(),
+            ["Chain 'bitcoin' token USDT can't be queried, Bitcoin has no tokens"]
+        );
+    }
+
+    #[test]
+    fn test_validate_checks_icp_canisters() {
+        let icp = |canister_id: Option<&str>, ledger: &str| {
+            serde_json::json!({
+                "type": "icp", "name": "Internet Computer", "rpc": "https://icp-api.io",
+                "canisterId": canister_id,
+                "nativeToken": { "symbol": "ICP", "decimals": 8 },
+                "tokens": { "ckBTC": { "address": ledger, "decimals": 8 } }
+            })
+        };
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "chains": {
+                "icp": icp(Some("ryjl3-tyaaa-aaaaa-aaaba-cai"), "mxzaz-hqaaa-aaaar-qaada-cai"),
+                "icp-broken": icp(None, "mxzaz-hqaaa-aaaar-qaada"),
+                "icp-typo": icp(Some("qyjl3-tyaaa-aaaaa-aaaba-cai"), "mxzaz-hqaaa-aaaar-qaada-cai")
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err(),
+            [
+                "Chain 'icp-broken' has no canisterId, which ICP chains require",
+                "Chain 'icp-broken' token ckBTC has an invalid address 'mxzaz-hqaaa-aaaar-qaada'",
+                "Chain 'icp-typo' has an invalid canisterId 'qyjl3-tyaaa-aaaaa-aaaba-cai'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_collects_all_problems() {
+        let mut config = network_config();
+        let sepolia = config.chains.get_mut("sepolia").unwrap();
+        sepolia.chain_id = None;
+        sepolia.rpc = vec!["sepolia.example".to_string()];
+        sepolia.tokens.insert(
+            "USDC".to_string(),
+            serde_json::from_str(r#"{ "address": "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C723" }"#)
+                .unwrap(),
+        );
+        let devnet = config.chains.get_mut("solana-devnet").unwrap();
+        devnet.tokens.insert(
+            "USDC".to_string(),
+            serde_json::from_str(r#"{ "address": "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238" }"#)
+                .unwrap(),
+        );
+        config.chains.get_mut("ethereum").unwrap().chain_type = "cosmos".to_string();
+
+        assert_eq!(
+            config.validate().unwrap_err(),
+            [
+                "Chain 'ethereum' has unsupported type 'cosmos'",
+                "Chain 'sepolia' has no chainId, which EVM chains require",
+                "Chain 'sepolia' has a malformed RPC URL 'sepolia.example'",
+                "Chain 'sepolia' token USDC has an invalid address '0x1c7D4B196Cb0C7B01d743Fbc6116a902379C723'",
+                "Chain 'solana-devnet' token USDC has an invalid address '0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_rpc_allowed_with_fallback() {
+        let mut config = network_config();
+        let ethereum = config.chains.get_mut("ethereum").unwrap();
+        ethereum.rpc = Vec::new();
+        ethereum.chain_id = Some(1);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_string_or_list() {
+        let mut config = network_config();
+        let ethereum = config.chains.get_mut("ethereum").unwrap();
+        assert_eq!(ethereum.rpc, ["https://eth.example"]);
+        assert_eq!(ethereum.primary_rpc(), "https://eth.example");
+
+        let json = serde_json::to_value(&*ethereum).unwrap();
+        assert_eq!(json["rpc"], "https://eth.example");
+
+        ethereum.rpc.push("https://eth-backup.example".to_string());
+        let json = serde_json::to_value(&*ethereum).unwrap();
+        assert_eq!(
+            json["rpc"],
+            serde_json::json!(["https://eth.example", "https://eth-backup.example"])
+        );
+        let parsed: ChainConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.rpc, ethereum.rpc);
+    }
+
+    #[test]
+    fn test_validate_checks_every_rpc() {
+        let mut config = network_config();
+        config.chains.get_mut("sepolia").unwrap().rpc = vec![
+            "https://sepolia.example".to_string(),
+            "sepolia.example".to_string(),
+        ];
+        let problems = config.validate().unwrap_err();
+        assert_eq!(
+            problems,
+            ["Chain 'sepolia' has a malformed RPC URL 'sepolia.example'"]
+        );
+    }
+
+    #[test]
+    fn test_rpc_env_override() {
+        assert_eq!(
+            rpc_env_var("solana-devnet"),
+            "BALANCE_CHECKER_RPC_SOLANA_DEVNET"
+        );
+        assert_eq!(rpc_env_var("sepolia"), "BALANCE_CHECKER_RPC_SEPOLIA");
+
+        let config = network_config().with_rpc_overrides(|name| match name {
+            "BALANCE_CHECKER_RPC_SEPOLIA" => Some("http://127.0.0.1:8545".to_string()),
+            "BALANCE_CHECKER_RPC_ETHEREUM" => Some(" ".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.chains["sepolia"].rpc, ["http://127.0.0.1:8545"]);
+        assert_eq!(config.chains["ethereum"].rpc, ["https://eth.example"]);
+    }
+
+    #[test]
+    fn test_chain_tokens() {
+        let config = Config::load().unwrap();
+        let tokens = config.chains["solana-devnet"].tokens().unwrap();
+        let symbols: Vec<&str> = tokens.iter().map(Token::symbol).collect();
+        assert_eq!(symbols, ["EURC", "USDC"]);
+        assert!(matches!(tokens[1], Token::Spl { decimals: 6, .. }));
+
+        let mut sepolia = config.chains["sepolia"].clone();
+        sepolia.tokens.get_mut("USDC").unwrap().decimals = None;
+        let error = sepolia.tokens().unwrap_err();
+        assert_eq!(error.to_string(), "Token USDC has no configured decimals");
+    }
+
     #[test]
     fn test_solana_config() {
         let config = Config::load().unwrap();
         let solana = config.get_chain("solana-devnet").unwrap();
         assert_eq!(solana.chain_type, "solana");
-        assert_eq!(solana.native_token.decimals, 9);
+        assert_eq!(solana.native_token.decimals, Some(9));
         assert!(solana.tokens.contains_key("USDC"));
     }
 }
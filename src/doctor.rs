@@ -0,0 +1,299 @@
+use crate::bitcoin::BitcoinProvider;
+use crate::config::{validate_rpc_url, ChainConfig, Config};
+use crate::ethereum::{EthereumProvider, MULTICALL3_ADDRESS};
+use crate::icp::IcpProvider;
+use crate::solana::SolanaProvider;
+
+/// Outcome of a single diagnostic check
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    pub(crate) fn pass(name: String, detail: String) -> Self {
+        Self {
+            name,
+            passed: true,
+            detail,
+        }
+    }
+
+    pub(crate) fn fail(name: String, detail: String) -> Self {
+        Self {
+            name,
+            passed: false,
+            detail,
+        }
+    }
+}
+
+/// Validate the config and probe every configured chain, returning a pass/fail checklist
+pub async fn diagnose(config: &Config) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let mut names: Vec<&String> = config.chains.keys().collect();
+    names.sort();
+
+    for name in names {
+        let chain = &config.chains[name];
+
+        let invalid = match chain.rpc.as_slice() {
+            [] => validate_rpc_url(name, "").err(),
+            rpcs => rpcs
+                .iter()
+                .find_map(|rpc| chain.validate_rpc_endpoint(name, rpc).err()),
+        };
+        if let Some(e) = invalid {
+            checks.push(Check::fail(format!("{}: rpc", name), e.to_string()));
+            continue;
+        }
+
+        match chain.chain_type.as_str() {
+            "evm" => diagnose_evm(name, chain, &mut checks).await,
+            "solana" => diagnose_solana(name, chain, &mut checks).await,
+            "icp" => diagnose_icp(name, chain, &mut checks).await,
+            "bitcoin" => diagnose_bitcoin(name, chain, &mut checks).await,
+            other => checks.push(Check::fail(
+                format!("{}: chain type", name),
+                format!("Unsupported chain type: {}", other),
+            )),
+        }
+    }
+
+    checks
+}
+
+async fn diagnose_evm(name: &str, chain: &ChainConfig, checks: &mut Vec<Check>) {
+    let mut reachable = false;
+    for rpc in &chain.rpc {
+        let provider =
+            EthereumProvider::new(rpc.clone()).with_rpc_methods(chain.rpc_methods.clone());
+        checks.push(match provider.block_number().await {
+            Ok(block) => {
+                reachable = true;
+                Check::pass(
+                    format!("{}: rpc", name),
+                    format!("{} answered at block {}", rpc, block),
+                )
+            }
+            Err(e) => Check::fail(
+                format!("{}: rpc", name),
+                format!("{} unreachable: {}", rpc, e),
+            ),
+        });
+    }
+    // Nothing else can be checked without a working endpoint
+    if !reachable {
+        return;
+    }
+    let provider = EthereumProvider::new(chain.primary_rpc().to_string())
+        .with_fallback_rpcs(chain.rpc[1..].to_vec())
+        .with_rpc_methods(chain.rpc_methods.clone());
+
+    let check_name = format!("{}: multicall3", name);
+    checks.push(match provider.has_code(MULTICALL3_ADDRESS).await {
+        Ok(true) => Check::pass(check_name, format!("deployed at {}", MULTICALL3_ADDRESS)),
+        Ok(false) => Check::fail(check_name, format!("no contract at {}", MULTICALL3_ADDRESS)),
+        Err(e) => Check::fail(check_name, e.to_string()),
+    });
+
+    for (symbol, address) in token_addresses(name, chain, checks) {
+        let check_name = format!("{}: token {}", name, symbol);
+        checks.push(match provider.has_code(&address).await {
+            Ok(true) => Check::pass(check_name, format!("contract at {}", address)),
+            Ok(false) => Check::fail(check_name, format!("no contract code at {}", address)),
+            Err(e) => Check::fail(check_name, e.to_string()),
+        });
+    }
+}
+
+async fn diagnose_solana(name: &str, chain: &ChainConfig, checks: &mut Vec<Check>) {
+    let mut reachable = false;
+    for rpc in &chain.rpc {
+        checks.push(match SolanaProvider::new(rpc.clone()).slot().await {
+            Ok(slot) => {
+                reachable = true;
+                Check::pass(
+                    format!("{}: rpc", name),
+                    format!("{} answered at slot {}", rpc, slot),
+                )
+            }
+            Err(e) => Check::fail(
+                format!("{}: rpc", name),
+                format!("{} unreachable: {}", rpc, e),
+            ),
+        });
+    }
+    if !reachable {
+        return;
+    }
+    let provider = match SolanaProvider::new(chain.primary_rpc().to_string())
+        .with_fallback_rpcs(chain.rpc[1..].to_vec())
+    {
+        Ok(provider) => provider,
+        Err(e) => {
+            checks.push(Check::fail(format!("{}: rpc", name), e.to_string()));
+            return;
+        }
+    };
+
+    for (symbol, mint) in token_addresses(name, chain, checks) {
+        let check_name = format!("{}: token {}", name, symbol);
+        checks.push(match provider.account_exists(&mint).await {
+            Ok(true) => Check::pass(check_name, format!("mint account {}", mint)),
+            Ok(false) => Check::fail(check_name, format!("no mint account at {}", mint)),
+            Err(e) => Check::fail(check_name, e.to_string()),
+        });
+    }
+}
+
+async fn diagnose_icp(name: &str, chain: &ChainConfig, checks: &mut Vec<Check>) {
+    let Some(ledger) = &chain.canister_id else {
+        checks.push(Check::fail(
+            format!("{}: canister", name),
+            "no canisterId configured".to_string(),
+        ));
+        return;
+    };
+    // The ledger's symbol is the cheapest query that goes through to a canister
+    let mut reachable = None;
+    for rpc in &chain.rpc {
+        let provider = IcpProvider::new(rpc.clone(), ledger.clone());
+        checks.push(match provider.token_metadata(ledger).await {
+            Ok((symbol, _)) => {
+                reachable.get_or_insert(provider);
+                Check::pass(
+                    format!("{}: rpc", name),
+                    format!("{} answered, ledger {} is {}", rpc, ledger, symbol),
+                )
+            }
+            Err(e) => Check::fail(
+                format!("{}: rpc", name),
+                format!("{} unreachable: {}", rpc, e),
+            ),
+        });
+    }
+    let Some(provider) = reachable else {
+        return;
+    };
+
+    for (symbol, token_ledger) in token_addresses(name, chain, checks) {
+        let check_name = format!("{}: token {}", name, symbol);
+        checks.push(match provider.token_metadata(&token_ledger).await {
+            Ok((ledger_symbol, _)) => Check::pass(
+                check_name,
+                format!("ICRC-1 ledger {} ({})", token_ledger, ledger_symbol),
+            ),
+            Err(e) => Check::fail(check_name, e.to_string()),
+        });
+    }
+}
+
+/// Bitcoin has no tokens or contracts, so probing each endpoint is all there is
+async fn diagnose_bitcoin(name: &str, chain: &ChainConfig, checks: &mut Vec<Check>) {
+    for rpc in &chain.rpc {
+        checks.push(match BitcoinProvider::new(rpc.clone()).tip_height().await {
+            Ok(height) => Check::pass(
+                format!("{}: rpc", name),
+                format!("{} answered at block {}", rpc, height),
+            ),
+            Err(e) => Check::fail(
+                format!("{}: rpc", name),
+                format!("{} unreachable: {}", rpc, e),
+            ),
+        });
+    }
+}
+
+/// Configured token addresses in symbol order, recording a failure for tokens without one
+fn token_addresses(
+    name: &str,
+    chain: &ChainConfig,
+    checks: &mut Vec<Check>,
+) -> Vec<(String, String)> {
+    let mut symbols: Vec<&String> = chain.tokens.keys().collect();
+    symbols.sort();
+
+    let mut addresses = Vec::new();
+    for symbol in symbols {
+        match &chain.tokens[symbol].address {
+            Some(address) => addresses.push((symbol.clone(), address.clone())),
+            None => checks.push(Check::fail(
+                format!("{}: token {}", name, symbol),
+                "no address configured".to_string(),
+            )),
+        }
+    }
+    addresses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn test_diagnose_reports_config_problems_without_network() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "chains": {
+                    "empty-rpc": {
+                        "type": "evm",
+                        "name": "Empty",
+                        "rpc": "",
+                        "nativeToken": { "symbol": "ETH", "decimals": 18 },
+                        "tokens": {}
+                    },
+                    "icp": {
+                        "type": "icp",
+                        "name": "Internet Computer",
+                        "rpc": "https://icp-api.io",
+                        "nativeToken": { "symbol": "ICP", "decimals": 8 },
+                        "tokens": {}
+                    },
+                    "unknown": {
+                        "type": "cosmos",
+                        "name": "Unknown",
+                        "rpc": "https://example.invalid",
+                        "nativeToken": { "symbol": "ATOM", "decimals": 6 },
+                        "tokens": {}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let checks = diagnose(&config).await;
+        assert_eq!(checks.len(), 3);
+        assert!(checks.iter().all(|check| !check.passed));
+        assert_eq!(checks[0].name, "empty-rpc: rpc");
+        assert_eq!(checks[1].name, "icp: canister");
+        assert_eq!(checks[2].name, "unknown: chain type");
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_probes_icp_gateways() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "chains": {
+                "icp": {
+                    "type": "icp",
+                    "name": "Internet Computer",
+                    "rpc": test_server::unreachable_url(),
+                    "canisterId": "ryjl3-tyaaa-aaaaa-aaaba-cai",
+                    "nativeToken": { "symbol": "ICP", "decimals": 8 },
+                    "tokens": {}
+                }
+            }
+        }))
+        .unwrap();
+
+        let checks = diagnose(&config).await;
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].name, "icp: rpc");
+        assert!(!checks[0].passed);
+        assert!(checks[0].detail.contains("unreachable"));
+    }
+}
@@ -0,0 +1,196 @@
+/// Errors of `get_balances` and the `ChainProvider` methods, for callers to
+/// match on. Converts into `anyhow::Error` with `?` (and back with `From`),
+/// so `downcast_ref::<BalanceError>()` still finds it in an `anyhow` chain.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BalanceError {
+    /// A chain's `rpc` is empty (with no fallback endpoints) or not an http(s) URL
+    #[error("Chain '{chain}' has {}", rpc_url_problem(url))]
+    InvalidRpcUrl { chain: String, url: String },
+    /// No chain with this name (or family) in the configuration
+    #[error("Chain '{chain}' not found in configuration")]
+    ChainNotFound { chain: String },
+    /// The chain's `type` isn't one this crate can read
+    #[error("Unsupported chain type: {chain_type}")]
+    UnsupportedChainType { chain_type: String },
+    /// The configuration can't be used for the query, e.g. a token of another chain type
+    #[error("{message}")]
+    Config { message: String },
+    /// The queried address isn't valid for the chain type
+    #[error("Invalid address '{address}'")]
+    InvalidAddress { address: String },
+    /// Connection failure or a server-side error from the RPC endpoint
+    #[error("Network error: {message}")]
+    Network { message: String },
+    /// The RPC endpoint didn't answer in time
+    #[error("RPC request timed out")]
+    Timeout,
+    /// The RPC endpoint rejected the request for exceeding its rate limit
+    #[error("RPC endpoint rate limit exceeded")]
+    RateLimited,
+    /// The block (or slot) balances would be read at is older than the
+    /// allowed staleness, e.g. because the node is syncing or stuck
+    #[error("Node is lagging: block {block} is {age_secs}s old (max staleness {max_secs}s)")]
+    StaleNode {
+        block: u64,
+        age_secs: u64,
+        max_secs: u64,
+    },
+    /// Any other failed read, e.g. a JSON-RPC error response or a reply that
+    /// doesn't decode, with the whole cause chain as its message
+    #[error("{message}")]
+    Rpc { message: String },
+}
+
+impl BalanceError {
+    /// Whether the same request may succeed if retried later. Configuration
+    /// and input errors are permanent; network trouble is transient. A stale
+    /// node isn't retried: one still syncing won't catch up within a backoff.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BalanceError::Network { .. } | BalanceError::Timeout | BalanceError::RateLimited => {
+                true
+            }
+            BalanceError::StaleNode { .. }
+            | BalanceError::InvalidRpcUrl { .. }
+            | BalanceError::ChainNotFound { .. }
+            | BalanceError::UnsupportedChainType { .. }
+            | BalanceError::Config { .. }
+            | BalanceError::InvalidAddress { .. }
+            | BalanceError::Rpc { .. } => false,
+        }
+    }
+}
+
+fn rpc_url_problem(url: &str) -> String {
+    match url.trim().is_empty() {
+        true => "an empty RPC URL".to_string(),
+        false => format!("a malformed RPC URL '{}'", url),
+    }
+}
+
+/// The `BalanceError` anywhere in the chain of `error`, e.g. a timeout inside
+/// an alloy error; HTTP timeouts and connection failures map to `Timeout` and
+/// `Network`, anything else to `Rpc`
+impl From<anyhow::Error> for BalanceError {
+    fn from(error: anyhow::Error) -> Self {
+        let typed = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<BalanceError>());
+        if let Some(typed) = typed {
+            return typed.clone();
+        }
+        let message = format!("{:#}", error);
+        match error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        {
+            Some(http) if http.is_timeout() => BalanceError::Timeout,
+            Some(http) if http.is_connect() => BalanceError::Network { message },
+            _ => BalanceError::Rpc { message },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retryable_variants() {
+        assert!(BalanceError::Network {
+            message: "connection reset".to_string()
+        }
+        .is_retryable());
+        assert!(BalanceError::Timeout.is_retryable());
+        assert!(BalanceError::RateLimited.is_retryable());
+    }
+
+    #[test]
+    fn test_permanent_variants() {
+        assert!(!BalanceError::InvalidRpcUrl {
+            chain: "sepolia".to_string(),
+            url: String::new(),
+        }
+        .is_retryable());
+        assert!(!BalanceError::ChainNotFound {
+            chain: "polygon".to_string()
+        }
+        .is_retryable());
+        assert!(!BalanceError::Config {
+            message: "token USDC isn't an SPL mint".to_string()
+        }
+        .is_retryable());
+        assert!(!BalanceError::InvalidAddress {
+            address: "0x123".to_string()
+        }
+        .is_retryable());
+        assert!(!BalanceError::UnsupportedChainType {
+            chain_type: "cosmos".to_string()
+        }
+        .is_retryable());
+        assert!(!BalanceError::Rpc {
+            message: "execution reverted".to_string()
+        }
+        .is_retryable());
+        assert!(!BalanceError::StaleNode {
+            block: 1,
+            age_secs: 120,
+            max_secs: 60,
+        }
+        .is_retryable());
+    }
+
+    #[test]
+    fn test_display() {
+        let empty = BalanceError::InvalidRpcUrl {
+            chain: "sepolia".to_string(),
+            url: " ".to_string(),
+        };
+        assert_eq!(empty.to_string(), "Chain 'sepolia' has an empty RPC URL");
+        let malformed = BalanceError::InvalidRpcUrl {
+            chain: "sepolia".to_string(),
+            url: "localhost".to_string(),
+        };
+        assert_eq!(
+            malformed.to_string(),
+            "Chain 'sepolia' has a malformed RPC URL 'localhost'"
+        );
+    }
+
+    /// Stands in for a library error that keeps ours as its source
+    #[derive(Debug)]
+    struct Wrapped(BalanceError);
+
+    impl std::fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "eth_call failed: {}", self.0)
+        }
+    }
+
+    impl std::error::Error for Wrapped {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn test_from_anyhow() {
+        let nested = anyhow::Error::new(Wrapped(BalanceError::Timeout));
+        assert_eq!(BalanceError::from(nested), BalanceError::Timeout);
+
+        let other = anyhow::anyhow!("execution reverted").context("eth_call failed");
+        assert_eq!(
+            BalanceError::from(other),
+            BalanceError::Rpc {
+                message: "eth_call failed: execution reverted".to_string()
+            }
+        );
+
+        // And back, still typed
+        let error = anyhow::Error::from(BalanceError::RateLimited);
+        assert_eq!(
+            error.downcast_ref::<BalanceError>(),
+            Some(&BalanceError::RateLimited)
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use alloy::rpc::json_rpc::{RequestPacket, Response, ResponsePacket, ResponsePayload, RpcError};
+use alloy::transports::{TransportError, TransportErrorKind, TransportFut, TransportResult};
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::{Client, StatusCode, Url};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tower::Service;
+
+use crate::error::BalanceError;
+use crate::fallback::RpcEndpoints;
+use crate::retry::RetryPolicy;
+use crate::telemetry;
+
+/// Responses kept for revalidation before the cache starts over
+const MAX_CACHED_RESPONSES: usize = 4096;
+
+/// Last response with an `ETag` per endpoint and request (IDs stripped),
+/// shared by every provider in the process
+static CACHE: OnceLock<Mutex<HashMap<String, CachedResponse>>> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    etag: String,
+    /// JSON-RPC IDs of the request the body answered
+    ids: Vec<Value>,
+    body: Value,
+}
+
+/// JSON-RPC over HTTP POST, as alloy's `Http` transport, optionally sending
+/// `If-None-Match` with the `ETag` of the last identical request so a caching
+/// proxy can answer `304 Not Modified` and the cached body is reused.
+/// Endpoints that send no `ETag` are unaffected. Standard method names found
+/// in `methods` are replaced by the chain's own before sending, and transient
+/// failures are retried under `retry`. A call still unanswered after
+/// `timeout`, retries included, fails with `BalanceError::Timeout`. When an
+/// endpoint is down the call moves on to the next of `endpoints`, with its
+/// own retries and timeout. Each attempt waits for a permit of `in_flight`.
+#[derive(Debug, Clone)]
+pub(crate) struct EtagTransport {
+    client: Client,
+    endpoints: Arc<RpcEndpoints>,
+    revalidate: bool,
+    methods: Arc<HashMap<String, String>>,
+    retry: RetryPolicy,
+    timeout: Duration,
+    in_flight: Arc<Semaphore>,
+}
+
+impl EtagTransport {
+    pub(crate) fn new(
+        client: Client,
+        endpoints: Arc<RpcEndpoints>,
+        revalidate: bool,
+        methods: Arc<HashMap<String, String>>,
+        retry: RetryPolicy,
+        timeout: Duration,
+        in_flight: Arc<Semaphore>,
+    ) -> Self {
+        Self {
+            client,
+            endpoints,
+            revalidate,
+            methods,
+            retry,
+            timeout,
+            in_flight,
+        }
+    }
+
+    fn request(&self, packet: RequestPacket) -> TransportFut<'static> {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut last_error = None;
+            for index in this.endpoints.order() {
+                let url = &this.endpoints.urls()[index];
+                let attempts = this
+                    .retry
+                    .run_async(|| this.send(url, packet.clone()), is_transient);
+                let error = match tokio::time::timeout(this.timeout, attempts).await {
+                    Ok(Err(error)) if is_endpoint_failure(&error) => error,
+                    Ok(result) => return result,
+                    Err(_) => TransportErrorKind::custom(BalanceError::Timeout),
+                };
+                this.endpoints.fail_over(index, &error);
+                last_error = Some(error);
+            }
+            Err(last_error.expect("a provider has at least one endpoint"))
+        })
+    }
+
+    /// One attempt at `packet` on `url` once a request slot is free
+    async fn send(&self, url: &str, packet: RequestPacket) -> TransportResult<ResponsePacket> {
+        let _permit = self.in_flight.acquire().await;
+        telemetry::rpc_call(url, self.post(url, packet)).await
+    }
+
+    /// POST `packet` to `url`; JSON-RPC errors that ask for a retry (rate
+    /// limits) are returned as errors so the retry policy sees them
+    async fn post(&self, url: &str, packet: RequestPacket) -> TransportResult<ResponsePacket> {
+        let url: Url = url.parse().map_err(TransportErrorKind::custom)?;
+        // Only go through a JSON value when the request has to be inspected
+        let body = match self.revalidate || !self.methods.is_empty() {
+            true => {
+                let body = serde_json::to_value(&packet).map_err(TransportErrorKind::custom)?;
+                Some(rename_methods(body, &self.methods))
+            }
+            false => None,
+        };
+        let (key, ids) = match (&body, self.revalidate) {
+            (Some(body), true) => {
+                let (request, ids) = strip_ids(body.clone());
+                (Some(format!("{} {}", url, request)), ids)
+            }
+            _ => (None, Vec::new()),
+        };
+        let cached = key.as_ref().and_then(|key| cache().get(key).cloned());
+
+        let mut request = match &body {
+            Some(body) => self.client.post(url).json(body),
+            None => self.client.post(url).json(&packet),
+        };
+        if let Some(cached) = &cached {
+            request = request.header(IF_NONE_MATCH, &cached.etag);
+        }
+        let response = request.send().await.map_err(TransportErrorKind::custom)?;
+        let status = response.status();
+
+        if let (StatusCode::NOT_MODIFIED, Some(cached)) = (status, cached) {
+            let body = replace_ids(cached.body, &cached.ids, &ids);
+            let body = serde_json::to_vec(&body).map_err(TransportErrorKind::custom)?;
+            return serde_json::from_slice(&body).map_err(TransportErrorKind::custom);
+        }
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await.map_err(TransportErrorKind::custom)?;
+        if status != StatusCode::OK {
+            return Err(TransportErrorKind::http_error(
+                status.as_u16(),
+                String::from_utf8_lossy(&body).into_owned(),
+            ));
+        }
+        let packet: ResponsePacket = serde_json::from_slice(&body)
+            .map_err(|err| TransportError::deser_err(err, String::from_utf8_lossy(&body)))?;
+
+        if let (Some(key), Some(etag), Ok(body)) =
+            (key, etag, serde_json::from_slice::<Value>(&body))
+        {
+            let mut cache = cache();
+            if cache.len() >= MAX_CACHED_RESPONSES {
+                cache.clear();
+            }
+            cache.insert(key, CachedResponse { etag, ids, body });
+        }
+        match retry_error(&packet) {
+            Some(error) => Err(error),
+            None => Ok(packet),
+        }
+    }
+}
+
+/// The error of a single-call response asking for a retry (a rate limit), so
+/// the retry policy sees it
+pub(crate) fn retry_error(packet: &ResponsePacket) -> Option<TransportError> {
+    match packet {
+        ResponsePacket::Single(Response {
+            payload: ResponsePayload::Failure(error),
+            ..
+        }) if error.is_retry_err() => Some(RpcError::ErrorResp(error.clone())),
+        _ => None,
+    }
+}
+
+/// Failures worth retrying: rate limits, temporarily unavailable endpoints,
+/// timeouts and connection errors. Bad requests and undecodable responses
+/// fail right away.
+pub(crate) fn is_transient(error: &TransportError) -> bool {
+    match error {
+        RpcError::Transport(kind) if kind.is_retry_err() => true,
+        RpcError::Transport(TransportErrorKind::Custom(error)) => error
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|error| error.is_timeout() || error.is_connect()),
+        RpcError::ErrorResp(payload) => payload.is_retry_err(),
+        _ => false,
+    }
+}
+
+/// Failures that say nothing about the request itself, so another endpoint
+/// may answer it: the endpoint is unreachable, errored or kept rate limiting
+fn is_endpoint_failure(error: &TransportError) -> bool {
+    matches!(error, RpcError::Transport(_)) || is_transient(error)
+}
+
+impl Service<RequestPacket> for EtagTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, packet: RequestPacket) -> Self::Future {
+        self.request(packet)
+    }
+}
+
+fn cache() -> MutexGuard<'static, HashMap<String, CachedResponse>> {
+    CACHE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Replace the `method` of each call by its configured alias, if any
+fn rename_methods(mut request: Value, methods: &HashMap<String, String>) -> Value {
+    let rename = |call: &mut Value| {
+        let alias = call
+            .get("method")
+            .and_then(Value::as_str)
+            .and_then(|method| methods.get(method));
+        if let Some(alias) = alias {
+            call["method"] = Value::String(alias.clone());
+        }
+    };
+    match &mut request {
+        Value::Array(calls) => calls.iter_mut().for_each(rename),
+        call => rename(call),
+    }
+    request
+}
+
+/// Remove the JSON-RPC `id` of each call, which differs between otherwise
+/// identical requests, returning the canonical request and the IDs in order
+fn strip_ids(mut request: Value) -> (String, Vec<Value>) {
+    let take_id = |call: &mut Value| {
+        call.as_object_mut()
+            .and_then(|call| call.remove("id"))
+            .unwrap_or(Value::Null)
+    };
+    let ids = match &mut request {
+        Value::Array(calls) => calls.iter_mut().map(take_id).collect(),
+        call => vec![take_id(call)],
+    };
+    (request.to_string(), ids)
+}
+
+/// Rewrite the IDs of a cached response (to a request with `old` IDs) so it
+/// answers the request with `new` IDs
+fn replace_ids(mut body: Value, old: &[Value], new: &[Value]) -> Value {
+    let replace = |response: &mut Value| {
+        if let Some(id) = response.get_mut("id") {
+            if let Some(index) = old.iter().position(|old_id| old_id == id) {
+                if let Some(new_id) = new.get(index) {
+                    *id = new_id.clone();
+                }
+            }
+        }
+    };
+    match &mut body {
+        Value::Array(responses) => responses.iter_mut().for_each(replace),
+        response => replace(response),
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use alloy::rpc::json_rpc::{Id, Request};
+    use serde_json::json;
+
+    #[test]
+    fn test_transient_transport_errors() {
+        assert!(is_transient(&TransportErrorKind::http_error(
+            429,
+            "Too Many Requests".to_string()
+        )));
+        assert!(is_transient(&TransportErrorKind::http_error(
+            503,
+            "Service Unavailable".to_string()
+        )));
+        assert!(!is_transient(&TransportErrorKind::http_error(
+            400,
+            "Bad Request".to_string()
+        )));
+
+        let rate_limited: ResponsePacket = serde_json::from_value(json!({
+            "jsonrpc": "2.0", "id": 1,
+            "error": { "code": -32005, "message": "limit exceeded" }
+        }))
+        .unwrap();
+        let ResponsePacket::Single(Response {
+            payload: ResponsePayload::Failure(error),
+            ..
+        }) = rate_limited
+        else {
+            panic!("expected an error response");
+        };
+        assert!(is_transient(&RpcError::ErrorResp(error)));
+
+        let malformed =
+            TransportError::deser_err(serde_json::from_str::<Value>("{").unwrap_err(), "{");
+        assert!(!is_transient(&malformed));
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_request_times_out() {
+        let (_listener, url) = test_server::silent();
+        let transport = EtagTransport::new(
+            Client::new(),
+            Arc::new(RpcEndpoints::new(vec![url])),
+            false,
+            Arc::default(),
+            RetryPolicy::none(),
+            Duration::from_millis(100),
+            Arc::new(Semaphore::new(1)),
+        );
+        let request = Request::new("eth_blockNumber", Id::Number(1), json!([]));
+        let packet = RequestPacket::from(request.serialize().unwrap());
+
+        let error = transport.request(packet).await.unwrap_err();
+        let error = anyhow::Error::from(error);
+        assert!(error
+            .chain()
+            .any(|cause| cause.downcast_ref::<BalanceError>() == Some(&BalanceError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_down_endpoint_fails_over_to_next() {
+        let down_url = test_server::unreachable_url();
+        let up_url = test_server::serve_json_rpc(|_| json!("0x10")).await;
+
+        let endpoints = Arc::new(RpcEndpoints::new(vec![down_url, up_url.clone()]));
+        let transport = EtagTransport::new(
+            Client::new(),
+            endpoints.clone(),
+            false,
+            Arc::default(),
+            RetryPolicy::none(),
+            Duration::from_secs(5),
+            Arc::new(Semaphore::new(1)),
+        );
+        let request = Request::new("eth_blockNumber", Id::Number(1), json!([]));
+        let packet = RequestPacket::from(request.serialize().unwrap());
+
+        assert!(transport.request(packet).await.is_ok());
+        assert_eq!(endpoints.active(), up_url);
+    }
+
+    #[test]
+    fn test_rename_methods() {
+        let methods =
+            HashMap::from([("eth_getBalance".to_string(), "klay_getBalance".to_string())]);
+        let batch = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "eth_getBalance", "params": [] },
+            { "jsonrpc": "2.0", "id": 2, "method": "eth_call", "params": [] }
+        ]);
+
+        let renamed = rename_methods(batch, &methods);
+        assert_eq!(renamed[0]["method"], "klay_getBalance");
+        assert_eq!(renamed[1]["method"], "eth_call");
+    }
+
+    #[test]
+    fn test_identical_requests_share_a_key() {
+        let first = json!({ "jsonrpc": "2.0", "id": 1, "method": "eth_blockNumber", "params": [] });
+        let second =
+            json!({ "jsonrpc": "2.0", "id": 7, "method": "eth_blockNumber", "params": [] });
+
+        let (first_key, first_ids) = strip_ids(first);
+        let (second_key, second_ids) = strip_ids(second);
+        assert_eq!(first_key, second_key);
+        assert_eq!(first_ids, [json!(1)]);
+        assert_eq!(second_ids, [json!(7)]);
+    }
+
+    #[test]
+    fn test_replace_ids_in_batch() {
+        let cached = json!([
+            { "jsonrpc": "2.0", "id": 2, "result": "0x2" },
+            { "jsonrpc": "2.0", "id": 1, "result": "0x1" }
+        ]);
+        let body = replace_ids(cached, &[json!(1), json!(2)], &[json!(11), json!(12)]);
+        assert_eq!(body[0]["id"], 12);
+        assert_eq!(body[1]["id"], 11);
+    }
+}
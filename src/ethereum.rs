@@ -1,69 +1,1765 @@
-use alloy::primitives::{Address, U256};
-use alloy::providers::{Provider, ProviderBuilder};
-use alloy::rpc::types::BlockNumberOrTag;
+use alloy::consensus::{Account, TxEnvelope};
+use alloy::network::Ethereum;
+use alloy::primitives::{
+    keccak256, Address, BlockHash, BlockNumber, Bytes, StorageKey, StorageValue, TxHash, B256,
+    U128, U256, U64,
+};
+use alloy::providers::utils::{Eip1559Estimation, EstimatorFunction};
+use alloy::providers::{
+    EthCall, FilterPollerBuilder, PendingTransaction, PendingTransactionBuilder,
+    PendingTransactionConfig, PendingTransactionError, Provider, ProviderBuilder, ProviderCall,
+    RootProvider, RpcWithBlock, SendableTx, WsConnect,
+};
+use alloy::pubsub::Subscription;
+use alloy::rpc::client::{ClientBuilder, NoParams, RpcClient};
+use alloy::rpc::types::simulate::{SimulatePayload, SimulatedBlock};
+use alloy::rpc::types::{
+    AccessListResult, Block, BlockId, BlockNumberOrTag, BlockTransactionsKind,
+    EIP1186AccountProofResponse, FeeHistory, Filter, FilterChanges, Header, Index, Log, SyncStatus,
+    Transaction, TransactionReceipt, TransactionRequest,
+};
 use alloy::sol;
-use anyhow::Result;
+use alloy::sol_types::SolCall;
+use alloy::transports::{BoxTransport, TransportResult};
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 
 use crate::chain::ChainProvider;
+use crate::config::{ChainConfig, VestingContract, VestingKind};
+use crate::error::BalanceError;
+use crate::etag::EtagTransport;
+use crate::fallback::{public_rpcs, rpc_host, RpcEndpoints};
+use crate::graphql::GraphQlProvider;
+use crate::http::{self, HttpConfig, RequestId, DEFAULT_RPC_TIMEOUT};
+use crate::options::{BlockTag, QueryOptions};
+use crate::retry::RetryPolicy;
+use crate::telemetry;
 use crate::types::{Balance, Token};
+use crate::ws::WsTransport;
 
-// ERC-20 ABI for balanceOf
+// ERC-20 ABI for balanceOf and token metadata
 sol! {
     #[sol(rpc)]
     interface IERC20 {
         function balanceOf(address account) external view returns (uint256);
+        function symbol() external view returns (string);
+        function decimals() external view returns (uint8);
     }
 }
 
-/// Ethereum chain provider using JSON-RPC
+// Multicall3 subset used to batch balance reads into one eth_call
+sol! {
+    #[sol(rpc)]
+    interface IMulticall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+
+        struct Result {
+            bool success;
+            bytes returnData;
+        }
+
+        function aggregate3(Call3[] calldata calls) external payable returns (Result[] memory returnData);
+        function getEthBalance(address addr) external view returns (uint256 balance);
+        function getBlockNumber() external view returns (uint256 blockNumber);
+    }
+}
+
+// ERC-1155 balance of a single token ID. ERC-721's balanceOf(address) has the
+// same selector and return type as ERC-20's, so IERC20 covers it.
+sol! {
+    #[sol(rpc)]
+    interface IERC1155 {
+        function balanceOf(address account, uint256 id) external view returns (uint256);
+    }
+}
+
+// Uniswap V2 pair subset used to value LP tokens
+sol! {
+    #[sol(rpc)]
+    interface IUniswapV2Pair {
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+        function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+        function totalSupply() external view returns (uint256);
+        function balanceOf(address owner) external view returns (uint256);
+    }
+}
+
+// OpenZeppelin VestingWallet (`owner` since 5.0, `beneficiary` before) and
+// TokenTimelock subsets used to split vested tokens into locked and available
+sol! {
+    #[sol(rpc)]
+    interface IVestingWallet {
+        function owner() external view returns (address);
+        function beneficiary() external view returns (address);
+        function releasable(address token) external view returns (uint256);
+    }
+}
+
+sol! {
+    #[sol(rpc)]
+    interface ITokenTimelock {
+        function beneficiary() external view returns (address);
+        function releaseTime() external view returns (uint256);
+    }
+}
+
+// Chainlink price feed subset
+sol! {
+    #[sol(rpc)]
+    interface IAggregatorV3 {
+        function decimals() external view returns (uint8);
+        function latestRoundData() external view returns (uint80 roundId, int256 answer, uint256 startedAt, uint256 updatedAt, uint80 answeredInRound);
+    }
+}
+
+// ENS registry and public resolver subsets used to resolve names
+sol! {
+    #[sol(rpc)]
+    interface IENSRegistry {
+        function resolver(bytes32 node) external view returns (address);
+    }
+}
+
+sol! {
+    #[sol(rpc)]
+    interface IENSResolver {
+        function addr(bytes32 node) external view returns (address);
+    }
+}
+
+/// Canonical Multicall3 deployment address, identical on most EVM chains
+pub const MULTICALL3_ADDRESS: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+/// ENS registry address on mainnet and the chains ENS is deployed to
+/// (Sepolia, Holesky); other chains have no code there
+pub const ENS_REGISTRY_ADDRESS: &str = "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+
+/// How long a resolved ENS name is reused before it is resolved again, so a
+/// long-lived provider follows names that move to a new address
+const ENS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Whether `address` is an ENS name to resolve rather than a hex address
+pub fn is_ens_name(address: &str) -> bool {
+    let name = address.to_ascii_lowercase();
+    name.len() > ".eth".len() && name.ends_with(".eth") && !name.contains(char::is_whitespace)
+}
+
+/// EIP-137 namehash of `name`, the ENS registry key of the name. Names are
+/// lowercased but not otherwise normalized.
+pub fn namehash(name: &str) -> B256 {
+    if name.is_empty() {
+        return B256::ZERO;
+    }
+    name.to_lowercase()
+        .rsplit('.')
+        .fold(B256::ZERO, |node, label| {
+            keccak256([node.as_slice(), keccak256(label).as_slice()].concat())
+        })
+}
+
+/// A call inside a Multicall3 batch reverted
+#[derive(Debug)]
+pub struct CallReverted {
+    pub target: String,
+}
+
+impl std::fmt::Display for CallReverted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "call to {} reverted", self.target)
+    }
+}
+
+impl std::error::Error for CallReverted {}
+
+/// Whether an error returned by `EthereumProvider` is a contract revert
+/// (as opposed to a transport failure or a missing contract)
+pub fn is_revert(error: &anyhow::Error) -> bool {
+    if error.downcast_ref::<CallReverted>().is_some() {
+        return true;
+    }
+
+    match error.downcast_ref::<alloy::contract::Error>() {
+        Some(alloy::contract::Error::TransportError(e)) => e
+            .as_error_resp()
+            .is_some_and(|resp| is_revert_response(resp.code, &resp.message)),
+        _ => false,
+    }
+}
+
+/// Whether a contract call failed because the contract has no such
+/// function: it reverted or, with a fallback function, returned nothing.
+/// Transport errors and other failures say nothing about the contract.
+fn is_missing_function(error: &anyhow::Error) -> bool {
+    is_revert(error)
+        || matches!(
+            error.downcast_ref::<alloy::contract::Error>(),
+            Some(alloy::contract::Error::AbiError(
+                alloy::dyn_abi::Error::SolTypes(alloy::sol_types::Error::Overrun)
+            ))
+        )
+}
+
+/// Geth-style nodes answer with code 3, others with a generic code and a revert message
+fn is_revert_response(code: i64, message: &str) -> bool {
+    code == 3 || message.to_ascii_lowercase().contains("revert")
+}
+
+/// Target and calldata of the `balanceOf` call for `token` held by `owner`;
+/// every supported standard returns a single uint256
+fn balance_call(token: &Token, owner: Address) -> Result<(Address, Bytes)> {
+    match token {
+        Token::Erc20 { address, .. } | Token::Erc721 { address, .. } => Ok((
+            address.parse()?,
+            IERC20::balanceOfCall { account: owner }.abi_encode().into(),
+        )),
+        Token::Erc1155 { address, id, .. } => Ok((
+            address.parse()?,
+            IERC1155::balanceOfCall {
+                account: owner,
+                id: id.parse()?,
+            }
+            .abi_encode()
+            .into(),
+        )),
+        Token::Native { .. } => Ok((
+            MULTICALL3_ADDRESS.parse()?,
+            IMulticall3::getEthBalanceCall { addr: owner }
+                .abi_encode()
+                .into(),
+        )),
+        other => Err(unsupported_token(other)),
+    }
+}
+
+/// Whether a token has ERC-20 `symbol()`/`decimals()` to read
+fn has_metadata(token: &Token) -> bool {
+    matches!(token, Token::Erc20 { .. })
+}
+
+/// Multicall3 calls reading the native balance of `owner`, then each token's
+/// balance, then the number of the block they are read at
+fn multicall_calls(
+    owner: Address,
+    tokens: &[Token],
+    with_metadata: bool,
+) -> Result<Vec<IMulticall3::Call3>> {
+    let call = |target: Address, call_data: Bytes| IMulticall3::Call3 {
+        target,
+        allowFailure: true,
+        callData: call_data,
+    };
+
+    let mut calls = vec![call(
+        MULTICALL3_ADDRESS.parse()?,
+        IMulticall3::getEthBalanceCall { addr: owner }
+            .abi_encode()
+            .into(),
+    )];
+    for token in tokens {
+        let (target, call_data) = balance_call(token, owner)?;
+        calls.push(call(target, call_data));
+        if with_metadata && has_metadata(token) {
+            calls.push(call(target, IERC20::symbolCall {}.abi_encode().into()));
+            calls.push(call(target, IERC20::decimalsCall {}.abi_encode().into()));
+        }
+    }
+    calls.push(call(
+        MULTICALL3_ADDRESS.parse()?,
+        IMulticall3::getBlockNumberCall {}.abi_encode().into(),
+    ));
+    Ok(calls)
+}
+
+fn decode_native(symbol: &str, result: Option<IMulticall3::Result>) -> Result<Balance> {
+    match result {
+        Some(result) if result.success => {
+            IMulticall3::getEthBalanceCall::abi_decode_returns(&result.returnData, true)
+                .map(|decoded| native_balance(symbol, decoded.balance))
+                .map_err(Into::into)
+        }
+        _ => Err(CallReverted {
+            target: MULTICALL3_ADDRESS.to_string(),
+        }
+        .into()),
+    }
+}
+
+fn decode_amount(token: &Token, result: Option<IMulticall3::Result>) -> Result<String> {
+    match result {
+        Some(result) if result.success => {
+            IERC20::balanceOfCall::abi_decode_returns(&result.returnData, true)
+                .map(|decoded| decoded._0.to_string())
+                .map_err(Into::into)
+        }
+        _ => Err(CallReverted {
+            target: balance_call_target(token).to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Block number returned by the batch's last call, `None` if it failed
+fn decode_block_number(result: Option<IMulticall3::Result>) -> Option<u64> {
+    decode_optional::<IMulticall3::getBlockNumberCall>(result)
+        .and_then(|decoded| u64::try_from(decoded.blockNumber).ok())
+}
+
+/// Contract the balance of `token` is read from
+fn balance_call_target(token: &Token) -> &str {
+    match token {
+        Token::Native { .. } => MULTICALL3_ADDRESS,
+        token => token.address(),
+    }
+}
+
+/// Decoded return value of a metadata call, `None` if it reverted or isn't
+/// ABI-compliant (e.g. a `bytes32` symbol)
+fn decode_optional<C: SolCall>(result: Option<IMulticall3::Result>) -> Option<C::Return> {
+    result
+        .filter(|result| result.success)
+        .and_then(|result| C::abi_decode_returns(&result.returnData, true).ok())
+}
+
+/// One token's raw balance and, when requested, the `symbol()` and
+/// `decimals()` read in the same batch (`None` where those reverted)
+pub(crate) struct TokenReading {
+    pub amount: Result<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+/// Made-up return data for a contract call sent by a balance read, for
+/// `explain` to answer it without a node: the block number is `head`,
+/// symbols are empty, reserves, amounts, decimals and addresses are zero, and
+/// each call of a Multicall3 batch succeeds with its own made-up data
+pub(crate) fn placeholder_output(input: &[u8], head: u64) -> Result<Bytes> {
+    let selector = input.get(..4).unwrap_or_default();
+    let output = if selector == IMulticall3::aggregate3Call::SELECTOR {
+        let calls = IMulticall3::aggregate3Call::abi_decode(input, true)?.calls;
+        let results = calls
+            .iter()
+            .map(|call| {
+                Ok(IMulticall3::Result {
+                    success: true,
+                    returnData: placeholder_output(&call.callData, head)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        IMulticall3::aggregate3Call::abi_encode_returns(&(results,))
+    } else if selector == IMulticall3::getBlockNumberCall::SELECTOR {
+        IMulticall3::getBlockNumberCall::abi_encode_returns(&(U256::from(head),))
+    } else if selector == IERC20::symbolCall::SELECTOR {
+        IERC20::symbolCall::abi_encode_returns(&(String::new(),))
+    } else if selector == IUniswapV2Pair::getReservesCall::SELECTOR {
+        // Two reserves and a timestamp
+        vec![0; 96]
+    } else {
+        // Every other call the reads make returns one static word
+        vec![0; 32]
+    };
+    Ok(output.into())
+}
+
+/// Whether `url` is a WebSocket RPC URL rather than an HTTP one
+pub(crate) fn is_ws_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "ws" | "wss"))
+}
+
+/// Native balance of `wei`, 18 decimals on every EVM chain
+fn native_balance(symbol: &str, wei: U256) -> Balance {
+    Balance::new(symbol.to_string(), wei.to_string(), 18).expect("an integer is a valid amount")
+}
+
+fn unsupported_token(token: &Token) -> anyhow::Error {
+    match token {
+        Token::UniV2Lp { .. } => anyhow!(
+            "LP token {} has two underlying balances, use get_lp_underlying_balances",
+            token.symbol()
+        ),
+        Token::Spl { .. } => anyhow!("{} is an SPL token, not an EVM token", token.symbol()),
+        other => anyhow!("EthereumProvider can't query token {:?}", other),
+    }
+}
+
+/// Holder's share of a pool reserve: `reserve * lp_balance / total_supply`, rounded down
+fn lp_share(reserve: U256, lp_balance: U256, total_supply: U256) -> Result<U256> {
+    if total_supply.is_zero() {
+        return Ok(U256::ZERO);
+    }
+    reserve
+        .checked_mul(lp_balance)
+        .map(|product| product / total_supply)
+        .ok_or_else(|| anyhow!("LP share overflows 256 bits"))
+}
+
+/// `(locked, available)` of a timelock holding `held` until `release_time`
+fn timelock_split(held: U256, release_time: U256, now: u64) -> (U256, U256) {
+    match U256::from(now) >= release_time {
+        true => (U256::ZERO, held),
+        false => (held, U256::ZERO),
+    }
+}
+
+/// Wraps the crate's RPC client in a caller-chosen alloy provider stack
+type ProviderFactory = Arc<dyn Fn(RpcClient<BoxTransport>) -> DynProvider + Send + Sync>;
+
+/// Alloy provider supplied by the caller instead of the default one
+#[derive(Clone)]
+enum CustomProvider {
+    Provider(DynProvider),
+    Builder(ProviderFactory),
+}
+
+/// Any alloy provider stack behind one sized type, as the `sol!` contract
+/// instances take their provider by value or reference and `dyn Provider`
+/// can't be either. Calls are forwarded to the stack, so its own layers and
+/// overrides apply to all of them.
+#[derive(Clone)]
+struct DynProvider(Arc<dyn Provider>);
+
+impl DynProvider {
+    fn new(provider: impl Provider + 'static) -> Self {
+        Self(Arc::new(provider))
+    }
+}
+
+/// Forwards every method the stack can override; those requiring `Self: Sized`
+/// keep their default, which goes through `root()`
+#[async_trait]
+impl Provider for DynProvider {
+    fn root(&self) -> &RootProvider<BoxTransport, Ethereum> {
+        self.0.root()
+    }
+
+    fn get_accounts(&self) -> ProviderCall<BoxTransport, NoParams, Vec<Address>> {
+        self.0.get_accounts()
+    }
+
+    fn get_blob_base_fee(&self) -> ProviderCall<BoxTransport, NoParams, U128, u128> {
+        self.0.get_blob_base_fee()
+    }
+
+    fn get_block_number(&self) -> ProviderCall<BoxTransport, NoParams, U64, BlockNumber> {
+        self.0.get_block_number()
+    }
+
+    fn call<'req>(
+        &self,
+        tx: &'req TransactionRequest,
+    ) -> EthCall<'req, BoxTransport, Ethereum, Bytes> {
+        self.0.call(tx)
+    }
+
+    fn simulate<'req>(
+        &self,
+        payload: &'req SimulatePayload,
+    ) -> RpcWithBlock<BoxTransport, &'req SimulatePayload, Vec<SimulatedBlock<Block>>> {
+        self.0.simulate(payload)
+    }
+
+    fn get_chain_id(&self) -> ProviderCall<BoxTransport, NoParams, U64, u64> {
+        self.0.get_chain_id()
+    }
+
+    fn create_access_list<'a>(
+        &self,
+        request: &'a TransactionRequest,
+    ) -> RpcWithBlock<BoxTransport, &'a TransactionRequest, AccessListResult> {
+        self.0.create_access_list(request)
+    }
+
+    fn estimate_gas<'req>(
+        &self,
+        tx: &'req TransactionRequest,
+    ) -> EthCall<'req, BoxTransport, Ethereum, U64, u64> {
+        self.0.estimate_gas(tx)
+    }
+
+    async fn estimate_eip1559_fees(
+        &self,
+        estimator: Option<EstimatorFunction>,
+    ) -> TransportResult<Eip1559Estimation> {
+        self.0.estimate_eip1559_fees(estimator).await
+    }
+
+    async fn get_fee_history(
+        &self,
+        block_count: u64,
+        last_block: BlockNumberOrTag,
+        reward_percentiles: &[f64],
+    ) -> TransportResult<FeeHistory> {
+        self.0
+            .get_fee_history(block_count, last_block, reward_percentiles)
+            .await
+    }
+
+    fn get_gas_price(&self) -> ProviderCall<BoxTransport, NoParams, U128, u128> {
+        self.0.get_gas_price()
+    }
+
+    fn get_account(&self, address: Address) -> RpcWithBlock<BoxTransport, Address, Account> {
+        self.0.get_account(address)
+    }
+
+    fn get_balance(&self, address: Address) -> RpcWithBlock<BoxTransport, Address, U256, U256> {
+        self.0.get_balance(address)
+    }
+
+    async fn get_block(
+        &self,
+        block: BlockId,
+        kind: BlockTransactionsKind,
+    ) -> TransportResult<Option<Block>> {
+        self.0.get_block(block, kind).await
+    }
+
+    async fn get_block_by_hash(
+        &self,
+        hash: BlockHash,
+        kind: BlockTransactionsKind,
+    ) -> TransportResult<Option<Block>> {
+        self.0.get_block_by_hash(hash, kind).await
+    }
+
+    async fn get_block_by_number(
+        &self,
+        number: BlockNumberOrTag,
+        kind: BlockTransactionsKind,
+    ) -> TransportResult<Option<Block>> {
+        self.0.get_block_by_number(number, kind).await
+    }
+
+    async fn get_block_transaction_count_by_hash(
+        &self,
+        hash: BlockHash,
+    ) -> TransportResult<Option<u64>> {
+        self.0.get_block_transaction_count_by_hash(hash).await
+    }
+
+    async fn get_block_transaction_count_by_number(
+        &self,
+        block_number: BlockNumberOrTag,
+    ) -> TransportResult<Option<u64>> {
+        self.0
+            .get_block_transaction_count_by_number(block_number)
+            .await
+    }
+
+    fn get_block_receipts(
+        &self,
+        block: BlockId,
+    ) -> ProviderCall<BoxTransport, (BlockId,), Option<Vec<TransactionReceipt>>> {
+        self.0.get_block_receipts(block)
+    }
+
+    fn get_code_at(&self, address: Address) -> RpcWithBlock<BoxTransport, Address, Bytes> {
+        self.0.get_code_at(address)
+    }
+
+    async fn watch_blocks(&self) -> TransportResult<FilterPollerBuilder<BoxTransport, B256>> {
+        self.0.watch_blocks().await
+    }
+
+    async fn watch_pending_transactions(
+        &self,
+    ) -> TransportResult<FilterPollerBuilder<BoxTransport, B256>> {
+        self.0.watch_pending_transactions().await
+    }
+
+    async fn watch_logs(
+        &self,
+        filter: &Filter,
+    ) -> TransportResult<FilterPollerBuilder<BoxTransport, Log>> {
+        self.0.watch_logs(filter).await
+    }
+
+    async fn watch_full_pending_transactions(
+        &self,
+    ) -> TransportResult<FilterPollerBuilder<BoxTransport, Transaction>> {
+        self.0.watch_full_pending_transactions().await
+    }
+
+    async fn get_filter_changes_dyn(&self, id: U256) -> TransportResult<FilterChanges> {
+        self.0.get_filter_changes_dyn(id).await
+    }
+
+    async fn get_filter_logs(&self, id: U256) -> TransportResult<Vec<Log>> {
+        self.0.get_filter_logs(id).await
+    }
+
+    async fn uninstall_filter(&self, id: U256) -> TransportResult<bool> {
+        self.0.uninstall_filter(id).await
+    }
+
+    async fn watch_pending_transaction(
+        &self,
+        config: PendingTransactionConfig,
+    ) -> Result<PendingTransaction, PendingTransactionError> {
+        self.0.watch_pending_transaction(config).await
+    }
+
+    async fn get_logs(&self, filter: &Filter) -> TransportResult<Vec<Log>> {
+        self.0.get_logs(filter).await
+    }
+
+    fn get_proof(
+        &self,
+        address: Address,
+        keys: Vec<StorageKey>,
+    ) -> RpcWithBlock<BoxTransport, (Address, Vec<StorageKey>), EIP1186AccountProofResponse> {
+        self.0.get_proof(address, keys)
+    }
+
+    fn get_storage_at(
+        &self,
+        address: Address,
+        key: U256,
+    ) -> RpcWithBlock<BoxTransport, (Address, U256), StorageValue> {
+        self.0.get_storage_at(address, key)
+    }
+
+    fn get_transaction_by_hash(
+        &self,
+        hash: TxHash,
+    ) -> ProviderCall<BoxTransport, (TxHash,), Option<Transaction>> {
+        self.0.get_transaction_by_hash(hash)
+    }
+
+    fn get_transaction_by_block_hash_and_index(
+        &self,
+        block_hash: B256,
+        index: usize,
+    ) -> ProviderCall<BoxTransport, (B256, Index), Option<Transaction>> {
+        self.0
+            .get_transaction_by_block_hash_and_index(block_hash, index)
+    }
+
+    fn get_raw_transaction_by_block_hash_and_index(
+        &self,
+        block_hash: B256,
+        index: usize,
+    ) -> ProviderCall<BoxTransport, (B256, Index), Option<Bytes>> {
+        self.0
+            .get_raw_transaction_by_block_hash_and_index(block_hash, index)
+    }
+
+    fn get_transaction_by_block_number_and_index(
+        &self,
+        block_number: BlockNumberOrTag,
+        index: usize,
+    ) -> ProviderCall<BoxTransport, (BlockNumberOrTag, Index), Option<Transaction>> {
+        self.0
+            .get_transaction_by_block_number_and_index(block_number, index)
+    }
+
+    fn get_raw_transaction_by_block_number_and_index(
+        &self,
+        block_number: BlockNumberOrTag,
+        index: usize,
+    ) -> ProviderCall<BoxTransport, (BlockNumberOrTag, Index), Option<Bytes>> {
+        self.0
+            .get_raw_transaction_by_block_number_and_index(block_number, index)
+    }
+
+    fn get_raw_transaction_by_hash(
+        &self,
+        hash: TxHash,
+    ) -> ProviderCall<BoxTransport, (TxHash,), Option<Bytes>> {
+        self.0.get_raw_transaction_by_hash(hash)
+    }
+
+    fn get_transaction_count(
+        &self,
+        address: Address,
+    ) -> RpcWithBlock<BoxTransport, Address, U64, u64, fn(U64) -> u64> {
+        self.0.get_transaction_count(address)
+    }
+
+    fn get_transaction_receipt(
+        &self,
+        hash: TxHash,
+    ) -> ProviderCall<BoxTransport, (TxHash,), Option<TransactionReceipt>> {
+        self.0.get_transaction_receipt(hash)
+    }
+
+    async fn get_uncle(&self, tag: BlockId, idx: u64) -> TransportResult<Option<Block>> {
+        self.0.get_uncle(tag, idx).await
+    }
+
+    async fn get_uncle_count(&self, tag: BlockId) -> TransportResult<u64> {
+        self.0.get_uncle_count(tag).await
+    }
+
+    fn get_max_priority_fee_per_gas(&self) -> ProviderCall<BoxTransport, NoParams, U128, u128> {
+        self.0.get_max_priority_fee_per_gas()
+    }
+
+    async fn new_block_filter(&self) -> TransportResult<U256> {
+        self.0.new_block_filter().await
+    }
+
+    async fn new_filter(&self, filter: &Filter) -> TransportResult<U256> {
+        self.0.new_filter(filter).await
+    }
+
+    async fn new_pending_transactions_filter(&self, full: bool) -> TransportResult<U256> {
+        self.0.new_pending_transactions_filter(full).await
+    }
+
+    async fn send_raw_transaction(
+        &self,
+        encoded_tx: &[u8],
+    ) -> TransportResult<PendingTransactionBuilder<BoxTransport, Ethereum>> {
+        self.0.send_raw_transaction(encoded_tx).await
+    }
+
+    async fn send_transaction(
+        &self,
+        tx: TransactionRequest,
+    ) -> TransportResult<PendingTransactionBuilder<BoxTransport, Ethereum>> {
+        self.0.send_transaction(tx).await
+    }
+
+    async fn send_tx_envelope(
+        &self,
+        tx: TxEnvelope,
+    ) -> TransportResult<PendingTransactionBuilder<BoxTransport, Ethereum>> {
+        self.0.send_tx_envelope(tx).await
+    }
+
+    async fn send_transaction_internal(
+        &self,
+        tx: SendableTx<Ethereum>,
+    ) -> TransportResult<PendingTransactionBuilder<BoxTransport, Ethereum>> {
+        self.0.send_transaction_internal(tx).await
+    }
+
+    async fn subscribe_blocks(&self) -> TransportResult<Subscription<Header>> {
+        self.0.subscribe_blocks().await
+    }
+
+    async fn subscribe_pending_transactions(&self) -> TransportResult<Subscription<B256>> {
+        self.0.subscribe_pending_transactions().await
+    }
+
+    async fn subscribe_full_pending_transactions(
+        &self,
+    ) -> TransportResult<Subscription<Transaction>> {
+        self.0.subscribe_full_pending_transactions().await
+    }
+
+    async fn subscribe_logs(&self, filter: &Filter) -> TransportResult<Subscription<Log>> {
+        self.0.subscribe_logs(filter).await
+    }
+
+    async fn unsubscribe(&self, id: B256) -> TransportResult<()> {
+        self.0.unsubscribe(id).await
+    }
+
+    fn syncing(&self) -> ProviderCall<BoxTransport, NoParams, SyncStatus> {
+        self.0.syncing()
+    }
+
+    fn get_client_version(&self) -> ProviderCall<BoxTransport, NoParams, String> {
+        self.0.get_client_version()
+    }
+
+    fn get_sha3(&self, data: &[u8]) -> ProviderCall<BoxTransport, (String,), B256> {
+        self.0.get_sha3(data)
+    }
+
+    fn get_net_version(&self) -> ProviderCall<BoxTransport, NoParams, U64, u64> {
+        self.0.get_net_version()
+    }
+
+    async fn raw_request_dyn(
+        &self,
+        method: Cow<'static, str>,
+        params: &RawValue,
+    ) -> TransportResult<Box<RawValue>> {
+        self.0.raw_request_dyn(method, params).await
+    }
+
+    fn transaction_request(&self) -> TransactionRequest {
+        self.0.transaction_request()
+    }
+}
+
+/// Ethereum chain provider using JSON-RPC, over HTTP or, for ws:// and
+/// wss:// URLs, over WebSocket. A WebSocket provider has the same timeout,
+/// retries, method names and in-flight limit, but no fallback endpoints or
+/// request ID.
+#[derive(Clone)]
 pub struct EthereumProvider {
-    rpc_url: String,
+    endpoints: Arc<RpcEndpoints>,
+    request_id: Option<RequestId>,
+    http: HttpConfig,
+    block_tag: BlockTag,
+    rpc_methods: Arc<HashMap<String, String>>,
+    retry: RetryPolicy,
+    timeout: Duration,
+    /// ENS names already resolved and when, shared by clones
+    ens_names: Arc<Mutex<HashMap<String, (Address, Instant)>>>,
+    /// ERC-20 decimals already read, shared by clones; they never change
+    decimals: Arc<Mutex<HashMap<Address, u8>>>,
+    // Built on first use and shared by every call so connections are pooled
+    client: OnceLock<reqwest::Client>,
+    /// Limits HTTP requests in flight, shared by clones
+    in_flight: Arc<Semaphore>,
+    /// The alloy provider every call goes through, built on first use and
+    /// shared with clones; any setting it depends on resets it. For a ws://
+    /// or wss:// RPC URL it holds the one WebSocket connection.
+    provider: Arc<tokio::sync::OnceCell<DynProvider>>,
+    custom: Option<CustomProvider>,
+    chain_name: String,
+    native_symbol: String,
 }
 
-impl EthereumProvider {
-    pub fn new(rpc_url: String) -> Self {
-        Self { rpc_url }
+impl EthereumProvider {
+    /// Provider for `rpc_url`, which is only parsed once the first call
+    /// builds the alloy provider; a malformed URL fails that call and every
+    /// later one
+    pub fn new(rpc_url: String) -> Self {
+        Self {
+            endpoints: Arc::new(RpcEndpoints::new(vec![rpc_url])),
+            request_id: None,
+            http: HttpConfig::default(),
+            block_tag: BlockTag::default(),
+            rpc_methods: Arc::default(),
+            retry: RetryPolicy::default(),
+            timeout: DEFAULT_RPC_TIMEOUT,
+            ens_names: Arc::default(),
+            decimals: Arc::default(),
+            client: OnceLock::new(),
+            in_flight: HttpConfig::default().in_flight_limiter(),
+            provider: Arc::default(),
+            custom: None,
+            chain_name: "Ethereum".to_string(),
+            native_symbol: "ETH".to_string(),
+        }
+    }
+
+    pub fn new_sepolia() -> Self {
+        // Using public Sepolia RPC endpoint
+        Self::new("https://ethereum-sepolia-rpc.publicnode.com".to_string())
+            .with_chain_name("Ethereum Sepolia Testnet".to_string())
+    }
+
+    /// Ethereum mainnet on public RPCs, for quick interactive use
+    pub fn new_mainnet() -> Self {
+        Self::new_public(1, "Ethereum", "ETH")
+    }
+
+    /// Polygon PoS on public RPCs; the native coin is POL (formerly MATIC)
+    pub fn new_polygon() -> Self {
+        Self::new_public(137, "Polygon", "POL")
+    }
+
+    /// Arbitrum One on public RPCs
+    pub fn new_arbitrum() -> Self {
+        Self::new_public(42161, "Arbitrum One", "ETH")
+    }
+
+    /// OP Mainnet on public RPCs
+    pub fn new_optimism() -> Self {
+        Self::new_public(10, "Optimism", "ETH")
+    }
+
+    /// Base on public RPCs
+    pub fn new_base() -> Self {
+        Self::new_public(8453, "Base", "ETH")
+    }
+
+    /// Provider on the first of the built-in public RPCs of `chain_id`, the
+    /// others as fallbacks
+    fn new_public(chain_id: u64, name: &str, native_symbol: &str) -> Self {
+        let rpcs = public_rpcs(chain_id);
+        Self::new(rpcs[0].to_string())
+            .with_fallback_rpcs(rpcs[1..].iter().map(|rpc| rpc.to_string()).collect())
+            .with_chain_name(name.to_string())
+            .with_native_symbol(native_symbol.to_string())
+    }
+
+    /// Name `chain_name` reports; "Ethereum" by default
+    pub fn with_chain_name(mut self, name: String) -> Self {
+        self.chain_name = name;
+        self
+    }
+
+    /// Symbol `native_symbol` reports for chains whose native coin isn't
+    /// ETH, e.g. "POL" on Polygon
+    pub fn with_native_symbol(mut self, symbol: String) -> Self {
+        self.native_symbol = symbol;
+        self
+    }
+
+    /// Endpoints to move on to, in order, when the current one is down
+    /// (unreachable, erroring, timing out or rate limiting after retries).
+    /// The endpoint that answered stays in use for later calls. Unused when
+    /// the RPC URL is a WebSocket one.
+    pub fn with_fallback_rpcs(mut self, rpcs: Vec<String>) -> Self {
+        let primary = self.endpoints.urls()[0].clone();
+        let urls = std::iter::once(primary).chain(rpcs).collect();
+        self.endpoints = Arc::new(RpcEndpoints::new(urls));
+        self.provider = Arc::default();
+        self
+    }
+
+    /// The RPC URL and fallbacks, in the order they are tried
+    pub fn rpc_urls(&self) -> &[String] {
+        self.endpoints.urls()
+    }
+
+    /// The RPC URL calls currently go to
+    pub fn active_rpc(&self) -> &str {
+        self.endpoints.active()
+    }
+
+    /// Send the given correlation ID header with every RPC request
+    pub fn with_request_id(mut self, request_id: RequestId) -> Self {
+        self.request_id = Some(request_id);
+        self.client = OnceLock::new();
+        self.provider = Arc::default();
+        self
+    }
+
+    /// Tune the connection pool of the underlying HTTP client
+    pub fn with_http_config(mut self, http: HttpConfig) -> Self {
+        self.in_flight = http.in_flight_limiter();
+        self.http = http;
+        self.client = OnceLock::new();
+        self.provider = Arc::default();
+        self
+    }
+
+    /// Send standard JSON-RPC methods under the chain's own names, see
+    /// `ChainConfig::rpc_methods`
+    pub fn with_rpc_methods(mut self, rpc_methods: HashMap<String, String>) -> Self {
+        self.rpc_methods = Arc::new(rpc_methods);
+        self.provider = Arc::default();
+        self
+    }
+
+    /// Retry policy for transient RPC failures (rate limits, timeouts,
+    /// connection errors, temporarily unavailable endpoints); three attempts
+    /// by default, `RetryPolicy::none()` disables retrying
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self.provider = Arc::default();
+        self
+    }
+
+    /// Give up on an RPC call to an endpoint, retries included, after
+    /// `timeout` with `BalanceError::Timeout` (then moving on to the next
+    /// fallback, if any); `DEFAULT_RPC_TIMEOUT` by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.provider = Arc::default();
+        self
+    }
+
+    /// Send every call through an already configured alloy provider. The RPC
+    /// URLs, HTTP, request ID, ETag, retry and timeout settings of this provider are then unused.
+    pub fn with_provider(mut self, provider: impl Provider + 'static) -> Self {
+        self.custom = Some(CustomProvider::Provider(DynProvider::new(provider)));
+        self.provider = Arc::default();
+        self
+    }
+
+    /// Build the alloy provider from the crate's RPC client, which keeps the
+    /// HTTP, request ID, ETag, retry and timeout settings, e.g. to add fillers:
+    /// `|client| ProviderBuilder::new().with_recommended_fillers().on_client(client)`
+    pub fn with_provider_builder<P: Provider + 'static>(
+        mut self,
+        build: impl Fn(RpcClient<BoxTransport>) -> P + Send + Sync + 'static,
+    ) -> Self {
+        let factory: ProviderFactory = Arc::new(move |client| DynProvider::new(build(client)));
+        self.custom = Some(CustomProvider::Builder(factory));
+        self.provider = Arc::default();
+        self
+    }
+
+    /// Read native and token balances at `block_tag` instead of `latest`
+    pub fn with_block_tag(mut self, block_tag: BlockTag) -> Self {
+        self.block_tag = block_tag;
+        self
+    }
+
+    /// GraphQL indexer on `url` with this provider's request ID, HTTP
+    /// settings, timeout and in-flight limit
+    pub(crate) fn graphql_indexer(&self, url: String, query: String) -> GraphQlProvider {
+        let mut indexer = GraphQlProvider::new(url, query)
+            .with_http_config(self.http.clone())
+            .with_timeout(self.timeout)
+            .with_in_flight(self.in_flight.clone());
+        if let Some(request_id) = &self.request_id {
+            indexer = indexer.with_request_id(request_id.clone());
+        }
+        indexer
+    }
+
+    fn block_id(&self) -> BlockId {
+        self.block_number_or_tag().into()
+    }
+
+    fn block_number_or_tag(&self) -> BlockNumberOrTag {
+        match self.block_tag {
+            BlockTag::Latest => BlockNumberOrTag::Latest,
+            BlockTag::Pending => BlockNumberOrTag::Pending,
+            BlockTag::Safe => BlockNumberOrTag::Safe,
+            BlockTag::Finalized => BlockNumberOrTag::Finalized,
+            BlockTag::Number(number) => BlockNumberOrTag::Number(number),
+        }
+    }
+
+    /// Latest block number, used as a connectivity check
+    pub async fn block_number(&self) -> Result<u64> {
+        let provider = self.connect().await?;
+        Ok(provider.get_block_number().await?)
+    }
+
+    /// Number and Unix timestamp of the block balances are read at
+    pub async fn block_timestamp(&self) -> Result<(u64, u64)> {
+        let provider = self.connect().await?;
+        let block = provider
+            .get_block_by_number(self.block_number_or_tag(), BlockTransactionsKind::Hashes)
+            .await?
+            .ok_or_else(|| anyhow!("Block {} not found", self.block_tag))?;
+        Ok((block.header.number, block.header.timestamp))
     }
 
-    pub fn new_sepolia() -> Self {
-        Self {
-            // Using public Sepolia RPC endpoint
-            rpc_url: "https://ethereum-sepolia-rpc.publicnode.com".to_string(),
+    /// Send a raw JSON-RPC request, e.g. a node-specific `anvil_*` method
+    pub(crate) async fn request(
+        &self,
+        method: &'static str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let provider = self.connect().await?;
+        Ok(provider.client().request(method, params).await?)
+    }
+
+    /// Whether contract code is deployed at `address`
+    pub async fn has_code(&self, address: &str) -> Result<bool> {
+        let provider = self.connect().await?;
+        let addr: Address = address.parse()?;
+        let code = provider.get_code_at(addr).await?;
+        Ok(!code.is_empty())
+    }
+
+    /// Address an ENS name (e.g. `vitalik.eth`) currently resolves to, through
+    /// the name's resolver in the ENS registry. Fails clearly on chains
+    /// without ENS and on names that are unregistered or have no address set.
+    /// A name resolved in the last five minutes isn't looked up again.
+    pub async fn resolve_ens(&self, name: &str) -> Result<Address> {
+        let key = name.to_lowercase();
+        match self.ens_names.lock().unwrap().get(&key) {
+            Some((address, resolved)) if resolved.elapsed() < ENS_CACHE_TTL => return Ok(*address),
+            _ => {}
+        }
+
+        let provider = self.connect().await?;
+        let registry: Address = ENS_REGISTRY_ADDRESS.parse()?;
+        if provider.get_code_at(registry).await?.is_empty() {
+            return Err(anyhow!(
+                "Cannot resolve '{}': this chain has no ENS registry, use a hex address",
+                name
+            ));
+        }
+
+        let node = namehash(name);
+        let resolver = IENSRegistry::new(registry, provider.clone())
+            .resolver(node)
+            .call()
+            .await?
+            ._0;
+        if resolver.is_zero() {
+            return Err(anyhow!("ENS name '{}' is not registered", name));
+        }
+        let address = IENSResolver::new(resolver, provider)
+            .addr(node)
+            .call()
+            .await?
+            ._0;
+        if address.is_zero() {
+            return Err(anyhow!("ENS name '{}' has no address set", name));
         }
+
+        self.ens_names
+            .lock()
+            .unwrap()
+            .insert(key, (address, Instant::now()));
+        Ok(address)
     }
-}
 
-#[async_trait]
-impl ChainProvider for EthereumProvider {
-    async fn get_native_balance(&self, address: &str) -> Result<Balance> {
-        let provider = ProviderBuilder::new().on_http(self.rpc_url.parse()?);
+    /// `address` as a hex address, resolving it first if it's an ENS name
+    pub async fn resolve_address(&self, address: &str) -> Result<String> {
+        match is_ens_name(address) {
+            true => Ok(self.resolve_ens(address).await?.to_string()),
+            false => Ok(address.to_string()),
+        }
+    }
+
+    /// An ERC-20 token's `decimals()`, read from chain once per token and
+    /// then reused, also by clones of this provider
+    pub async fn token_decimals(&self, token_address: &str) -> Result<u8> {
+        let token_addr: Address = token_address.parse()?;
+        if let Some(decimals) = self.decimals.lock().unwrap().get(&token_addr) {
+            return Ok(*decimals);
+        }
+
+        let provider = self.connect().await?;
+        let decimals = IERC20::new(token_addr, provider)
+            .decimals()
+            .call()
+            .await?
+            ._0;
+        self.decimals.lock().unwrap().insert(token_addr, decimals);
+        Ok(decimals)
+    }
+
+    /// Read an ERC-20 token's `symbol()` and `decimals()` from chain
+    pub async fn fetch_token_metadata(&self, token_address: &str) -> Result<(String, u8)> {
+        let provider = self.connect().await?;
+        let token_addr: Address = token_address.parse()?;
+
+        let contract = IERC20::new(token_addr, provider);
+        let symbol = contract.symbol().call().await?._0;
+        let decimals = contract.decimals().call().await?._0;
+
+        Ok((symbol, decimals))
+    }
+
+    /// Underlying balances of a Uniswap V2 LP position: the holder's share of
+    /// each pair reserve, reported as `LP_SYMBOL:TOKEN_SYMBOL` for token0 then
+    /// token1. Pairs without a `getReserves` (it reverts or returns nothing)
+    /// are valued from the underlying tokens' `balanceOf(pair)` instead; any
+    /// other failure of the call is returned.
+    pub async fn get_lp_underlying_balances(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Vec<Balance>> {
+        let Token::UniV2Lp { pair, symbol } = token else {
+            return Err(anyhow!("{} is not an LP token", token.symbol()));
+        };
 
+        let provider = self.connect().await?;
         let addr: Address = address.parse()?;
-        let balance = provider
-            .get_balance(addr)
-            .block_id(BlockNumberOrTag::Latest.into())
-            .await?;
+        let pair_addr: Address = pair.parse()?;
+        let contract = IUniswapV2Pair::new(pair_addr, &provider);
+
+        let block = self.block_id();
+        let token0 = contract.token0().call().await?._0;
+        let token1 = contract.token1().call().await?._0;
+        let lp_balance = contract.balanceOf(addr).block(block).call().await?._0;
+        let total_supply = contract.totalSupply().block(block).call().await?._0;
+
+        let reserves = contract.getReserves().block(block).call().await;
+        let reserves = match reserves.map_err(anyhow::Error::from) {
+            Ok(reserves) => [U256::from(reserves.reserve0), U256::from(reserves.reserve1)],
+            Err(e) if is_missing_function(&e) => [
+                IERC20::new(token0, &provider)
+                    .balanceOf(pair_addr)
+                    .block(block)
+                    .call()
+                    .await?
+                    ._0,
+                IERC20::new(token1, &provider)
+                    .balanceOf(pair_addr)
+                    .block(block)
+                    .call()
+                    .await?
+                    ._0,
+            ],
+            Err(e) => return Err(e),
+        };
+
+        let mut balances = Vec::with_capacity(2);
+        for (underlying, reserve) in [token0, token1].into_iter().zip(reserves) {
+            let (underlying_symbol, decimals) =
+                self.fetch_token_metadata(&underlying.to_string()).await?;
+            let share = lp_share(reserve, lp_balance, total_supply)?;
+            balances.push(Balance::new(
+                format!("{}:{}", symbol, underlying_symbol),
+                share.to_string(),
+                decimals,
+            )?);
+        }
 
-        Ok(Balance::new("ETH".to_string(), balance.to_string(), 18))
+        Ok(balances)
     }
 
-    async fn get_token_balance(&self, address: &str, token: &Token) -> Result<Balance> {
+    /// Locked and available amounts of an ERC-20 `token` held for `address`
+    /// by a vesting contract, reported as `SYMBOL:locked` and
+    /// `SYMBOL:available`. Both are zero when `address` isn't the contract's
+    /// beneficiary. Timelocks are compared against the timestamp of the block
+    /// balances are read at, so a historical block tag sees them as they were.
+    pub async fn get_vesting_balances(
+        &self,
+        address: &str,
+        token: &Token,
+        vesting: &VestingContract,
+    ) -> Result<Vec<Balance>> {
         let Token::Erc20 {
             address: token_address,
             symbol,
             decimals,
-        } = token;
-
-        let provider = ProviderBuilder::new().on_http(self.rpc_url.parse()?);
+        } = token
+        else {
+            return Err(anyhow!("Vesting is only supported for ERC-20 tokens"));
+        };
 
+        let provider = self.connect().await?;
         let addr: Address = address.parse()?;
         let token_addr: Address = token_address.parse()?;
+        let contract_addr: Address = vesting.contract.parse()?;
+        let block = self.block_id();
 
-        let contract = IERC20::new(token_addr, provider);
-        let balance: U256 = contract.balanceOf(addr).call().await?._0;
+        let held = IERC20::new(token_addr, &provider)
+            .balanceOf(contract_addr)
+            .block(block)
+            .call()
+            .await?
+            ._0;
+        let (beneficiary, (locked, available)) = match vesting.kind {
+            VestingKind::VestingWallet => {
+                let wallet = IVestingWallet::new(contract_addr, &provider);
+                // OpenZeppelin 5 wallets have an `owner()`, older ones a `beneficiary()`
+                let owner = wallet.owner().block(block).call().await;
+                let beneficiary = match owner.map_err(anyhow::Error::from) {
+                    Ok(owner) => owner._0,
+                    Err(e) if is_missing_function(&e) => {
+                        wallet.beneficiary().block(block).call().await?._0
+                    }
+                    Err(e) => return Err(e),
+                };
+                let releasable = wallet.releasable(token_addr).block(block).call().await?._0;
+                let available = releasable.min(held);
+                (beneficiary, (held - available, available))
+            }
+            VestingKind::TokenTimelock => {
+                let timelock = ITokenTimelock::new(contract_addr, &provider);
+                let beneficiary = timelock.beneficiary().block(block).call().await?._0;
+                let release_time = timelock.releaseTime().block(block).call().await?._0;
+                let (_, now) = self.block_timestamp().await?;
+                (beneficiary, timelock_split(held, release_time, now))
+            }
+        };
+
+        let (locked, available) = match beneficiary == addr {
+            true => (locked, available),
+            false => (U256::ZERO, U256::ZERO),
+        };
+        Ok(vec![
+            Balance::new(format!("{}:locked", symbol), locked.to_string(), *decimals)?,
+            Balance::new(
+                format!("{}:available", symbol),
+                available.to_string(),
+                *decimals,
+            )?,
+        ])
+    }
+
+    /// Number of NFTs `address` holds of an ERC-721 collection (`balanceOf(owner)`)
+    /// or of one ERC-1155 token ID (`balanceOf(owner, id)`), as a 0-decimal
+    /// `Balance` of kind `Nft`. Fails for fungible tokens.
+    pub async fn get_nft_balance(&self, address: &str, token: &Token) -> Result<Balance> {
+        if !token.is_nft() {
+            return Err(anyhow!(
+                "{} is not an ERC-721 or ERC-1155 token",
+                token.symbol()
+            ));
+        }
+        Ok(self.get_token_balance(address, token).await?)
+    }
+
+    /// Latest answer of a Chainlink price feed as `(answer, decimals)`;
+    /// the price is `answer / 10^decimals`
+    pub async fn read_price_feed(&self, feed_address: &str) -> Result<(String, u8)> {
+        let provider = self.connect().await?;
+        let feed_addr: Address = feed_address.parse()?;
+
+        let feed = IAggregatorV3::new(feed_addr, provider);
+        let decimals = feed.decimals().call().await?._0;
+        let answer = feed.latestRoundData().call().await?.answer;
+        if !answer.is_positive() {
+            return Err(anyhow!(
+                "Price feed {} returned non-positive answer {}",
+                feed_address,
+                answer
+            ));
+        }
+
+        Ok((answer.to_string(), decimals))
+    }
+
+    /// Native balance as of `block` instead of the configured block tag, e.g.
+    /// for accounting snapshots at a past block (needs an archive node)
+    pub async fn get_native_balance_at(
+        &self,
+        address: &str,
+        block: BlockNumberOrTag,
+    ) -> Result<Balance> {
+        let provider = self.connect().await?;
+
+        let addr: Address = self.resolve_address(address).await?.parse()?;
+        let balance = provider.get_balance(addr).block_id(block.into()).await?;
+
+        Ok(Balance {
+            block: block.as_number(),
+            ..native_balance(&self.native_symbol, balance)
+        })
+    }
+
+    /// Token balance as of `block`, passed as the block id of the `balanceOf` call
+    pub async fn get_token_balance_at(
+        &self,
+        address: &str,
+        token: &Token,
+        block: BlockNumberOrTag,
+    ) -> Result<Balance> {
+        let provider = self.connect().await?;
+        let addr: Address = self.resolve_address(address).await?.parse()?;
+
+        let balance: U256 = match token {
+            Token::Erc20 { address, .. } | Token::Erc721 { address, .. } => {
+                let contract = IERC20::new(address.parse()?, provider);
+                contract
+                    .balanceOf(addr)
+                    .block(block.into())
+                    .call()
+                    .await?
+                    ._0
+            }
+            Token::Erc1155 { address, id, .. } => {
+                let contract = IERC1155::new(address.parse()?, provider);
+                contract
+                    .balanceOf(addr, id.parse()?)
+                    .block(block.into())
+                    .call()
+                    .await?
+                    ._0
+            }
+            Token::Native { .. } => provider.get_balance(addr).block_id(block.into()).await?,
+            other => return Err(unsupported_token(other)),
+        };
+
+        Ok(Balance {
+            block: block.as_number(),
+            ..Balance::for_token(token, balance.to_string())?
+        })
+    }
+
+    /// The native balance of `address` now, then again after each new block
+    /// that changes it, each with the block it was read at. Blocks arrive
+    /// over an `eth_subscribe` subscription, so the RPC URL must be a ws://
+    /// or wss:// one. A block whose balance can't be read is skipped with a
+    /// warning; the stream ends when the subscription does.
+    pub async fn watch_native_balance(&self, address: &str) -> Result<impl Stream<Item = Balance>> {
+        let provider = self.connect().await?;
+        let addr: Address = self.resolve_address(address).await?.parse()?;
+        // Subscriptions need the bare pubsub client, which `connect` wraps
+        let url = &self.endpoints.urls()[0];
+        let subscriber = match &self.custom {
+            None if is_ws_url(url) => {
+                let client = ClientBuilder::default().ws(WsConnect::new(url)).await?;
+                DynProvider::new(ProviderBuilder::new().on_client(client.boxed()))
+            }
+            _ => provider.clone(),
+        };
+
+        // Subscribe first so no block is missed between the two calls
+        let headers = Box::pin(subscriber.subscribe_blocks().await?.into_stream());
+        let head = provider.get_block_number().await?;
+        let current = provider.get_balance(addr).block_id(head.into()).await?;
+        let symbol = self.native_symbol.clone();
+        let first = Balance {
+            block: Some(head),
+            ..native_balance(&symbol, current)
+        };
+
+        let changes = futures::stream::unfold(
+            (provider, subscriber, headers, current),
+            move |(provider, subscriber, mut headers, mut last)| {
+                let symbol = symbol.clone();
+                async move {
+                    while let Some(header) = headers.next().await {
+                        let balance = provider
+                            .get_balance(addr)
+                            .block_id(header.number.into())
+                            .await;
+                        match balance {
+                            Ok(wei) if wei != last => {
+                                last = wei;
+                                let balance = Balance {
+                                    block: Some(header.number),
+                                    ..native_balance(&symbol, wei)
+                                };
+                                return Some((balance, (provider, subscriber, headers, last)));
+                            }
+                            Ok(_) => {}
+                            Err(e) => tracing::warn!(
+                                block = header.number,
+                                error = %e,
+                                "Skipping block, its balance couldn't be read"
+                            ),
+                        }
+                    }
+                    None
+                }
+            },
+        );
+        Ok(futures::stream::once(async move { first }).chain(changes))
+    }
+
+    /// Native and token balances (native first) in a single Multicall3
+    /// `aggregate3` request, reading the native balance via `getEthBalance`.
+    /// Each entry fails independently; falls back to one request per balance
+    /// when Multicall3 isn't deployed on the chain.
+    pub async fn get_all_balances_multicall(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Result<Balance>>> {
+        let (native, readings, block) = self.read_balances(address, tokens, false).await?;
+        let mut balances = vec![native];
+        for (token, reading) in tokens.iter().zip(readings) {
+            balances.push(
+                reading
+                    .amount
+                    .and_then(|amount| Balance::for_token(token, amount))
+                    .map(|balance| Balance { block, ..balance }),
+            );
+        }
+        Ok(balances)
+    }
+
+    /// Token balances only, in the order of `tokens`, from the same single
+    /// Multicall3 request (one request per token without Multicall3). Unlike
+    /// `get_all_balances_multicall`, fails if any balance can't be read.
+    pub async fn get_token_balances_multicall(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Balance>> {
+        let (_, readings, block) = self.read_balances(address, tokens, false).await?;
+        tokens
+            .iter()
+            .zip(readings)
+            .map(|(token, reading)| {
+                let balance = Balance::for_token(token, reading.amount?)?;
+                Ok(Balance { block, ..balance })
+            })
+            .collect()
+    }
+
+    /// `get_all_balances_multicall` that also reads each ERC-20's `symbol()`
+    /// and `decimals()` in the same request. Balances carry the on-chain
+    /// symbol and decimals, or the token's configured ones where those revert.
+    pub async fn get_all_balances_with_metadata(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Result<Balance>>> {
+        let (native, readings, block) = self.read_balances(address, tokens, true).await?;
+        let mut balances = vec![native];
+        for (token, reading) in tokens.iter().zip(readings) {
+            let TokenReading {
+                amount,
+                symbol,
+                decimals,
+            } = reading;
+            let balance = amount.and_then(|amount| match has_metadata(token) {
+                true => Balance::new(
+                    symbol.unwrap_or_else(|| token.symbol().to_string()),
+                    amount,
+                    decimals.unwrap_or(token.decimals()),
+                ),
+                false => Balance::for_token(token, amount),
+            });
+            balances.push(balance.map(|balance| Balance { block, ..balance }));
+        }
+        Ok(balances)
+    }
+
+    /// Native balance and one reading per token from one `aggregate3` call,
+    /// with ERC-20 metadata if `with_metadata`, and the number of the block
+    /// they were read at (`None` when read one by one at a block tag).
+    /// `address` may be an ENS name.
+    pub(crate) async fn read_balances(
+        &self,
+        address: &str,
+        tokens: &[Token],
+        with_metadata: bool,
+    ) -> Result<(Result<Balance>, Vec<TokenReading>, Option<u64>)> {
+        let address = &self.resolve_address(address).await?;
+        let provider = self.connect().await?;
+
+        let addr: Address = address.parse()?;
+        let multicall_addr: Address = MULTICALL3_ADDRESS.parse()?;
+        let calls = multicall_calls(addr, tokens, with_metadata)?;
+
+        let multicall = IMulticall3::new(multicall_addr, &provider);
+        let results = match multicall
+            .aggregate3(calls)
+            .block(self.block_id())
+            .call()
+            .await
+        {
+            Ok(response) => response.returnData,
+            // Empty return data overruns the decoder: no Multicall3 contract
+            // on this chain
+            Err(alloy::contract::Error::AbiError(alloy::dyn_abi::Error::SolTypes(
+                alloy::sol_types::Error::Overrun,
+            ))) => {
+                return Ok(self
+                    .read_balances_individually(address, tokens, with_metadata)
+                    .await)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut results = results.into_iter();
+        let native = decode_native(&self.native_symbol, results.next());
+        let mut readings = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let amount = decode_amount(token, results.next());
+            let (symbol, decimals) = match with_metadata && has_metadata(token) {
+                true => (
+                    decode_optional::<IERC20::symbolCall>(results.next()).map(|symbol| symbol._0),
+                    decode_optional::<IERC20::decimalsCall>(results.next())
+                        .map(|decimals| decimals._0),
+                ),
+                false => (None, None),
+            };
+            readings.push(TokenReading {
+                amount,
+                symbol,
+                decimals,
+            });
+        }
+        let block = decode_block_number(results.next());
+        let native = native.map(|native| Balance { block, ..native });
+
+        Ok((native, readings, block))
+    }
+
+    async fn read_balances_individually(
+        &self,
+        address: &str,
+        tokens: &[Token],
+        with_metadata: bool,
+    ) -> (Result<Balance>, Vec<TokenReading>, Option<u64>) {
+        // The native and every token read concurrently, readings in token order
+        let native = self.get_native_balance_at(address, self.block_number_or_tag());
+        let readings = futures::future::join_all(tokens.iter().map(|token| async move {
+            let amount = self
+                .get_token_balance_at(address, token, self.block_number_or_tag())
+                .await
+                .map(|balance| balance.amount);
+            let (symbol, decimals) = match with_metadata && has_metadata(token) {
+                true => self
+                    .fetch_token_metadata(token.address())
+                    .await
+                    .ok()
+                    .unzip(),
+                false => (None, None),
+            };
+            TokenReading {
+                amount,
+                symbol,
+                decimals,
+            }
+        }));
+        let (native, readings) = futures::future::join(native, readings).await;
+        let block = self.block_number_or_tag().as_number();
+        (native, readings, block)
+    }
+
+    /// The alloy provider, built by the first call and reused by all later
+    /// ones, including those of clones, instead of being rebuilt per request
+    async fn connect(&self) -> Result<DynProvider> {
+        let provider = self
+            .provider
+            .get_or_try_init(|| self.build_provider())
+            .await?;
+        Ok(provider.clone())
+    }
+
+    async fn build_provider(&self) -> Result<DynProvider> {
+        let build = match &self.custom {
+            Some(CustomProvider::Provider(provider)) => return Ok(provider.clone()),
+            Some(CustomProvider::Builder(build)) => Some(build),
+            None => None,
+        };
+
+        let url = &self.endpoints.urls()[0];
+        if is_ws_url(url) {
+            if self.endpoints.urls().len() > 1 {
+                return Err(BalanceError::Config {
+                    message: format!(
+                        "WebSocket RPC {} can't fail over to other endpoints, configure it alone",
+                        rpc_host(url)
+                    ),
+                }
+                .into());
+            }
+            if self.request_id.is_some() {
+                return Err(BalanceError::Config {
+                    message: format!(
+                        "Request IDs are sent as an HTTP header, which WebSocket RPC {} \
+                         doesn't carry",
+                        rpc_host(url)
+                    ),
+                }
+                .into());
+            }
+            let transport = WsTransport::connect(
+                url,
+                self.rpc_methods.clone(),
+                self.retry,
+                self.timeout,
+                self.in_flight.clone(),
+            )
+            .await?;
+            let client = RpcClient::new(transport, false).boxed();
+            return Ok(match build {
+                Some(build) => build(client),
+                None => DynProvider::new(ProviderBuilder::new().on_client(client)),
+            });
+        }
+
+        let client = match self.client.get() {
+            Some(client) => client.clone(),
+            None => {
+                let client = http::client_builder(self.request_id.as_ref(), &self.http)?.build()?;
+                self.client.get_or_init(|| client).clone()
+            }
+        };
+        for url in self.endpoints.urls() {
+            url.parse::<reqwest::Url>()?;
+        }
+        let transport = EtagTransport::new(
+            client,
+            self.endpoints.clone(),
+            self.http.etag_cache == Some(true),
+            self.rpc_methods.clone(),
+            self.retry,
+            self.timeout,
+            self.in_flight.clone(),
+        );
+        let client = RpcClient::new(transport, false).boxed();
+        Ok(match build {
+            Some(build) => build(client),
+            None => DynProvider::new(ProviderBuilder::new().on_client(client)),
+        })
+    }
+}
+
+#[async_trait]
+impl ChainProvider for EthereumProvider {
+    fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    fn native_symbol(&self) -> &str {
+        &self.native_symbol
+    }
+
+    async fn get_balances(
+        &self,
+        config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        Ok(crate::get_evm_balances(config, self, address, options).await?)
+    }
+
+    fn balance_stream<'a>(
+        &'a self,
+        config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        crate::stream_reads(crate::evm_balance_reads(config, self, address, options))
+    }
+
+    fn active_endpoint(&self) -> Option<&str> {
+        Some(self.active_rpc())
+    }
+
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        let read = self.get_native_balance_at(address, self.block_number_or_tag());
+        Ok(telemetry::balance_read(
+            &self.chain_name,
+            address,
+            &self.native_symbol,
+            self.request_id.as_ref(),
+            read,
+        )
+        .await?)
+    }
+
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        let read = self.get_token_balance_at(address, token, self.block_number_or_tag());
+        Ok(telemetry::balance_read(
+            &self.chain_name,
+            address,
+            token.symbol(),
+            self.request_id.as_ref(),
+            read,
+        )
+        .await?)
+    }
+
+    async fn get_all_balances(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Balance>, BalanceError> {
+        let read = async {
+            let (lp_tokens, tokens): (Vec<Token>, Vec<Token>) = tokens
+                .iter()
+                .cloned()
+                .partition(|token| matches!(token, Token::UniV2Lp { .. }));
+            let address = &self.resolve_address(address).await?;
 
-        Ok(Balance::new(symbol.clone(), balance.to_string(), *decimals))
+            let mut balances = self
+                .get_all_balances_multicall(address, &tokens)
+                .await?
+                .into_iter()
+                .collect::<Result<Vec<_>>>()?;
+            for lp_token in &lp_tokens {
+                balances.extend(self.get_lp_underlying_balances(address, lp_token).await?);
+            }
+            Ok::<_, anyhow::Error>(balances)
+        };
+        // One span for the whole batch, "*" standing for every token
+        Ok(telemetry::balance_read(
+            &self.chain_name,
+            address,
+            "*",
+            self.request_id.as_ref(),
+            read,
+        )
+        .await?)
     }
 }
 
@@ -71,7 +1767,454 @@ impl ChainProvider for EthereumProvider {
 mod tests {
     use super::*;
     use crate::chain::ChainProvider;
+    use crate::test_server;
     use crate::types::Token;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_is_revert_response() {
+        assert!(is_revert_response(3, "execution reverted"));
+        assert!(is_revert_response(
+            -32000,
+            "execution reverted: not a holder"
+        ));
+        assert!(!is_revert_response(-32005, "rate limit exceeded"));
+    }
+
+    #[test]
+    fn test_only_reverts_and_empty_returns_are_missing_functions() {
+        let reverted = CallReverted {
+            target: "0x0".to_string(),
+        };
+        assert!(is_missing_function(&reverted.into()));
+        let empty = alloy::contract::Error::AbiError(alloy::dyn_abi::Error::SolTypes(
+            alloy::sol_types::Error::Overrun,
+        ));
+        assert!(is_missing_function(&empty.into()));
+
+        let down = alloy::contract::Error::TransportError(
+            alloy::transports::TransportErrorKind::custom_str("connection refused"),
+        );
+        assert!(!is_missing_function(&down.into()));
+    }
+
+    #[test]
+    fn test_missing_multicall_overruns_the_decoder() {
+        // What `eth_call` returns for an address without code
+        assert!(matches!(
+            IMulticall3::aggregate3Call::abi_decode_returns(&[], true),
+            Err(alloy::sol_types::Error::Overrun)
+        ));
+    }
+
+    #[test]
+    fn test_namehash() {
+        // Vectors from EIP-137
+        assert_eq!(namehash(""), B256::ZERO);
+        assert_eq!(
+            namehash("eth").to_string(),
+            "0x93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+        );
+        assert_eq!(
+            namehash("foo.eth").to_string(),
+            "0xde9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+        );
+        assert_eq!(namehash("Foo.ETH"), namehash("foo.eth"));
+    }
+
+    #[test]
+    fn test_is_ens_name() {
+        assert!(is_ens_name("vitalik.eth"));
+        assert!(is_ens_name("pay.Vitalik.ETH"));
+        assert!(!is_ens_name(".eth"));
+        assert!(!is_ens_name("vitalik eth.eth"));
+        assert!(!is_ens_name("0x78697a9cfc48c1e9d1040172d51833ef78083b10"));
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_resolve_ens_without_registry() {
+        // Polygon has no ENS registry
+        let provider = EthereumProvider::new("https://polygon-rpc.com".to_string());
+        let error = provider.resolve_ens("vitalik.eth").await.unwrap_err();
+        assert!(error.to_string().contains("no ENS registry"));
+    }
+
+    #[test]
+    fn test_balance_call_per_standard() {
+        let owner: Address = "0x78697a9cfc48C1e9d1040172d51833EF78083b10"
+            .parse()
+            .unwrap();
+        let erc721 = Token::Erc721 {
+            address: "0xb47e3cd837dDF8e4c57F05d70Ab865de6e193BBB".to_string(),
+            symbol: "PUNK".to_string(),
+        };
+        let erc1155 = Token::Erc1155 {
+            address: "0x76BE3b62873462d2142405439777e971754E8E77".to_string(),
+            id: "10144".to_string(),
+            symbol: "BADGE".to_string(),
+        };
+
+        let (_, data) = balance_call(&erc721, owner).unwrap();
+        assert_eq!(data[..4], [0x70, 0xa0, 0x82, 0x31]);
+        let (_, data) = balance_call(&erc1155, owner).unwrap();
+        assert_eq!(data[..4], [0x00, 0xfd, 0xd5, 0x8e]);
+        assert_eq!(data.len(), 4 + 32 * 2);
+
+        let lp = Token::UniV2Lp {
+            pair: "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc".to_string(),
+            symbol: "UNI-V2".to_string(),
+        };
+        assert!(balance_call(&lp, owner).is_err());
+
+        let native = Token::Native {
+            symbol: "POL".to_string(),
+            decimals: 18,
+        };
+        let (target, data) = balance_call(&native, owner).unwrap();
+        assert_eq!(target, MULTICALL3_ADDRESS.parse::<Address>().unwrap());
+        assert_eq!(
+            data,
+            Bytes::from(IMulticall3::getEthBalanceCall { addr: owner }.abi_encode())
+        );
+
+        let spl = Token::Spl {
+            mint: "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU".to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+        let error = balance_call(&spl, owner).unwrap_err();
+        assert!(error.to_string().contains("SPL token"));
+    }
+
+    #[test]
+    fn test_multicall_calls_with_metadata() {
+        let owner: Address = "0x78697a9cfc48C1e9d1040172d51833EF78083b10"
+            .parse()
+            .unwrap();
+        let tokens = [
+            Token::Erc20 {
+                address: "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238".to_string(),
+                symbol: "USDC".to_string(),
+                decimals: 6,
+            },
+            Token::Erc721 {
+                address: "0x0000000000000000000000000000000000000721".to_string(),
+                symbol: "NFT".to_string(),
+            },
+        ];
+
+        assert_eq!(multicall_calls(owner, &tokens, false).unwrap().len(), 4);
+
+        // balanceOf, symbol and decimals for the ERC-20, balanceOf only for the
+        // NFT, then the block number
+        let calls = multicall_calls(owner, &tokens, true).unwrap();
+        assert_eq!(calls.len(), 6);
+        assert_eq!(
+            calls[2].callData,
+            Bytes::from(IERC20::symbolCall {}.abi_encode())
+        );
+        assert_eq!(
+            calls[3].callData,
+            Bytes::from(IERC20::decimalsCall {}.abi_encode())
+        );
+        assert_eq!(
+            calls[4].target,
+            "0x0000000000000000000000000000000000000721"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert_eq!(
+            calls[5].callData,
+            Bytes::from(IMulticall3::getBlockNumberCall {}.abi_encode())
+        );
+    }
+
+    #[test]
+    fn test_decode_optional_metadata() {
+        let decimals = IMulticall3::Result {
+            success: true,
+            returnData: IERC20::decimalsCall::abi_encode_returns(&(6u8,)).into(),
+        };
+        assert_eq!(
+            decode_optional::<IERC20::decimalsCall>(Some(decimals)).map(|d| d._0),
+            Some(6)
+        );
+
+        let reverted = IMulticall3::Result {
+            success: false,
+            returnData: Bytes::new(),
+        };
+        assert!(decode_optional::<IERC20::symbolCall>(Some(reverted)).is_none());
+        assert!(decode_optional::<IERC20::symbolCall>(None).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_nft_balance_rejects_fungible_tokens() {
+        let provider = EthereumProvider::new("http://127.0.0.1:1".to_string());
+        let usdc = Token::Erc20 {
+            address: "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238".to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+        let error = provider
+            .get_nft_balance("0x78697a9cfc48c1e9d1040172d51833ef78083b10", &usdc)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "USDC is not an ERC-721 or ERC-1155 token"
+        );
+    }
+
+    #[test]
+    fn test_decode_block_number() {
+        let block = IMulticall3::Result {
+            success: true,
+            returnData: IMulticall3::getBlockNumberCall::abi_encode_returns(&(U256::from(
+                7_000_000u64,
+            ),))
+            .into(),
+        };
+        assert_eq!(decode_block_number(Some(block)), Some(7_000_000));
+        assert_eq!(decode_block_number(None), None);
+    }
+
+    #[test]
+    fn test_lp_share() {
+        let share = lp_share(
+            U256::from(1_000_000u64),
+            U256::from(25u64),
+            U256::from(100u64),
+        );
+        assert_eq!(share.unwrap(), U256::from(250_000u64));
+        // Rounds down like the pair's own burn()
+        let share = lp_share(U256::from(10u64), U256::from(1u64), U256::from(3u64));
+        assert_eq!(share.unwrap(), U256::from(3u64));
+        assert_eq!(
+            lp_share(U256::from(10u64), U256::ZERO, U256::ZERO).unwrap(),
+            U256::ZERO
+        );
+        assert!(lp_share(U256::MAX, U256::from(2u64), U256::from(2u64)).is_err());
+    }
+
+    #[test]
+    fn test_timelock_split() {
+        let held = U256::from(500u64);
+        let release = U256::from(1_700_000_000u64);
+        assert_eq!(
+            timelock_split(held, release, 1_600_000_000),
+            (held, U256::ZERO)
+        );
+        assert_eq!(
+            timelock_split(held, release, 1_700_000_000),
+            (U256::ZERO, held)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_provider_builder_is_used_for_calls() {
+        let built = Arc::new(AtomicUsize::new(0));
+        let counter = built.clone();
+        let provider = EthereumProvider::new("http://127.0.0.1:1".to_string())
+            .with_provider_builder(move |client| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                ProviderBuilder::new()
+                    .with_recommended_fillers()
+                    .on_client(client)
+            });
+
+        // Nothing listens there; only the provider construction matters
+        assert!(provider.block_number().await.is_err());
+        assert_eq!(built.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_provider_is_built_once() {
+        let built = Arc::new(AtomicUsize::new(0));
+        let counter = built.clone();
+        let provider = EthereumProvider::new("http://127.0.0.1:1".to_string())
+            .with_provider_builder(move |client| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                ProviderBuilder::new().on_client(client)
+            });
+        // Cloned before first use, still sharing the provider
+        let at_block = provider.clone().with_block_tag(BlockTag::Number(1));
+
+        let address = "0x0000000000000000000000000000000000000001";
+        assert!(provider.get_native_balance(address).await.is_err());
+        assert!(provider.block_number().await.is_err());
+        assert!(at_block.get_native_balance(address).await.is_err());
+        assert_eq!(built.load(Ordering::SeqCst), 1);
+
+        // A changed setting the provider depends on builds a new one
+        let provider = provider.with_http_config(HttpConfig::default());
+        assert!(provider.block_number().await.is_err());
+        assert_eq!(built.load(Ordering::SeqCst), 2);
+
+        let provider = provider.with_retry_policy(RetryPolicy::none());
+        assert!(provider.block_number().await.is_err());
+        assert_eq!(built.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_changed_after_a_call_applies() {
+        let (_silent, url) = test_server::silent();
+        let provider = EthereumProvider::new(url)
+            .with_retry_policy(RetryPolicy::none())
+            .with_timeout(Duration::from_millis(50));
+        assert!(provider.block_number().await.is_err());
+
+        let provider = provider.with_timeout(Duration::from_secs(30));
+        let call = tokio::time::timeout(Duration::from_millis(500), provider.block_number()).await;
+        assert!(
+            call.is_err(),
+            "the 50ms timeout of the first call still applied"
+        );
+    }
+
+    /// Answers `eth_chainId` itself, as a layer overriding it would
+    struct FixedChainId(RootProvider<BoxTransport>);
+
+    impl Provider for FixedChainId {
+        fn root(&self) -> &RootProvider<BoxTransport> {
+            &self.0
+        }
+
+        fn get_chain_id(&self) -> ProviderCall<BoxTransport, NoParams, U64, u64> {
+            ProviderCall::ready(Ok(11155111))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_injected_provider_overrides_apply() {
+        // Nothing listens there, so only the override can answer
+        let root = ProviderBuilder::new()
+            .on_http("http://127.0.0.1:1".parse().unwrap())
+            .boxed();
+        let provider = DynProvider::new(FixedChainId(root));
+        assert_eq!(provider.get_chain_id().await.unwrap(), 11155111);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_rpc_url_fails_every_call() {
+        let provider = EthereumProvider::new("not a url".to_string());
+        assert!(provider.block_number().await.is_err());
+        assert!(provider.block_number().await.is_err());
+    }
+
+    #[test]
+    fn test_is_ws_url() {
+        assert!(is_ws_url("ws://127.0.0.1:8546"));
+        assert!(is_ws_url("wss://ethereum-sepolia-rpc.publicnode.com"));
+        assert!(!is_ws_url("https://ethereum-sepolia-rpc.publicnode.com"));
+        assert!(!is_ws_url("ethereum-sepolia-rpc.publicnode.com"));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_rpc_rejects_fallbacks_and_request_ids() {
+        let provider = EthereumProvider::new("ws://127.0.0.1:1".to_string())
+            .with_fallback_rpcs(vec!["https://sepolia.example".to_string()]);
+        let error = provider.block_number().await.unwrap_err();
+        assert!(error.to_string().contains("can't fail over"));
+
+        let provider = EthereumProvider::new("ws://127.0.0.1:1".to_string())
+            .with_request_id(RequestId::generate(http::DEFAULT_REQUEST_ID_HEADER).unwrap());
+        let error = provider.block_number().await.unwrap_err();
+        assert!(error.to_string().contains("Request IDs"));
+    }
+
+    #[test]
+    fn test_non_contract_error_is_not_revert() {
+        assert!(!is_revert(&anyhow::anyhow!("connection refused")));
+    }
+
+    #[test]
+    fn test_public_presets() {
+        let polygon = EthereumProvider::new_polygon();
+        assert_eq!(polygon.chain_name(), "Polygon");
+        assert_eq!(polygon.native_symbol(), "POL");
+        assert_eq!(polygon.rpc_urls(), public_rpcs(137));
+        assert_eq!(native_balance("POL", U256::from(1u8)).token, "POL");
+
+        let base = EthereumProvider::new_base();
+        assert_eq!(base.chain_name(), "Base");
+        assert_eq!(base.native_symbol(), "ETH");
+        assert_eq!(base.rpc_urls(), public_rpcs(8453));
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_sepolia_multicall_matches_individual_calls() {
+        let provider = EthereumProvider::new_sepolia();
+        let address = "0x78697a9cfc48C1e9d1040172d51833EF78083b10";
+        let usdc = Token::Erc20 {
+            address: "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238".to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+
+        let batched = provider
+            .get_all_balances_multicall(address, std::slice::from_ref(&usdc))
+            .await
+            .unwrap();
+        assert_eq!(batched.len(), 2);
+
+        let native = batched[0].as_ref().unwrap();
+        assert_eq!(native.token, "ETH");
+
+        let token = batched[1].as_ref().unwrap();
+        let individual = provider.get_token_balance(address, &usdc).await.unwrap();
+        assert_eq!(token.amount, individual.amount);
+
+        let tokens_only = provider
+            .get_token_balances_multicall(address, std::slice::from_ref(&usdc))
+            .await
+            .unwrap();
+        assert_eq!(tokens_only.len(), 1);
+        assert_eq!(tokens_only[0].amount, individual.amount);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_sepolia_watch_native_balance_over_ws() {
+        let provider =
+            EthereumProvider::new("wss://ethereum-sepolia-rpc.publicnode.com".to_string());
+        let mut balances = Box::pin(
+            provider
+                .watch_native_balance("0x78697a9cfc48c1e9d1040172d51833ef78083b10")
+                .await
+                .unwrap(),
+        );
+
+        // The current balance comes first, without waiting for a block
+        let first = tokio::time::timeout(Duration::from_secs(30), balances.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.token, "ETH");
+        assert_eq!(first.decimals, 18);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_sepolia_balance_at_past_block() {
+        let provider = EthereumProvider::new_sepolia();
+        let address = "0x78697a9cfc48C1e9d1040172d51833EF78083b10";
+
+        // Not yet funded right after genesis
+        let genesis = provider
+            .get_native_balance_at(address, BlockNumberOrTag::Number(1))
+            .await
+            .unwrap();
+        assert_eq!(genesis.amount, "0");
+
+        let latest = provider
+            .get_native_balance_at(address, BlockNumberOrTag::Latest)
+            .await
+            .unwrap();
+        assert_ne!(latest.amount, "0");
+    }
 
     #[tokio::test]
     #[ignore] // Requires network access
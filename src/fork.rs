@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::ethereum::EthereumProvider;
+use crate::options::{BlockTag, QueryOptions};
+use crate::types::Balance;
+
+/// A local fork of a configured EVM chain, e.g. started with
+/// `anvil --fork-url <rpc> --fork-block-number <n>`. Balances use the
+/// chain's token configuration but are read only from the fork endpoint,
+/// never from the configured RPC or public fallbacks.
+pub struct Fork {
+    chain: String,
+    rpc_url: String,
+}
+
+impl Fork {
+    pub fn new(chain: &str, rpc_url: &str) -> Self {
+        Self {
+            chain: chain.to_string(),
+            rpc_url: rpc_url.to_string(),
+        }
+    }
+
+    /// Block the fork was created from, read with `anvil_nodeInfo`
+    pub async fn fork_block(&self) -> Result<u64> {
+        let info = self.provider().request("anvil_nodeInfo", json!([])).await?;
+        fork_block_from_node_info(&info)
+    }
+
+    /// Balances of `address` on the fork at `block`: the fork block, any
+    /// later local block, or `None` for the fork's latest block
+    pub async fn balances_at(&self, address: &str, block: Option<u64>) -> Result<Vec<Balance>> {
+        let options = QueryOptions {
+            block_tag: block.map(BlockTag::Number).unwrap_or_default(),
+            ..Default::default()
+        };
+        self.balances_with_options(address, &options).await
+    }
+
+    /// `balances_at` with explicit query options
+    pub async fn balances_with_options(
+        &self,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>> {
+        let config = Config::shared()?;
+        let (chain, chain_config) =
+            config.resolve_chain_config(&self.chain, options.network.as_deref())?;
+        if chain_config.chain_type != "evm" {
+            return Err(anyhow!("Forks are only supported for EVM chains"));
+        }
+        let options = &QueryOptions {
+            http: options.http.or(&config.http),
+            ..options.clone()
+        };
+
+        let provider = crate::chain::ethereum_provider(chain_config, &self.rpc_url, options);
+        let mut balances =
+            crate::get_evm_balances(chain_config, &provider, address, options).await?;
+        for balance in &mut balances {
+            balance.chain = Some(chain.clone());
+            balance.address = Some(crate::normalize_address(address));
+            balance.rpc = Some(crate::rpc_host(&self.rpc_url));
+        }
+        Ok(crate::sort_balances(balances))
+    }
+
+    /// Let the fork accept transactions from `address` without its key
+    /// (`anvil_impersonateAccount`), to set up scenarios before reading balances
+    pub async fn impersonate(&self, address: &str) -> Result<()> {
+        self.provider()
+            .request("anvil_impersonateAccount", json!([address]))
+            .await?;
+        Ok(())
+    }
+
+    /// Undo `impersonate`
+    pub async fn stop_impersonating(&self, address: &str) -> Result<()> {
+        self.provider()
+            .request("anvil_stopImpersonatingAccount", json!([address]))
+            .await?;
+        Ok(())
+    }
+
+    fn provider(&self) -> EthereumProvider {
+        EthereumProvider::new(self.rpc_url.clone())
+    }
+}
+
+fn fork_block_from_node_info(info: &Value) -> Result<u64> {
+    info["forkConfig"]["forkBlockNumber"]
+        .as_u64()
+        .ok_or_else(|| anyhow!("Node is not a fork: anvil_nodeInfo has no forkBlockNumber"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANVIL_RPC: &str = "http://127.0.0.1:8545";
+
+    #[test]
+    fn test_fork_block_from_node_info() {
+        let info = json!({
+            "currentBlockNumber": 7000002,
+            "forkConfig": {
+                "forkUrl": "https://ethereum-sepolia-rpc.publicnode.com",
+                "forkBlockNumber": 7000000
+            }
+        });
+        assert_eq!(fork_block_from_node_info(&info).unwrap(), 7000000);
+
+        let not_forked = json!({ "currentBlockNumber": 0, "forkConfig": {} });
+        assert!(fork_block_from_node_info(&not_forked).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires `anvil --fork-url <sepolia rpc>` on 127.0.0.1:8545
+    async fn test_anvil_fork_balances_match_fork_block() {
+        let address = "0x78697a9cfc48C1e9d1040172d51833EF78083b10";
+        let fork = Fork::new("sepolia", ANVIL_RPC);
+        let block = fork.fork_block().await.unwrap();
+
+        let on_fork = fork.balances_at(address, Some(block)).await.unwrap();
+        let upstream = crate::get_balances_with_options(
+            "sepolia",
+            address,
+            &QueryOptions {
+                block_tag: BlockTag::Number(block),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let amounts = |balances: &[Balance]| -> Vec<(String, String)> {
+            balances
+                .iter()
+                .map(|balance| (balance.token.clone(), balance.amount.clone()))
+                .collect()
+        };
+        assert_eq!(amounts(&on_fork), amounts(&upstream));
+
+        fork.impersonate(address).await.unwrap();
+        fork.stop_impersonating(address).await.unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::error::BalanceError;
+use crate::http::{self, HttpConfig, RequestId, DEFAULT_RPC_TIMEOUT};
+use crate::telemetry;
+use crate::types::Balance;
+
+/// Token balance source backed by a GraphQL indexer (The Graph, Subsquid, ...)
+///
+/// The configured query is sent with the queried address as the `$address`
+/// variable and must return a list of `{ symbol, amount, decimals }` objects
+/// under `data.balances` (alias fields in the query to match). A field may
+/// be a nested selection of a single value, e.g. `token { symbol }`, which
+/// is read through to that value:
+///
+/// ```graphql
+/// query($address: String!) {
+///   balances: accountBalances(where: { account: $address }) {
+///     symbol: token { symbol } amount: value decimals: token { decimals }
+///   }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct GraphQlProvider {
+    url: String,
+    query: String,
+    request_id: Option<RequestId>,
+    http: HttpConfig,
+    timeout: Duration,
+    // Built on first use and shared by every call so connections are pooled
+    client: OnceLock<reqwest::Client>,
+    /// Limits requests in flight, shared by clones
+    in_flight: Arc<Semaphore>,
+}
+
+impl GraphQlProvider {
+    pub fn new(url: String, query: String) -> Self {
+        Self {
+            url,
+            query,
+            request_id: None,
+            http: HttpConfig::default(),
+            timeout: DEFAULT_RPC_TIMEOUT,
+            client: OnceLock::new(),
+            in_flight: HttpConfig::default().in_flight_limiter(),
+        }
+    }
+
+    /// Send the given correlation ID header with every request
+    pub fn with_request_id(mut self, request_id: RequestId) -> Self {
+        self.request_id = Some(request_id);
+        self.client = OnceLock::new();
+        self
+    }
+
+    /// Tune the connection pool and TLS settings of the underlying HTTP client
+    pub fn with_http_config(mut self, http: HttpConfig) -> Self {
+        self.in_flight = http.in_flight_limiter();
+        self.http = http;
+        self.client = OnceLock::new();
+        self
+    }
+
+    /// Give up on a query after `timeout` with `BalanceError::Timeout`;
+    /// `DEFAULT_RPC_TIMEOUT` by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.client = OnceLock::new();
+        self
+    }
+
+    /// Count queries against the in-flight limit of the chain's provider
+    pub(crate) fn with_in_flight(mut self, in_flight: Arc<Semaphore>) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    /// Fetch all token balances the indexer knows for `address`
+    pub async fn get_token_balances(&self, address: &str) -> Result<Vec<Balance>> {
+        let client = match self.client.get() {
+            Some(client) => client.clone(),
+            None => {
+                let client = http::client_builder(self.request_id.as_ref(), &self.http)?
+                    .timeout(self.timeout)
+                    .build()?;
+                self.client.get_or_init(|| client).clone()
+            }
+        };
+        let body = json!({
+            "query": self.query,
+            "variables": { "address": address },
+        });
+
+        let _permit = self.in_flight.acquire().await;
+        let request = async {
+            client
+                .post(&self.url)
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Value>()
+                .await
+        };
+        let response = telemetry::rpc_call(&self.url, request)
+            .await
+            .map_err(request_error)?;
+
+        parse_balances(&response)
+    }
+}
+
+/// A timed out query as `BalanceError::Timeout`, other failures as they are
+fn request_error(error: reqwest::Error) -> anyhow::Error {
+    match error.is_timeout() {
+        true => BalanceError::Timeout.into(),
+        false => error.into(),
+    }
+}
+
+/// Map a GraphQL response into balances, surfacing any GraphQL errors
+fn parse_balances(response: &Value) -> Result<Vec<Balance>> {
+    if let Some(errors) = response.get("errors").and_then(Value::as_array) {
+        if !errors.is_empty() {
+            let messages: Vec<&str> = errors
+                .iter()
+                .filter_map(|error| error.get("message").and_then(Value::as_str))
+                .collect();
+            return Err(anyhow!("GraphQL query failed: {}", messages.join("; ")));
+        }
+    }
+
+    let entries = response
+        .pointer("/data/balances")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("GraphQL response has no data.balances list"))?;
+
+    entries
+        .iter()
+        .map(|entry| {
+            let symbol = field_str(entry, "symbol")?;
+            let amount = field_str(entry, "amount")?;
+            let decimals: u8 = field_str(entry, "decimals")?
+                .parse()
+                .map_err(|_| anyhow!("Invalid decimals for {} in GraphQL response", symbol))?;
+            Balance::new(symbol, amount, decimals)
+        })
+        .collect()
+}
+
+/// Indexers return numbers either as JSON numbers or strings (for big ints).
+/// A nested selection of one field, e.g. `{ "symbol": "USDC" }` for
+/// `token { symbol }`, stands for the value it holds.
+fn field_str(entry: &Value, field: &str) -> Result<String> {
+    let mut value = entry.get(field);
+    while let Some(Value::Object(selection)) = value {
+        value = match selection.len() {
+            1 => selection.values().next(),
+            _ => None,
+        };
+    }
+    match value {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(Value::Number(value)) => Ok(value.to_string()),
+        _ => Err(anyhow!("GraphQL balance entry is missing '{}'", field)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_balances() {
+        let response = json!({
+            "data": {
+                "balances": [
+                    { "symbol": "USDC", "amount": "1500000", "decimals": 6 },
+                    { "symbol": "EURC", "amount": 250000, "decimals": "6" }
+                ]
+            }
+        });
+
+        let balances = parse_balances(&response).unwrap();
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[0].token, "USDC");
+        assert_eq!(balances[0].formatted, "1.5");
+        assert_eq!(balances[1].amount, "250000");
+        assert_eq!(balances[1].decimals, 6);
+    }
+
+    #[test]
+    fn test_parse_balances_reads_through_nested_selections() {
+        // Shape returned for the query in the `GraphQlProvider` docs
+        let response = json!({
+            "data": {
+                "balances": [{
+                    "symbol": { "symbol": "USDC" },
+                    "amount": "1500000",
+                    "decimals": { "decimals": 6 }
+                }]
+            }
+        });
+
+        let balances = parse_balances(&response).unwrap();
+        assert_eq!(balances[0].token, "USDC");
+        assert_eq!(balances[0].decimals, 6);
+
+        let ambiguous = json!({
+            "data": {
+                "balances": [{
+                    "symbol": { "symbol": "USDC", "name": "USD Coin" },
+                    "amount": "1", "decimals": 6
+                }]
+            }
+        });
+        assert!(parse_balances(&ambiguous).is_err());
+    }
+
+    #[test]
+    fn test_parse_balances_surfaces_graphql_errors() {
+        let response = json!({ "errors": [{ "message": "unknown field" }] });
+        let err = parse_balances(&response).unwrap_err();
+        assert!(err.to_string().contains("unknown field"));
+    }
+
+    #[test]
+    fn test_parse_balances_requires_balances_list() {
+        assert!(parse_balances(&json!({ "data": {} })).is_err());
+    }
+}
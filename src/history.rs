@@ -0,0 +1,169 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::config::Config;
+use crate::options::{BlockTag, OnError, QueryOptions};
+use crate::types::Balance;
+
+/// Most blocks one `balance_history` call will query
+pub const MAX_HISTORY_POINTS: usize = 500;
+
+/// Block queries `balance_history` keeps in flight at once
+pub const HISTORY_CONCURRENCY: usize = 8;
+
+/// Balances of an address at one block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryPoint {
+    pub block: u64,
+    pub balances: Vec<Balance>,
+}
+
+/// A block `balance_history_report` couldn't read with `OnError::Skip`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryFailure {
+    pub block: u64,
+    pub error: String,
+}
+
+/// Points read by `balance_history_report` and the blocks that failed, each
+/// in block order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryReport {
+    pub points: Vec<HistoryPoint>,
+    pub failures: Vec<HistoryFailure>,
+}
+
+/// `from`, `from + step`, ... up to and including `to`. Fails on an empty
+/// range, a zero step or more than `MAX_HISTORY_POINTS` blocks.
+pub fn history_blocks(from: u64, to: u64, step: u64) -> Result<Vec<u64>> {
+    if step == 0 {
+        return Err(anyhow!("History step must be at least 1"));
+    }
+    if from > to {
+        return Err(anyhow!("History range {}..{} is empty", from, to));
+    }
+    let points = (to - from) / step + 1;
+    if points > MAX_HISTORY_POINTS as u64 {
+        return Err(anyhow!(
+            "{}..{} every {} blocks is {} points, more than the limit of {}; use a larger step",
+            from,
+            to,
+            step,
+            points,
+            MAX_HISTORY_POINTS
+        ));
+    }
+    Ok((from..=to).step_by(step as usize).collect())
+}
+
+/// Balances of `address` on the EVM chain `chain` at every `step`-th block of
+/// `from..=to`, in block order. Blocks are queried concurrently (at most
+/// `HISTORY_CONCURRENCY` at a time); `options.block_tag` is ignored. Reading
+/// old blocks needs an archive node. With `OnError::Skip` failed blocks are
+/// left out, see `balance_history_report` to list them.
+pub async fn balance_history(
+    chain: &str,
+    address: &str,
+    from: u64,
+    to: u64,
+    step: u64,
+    options: &QueryOptions,
+) -> Result<Vec<HistoryPoint>> {
+    Ok(
+        balance_history_report(chain, address, from, to, step, options)
+            .await?
+            .points,
+    )
+}
+
+/// `balance_history` that also returns the blocks skipped with `OnError::Skip`.
+/// With `OnError::Abort` the first failed block fails the call and cancels
+/// the remaining ones.
+pub async fn balance_history_report(
+    chain: &str,
+    address: &str,
+    from: u64,
+    to: u64,
+    step: u64,
+    options: &QueryOptions,
+) -> Result<HistoryReport> {
+    let config = Config::shared()?;
+    let (chain, chain_config) = config.resolve_chain_config(chain, options.network.as_deref())?;
+    if chain_config.chain_type != "evm" {
+        return Err(anyhow!(
+            "History is only supported on EVM chains, {} is {}",
+            chain,
+            chain_config.chain_type
+        ));
+    }
+
+    let limiter = Arc::new(Semaphore::new(HISTORY_CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for block in history_blocks(from, to, step)? {
+        let limiter = limiter.clone();
+        let chain = chain.clone();
+        let address = address.to_string();
+        let options = QueryOptions {
+            block_tag: BlockTag::Number(block),
+            ..options.clone()
+        };
+        tasks.spawn(async move {
+            let result = match limiter.acquire_owned().await {
+                Ok(_permit) => crate::get_balances_with_options(&chain, &address, &options)
+                    .await
+                    .map_err(anyhow::Error::from),
+                Err(e) => Err(e.into()),
+            };
+            (block, result)
+        });
+    }
+
+    // Returning early drops the JoinSet, which cancels the blocks still in flight
+    let mut report = HistoryReport::default();
+    while let Some(joined) = tasks.join_next().await {
+        let (block, result) = joined?;
+        match result {
+            Ok(balances) => report.points.push(HistoryPoint { block, balances }),
+            Err(e) if options.on_error == OnError::Skip => report.failures.push(HistoryFailure {
+                block,
+                error: e.to_string(),
+            }),
+            Err(e) => return Err(anyhow!("block {}: {}", block, e)),
+        }
+    }
+    report.points.sort_by_key(|point| point.block);
+    report.failures.sort_by_key(|failure| failure.block);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_blocks() {
+        assert_eq!(history_blocks(100, 130, 10).unwrap(), [100, 110, 120, 130]);
+        assert_eq!(history_blocks(100, 125, 10).unwrap(), [100, 110, 120]);
+        assert_eq!(history_blocks(7, 7, 1).unwrap(), [7]);
+        assert!(history_blocks(100, 130, 0).is_err());
+        assert!(history_blocks(130, 100, 10).is_err());
+        assert!(history_blocks(0, MAX_HISTORY_POINTS as u64, 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_history_rejects_solana() {
+        let result = balance_history(
+            "solana-devnet",
+            "8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4",
+            1,
+            2,
+            1,
+            &QueryOptions::default(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+}
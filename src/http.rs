@@ -0,0 +1,254 @@
+use anyhow::{anyhow, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Once};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+
+/// Default header used to carry the correlation ID
+pub const DEFAULT_REQUEST_ID_HEADER: &str = "X-Request-ID";
+
+/// How long providers wait for an RPC call before failing with
+/// `BalanceError::Timeout`, unless set with `with_timeout`
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests a provider has in flight at once unless `HttpConfig::max_in_flight`
+/// says otherwise; low enough for the rate limits of free public RPCs
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 5;
+
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+static INSECURE_TLS_WARNING: Once = Once::new();
+
+/// Correlation ID sent as an HTTP header with every RPC request of one operation
+#[derive(Debug, Clone)]
+pub struct RequestId {
+    header: HeaderName,
+    value: HeaderValue,
+}
+
+impl RequestId {
+    /// Use an explicit ID value under the given header name
+    pub fn new(header: &str, value: &str) -> Result<Self> {
+        let header = HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| anyhow!("Invalid request ID header name: {}", header))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| anyhow!("Invalid request ID value: {}", value))?;
+        Ok(Self { header, value })
+    }
+
+    /// Generate a fresh ID for a new operation
+    pub fn generate(header: &str) -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let counter = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let value = format!("{:x}-{:x}-{:x}", nanos, std::process::id(), counter);
+        Self::new(header, &value)
+    }
+
+    pub fn header(&self) -> &str {
+        self.header.as_str()
+    }
+
+    pub fn value(&self) -> &str {
+        // Generated and validated values are always visible ASCII
+        self.value.to_str().unwrap_or_default()
+    }
+}
+
+/// Connection pool, protocol and TLS settings for the HTTP client each provider
+/// reuses for its RPC calls.
+///
+/// Unset pool fields keep reqwest's defaults: unlimited idle connections per host
+/// and a 90 second idle timeout. Raising the idle limit helps large batch jobs
+/// against one endpoint reuse connections instead of reconnecting; lowering the
+/// timeout frees sockets sooner when endpoints drop idle connections early.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HttpConfig {
+    #[serde(rename = "poolMaxIdlePerHost", skip_serializing_if = "Option::is_none")]
+    pub pool_max_idle_per_host: Option<usize>,
+    #[serde(
+        rename = "poolIdleTimeoutSecs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// HTTP/2 is negotiated over TLS (ALPN) when the endpoint supports it;
+    /// `false` forces HTTP/1.1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http2: Option<bool>,
+    /// PEM file with an extra root CA to trust, for private endpoints behind
+    /// an internal certificate authority
+    #[serde(rename = "caCertPath", skip_serializing_if = "Option::is_none")]
+    pub ca_cert_path: Option<PathBuf>,
+    /// Skip TLS certificate verification. Only for local development: anyone on
+    /// the network path can then read and alter RPC responses.
+    #[serde(
+        rename = "dangerAcceptInvalidCerts",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub danger_accept_invalid_certs: Option<bool>,
+    /// Revalidate repeated EVM requests with `If-None-Match`, for endpoints
+    /// behind a caching proxy that sends `ETag`s; a `304` reuses the cached body
+    #[serde(rename = "etagCache", skip_serializing_if = "Option::is_none")]
+    pub etag_cache: Option<bool>,
+    /// Most RPC requests a provider (and its clones) sends at once; more wait
+    /// for one to finish. Retry backoff doesn't hold a slot. Unset means
+    /// `DEFAULT_MAX_IN_FLIGHT`, 0 is treated as 1.
+    #[serde(rename = "maxInFlight", skip_serializing_if = "Option::is_none")]
+    pub max_in_flight: Option<usize>,
+}
+
+impl HttpConfig {
+    /// Fields set on `self` win, the rest come from `fallback`
+    pub fn or(&self, fallback: &HttpConfig) -> HttpConfig {
+        HttpConfig {
+            pool_max_idle_per_host: self
+                .pool_max_idle_per_host
+                .or(fallback.pool_max_idle_per_host),
+            pool_idle_timeout_secs: self
+                .pool_idle_timeout_secs
+                .or(fallback.pool_idle_timeout_secs),
+            http2: self.http2.or(fallback.http2),
+            ca_cert_path: self
+                .ca_cert_path
+                .clone()
+                .or_else(|| fallback.ca_cert_path.clone()),
+            danger_accept_invalid_certs: self
+                .danger_accept_invalid_certs
+                .or(fallback.danger_accept_invalid_certs),
+            etag_cache: self.etag_cache.or(fallback.etag_cache),
+            max_in_flight: self.max_in_flight.or(fallback.max_in_flight),
+        }
+    }
+
+    /// Semaphore with one permit per request allowed in flight
+    pub(crate) fn in_flight_limiter(&self) -> Arc<Semaphore> {
+        let permits = self.max_in_flight.unwrap_or(DEFAULT_MAX_IN_FLIGHT).max(1);
+        Arc::new(Semaphore::new(permits))
+    }
+}
+
+/// Start a reqwest client builder with the optional correlation header and pool settings applied
+pub(crate) fn client_builder(
+    request_id: Option<&RequestId>,
+    config: &HttpConfig,
+) -> Result<reqwest::ClientBuilder> {
+    let mut headers = HeaderMap::new();
+    if let Some(id) = request_id {
+        headers.insert(id.header.clone(), id.value.clone());
+    }
+
+    // rustls offers h2 via ALPN, so HTTP/2-capable endpoints multiplex requests
+    let mut builder = reqwest::Client::builder()
+        .default_headers(headers)
+        .use_rustls_tls();
+    if let Some(max_idle) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(secs) = config.pool_idle_timeout_secs {
+        builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+    }
+    if config.http2 == Some(false) {
+        builder = builder.http1_only();
+    }
+    if let Some(path) = &config.ca_cert_path {
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow!("Can't read CA certificate {}: {}", path.display(), e))?;
+        let cert = reqwest::Certificate::from_pem(&pem)
+            .map_err(|e| anyhow!("Invalid CA certificate {}: {}", path.display(), e))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    if config.danger_accept_invalid_certs == Some(true) {
+        INSECURE_TLS_WARNING.call_once(|| {
+            tracing::warn!(
+                "TLS certificate verification is DISABLED (dangerAcceptInvalidCerts). \
+                 RPC responses can be intercepted and forged; never use this outside local development."
+            );
+        });
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_ids_are_unique() {
+        let a = RequestId::generate(DEFAULT_REQUEST_ID_HEADER).unwrap();
+        let b = RequestId::generate(DEFAULT_REQUEST_ID_HEADER).unwrap();
+        assert_ne!(a.value(), b.value());
+        assert_eq!(a.header(), "x-request-id");
+    }
+
+    #[test]
+    fn test_http_config_override() {
+        let config = HttpConfig {
+            pool_max_idle_per_host: Some(4),
+            pool_idle_timeout_secs: Some(30),
+            http2: Some(false),
+            ..Default::default()
+        };
+        let flags = HttpConfig {
+            pool_max_idle_per_host: Some(32),
+            pool_idle_timeout_secs: None,
+            ..Default::default()
+        };
+
+        let merged = flags.or(&config);
+        assert_eq!(merged.pool_max_idle_per_host, Some(32));
+        assert_eq!(merged.pool_idle_timeout_secs, Some(30));
+        assert_eq!(merged.http2, Some(false));
+    }
+
+    #[test]
+    fn test_in_flight_limiter() {
+        let config = HttpConfig::default();
+        assert_eq!(
+            config.in_flight_limiter().available_permits(),
+            DEFAULT_MAX_IN_FLIGHT
+        );
+
+        let config: HttpConfig = serde_json::from_str(r#"{ "maxInFlight": 2 }"#).unwrap();
+        assert_eq!(config.in_flight_limiter().available_permits(), 2);
+        let flags = HttpConfig {
+            max_in_flight: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(flags.or(&config).in_flight_limiter().available_permits(), 1);
+    }
+
+    #[test]
+    fn test_tls_config_from_json() {
+        let config: HttpConfig = serde_json::from_str(
+            r#"{ "http2": false, "caCertPath": "/etc/ssl/internal-ca.pem", "dangerAcceptInvalidCerts": true }"#,
+        )
+        .unwrap();
+        assert_eq!(config.http2, Some(false));
+        assert_eq!(
+            config.ca_cert_path,
+            Some(PathBuf::from("/etc/ssl/internal-ca.pem"))
+        );
+        assert_eq!(config.danger_accept_invalid_certs, Some(true));
+    }
+
+    #[test]
+    fn test_missing_ca_cert_is_an_error() {
+        let config = HttpConfig {
+            ca_cert_path: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..Default::default()
+        };
+        let error = client_builder(None, &config).unwrap_err();
+        assert!(error.to_string().contains("/nonexistent/ca.pem"));
+    }
+
+    #[test]
+    fn test_invalid_header_name() {
+        assert!(RequestId::new("bad header", "abc").is_err());
+    }
+}
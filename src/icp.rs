@@ -0,0 +1,541 @@
+use alloy::primitives::U256;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use candid::{CandidType, Decode, Encode, Nat, Principal};
+use ciborium::tag::{Accepted, Required};
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+
+use crate::chain::ChainProvider;
+use crate::config::ChainConfig;
+use crate::error::BalanceError;
+use crate::fallback::rpc_host;
+use crate::http::{self, HttpConfig, RequestId, DEFAULT_RPC_TIMEOUT};
+use crate::options::QueryOptions;
+use crate::telemetry;
+use crate::types::{Balance, Token};
+
+/// Canister ID of the ICP ledger on mainnet
+pub const ICP_LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+/// ICP has 8 decimals: one ICP is 10^8 e8s
+const ICP_DECIMALS: u8 = 8;
+
+const ICP_SYMBOL: &str = "ICP";
+
+/// How far ahead calls expire; the IC rejects expiries over five minutes out
+const INGRESS_EXPIRY: Duration = Duration::from_secs(4 * 60);
+
+/// Reject code of transient errors, which may go away when retried
+const SYS_TRANSIENT: u64 = 2;
+
+/// CBOR tag marking a body as CBOR (the 0xd9d9f7 prefix)
+const SELF_DESCRIBE_TAG: u64 = 55799;
+
+/// An ICRC-1 account: a principal and one of its subaccounts, the default
+/// one when `None`
+#[derive(CandidType)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+/// CBOR body of a query call
+#[derive(Serialize)]
+struct QueryEnvelope<'a> {
+    content: QueryContent<'a>,
+}
+
+#[derive(Serialize)]
+struct QueryContent<'a> {
+    request_type: &'static str,
+    sender: Principal,
+    canister_id: Principal,
+    method_name: &'a str,
+    #[serde(with = "serde_bytes")]
+    arg: &'a [u8],
+    ingress_expiry: u64,
+}
+
+/// CBOR body of the answer to a query call
+#[derive(Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum QueryResponse {
+    Replied {
+        reply: QueryReply,
+    },
+    Rejected {
+        reject_code: u64,
+        reject_message: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct QueryReply {
+    arg: ByteBuf,
+}
+
+/// Internet Computer provider calling ICRC-1 ledger canisters with anonymous
+/// query calls through an HTTP gateway such as `https://icp-api.io`. The
+/// native ICP balance is read from the ICP ledger, token balances from each
+/// `Token::Icrc1` ledger.
+///
+/// Query replies aren't certified: they aren't checked against the subnet's
+/// signatures, so a malicious or compromised gateway (or boundary node) can
+/// forge any balance. The gateway is trusted as other chains' RPC endpoints
+/// are; use one you trust.
+pub struct IcpProvider {
+    gateway_url: String,
+    ledger: String,
+    request_id: Option<RequestId>,
+    http: HttpConfig,
+    timeout: Duration,
+    client: reqwest::Client,
+    /// Limits requests in flight
+    in_flight: Arc<Semaphore>,
+    chain_name: String,
+}
+
+impl IcpProvider {
+    /// Provider on the gateway at `gateway_url` reading native balances from
+    /// the ICP ledger canister `ledger`
+    pub fn new(gateway_url: String, ledger: String) -> Self {
+        Self {
+            gateway_url,
+            ledger,
+            request_id: None,
+            http: HttpConfig::default(),
+            timeout: DEFAULT_RPC_TIMEOUT,
+            client: reqwest::Client::new(),
+            in_flight: HttpConfig::default().in_flight_limiter(),
+            chain_name: "Internet Computer".to_string(),
+        }
+    }
+
+    pub fn new_mainnet() -> Self {
+        Self::new(
+            "https://icp-api.io".to_string(),
+            ICP_LEDGER_CANISTER_ID.to_string(),
+        )
+    }
+
+    /// Name `chain_name` reports; "Internet Computer" by default
+    pub fn with_chain_name(mut self, name: String) -> Self {
+        self.chain_name = name;
+        self
+    }
+
+    /// The gateway calls go to
+    pub fn gateway_url(&self) -> &str {
+        &self.gateway_url
+    }
+
+    /// The ICP ledger canister native balances are read from
+    pub fn ledger(&self) -> &str {
+        &self.ledger
+    }
+
+    /// Send the given correlation ID header with every request
+    pub fn with_request_id(mut self, request_id: RequestId) -> Result<Self> {
+        self.request_id = Some(request_id);
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    /// Tune the connection pool of the underlying HTTP client
+    pub fn with_http_config(mut self, http: HttpConfig) -> Result<Self> {
+        self.in_flight = http.in_flight_limiter();
+        self.http = http;
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    /// Fail each gateway request unanswered after `timeout` with
+    /// `BalanceError::Timeout`; `DEFAULT_RPC_TIMEOUT` by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn rebuild_client(&mut self) -> Result<()> {
+        self.client = http::client_builder(self.request_id.as_ref(), &self.http)?.build()?;
+        Ok(())
+    }
+
+    /// `icrc1_balance_of` the default account of principal `owner` on the
+    /// ICRC-1 ledger canister `ledger`, in the token's smallest unit
+    pub async fn balance_of(&self, ledger: &str, owner: &str) -> Result<U256> {
+        let owner = Principal::from_text(owner).map_err(|_| BalanceError::InvalidAddress {
+            address: owner.to_string(),
+        })?;
+        let account = Account {
+            owner,
+            subaccount: None,
+        };
+        let reply = self
+            .query(ledger, "icrc1_balance_of", &Encode!(&account)?)
+            .await?;
+        let balance = Decode!(&reply, Nat)?;
+        U256::try_from_be_slice(&balance.0.to_bytes_be())
+            .ok_or_else(|| anyhow!("Balance {} on {} is over 256 bits", balance, ledger))
+    }
+
+    /// Symbol and decimals the ICRC-1 ledger canister `ledger` reports
+    pub async fn token_metadata(&self, ledger: &str) -> Result<(String, u8)> {
+        let symbol = self.query(ledger, "icrc1_symbol", &Encode!()?).await?;
+        let decimals = self.query(ledger, "icrc1_decimals", &Encode!()?).await?;
+        Ok((Decode!(&symbol, String)?, Decode!(&decimals, u8)?))
+    }
+
+    /// Candid reply of query `method` of `canister` called with `arg`
+    async fn query(&self, canister: &str, method: &str, arg: &[u8]) -> Result<Vec<u8>> {
+        let canister_id = Principal::from_text(canister)
+            .map_err(|e| anyhow!("Invalid canister ID '{}': {}", canister, e))?;
+        let expiry = (SystemTime::now() + INGRESS_EXPIRY)
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos() as u64;
+
+        let url = format!(
+            "{}/api/v2/canister/{}/query",
+            self.gateway_url.trim_end_matches('/'),
+            canister
+        );
+        let _permit = self.in_flight.acquire().await;
+        let request = self
+            .client
+            .post(&url)
+            .timeout(self.timeout)
+            .header(reqwest::header::CONTENT_TYPE, "application/cbor")
+            .body(query_request(canister_id, method, arg, expiry)?)
+            .send();
+        let response = telemetry::rpc_call(&url, request)
+            .await
+            .map_err(request_error)?;
+        let status = response.status();
+        let body = response.bytes().await.map_err(request_error)?;
+        match status {
+            reqwest::StatusCode::TOO_MANY_REQUESTS => Err(BalanceError::RateLimited.into()),
+            status if status.is_server_error() => Err(BalanceError::Network {
+                message: format!("{} answered {}", rpc_host(&url), status),
+            }
+            .into()),
+            status if !status.is_success() => Err(anyhow!(
+                "{} answered {}: {}",
+                rpc_host(&url),
+                status,
+                String::from_utf8_lossy(&body).trim()
+            )),
+            _ => reply_arg(&body, canister, method),
+        }
+    }
+}
+
+/// A failed gateway request as a `BalanceError`: a timeout or, for connection
+/// trouble, a network error
+fn request_error(error: reqwest::Error) -> anyhow::Error {
+    match error.is_timeout() {
+        true => BalanceError::Timeout.into(),
+        false => BalanceError::Network {
+            message: error.to_string(),
+        }
+        .into(),
+    }
+}
+
+/// Whether `id` is a principal in its textual form with a valid checksum,
+/// e.g. "ryjl3-tyaaa-aaaaa-aaaba-cai"
+pub(crate) fn is_principal(id: &str) -> bool {
+    Principal::from_text(id).is_ok()
+}
+
+/// CBOR body of an anonymous query call, as the HTTP interface expects it
+fn query_request(canister: Principal, method: &str, arg: &[u8], expiry: u64) -> Result<Vec<u8>> {
+    let envelope = QueryEnvelope {
+        content: QueryContent {
+            request_type: "query",
+            sender: Principal::anonymous(),
+            canister_id: canister,
+            method_name: method,
+            arg,
+            ingress_expiry: expiry,
+        },
+    };
+    let mut body = Vec::new();
+    ciborium::into_writer(&Required::<_, SELF_DESCRIBE_TAG>(envelope), &mut body)?;
+    Ok(body)
+}
+
+/// The Candid reply of a query response, or why the canister rejected it.
+/// A transient reject (`SYS_TRANSIENT`) is a `BalanceError::Network`.
+fn reply_arg(response: &[u8], canister: &str, method: &str) -> Result<Vec<u8>> {
+    let response: Accepted<QueryResponse, SELF_DESCRIBE_TAG> = ciborium::from_reader(response)
+        .map_err(|e| anyhow!("Unexpected response to {} of {}: {}", method, canister, e))?;
+    match response.0 {
+        QueryResponse::Replied { reply } => Ok(reply.arg.into_vec()),
+        QueryResponse::Rejected {
+            reject_code,
+            reject_message,
+        } => {
+            let message = format!(
+                "{} of {} was rejected: {}",
+                method, canister, reject_message
+            );
+            match reject_code {
+                SYS_TRANSIENT => Err(BalanceError::Network { message }.into()),
+                _ => Err(anyhow!(message)),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ChainProvider for IcpProvider {
+    fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    fn native_symbol(&self) -> &str {
+        ICP_SYMBOL
+    }
+
+    async fn get_balances(
+        &self,
+        config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        Ok(crate::get_icp_balances(config, self, address, options).await?)
+    }
+
+    fn balance_stream<'a>(
+        &'a self,
+        config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        crate::stream_reads(crate::icp_balance_reads(config, self, address, options))
+    }
+
+    fn active_endpoint(&self) -> Option<&str> {
+        Some(self.gateway_url())
+    }
+
+    /// Balance of the principal's default account on the ICP ledger
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        let read = async {
+            let e8s = self.balance_of(&self.ledger, address).await?;
+            Ok(Balance::new(
+                ICP_SYMBOL.to_string(),
+                e8s.to_string(),
+                ICP_DECIMALS,
+            )?)
+        };
+        telemetry::balance_read(
+            &self.chain_name,
+            address,
+            ICP_SYMBOL,
+            self.request_id.as_ref(),
+            read,
+        )
+        .await
+    }
+
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        let read = async {
+            match token {
+                Token::Icrc1 { ledger, .. } => {
+                    let amount = self.balance_of(ledger, address).await?;
+                    Ok(Balance::for_token(token, amount.to_string())?)
+                }
+                Token::Native { .. } => {
+                    let native = self.get_native_balance(address).await?;
+                    Ok(Balance::for_token(token, native.amount)?)
+                }
+                other => Err(BalanceError::Config {
+                    message: format!(
+                        "{} is not an ICP token, ICP chains read ICRC-1 ledgers",
+                        other.symbol()
+                    ),
+                }),
+            }
+        };
+        telemetry::balance_read(
+            &self.chain_name,
+            address,
+            token.symbol(),
+            self.request_id.as_ref(),
+            read,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::value::Value;
+
+    const OWNER: &str = "2vxsx-fae";
+    const CKBTC_LEDGER: &str = "mxzaz-hqaaa-aaaar-qaada-cai";
+
+    /// CBOR map of text keys
+    fn cbor_map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Value::Text(key.to_string()), value))
+                .collect(),
+        )
+    }
+
+    fn get<'a>(map: &'a Value, key: &str) -> Option<&'a Value> {
+        let entries = map.as_map()?;
+        entries
+            .iter()
+            .find(|(k, _)| k.as_text() == Some(key))
+            .map(|(_, value)| value)
+    }
+
+    fn to_cbor(value: &Value) -> Vec<u8> {
+        let mut body = Vec::new();
+        ciborium::into_writer(value, &mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn test_is_principal() {
+        assert!(is_principal(ICP_LEDGER_CANISTER_ID));
+        assert!(is_principal(OWNER));
+        assert!(is_principal(CKBTC_LEDGER));
+        for invalid in [
+            "",
+            "ryjl3-tyaaa-aaaaa-aaaba-caa",
+            "ryjl3-tyaaa-aaaaa-aaaba-cai-",
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+        ] {
+            assert!(!is_principal(invalid), "{} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn test_account_arg() {
+        let account = Account {
+            owner: Principal::anonymous(),
+            subaccount: None,
+        };
+        assert_eq!(
+            Encode!(&account).unwrap(),
+            b"DIDL\x03\x6c\x02\xb3\xb0\xda\xc3\x03\x68\xad\x86\xca\x83\x05\x01\
+              \x6e\x02\x6d\x7b\x01\x00\x01\x01\x04\x00"
+        );
+    }
+
+    #[test]
+    fn test_query_request() {
+        let canister = Principal::from_text(ICP_LEDGER_CANISTER_ID).unwrap();
+        let arg = Encode!().unwrap();
+        let body = query_request(canister, "icrc1_symbol", &arg, 1).unwrap();
+        // Self-described CBOR
+        assert_eq!(body[..3], [0xd9, 0xd9, 0xf7]);
+
+        let request: Value = ciborium::from_reader(&body[..]).unwrap();
+        let (tag, request) = request.into_tag().unwrap();
+        assert_eq!(tag, SELF_DESCRIBE_TAG);
+        let content = get(&request, "content").unwrap();
+        let field = |key| get(content, key).unwrap().clone();
+        assert_eq!(field("request_type"), Value::Text("query".to_string()));
+        assert_eq!(field("sender"), Value::Bytes(vec![4]));
+        assert_eq!(
+            field("canister_id"),
+            Value::Bytes(vec![0, 0, 0, 0, 0, 0, 0, 2, 1, 1])
+        );
+        assert_eq!(
+            field("method_name"),
+            Value::Text("icrc1_symbol".to_string())
+        );
+        assert_eq!(field("arg"), Value::Bytes(arg));
+        assert_eq!(field("ingress_expiry"), Value::Integer(1.into()));
+    }
+
+    #[test]
+    fn test_reply_arg() {
+        let replied = cbor_map(vec![
+            ("status", Value::Text("replied".to_string())),
+            (
+                "reply",
+                cbor_map(vec![(
+                    "arg",
+                    Value::Bytes(Encode!(&Nat::from(2_500u64)).unwrap()),
+                )]),
+            ),
+        ]);
+        let reply = reply_arg(&to_cbor(&replied), CKBTC_LEDGER, "icrc1_balance_of");
+        assert_eq!(Decode!(&reply.unwrap(), Nat).unwrap(), 2_500u64);
+
+        // Gateways send the self-describe tag too
+        let tagged = Value::Tag(SELF_DESCRIBE_TAG, Box::new(replied));
+        let reply = reply_arg(&to_cbor(&tagged), CKBTC_LEDGER, "icrc1_balance_of");
+        assert_eq!(Decode!(&reply.unwrap(), Nat).unwrap(), 2_500u64);
+
+        let rejected = |code: u64| {
+            let response = cbor_map(vec![
+                ("status", Value::Text("rejected".to_string())),
+                ("reject_code", Value::Integer(code.into())),
+                (
+                    "reject_message",
+                    Value::Text("Canister not found".to_string()),
+                ),
+            ]);
+            to_cbor(&response)
+        };
+        let error =
+            reply_arg(&rejected(3), ICP_LEDGER_CANISTER_ID, "icrc1_balance_of").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "icrc1_balance_of of ryjl3-tyaaa-aaaaa-aaaba-cai was rejected: Canister not found"
+        );
+        assert!(error.downcast_ref::<BalanceError>().is_none());
+
+        // SYS_TRANSIENT rejects may succeed when retried
+        let error =
+            reply_arg(&rejected(2), ICP_LEDGER_CANISTER_ID, "icrc1_balance_of").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<BalanceError>(),
+            Some(BalanceError::Network { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_principal_is_an_invalid_address() {
+        let provider = IcpProvider::new_mainnet();
+        let error = provider
+            .get_native_balance("not-a-principal")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, BalanceError::InvalidAddress { .. }));
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_mainnet_ledger() {
+        let provider = IcpProvider::new_mainnet();
+        let (symbol, decimals) = provider
+            .token_metadata(ICP_LEDGER_CANISTER_ID)
+            .await
+            .unwrap();
+        assert_eq!(symbol, "ICP");
+        assert_eq!(decimals, 8);
+
+        let balance = provider.get_native_balance(OWNER).await.unwrap();
+        assert_eq!(balance.token, "ICP");
+        assert_eq!(balance.decimals, 8);
+    }
+}
@@ -1,86 +1,1563 @@
+mod address;
+mod aggregate;
+mod alert;
+mod amount;
+mod assertion;
+mod audit;
+mod bitcoin;
 mod chain;
 mod config;
+mod doctor;
+mod error;
+mod etag;
 mod ethereum;
+mod explain;
+mod fallback;
+mod fork;
+mod graphql;
+mod history;
+mod http;
+mod icp;
+mod middleware;
+mod options;
+mod price;
+mod retry;
+mod snapshot;
 mod solana;
+mod sqlite;
+mod telemetry;
+#[cfg(test)]
+mod test_server;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod types;
+mod ws;
 
-pub use chain::ChainProvider;
-pub use config::{ChainConfig, Config, TokenInfo};
-pub use ethereum::EthereumProvider;
-pub use solana::SolanaProvider;
-pub use types::{Balance, Token};
+pub use address::{mask_address, normalize_address};
+pub use aggregate::total_by_token;
+pub use alert::{changes_exceeding, BalanceChange};
+pub use amount::AmountParser;
+pub use assertion::{evaluate_all, AssertionResult, Comparison, Expectation};
+pub use audit::audit_chain;
+pub use bitcoin::BitcoinProvider;
+pub use chain::{provider_for, provider_with_options, validate_address, ChainProvider};
+pub use config::{
+    reload_config, rpc_env_var, validate_rpc_url, ChainConfig, Config, TokenInfo, VestingContract,
+    VestingKind, DEFAULT_CHAIN, RPC_ENV_PREFIX,
+};
+pub use doctor::{diagnose, Check};
+pub use error::BalanceError;
+pub use ethereum::{
+    is_ens_name, is_revert, namehash, CallReverted, EthereumProvider, ENS_REGISTRY_ADDRESS,
+    MULTICALL3_ADDRESS,
+};
+pub use explain::RpcCall;
+pub use fallback::{public_rpcs, rpc_candidates, rpc_host};
+pub use fork::Fork;
+pub use graphql::GraphQlProvider;
+pub use history::{
+    balance_history, balance_history_report, history_blocks, HistoryFailure, HistoryPoint,
+    HistoryReport, HISTORY_CONCURRENCY, MAX_HISTORY_POINTS,
+};
+pub use http::{
+    HttpConfig, RequestId, DEFAULT_MAX_IN_FLIGHT, DEFAULT_REQUEST_ID_HEADER, DEFAULT_RPC_TIMEOUT,
+};
+pub use icp::{IcpProvider, ICP_LEDGER_CANISTER_ID};
+pub use middleware::{is_retryable, Cache, MiddlewareConfig, ProviderStack, RateLimited, Retry};
+pub use options::{BlockTag, OnError, QueryOptions};
+pub use price::{
+    price_provider, ChainlinkPriceProvider, CoinGeckoPriceProvider, CoinMarketCapPriceProvider,
+    PriceConfig, PriceProvider, PriceSource,
+};
+pub use retry::RetryPolicy;
+/// The exact decimal type returned by `AmountParser` and `Balance::as_decimal`
+pub use rust_decimal::Decimal;
+pub use snapshot::{
+    diff_balances, latest_block, snapshot, snapshot_of_balances, snapshot_with_options, Snapshot,
+    SnapshotDiff, TokenDelta,
+};
+pub use solana::{
+    AccountState, SolanaProvider, TokenAccount, TokenAccounts, TOKEN_2022_PROGRAM_ID,
+    WRAPPED_SOL_MINT, WRAPPED_SOL_SYMBOL,
+};
+pub use sqlite::SqliteStore;
+pub use types::{filter_nonzero, rescale, Balance, BalanceKind, Rounding, Token, TokenSpec};
 
 use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, FuturesUnordered};
+use futures::{Future, FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
 
 /// Get balances for an address on a specific chain
-pub async fn get_balances(chain_name: &str, address: &str) -> Result<Vec<Balance>> {
-    let config = Config::load()?;
-    let chain_config = config
-        .get_chain(chain_name)
-        .ok_or_else(|| anyhow!("Chain '{}' not found in configuration", chain_name))?;
-
-    match chain_config.chain_type.as_str() {
-        "evm" => get_evm_balances(chain_config, address).await,
-        "solana" => get_solana_balances(chain_config, address).await,
-        _ => Err(anyhow!(
-            "Unsupported chain type: {}",
-            chain_config.chain_type
-        )),
+///
+/// The native balance always comes first, followed by token balances sorted
+/// by symbol, so the order is identical across calls and output formats.
+pub async fn get_balances(chain_name: &str, address: &str) -> Result<Vec<Balance>, BalanceError> {
+    let config = Config::shared()?;
+    get_balances_with_config(&config, chain_name, address).await
+}
+
+/// `get_balances` against `config` instead of the shared configuration, so a
+/// caller can parse or build its own config once and reuse it for every query
+pub async fn get_balances_with_config(
+    config: &Config,
+    chain_name: &str,
+    address: &str,
+) -> Result<Vec<Balance>, BalanceError> {
+    Ok(query_balances(config, chain_name, address, &QueryOptions::default()).await?)
+}
+
+/// Get balances for an address on a specific chain with explicit query options
+pub async fn get_balances_with_options(
+    chain_name: &str,
+    address: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Balance>, BalanceError> {
+    let config = Config::shared()?;
+    Ok(query_balances(&config, chain_name, address, options).await?)
+}
+
+/// `get_balances_with_options` yielding balances as soon as the read that
+/// returns them completes, so a UI can show the native balance while tokens
+/// still load. The reads are those of `get_balances_with_options`, so the
+/// balances are the same, but arrive in completion order rather than
+/// sorted. A failed read is yielded as an error without ending the stream.
+///
+/// Reads batched into one request (e.g. the native balance and tokens of one
+/// EVM multicall) arrive together. Middleware `retryAttempts` doesn't retry
+/// streamed reads.
+pub fn get_balances_streaming(
+    chain_name: &str,
+    address: &str,
+    options: &QueryOptions,
+) -> impl Stream<Item = Result<Balance, BalanceError>> + Send {
+    let (chain_name, address, options) =
+        (chain_name.to_string(), address.to_string(), options.clone());
+    // The reader borrows the config, so it lives in this future, which
+    // forwards what it reads to the returned stream
+    let (sender, receiver) = futures::channel::mpsc::unbounded();
+    let read = async move {
+        let config = match Config::shared() {
+            Ok(config) => config,
+            Err(e) => return drop(sender.unbounded_send(Err(BalanceError::from(e)))),
+        };
+        let reader = match ChainReader::new(&config, &chain_name, &options) {
+            Ok(reader) => reader,
+            Err(e) => return drop(sender.unbounded_send(Err(e))),
+        };
+        let mut reads = reader.stream(&address);
+        while let Some(read) = reads.next().await {
+            if sender.unbounded_send(read).is_err() {
+                return;
+            }
+        }
+    };
+    let balances = receiver.flat_map(|read| {
+        let balances: Vec<Result<Balance, BalanceError>> = match read {
+            Ok(balances) => balances.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        };
+        futures::stream::iter(balances)
+    });
+    futures::stream::select(
+        balances.map(Some),
+        futures::stream::once(read).map(|()| None),
+    )
+    .filter_map(futures::future::ready)
+}
+
+/// `get_balances_with_options` against `config`, read through `provider`
+/// instead of the one `provider_with_options` builds for the chain. The
+/// chain is resolved and the address validated as usual, and the balances
+/// are labelled and sorted the same way, so a `MockProvider` (with the
+/// `testing` feature) can stand in for the network.
+pub async fn get_balances_with_provider(
+    config: &Config,
+    chain_name: &str,
+    address: &str,
+    options: &QueryOptions,
+    provider: Box<dyn ChainProvider>,
+) -> Result<Vec<Balance>, BalanceError> {
+    ChainReader::with_provider(config, chain_name, options, provider)?
+        .read(address)
+        .await
+}
+
+async fn query_balances(
+    config: &Config,
+    chain_name: &str,
+    address: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Balance>> {
+    Ok(ChainReader::new(config, chain_name, options)?
+        .read(address)
+        .await?)
+}
+
+/// `get_balances_with_options` for each of `addresses` on one chain, in the
+/// order given. Every address is read through the same providers, so they
+/// share connections, and a failed address doesn't stop the others.
+pub async fn get_balances_for_addresses(
+    chain_name: &str,
+    addresses: &[String],
+    options: &QueryOptions,
+) -> Vec<Result<Vec<Balance>, BalanceError>> {
+    match Config::shared() {
+        Ok(config) => query_addresses(&config, chain_name, addresses, options).await,
+        Err(e) => {
+            let e = BalanceError::from(e);
+            addresses.iter().map(|_| Err(e.clone())).collect()
+        }
     }
 }
 
-async fn get_evm_balances(config: &ChainConfig, address: &str) -> Result<Vec<Balance>> {
-    let provider = EthereumProvider::new(config.rpc.clone());
+async fn query_addresses(
+    config: &Config,
+    chain_name: &str,
+    addresses: &[String],
+    options: &QueryOptions,
+) -> Vec<Result<Vec<Balance>, BalanceError>> {
+    // A generated request ID is per address, so each address needs its own providers
+    if options.request_id.is_none() && options.request_id_header.is_some() {
+        let reads = addresses.iter().map(|address| async move {
+            ChainReader::new(config, chain_name, options)?
+                .read(address)
+                .await
+        });
+        return futures::future::join_all(reads).await;
+    }
 
-    // Get native balance
-    let mut balances = vec![provider.get_native_balance(address).await?];
+    let reader = match ChainReader::new(config, chain_name, options) {
+        Ok(reader) => reader,
+        Err(e) => return addresses.iter().map(|_| Err(e.clone())).collect(),
+    };
+    let reads = addresses.iter().map(|address| reader.read(address));
+    futures::future::join_all(reads).await
+}
 
-    // Get token balances
-    for (symbol, token_info) in &config.tokens {
-        if let Some(token_address) = &token_info.address {
-            let token = Token::Erc20 {
-                address: token_address.clone(),
-                symbol: symbol.clone(),
-                decimals: token_info.decimals,
-            };
-            balances.push(provider.get_token_balance(address, &token).await?);
+/// A resolved chain with its provider from `provider_with_options`, on every
+/// endpoint to try (the configured RPC first, then any built-in public
+/// fallbacks) and wrapped in the chain's middleware, built once and shared by
+/// every read through it. Reads are those of `get_balances_with_options`, so
+/// a caller reading repeatedly (e.g. a refresh loop) keeps one reader and
+/// its connections.
+pub struct ChainReader<'a> {
+    name: String,
+    config: &'a ChainConfig,
+    options: QueryOptions,
+    provider: Box<dyn ChainProvider>,
+}
+
+impl<'a> ChainReader<'a> {
+    /// Reader of `chain_name` in `config`, with `options` for every read. A
+    /// request ID generated for `options.request_id_header` is the reader's
+    /// own, sent with all of its reads.
+    pub fn new(
+        config: &'a Config,
+        chain_name: &str,
+        options: &QueryOptions,
+    ) -> Result<Self, BalanceError> {
+        let (name, chain_config, mut options) = Self::resolve(config, chain_name, options)?;
+        if let (None, Some(header)) = (&options.request_id, &options.request_id_header) {
+            let request_id = RequestId::generate(header)?;
+            tracing::info!(chain = %name, request_id = request_id.value(), "request ID");
+            options.request_id = Some(request_id);
+        }
+        let provider = chain::provider_with_options(chain_config, &options)?;
+        Ok(Self {
+            name,
+            config: chain_config,
+            options,
+            provider,
+        })
+    }
+
+    /// `new` reading through `provider` rather than building one
+    fn with_provider(
+        config: &'a Config,
+        chain_name: &str,
+        options: &QueryOptions,
+        provider: Box<dyn ChainProvider>,
+    ) -> Result<Self> {
+        let (name, chain_config, options) = Self::resolve(config, chain_name, options)?;
+        Ok(Self {
+            name,
+            config: chain_config,
+            options,
+            provider,
+        })
+    }
+
+    /// The chain's name and config, and `options` with the config's defaults
+    fn resolve(
+        config: &'a Config,
+        chain_name: &str,
+        options: &QueryOptions,
+    ) -> Result<(String, &'a ChainConfig, QueryOptions)> {
+        let (name, chain_config) =
+            config.resolve_chain_config(chain_name, options.network.as_deref())?;
+
+        // Fail early with a clear error instead of a URL parse error mid-request
+        chain_config.validate_rpc(&name)?;
+        // Only EVM reads take a block; other chains would silently read the latest
+        if options.block_tag != BlockTag::Latest && chain_config.chain_type != "evm" {
+            return Err(BalanceError::Config {
+                message: format!(
+                    "Chain '{}' can't be read at block {}: only EVM chains take a block tag",
+                    name, options.block_tag
+                ),
+            }
+            .into());
+        }
+
+        let options = QueryOptions {
+            http: options.http.or(&config.http),
+            ..options.clone()
+        };
+        Ok((name, chain_config, options))
+    }
+
+    /// Balances of `address`, as `get_balances_with_options` returns them.
+    /// The provider moves on to the next endpoint itself when one is down;
+    /// any other failure fails the read.
+    pub async fn read(&self, address: &str) -> Result<Vec<Balance>, BalanceError> {
+        validate_address(&self.config.chain_type, address)?;
+
+        let mut balances = self
+            .provider
+            .get_balances(self.config, address, &self.options)
+            .await?;
+        let rpc = self.provider.active_endpoint().map(rpc_host);
+        for balance in &mut balances {
+            balance.chain = Some(self.name.clone());
+            balance.address = Some(normalize_address(address));
+            balance.rpc = rpc.clone();
         }
+        Ok(sort_balances(balances))
+    }
+
+    /// `read` as a stream of the balances of each read, labelled the same
+    /// way but in completion order
+    fn stream<'b>(&'b self, address: &'b str) -> BoxStream<'b, Result<Vec<Balance>, BalanceError>> {
+        if let Err(e) = validate_address(&self.config.chain_type, address) {
+            return futures::stream::once(async { Err(BalanceError::from(e)) }).boxed();
+        }
+        self.provider
+            .balance_stream(self.config, address, &self.options)
+            .map_ok(move |mut balances| {
+                let rpc = self.provider.active_endpoint().map(rpc_host);
+                for balance in &mut balances {
+                    balance.chain = Some(self.name.clone());
+                    balance.address = Some(normalize_address(address));
+                    balance.rpc = rpc.clone();
+                }
+                balances
+            })
+            .boxed()
+    }
+}
+
+/// Every SPL token `address` has a token account for on a Solana chain, found
+/// from its accounts rather than the config. Only tokens currently held are
+/// returned unless `include_zero`, which adds those whose accounts are empty.
+/// Configured mints keep their symbol and decimals, others are labelled by
+/// mint address.
+pub async fn discover_tokens(
+    chain_name: &str,
+    address: &str,
+    include_zero: bool,
+    options: &QueryOptions,
+) -> Result<Vec<Balance>> {
+    let config = Config::shared()?;
+    let (chain_name, chain_config) =
+        config.resolve_chain_config(chain_name, options.network.as_deref())?;
+    if chain_config.chain_type != "solana" {
+        return Err(anyhow!(
+            "Token discovery is only supported for Solana chains, '{}' is {}",
+            chain_name,
+            chain_config.chain_type
+        ));
     }
+    chain_config.validate_rpc(&chain_name)?;
+    validate_address(&chain_config.chain_type, address)?;
 
+    let options = &QueryOptions {
+        http: options.http.or(&config.http),
+        ..options.clone()
+    };
+    let mut rpcs = rpc_candidates(chain_config, !options.disable_fallback_rpc);
+    if rpcs.is_empty() {
+        return Err(anyhow!(
+            "No RPC endpoint configured for chain '{}'",
+            chain_name
+        ));
+    }
+    let provider = chain::solana_provider(&rpcs.remove(0), options)?.with_fallback_rpcs(rpcs)?;
+
+    let mut balances = Vec::new();
+    for (mint, total) in provider.discover_tokens(address, include_zero).await? {
+        let configured = chain_config
+            .tokens
+            .iter()
+            .find(|(_, info)| info.address.as_deref() == Some(mint.as_str()));
+        let spec = TokenSpec {
+            symbol: configured.map(|(key, info)| info.symbol_or(key).to_string()),
+            decimals: configured.and_then(|(_, info)| info.decimals),
+            address: mint,
+        };
+        let token = resolve_solana_token(&provider, &spec).await?;
+        balances.push(Balance {
+            chain: Some(chain_name.clone()),
+            address: Some(normalize_address(address)),
+            ..Balance::from_minor_units(token.symbol().to_string(), total, token.decimals())
+        });
+    }
     Ok(balances)
 }
 
-async fn get_solana_balances(config: &ChainConfig, address: &str) -> Result<Vec<Balance>> {
-    let provider = SolanaProvider::new(config.rpc.clone());
+/// Every token account `address` owns for `mint` on a Solana chain, with each
+/// account's amount and state and their total. Configured mints keep their
+/// symbol and decimals, others are labelled by mint address.
+pub async fn get_token_accounts(
+    chain_name: &str,
+    address: &str,
+    mint: &str,
+    options: &QueryOptions,
+) -> Result<TokenAccounts> {
+    let config = Config::shared()?;
+    let (chain_name, chain_config) =
+        config.resolve_chain_config(chain_name, options.network.as_deref())?;
+    if chain_config.chain_type != "solana" {
+        return Err(anyhow!(
+            "Token accounts are only supported for Solana chains, '{}' is {}",
+            chain_name,
+            chain_config.chain_type
+        ));
+    }
+    chain_config.validate_rpc(&chain_name)?;
+    validate_address(&chain_config.chain_type, address)?;
 
-    // Get native balance
-    let mut balances = vec![provider.get_native_balance(address).await?];
+    let options = &QueryOptions {
+        http: options.http.or(&config.http),
+        ..options.clone()
+    };
+    let mut rpcs = rpc_candidates(chain_config, !options.disable_fallback_rpc);
+    if rpcs.is_empty() {
+        return Err(anyhow!(
+            "No RPC endpoint configured for chain '{}'",
+            chain_name
+        ));
+    }
+    let provider = chain::solana_provider(&rpcs.remove(0), options)?.with_fallback_rpcs(rpcs)?;
 
-    // Get token balances
-    for (symbol, token_info) in &config.tokens {
-        if let Some(token_address) = &token_info.address {
-            let token = Token::Erc20 {
-                address: token_address.clone(),
-                symbol: symbol.clone(),
-                decimals: token_info.decimals,
+    let configured = chain_config
+        .tokens
+        .iter()
+        .find(|(_, info)| info.address.as_deref() == Some(mint));
+    let spec = TokenSpec {
+        address: mint.to_string(),
+        symbol: configured.map(|(key, info)| info.symbol_or(key).to_string()),
+        decimals: configured.and_then(|(_, info)| info.decimals),
+    };
+    let token = resolve_solana_token(&provider, &spec).await?;
+
+    let mut accounts = provider.token_accounts(address, &token).await?;
+    accounts.total.chain = Some(chain_name);
+    accounts.total.address = Some(normalize_address(address));
+    accounts.total.rpc = Some(rpc_host(provider.active_rpc()));
+    Ok(accounts)
+}
+
+/// Query `address` on each of `chains` and sum same-symbol balances, see
+/// `total_by_token` for how differing decimals are normalized
+pub async fn get_cross_chain_totals(
+    chains: &[&str],
+    address: &str,
+    options: &QueryOptions,
+    decimals: Option<u8>,
+) -> Result<Vec<Balance>> {
+    let mut per_chain = Vec::with_capacity(chains.len());
+    for chain in chains {
+        per_chain.push(get_balances_with_options(chain, address, options).await?);
+    }
+    total_by_token(&per_chain, decimals)
+}
+
+/// Balances of `address` on each of `chains`, in the order given. With
+/// `parallel` all chains are queried at once, but results are still returned
+/// in request order rather than completion order. With `OnError::Abort`
+/// (`options.on_error`) the first failure stops the batch: chains not queried
+/// or cancelled yet report an error saying so.
+pub async fn get_balances_for_chains(
+    chains: &[String],
+    address: &str,
+    options: &QueryOptions,
+    parallel: bool,
+) -> Vec<Result<Vec<Balance>>> {
+    let abort = options.on_error == OnError::Abort;
+    if !parallel {
+        let mut results = Vec::with_capacity(chains.len());
+        for chain in chains {
+            let failed = results.iter().any(|result: &Result<_>| result.is_err());
+            results.push(match abort && failed {
+                true => Err(anyhow!("Not queried after an earlier chain failed")),
+                false => get_balances_with_options(chain, address, options)
+                    .await
+                    .map_err(anyhow::Error::from),
+            });
+        }
+        return results;
+    }
+
+    let mut tasks = JoinSet::new();
+    for (index, chain) in chains.iter().enumerate() {
+        let chain = chain.clone();
+        let address = address.to_string();
+        let options = options.clone();
+        tasks.spawn(async move {
+            let result = get_balances_with_options(&chain, &address, &options)
+                .await
+                .map_err(anyhow::Error::from);
+            (index, result)
+        });
+    }
+
+    // Buffer by index so completion order doesn't leak into the output
+    let mut results: Vec<Option<Result<Vec<Balance>>>> = chains.iter().map(|_| None).collect();
+    let mut aborted = false;
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((index, result)) = joined {
+            if abort && result.is_err() && !aborted {
+                aborted = true;
+                tasks.abort_all();
+            }
+            results[index] = Some(result);
+        }
+    }
+    results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| match aborted {
+                true => Err(anyhow!("Cancelled after another chain failed")),
+                false => Err(anyhow!("Balance query task panicked")),
+            })
+        })
+        .collect()
+}
+
+/// Balances on every configured chain, in chain name order. Each chain is
+/// queried with the address `addresses` maps its type to (e.g. "evm",
+/// "solana"); chains of a type without an address are left out, and with
+/// `options.network` so are chains of another network. A chain that fails
+/// reports its error in place of its balances instead of ending the portfolio.
+pub async fn get_portfolio(
+    config: &Config,
+    addresses: &HashMap<String, String>,
+    options: &QueryOptions,
+) -> Vec<(String, Result<Vec<Balance>>)> {
+    let mut chains: Vec<(&String, &String)> = config
+        .chains
+        .iter()
+        .filter(|(_, chain)| match options.network.as_deref() {
+            Some(network) => chain.network.as_deref() == Some(network),
+            None => true,
+        })
+        .filter_map(|(name, chain)| Some((name, addresses.get(&chain.chain_type)?)))
+        .collect();
+    chains.sort();
+
+    // Chain keys are queried as-is, the network only selects which ones
+    let options = &QueryOptions {
+        network: None,
+        ..options.clone()
+    };
+    let queries = chains
+        .iter()
+        .map(|(name, address)| query_balances(config, name, address, options));
+    let results = futures::future::join_all(queries).await;
+    chains
+        .into_iter()
+        .map(|(name, _)| name.clone())
+        .zip(results)
+        .collect()
+}
+
+/// The RPC requests `get_balances_with_options` would send for this query to
+/// the first endpoint it tries, without sending them
+pub async fn explain_rpc(
+    chain_name: &str,
+    address: &str,
+    options: &QueryOptions,
+) -> Result<Vec<RpcCall>> {
+    let config = Config::shared()?;
+    let (chain_name, chain_config) =
+        config.resolve_chain_config(chain_name, options.network.as_deref())?;
+    let chain_name = &chain_name;
+    chain_config.validate_rpc(chain_name)?;
+
+    let rpcs = rpc_candidates(chain_config, !options.disable_fallback_rpc);
+    let rpc = rpcs
+        .first()
+        .ok_or_else(|| anyhow!("No RPC endpoint configured for chain '{}'", chain_name))?;
+    explain::plan(chain_config, rpc, address, options).await
+}
+
+/// The block `confirmations` below `head`, failing if the chain is shorter
+fn confirmed_block(head: u64, confirmations: u64) -> Result<u64> {
+    head.checked_sub(confirmations).ok_or_else(|| {
+        anyhow!(
+            "{} confirmations is deeper than the chain's {} blocks",
+            confirmations,
+            head
+        )
+    })
+}
+
+/// `BalanceError::StaleNode` if `block`, produced at Unix time `timestamp`,
+/// is more than `max_secs` older than `now`
+fn check_staleness(block: u64, timestamp: u64, max_secs: u64, now: u64) -> Result<()> {
+    let age_secs = now.saturating_sub(timestamp);
+    match age_secs > max_secs {
+        true => Err(BalanceError::StaleNode {
+            block,
+            age_secs,
+            max_secs,
+        }
+        .into()),
+        false => Ok(()),
+    }
+}
+
+fn unix_now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Complete an ad-hoc ERC-20 token with the symbol/decimals read from chain
+/// where `spec` doesn't give them
+fn complete_evm_token(
+    spec: &TokenSpec,
+    chain_symbol: Option<String>,
+    chain_decimals: Option<u8>,
+) -> Result<Token> {
+    let symbol = spec
+        .symbol
+        .clone()
+        .or(chain_symbol)
+        .ok_or_else(|| anyhow!("Could not read symbol() of token {}", spec.address))?;
+    let decimals = spec
+        .decimals
+        .or(chain_decimals)
+        .ok_or_else(|| anyhow!("Could not read decimals() of token {}", spec.address))?;
+
+    Ok(Token::Erc20 {
+        address: spec.address.clone(),
+        symbol,
+        decimals,
+    })
+}
+
+/// Complete an ad-hoc SPL token; the mint address stands in for a missing symbol
+async fn resolve_solana_token(provider: &SolanaProvider, spec: &TokenSpec) -> Result<Token> {
+    let decimals = match spec.decimals {
+        Some(decimals) => decimals,
+        None => provider.fetch_mint_decimals(&spec.address).await?,
+    };
+
+    Ok(Token::Spl {
+        mint: spec.address.clone(),
+        symbol: spec.symbol.clone().unwrap_or_else(|| spec.address.clone()),
+        decimals,
+    })
+}
+
+/// Complete an ad-hoc ICRC-1 token with the symbol/decimals its ledger reports
+/// where `spec` doesn't give them
+async fn resolve_icp_token(provider: &IcpProvider, spec: &TokenSpec) -> Result<Token> {
+    let (symbol, decimals) = match (&spec.symbol, spec.decimals) {
+        (Some(symbol), Some(decimals)) => (symbol.clone(), decimals),
+        (symbol, decimals) => {
+            let (read_symbol, read_decimals) = provider.token_metadata(&spec.address).await?;
+            (
+                symbol.clone().unwrap_or(read_symbol),
+                decimals.unwrap_or(read_decimals),
+            )
+        }
+    };
+
+    Ok(Token::Icrc1 {
+        ledger: spec.address.clone(),
+        symbol,
+        decimals,
+    })
+}
+
+/// URL and query of the GraphQL indexer for the chain's token balances, if
+/// one is configured
+fn graphql_indexer(config: &ChainConfig) -> Result<Option<(String, String)>> {
+    match (&config.graphql_url, &config.graphql_query) {
+        (Some(url), Some(query)) => Ok(Some((url.clone(), query.clone()))),
+        (Some(_), None) => Err(anyhow!(
+            "Chain '{}' sets graphqlUrl without a graphqlQuery",
+            config.name
+        )),
+        (None, _) => Ok(None),
+    }
+}
+
+/// Keep the native balance (always produced first) in front and order the
+/// token balances by symbol, independent of config `HashMap` iteration order.
+/// Balances sharing a symbol (two ERC-1155 IDs of one collection, config keys
+/// with the same `symbol`) are ordered by decimals, raw amount and kind, so
+/// the order is total.
+fn sort_balances(mut balances: Vec<Balance>) -> Vec<Balance> {
+    if balances.len() > 1 {
+        balances[1..].sort_by(|a, b| {
+            a.token
+                .cmp(&b.token)
+                .then(a.decimals.cmp(&b.decimals))
+                .then_with(|| raw_amount_order(&a.amount, &b.amount))
+                .then((a.kind == BalanceKind::Nft).cmp(&(b.kind == BalanceKind::Nft)))
+        });
+    }
+    balances
+}
+
+/// Numeric order of two raw integer amounts of any size
+fn raw_amount_order(a: &str, b: &str) -> std::cmp::Ordering {
+    let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// One read of a balance query and the balances it returns, e.g. one
+/// multicall or one token account query
+pub(crate) type BalanceRead<'a> = BoxFuture<'a, Result<Vec<Balance>>>;
+
+/// The balances of every read, in the order of `reads`
+async fn join_reads(reads: Vec<BalanceRead<'_>>) -> Result<Vec<Balance>> {
+    let balances = futures::future::try_join_all(reads).await?;
+    Ok(balances.into_iter().flatten().collect())
+}
+
+/// The balances of every read `reads` sets up, yielded as each read
+/// completes. A failed read is yielded without ending the stream; a failed
+/// setup is the only item.
+pub(crate) fn stream_reads<'a>(
+    reads: impl Future<Output = Result<Vec<BalanceRead<'a>>>> + Send + 'a,
+) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+    futures::stream::once(reads)
+        .map_ok(|reads| reads.into_iter().collect::<FuturesUnordered<_>>())
+        .try_flatten()
+        .map_err(BalanceError::from)
+        .boxed()
+}
+
+pub(crate) async fn get_evm_balances(
+    config: &ChainConfig,
+    provider: &EthereumProvider,
+    address: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Balance>> {
+    join_reads(evm_balance_reads(config, provider, address, options).await?).await
+}
+
+/// The reads of an EVM balance query: the native balance and tokens in one
+/// Multicall3 request where available, one per confirmation depth, then
+/// each LP position and vesting schedule
+pub(crate) async fn evm_balance_reads<'a>(
+    config: &'a ChainConfig,
+    provider: &'a EthereumProvider,
+    address: &str,
+    options: &'a QueryOptions,
+) -> Result<Vec<BalanceRead<'a>>> {
+    let indexer = graphql_indexer(config)?;
+    // Indexers only report current balances
+    if indexer.is_some() && options.block_tag != BlockTag::Latest {
+        return Err(BalanceError::Config {
+            message: format!(
+                "Chain '{}' can't be read at block {}: its token balances come from a GraphQL indexer",
+                config.name, options.block_tag
+            ),
+        }
+        .into());
+    }
+
+    // Resolve an ENS name once for the native and every token read
+    let address: Arc<str> = provider.resolve_address(address).await?.into();
+
+    if let Some((url, query)) = indexer {
+        let indexer = provider.graphql_indexer(url, query);
+        let native = {
+            let address = address.clone();
+            async move {
+                if let Some(max_secs) = options.max_staleness_secs {
+                    let (block, timestamp) = provider.block_timestamp().await?;
+                    check_staleness(block, timestamp, max_secs, unix_now()?)?;
+                }
+                Ok(vec![provider.get_native_balance(&address).await?])
+            }
+        };
+        let tokens = async move { indexer.get_token_balances(&address).await };
+        return Ok(vec![native.boxed(), tokens.boxed()]);
+    }
+
+    let mut tokens = Vec::new();
+    let mut lp_tokens = Vec::new();
+    let mut vested = Vec::new();
+    let mut treat_revert_as_zero = Vec::new();
+    // Tokens with their own confirmation depth, grouped by depth
+    let mut confirmed: BTreeMap<u64, Vec<(Token, bool)>> = BTreeMap::new();
+    // Configured ERC-20s without decimals have them read from chain, all at once
+    let read_decimals: HashMap<&str, u8> =
+        futures::future::try_join_all(config.tokens.iter().filter_map(|(key, token_info)| {
+            let address = token_info.address.as_deref()?;
+            let read = async move {
+                Ok::<_, anyhow::Error>((key.as_str(), provider.token_decimals(address).await?))
             };
-            balances.push(provider.get_token_balance(address, &token).await?);
+            token_info.needs_decimals().then_some(read)
+        }))
+        .await?
+        .into_iter()
+        .collect();
+    for (key, token_info) in &config.tokens {
+        let token = match read_decimals.get(key.as_str()) {
+            Some(&decimals) => TokenInfo {
+                decimals: Some(decimals),
+                ..token_info.clone()
+            }
+            .evm_token(key)?,
+            None => token_info.evm_token(key)?,
+        };
+        match token {
+            Some(token @ Token::UniV2Lp { .. }) => lp_tokens.push(token),
+            Some(token) => {
+                if let Some(vesting) = &token_info.vesting {
+                    vested.push((token.clone(), vesting));
+                }
+                let revert_as_zero = token_info
+                    .treat_revert_as_zero
+                    .unwrap_or(options.treat_revert_as_zero);
+                match token_info.confirmations {
+                    Some(confirmations) if options.block_tag == BlockTag::Latest => confirmed
+                        .entry(confirmations)
+                        .or_default()
+                        .push((token, revert_as_zero)),
+                    _ => {
+                        tokens.push(token);
+                        treat_revert_as_zero.push(revert_as_zero);
+                    }
+                }
+            }
+            None => {}
         }
     }
 
-    Ok(balances)
+    // Ad-hoc tokens follow the configured ones; placeholders until their
+    // metadata is known
+    let configured = tokens.len();
+    for spec in &options.extra_tokens {
+        tokens.push(Token::Erc20 {
+            address: spec.address.clone(),
+            symbol: spec.symbol.clone().unwrap_or_default(),
+            decimals: spec.decimals.unwrap_or_default(),
+        });
+        treat_revert_as_zero.push(options.treat_revert_as_zero);
+    }
+    let with_metadata = options
+        .extra_tokens
+        .iter()
+        .any(|spec| spec.symbol.is_none() || spec.decimals.is_none());
+
+    // Native + all tokens (and missing ad-hoc metadata) in one Multicall3
+    // request where available
+    let batch = {
+        let address = address.clone();
+        async move {
+            let (native, readings, block) = provider
+                .read_balances(&address, &tokens, with_metadata)
+                .await?;
+
+            // Judged on the block the batch was read at, not a separate head read
+            if let Some(max_secs) = options.max_staleness_secs {
+                let (block, timestamp) = match block {
+                    Some(number) => {
+                        provider
+                            .clone()
+                            .with_block_tag(BlockTag::Number(number))
+                            .block_timestamp()
+                            .await?
+                    }
+                    None => provider.block_timestamp().await?,
+                };
+                check_staleness(block, timestamp, max_secs, unix_now()?)?;
+            }
+            let mut balances = vec![native?];
+
+            for (index, ((token, revert_as_zero), reading)) in tokens
+                .iter()
+                .zip(treat_revert_as_zero)
+                .zip(readings)
+                .enumerate()
+            {
+                let token = match index.checked_sub(configured) {
+                    Some(extra) => complete_evm_token(
+                        &options.extra_tokens[extra],
+                        reading.symbol,
+                        reading.decimals,
+                    )?,
+                    None => token.clone(),
+                };
+                let amount = match reading.amount {
+                    Err(e) if revert_as_zero && ethereum::is_revert(&e) => "0".to_string(),
+                    amount => amount?,
+                };
+                balances.push(Balance {
+                    block,
+                    ..Balance::for_token(&token, amount)?
+                });
+            }
+            Ok(balances)
+        }
+    };
+    let mut reads = vec![batch.boxed()];
+
+    // One multicall per confirmation depth, counted back from the latest
+    // block, which is read once for all of them
+    let head = provider
+        .block_number()
+        .map_err(BalanceError::from)
+        .boxed()
+        .shared();
+    reads.extend(confirmed.into_iter().map(|(confirmations, group)| {
+        let (address, head) = (address.clone(), head.clone());
+        async move {
+            let block = confirmed_block(head.await?, confirmations)?;
+            let (tokens, treat_revert_as_zero): (Vec<Token>, Vec<bool>) = group.into_iter().unzip();
+            let (_, readings, _) = provider
+                .clone()
+                .with_block_tag(BlockTag::Number(block))
+                .read_balances(&address, &tokens, false)
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "Reading block {} ({} confirmations) failed, the node may not keep \
+                         state that old: {}",
+                        block,
+                        confirmations,
+                        e
+                    )
+                })?;
+            let mut balances = Vec::new();
+            for ((token, revert_as_zero), reading) in
+                tokens.iter().zip(treat_revert_as_zero).zip(readings)
+            {
+                let amount = match reading.amount {
+                    Err(e) if revert_as_zero && ethereum::is_revert(&e) => "0".to_string(),
+                    amount => amount?,
+                };
+                balances.push(Balance {
+                    block: Some(block),
+                    ..Balance::for_token(token, amount)?
+                });
+            }
+            Ok(balances)
+        }
+        .boxed()
+    }));
+
+    // LP positions and vesting schedules, in config order
+    reads.extend(lp_tokens.into_iter().map(|lp_token| {
+        let address = address.clone();
+        async move {
+            provider
+                .get_lp_underlying_balances(&address, &lp_token)
+                .await
+        }
+        .boxed()
+    }));
+    reads.extend(vested.into_iter().map(|(token, vesting)| {
+        let address = address.clone();
+        async move {
+            provider
+                .get_vesting_balances(&address, &token, vesting)
+                .await
+        }
+        .boxed()
+    }));
+    Ok(reads)
+}
+
+/// The confirmed BTC balance; Bitcoin has no tokens to add to it
+pub(crate) async fn get_bitcoin_balances(
+    provider: &BitcoinProvider,
+    address: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Balance>> {
+    join_reads(bitcoin_balance_reads(provider, address, options).await?).await
+}
+
+/// The single read of a Bitcoin balance query, after any staleness check
+pub(crate) async fn bitcoin_balance_reads<'a>(
+    provider: &'a BitcoinProvider,
+    address: &'a str,
+    options: &QueryOptions,
+) -> Result<Vec<BalanceRead<'a>>> {
+    if let Some(spec) = options.extra_tokens.first() {
+        return Err(anyhow!(
+            "Can't query token {} on a Bitcoin chain, Bitcoin has no tokens",
+            spec.address
+        ));
+    }
+    if let Some(max_secs) = options.max_staleness_secs {
+        let (height, timestamp) = provider.tip_time().await?;
+        check_staleness(height, timestamp, max_secs, unix_now()?)?;
+    }
+
+    let native = async move { Ok(vec![provider.get_native_balance(address).await?]) };
+    Ok(vec![native.boxed()])
+}
+
+pub(crate) async fn get_solana_balances(
+    config: &ChainConfig,
+    provider: &SolanaProvider,
+    address: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Balance>> {
+    join_reads(solana_balance_reads(config, provider, address, options).await?).await
+}
+
+/// The reads of a Solana balance query: the native balance, each configured
+/// then ad-hoc token (or the GraphQL indexer's tokens), and wrapped SOL
+pub(crate) async fn solana_balance_reads<'a>(
+    config: &'a ChainConfig,
+    provider: &'a SolanaProvider,
+    address: &'a str,
+    options: &'a QueryOptions,
+) -> Result<Vec<BalanceRead<'a>>> {
+    if let Some(max_secs) = options.max_staleness_secs {
+        let (slot, timestamp) = provider.slot_time().await?;
+        check_staleness(slot, timestamp.max(0) as u64, max_secs, unix_now()?)?;
+    }
+
+    let native = async move { Ok(vec![provider.get_native_balance(address).await?]) };
+    let mut reads = vec![native.boxed()];
+
+    if let Some((url, query)) = graphql_indexer(config)? {
+        let indexer = provider.graphql_indexer(url, query);
+        reads.push(async move { indexer.get_token_balances(address).await }.boxed());
+        return Ok(reads);
+    }
+
+    // Configured tokens, then ad-hoc ones; decimals the config leaves out
+    // are read from the mint
+    let configured = config.tokens.iter().filter_map(|(key, token_info)| {
+        Some(TokenSpec {
+            address: token_info.address.clone()?,
+            symbol: Some(token_info.symbol_or(key).to_string()),
+            decimals: token_info.decimals,
+        })
+    });
+    reads.extend(
+        configured
+            .chain(options.extra_tokens.iter().cloned())
+            .map(|spec| {
+                async move {
+                    let token = resolve_solana_token(provider, &spec).await?;
+                    Ok(vec![provider.get_token_balance(address, &token).await?])
+                }
+                .boxed()
+            }),
+    );
+
+    // Wrapped SOL is reported separately from native SOL, unless already configured
+    let wsol_configured = config
+        .tokens
+        .values()
+        .any(|info| info.address.as_deref() == Some(WRAPPED_SOL_MINT));
+    if options.include_wrapped && !wsol_configured {
+        let wsol = Token::Spl {
+            mint: WRAPPED_SOL_MINT.to_string(),
+            symbol: WRAPPED_SOL_SYMBOL.to_string(),
+            decimals: config.native_token.decimals.unwrap_or(9),
+        };
+        reads.push(
+            async move { Ok(vec![provider.get_token_balance(address, &wsol).await?]) }.boxed(),
+        );
+    }
+
+    Ok(reads)
+}
+
+/// The ICP balance, read from the ledger, and every ICRC-1 token, configured
+/// then ad-hoc
+pub(crate) async fn get_icp_balances(
+    config: &ChainConfig,
+    provider: &IcpProvider,
+    address: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Balance>> {
+    join_reads(icp_balance_reads(config, provider, address, options).await?).await
+}
+
+/// The reads of an ICP balance query: the native balance, then each token
+pub(crate) async fn icp_balance_reads<'a>(
+    config: &'a ChainConfig,
+    provider: &'a IcpProvider,
+    address: &'a str,
+    options: &'a QueryOptions,
+) -> Result<Vec<BalanceRead<'a>>> {
+    if options.max_staleness_secs.is_some() {
+        return Err(anyhow!(
+            "Can't check staleness on an ICP chain, ledger queries report no block time"
+        ));
+    }
+
+    let native = async move { Ok(vec![provider.get_native_balance(address).await?]) };
+    let mut reads = vec![native.boxed()];
+    let specs = config
+        .tokens
+        .iter()
+        .filter_map(|(key, token_info)| {
+            Some(TokenSpec {
+                address: token_info.address.clone()?,
+                symbol: Some(token_info.symbol_or(key).to_string()),
+                decimals: token_info.decimals,
+            })
+        })
+        .chain(options.extra_tokens.iter().cloned());
+    reads.extend(specs.map(|spec| {
+        async move {
+            let token = resolve_icp_token(provider, &spec).await?;
+            Ok(vec![provider.get_token_balance(address, &token).await?])
+        }
+        .boxed()
+    }));
+    Ok(reads)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_confirmed_block() {
+        assert_eq!(confirmed_block(1_000, 12).unwrap(), 988);
+        assert_eq!(confirmed_block(12, 12).unwrap(), 0);
+        assert!(confirmed_block(11, 12).is_err());
+    }
+
+    #[test]
+    fn test_check_staleness() {
+        assert!(check_staleness(100, 1_000, 60, 1_060).is_ok());
+        // A block timestamp slightly ahead of the local clock isn't stale
+        assert!(check_staleness(100, 1_010, 60, 1_000).is_ok());
+
+        let error = check_staleness(100, 1_000, 60, 1_061).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<BalanceError>(),
+            Some(&BalanceError::StaleNode {
+                block: 100,
+                age_secs: 61,
+                max_secs: 60,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_tag_is_rejected_on_non_evm_chains() {
+        let config = Config::load().unwrap();
+        let options = QueryOptions {
+            block_tag: BlockTag::Number(1),
+            ..Default::default()
+        };
+        let error = query_balances(
+            &config,
+            "solana-devnet",
+            "8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4",
+            &options,
+        )
+        .await
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("only EVM chains take a block tag"));
+    }
+
+    #[tokio::test]
+    async fn test_block_tag_is_rejected_with_a_graphql_indexer() {
+        let config: ChainConfig = serde_json::from_value(serde_json::json!({
+            "type": "evm",
+            "name": "Indexed",
+            "rpc": "http://127.0.0.1:9",
+            "nativeToken": { "symbol": "ETH", "decimals": 18 },
+            "tokens": {},
+            "graphqlUrl": "http://127.0.0.1:9/graphql",
+            "graphqlQuery": "query($address: String!) { balances { symbol amount decimals } }"
+        }))
+        .unwrap();
+        let provider = EthereumProvider::new("http://127.0.0.1:9".to_string());
+        let options = QueryOptions {
+            block_tag: BlockTag::Number(1),
+            ..Default::default()
+        };
+
+        let error = get_evm_balances(
+            &config,
+            &provider,
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+            &options,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("GraphQL indexer"));
+    }
+
+    #[test]
+    fn test_each_reader_generates_its_own_request_id() {
+        let config = Config::load().unwrap();
+        let options = QueryOptions {
+            request_id_header: Some(DEFAULT_REQUEST_ID_HEADER.to_string()),
+            ..Default::default()
+        };
+        let first = ChainReader::new(&config, "sepolia", &options).unwrap();
+        let second = ChainReader::new(&config, "sepolia", &options).unwrap();
+
+        let id = |reader: &ChainReader| reader.options.request_id.clone().unwrap();
+        assert_eq!(id(&first).header(), "x-request-id");
+        assert_ne!(id(&first).value(), id(&second).value());
+    }
+
+    #[tokio::test]
+    async fn test_chains_abort_stops_after_first_failure() {
+        let chains = vec!["no-such-chain".to_string(), "sepolia".to_string()];
+        let results = get_balances_for_chains(
+            &chains,
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+            &QueryOptions::default(),
+            false,
+        )
+        .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            results[0]
+                .as_ref()
+                .unwrap_err()
+                .downcast_ref::<BalanceError>(),
+            Some(BalanceError::ChainNotFound { .. })
+        ));
+        assert!(results[1]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("Not queried"));
+    }
+
+    #[tokio::test]
+    async fn test_chains_skip_reports_every_failure() {
+        let chains = vec!["no-such-chain".to_string(), "other-chain".to_string()];
+        let options = QueryOptions {
+            on_error: OnError::Skip,
+            ..Default::default()
+        };
+        let results = get_balances_for_chains(
+            &chains,
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+            &options,
+            true,
+        )
+        .await;
+
+        for result in &results {
+            assert!(matches!(
+                result.as_ref().unwrap_err().downcast_ref::<BalanceError>(),
+                Some(BalanceError::ChainNotFound { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_addresses_fail_one_by_one() {
+        // Nothing listens on port 1, so a valid address fails with a network error
+        let config: Config = r#"{
+            "chains": {
+                "local": {
+                    "type": "evm",
+                    "name": "Local",
+                    "rpc": "http://127.0.0.1:1",
+                    "chainId": 31337,
+                    "nativeToken": { "symbol": "ETH", "decimals": 18 },
+                    "tokens": {}
+                }
+            }
+        }"#
+        .parse()
+        .unwrap();
+        let addresses = [
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+            "not-an-address",
+        ]
+        .map(String::from);
+        let options = QueryOptions {
+            disable_fallback_rpc: true,
+            ..Default::default()
+        };
+
+        let results = query_addresses(&config, "local", &addresses, &options).await;
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[0], Err(BalanceError::Network { .. })));
+        assert!(matches!(
+            results[1],
+            Err(BalanceError::InvalidAddress { .. })
+        ));
+
+        let results = query_addresses(&config, "missing", &addresses, &options).await;
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(BalanceError::ChainNotFound { .. }))));
+    }
+
+    fn balance(token: &str, amount: &str) -> Balance {
+        Balance::new(token.to_string(), amount.to_string(), 6).unwrap()
+    }
+
+    #[test]
+    fn test_sort_balances_native_first_then_by_symbol() {
+        let sorted = sort_balances(vec![
+            balance("SOL", "1"),
+            balance("USDC", "2"),
+            balance("EURC", "3"),
+            balance("wSOL", "4"),
+        ]);
+        let tokens: Vec<&str> = sorted.iter().map(|b| b.token.as_str()).collect();
+        assert_eq!(tokens, vec!["SOL", "EURC", "USDC", "wSOL"]);
+    }
+
+    #[test]
+    fn test_sort_balances_is_independent_of_input_order() {
+        let first = sort_balances(vec![
+            balance("ETH", "1"),
+            balance("USDC", "2"),
+            balance("EURC", "3"),
+        ]);
+        let second = sort_balances(vec![
+            balance("ETH", "1"),
+            balance("EURC", "3"),
+            balance("USDC", "2"),
+        ]);
+
+        let first: Vec<(&str, &str)> = first
+            .iter()
+            .map(|b| (b.token.as_str(), b.amount.as_str()))
+            .collect();
+        let second: Vec<(&str, &str)> = second
+            .iter()
+            .map(|b| (b.token.as_str(), b.amount.as_str()))
+            .collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_sort_balances_orders_same_symbol_by_amount() {
+        let sorted = |balances: Vec<Balance>| -> Vec<String> {
+            sort_balances(balances)
+                .into_iter()
+                .map(|b| b.amount)
+                .collect()
+        };
+        let ids = [
+            balance("ETH", "1"),
+            balance("ITEM", "20"),
+            balance("ITEM", "3"),
+        ];
+
+        let expected = vec!["1", "3", "20"];
+        assert_eq!(sorted(ids.to_vec()), expected);
+        assert_eq!(
+            sorted(vec![ids[0].clone(), ids[2].clone(), ids[1].clone()]),
+            expected
+        );
+    }
+
     #[test]
     fn test_config_loads() {
         let config = Config::load();
         assert!(config.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_get_balances_with_config_uses_given_config() {
+        // A chain the shared config doesn't have, with an RPC URL that fails
+        // validation before any request is sent
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "chains": {
+                "local": {
+                    "type": "evm",
+                    "name": "Local",
+                    "rpc": "not a url",
+                    "chainId": 31337,
+                    "nativeToken": { "symbol": "ETH", "decimals": 18 },
+                    "tokens": {}
+                }
+            }
+        }))
+        .unwrap();
+
+        let error = get_balances_with_config(
+            &config,
+            "local",
+            "0x78697a9cfc48c1e9d1040172d51833ef78083b10",
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            BalanceError::InvalidRpcUrl { ref chain, .. } if chain == "local"
+        ));
+    }
+
+    fn mock_chain() -> Config {
+        r#"{
+            "chains": {
+                "local": {
+                    "type": "evm",
+                    "name": "Local",
+                    "rpc": "http://127.0.0.1:1",
+                    "chainId": 31337,
+                    "nativeToken": { "symbol": "ETH", "decimals": 18 },
+                    "tokens": {
+                        "USDC": { "address": "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238", "decimals": 6 },
+                        "EURC": { "address": "0x08210F9170F89Ab7658F0B5E3fF39b0E03C594D4", "decimals": 6 }
+                    }
+                }
+            }
+        }"#
+        .parse()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_provider_balances_are_labelled_and_sorted() {
+        let config = mock_chain();
+        let address = "0x78697a9cfc48c1e9d1040172d51833ef78083b10";
+        let tokens = config.chains["local"].tokens().unwrap();
+        let mut provider = testing::MockProvider::new()
+            .with_native(address, Balance::new("ETH".into(), "1".into(), 18).unwrap());
+        for token in &tokens {
+            provider = provider.with_token(address, token, balance(token.symbol(), "5"));
+        }
+
+        let balances = get_balances_with_provider(
+            &config,
+            "local",
+            address,
+            &QueryOptions::default(),
+            Box::new(provider),
+        )
+        .await
+        .unwrap();
+        let tokens: Vec<&str> = balances.iter().map(|b| b.token.as_str()).collect();
+        assert_eq!(tokens, vec!["ETH", "EURC", "USDC"]);
+        assert!(balances.iter().all(|b| b.chain.as_deref() == Some("local")
+            && b.address.as_deref() == Some(&normalize_address(address)[..])));
+    }
+
+    #[tokio::test]
+    async fn test_provider_failures_fail_the_read() {
+        let config = mock_chain();
+        let address = "0x78697a9cfc48c1e9d1040172d51833ef78083b10";
+        let provider =
+            testing::MockProvider::new().with_native_error(address, BalanceError::Timeout);
+
+        let error = get_balances_with_provider(
+            &config,
+            "local",
+            address,
+            &QueryOptions::default(),
+            Box::new(provider.clone()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error, BalanceError::Timeout);
+
+        // The address is validated before the provider is asked
+        let error = get_balances_with_provider(
+            &config,
+            "local",
+            "not-an-address",
+            &QueryOptions::default(),
+            Box::new(provider),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, BalanceError::InvalidAddress { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_yields_setup_errors() {
+        let options = QueryOptions::default();
+        let results: Vec<Result<Balance, BalanceError>> =
+            get_balances_streaming("sepolia", "not-an-address", &options)
+                .collect()
+                .await;
+        assert_eq!(results.len(), 1);
+        assert!(matches!(
+            results[0],
+            Err(BalanceError::InvalidAddress { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_portfolio_reports_each_chain_with_an_address() {
+        // RPC URLs fail validation, so every chain errors without a request
+        let chain = |chain_type: &str, network: &str| {
+            serde_json::json!({
+                "type": chain_type,
+                "name": chain_type,
+                "rpc": "not a url",
+                "chainId": 1,
+                "network": network,
+                "nativeToken": { "symbol": "X", "decimals": 18 },
+                "tokens": {}
+            })
+        };
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "chains": {
+                "zeta": chain("evm", "mainnet"),
+                "alpha": chain("evm", "testnet"),
+                "sol": chain("solana", "mainnet"),
+                "btc": chain("bitcoin", "mainnet")
+            }
+        }))
+        .unwrap();
+        let addresses = HashMap::from([
+            (
+                "evm".to_string(),
+                "0x78697a9cfc48c1e9d1040172d51833ef78083b10".to_string(),
+            ),
+            (
+                "solana".to_string(),
+                "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM".to_string(),
+            ),
+        ]);
+
+        let portfolio = get_portfolio(&config, &addresses, &QueryOptions::default()).await;
+        let chains: Vec<&str> = portfolio.iter().map(|(chain, _)| chain.as_str()).collect();
+        assert_eq!(chains, ["alpha", "sol", "zeta"]);
+        assert!(portfolio.iter().all(|(_, result)| result.is_err()));
+
+        let options = QueryOptions {
+            network: Some("mainnet".to_string()),
+            ..Default::default()
+        };
+        let portfolio = get_portfolio(&config, &addresses, &options).await;
+        let chains: Vec<&str> = portfolio.iter().map(|(chain, _)| chain.as_str()).collect();
+        assert_eq!(chains, ["sol", "zeta"]);
+    }
+
     #[tokio::test]
     #[ignore] // Requires network access
     async fn test_get_sepolia_balances() {
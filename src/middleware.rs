@@ -0,0 +1,624 @@
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::chain::ChainProvider;
+use crate::config::ChainConfig;
+use crate::error::BalanceError;
+use crate::options::QueryOptions;
+use crate::retry::RetryPolicy;
+use crate::types::{Balance, Token};
+
+/// Middleware stack of a chain, from config `middleware`. Unset layers are
+/// left out; see `ProviderStack::from_config` for the order they wrap in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MiddlewareConfig {
+    /// Total attempts per call for transient failures (1 disables retrying)
+    #[serde(rename = "retryAttempts", skip_serializing_if = "Option::is_none")]
+    pub retry_attempts: Option<u32>,
+    /// Reuse balances read less than this many seconds ago
+    #[serde(rename = "cacheTtlSecs", skip_serializing_if = "Option::is_none")]
+    pub cache_ttl_secs: Option<u64>,
+    /// Space calls to the provider to at most this many per second
+    #[serde(rename = "requestsPerSecond", skip_serializing_if = "Option::is_none")]
+    pub requests_per_second: Option<u32>,
+}
+
+/// Whether a provider error is worth retrying: a retryable `BalanceError`
+/// or an HTTP timeout or connection failure anywhere in its chain
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<BalanceError>() {
+            return error.is_retryable();
+        }
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            return error.is_timeout() || error.is_connect();
+        }
+        false
+    })
+}
+
+/// Retries calls of the inner provider that fail with a retryable `BalanceError`
+pub struct Retry<P> {
+    inner: P,
+    policy: RetryPolicy,
+}
+
+impl<P> Retry<P> {
+    pub fn new(inner: P, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+#[async_trait]
+impl<P: ChainProvider> ChainProvider for Retry<P> {
+    fn chain_name(&self) -> &str {
+        self.inner.chain_name()
+    }
+
+    fn native_symbol(&self) -> &str {
+        self.inner.native_symbol()
+    }
+
+    async fn get_balances(
+        &self,
+        config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        self.policy
+            .run_async(
+                || self.inner.get_balances(config, address, options),
+                BalanceError::is_retryable,
+            )
+            .await
+    }
+
+    /// Not retried: the reads already yielded can't be taken back
+    fn balance_stream<'a>(
+        &'a self,
+        config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        self.inner.balance_stream(config, address, options)
+    }
+
+    fn active_endpoint(&self) -> Option<&str> {
+        self.inner.active_endpoint()
+    }
+
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        self.policy
+            .run_async(
+                || self.inner.get_native_balance(address),
+                BalanceError::is_retryable,
+            )
+            .await
+    }
+
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        self.policy
+            .run_async(
+                || self.inner.get_token_balance(address, token),
+                BalanceError::is_retryable,
+            )
+            .await
+    }
+
+    async fn get_all_balances(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Balance>, BalanceError> {
+        self.policy
+            .run_async(
+                || self.inner.get_all_balances(address, tokens),
+                BalanceError::is_retryable,
+            )
+            .await
+    }
+}
+
+/// A `get_balances` result and when it was read
+type CachedQuery = (Instant, Vec<Balance>);
+
+/// Serves balances read less than `ttl` ago from memory. Entries are keyed
+/// by address and token contract only, so use one `Cache` per chain.
+pub struct Cache<P> {
+    inner: P,
+    ttl: Duration,
+    entries: Mutex<HashMap<(String, String), (Instant, Balance)>>,
+    /// Whole `get_balances` results, keyed by address and options
+    queries: Mutex<HashMap<(String, String), CachedQuery>>,
+}
+
+impl<P> Cache<P> {
+    pub fn new(inner: P, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            queries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key(address: &str, token: Option<&Token>) -> (String, String) {
+        let token = token.map(|token| match token {
+            // Same amount as the native balance but labelled differently
+            Token::Native { symbol, .. } => format!("native:{}", symbol),
+            token => token.address().to_lowercase(),
+        });
+        (address.to_lowercase(), token.unwrap_or_default())
+    }
+
+    fn get(&self, key: &(String, String)) -> Option<Balance> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(read_at, _)| read_at.elapsed() < self.ttl)
+            .map(|(_, balance)| balance.clone())
+    }
+
+    fn put(&self, key: (String, String), balance: &Balance) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key, (Instant::now(), balance.clone()));
+    }
+
+    fn query_key(address: &str, options: &QueryOptions) -> (String, String) {
+        (address.to_lowercase(), format!("{:?}", options))
+    }
+
+    /// A `get_balances` result read less than `ttl` ago
+    fn cached_query(&self, key: &(String, String)) -> Option<Vec<Balance>> {
+        let queries = self.queries.lock().unwrap();
+        queries
+            .get(key)
+            .filter(|(read_at, _)| read_at.elapsed() < self.ttl)
+            .map(|(_, balances)| balances.clone())
+    }
+}
+
+#[async_trait]
+impl<P: ChainProvider> ChainProvider for Cache<P> {
+    fn chain_name(&self) -> &str {
+        self.inner.chain_name()
+    }
+
+    fn native_symbol(&self) -> &str {
+        self.inner.native_symbol()
+    }
+
+    /// Answered from the cache when the same query (address and options)
+    /// was read less than `ttl` ago
+    async fn get_balances(
+        &self,
+        config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        let key = Self::query_key(address, options);
+        if let Some(balances) = self.cached_query(&key) {
+            return Ok(balances);
+        }
+
+        let balances = self.inner.get_balances(config, address, options).await?;
+        let mut queries = self.queries.lock().unwrap();
+        queries.insert(key, (Instant::now(), balances.clone()));
+        Ok(balances)
+    }
+
+    /// A fresh cached `get_balances` result as one item, otherwise the
+    /// inner stream, which isn't cached
+    fn balance_stream<'a>(
+        &'a self,
+        config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        match self.cached_query(&Self::query_key(address, options)) {
+            Some(balances) => futures::stream::once(async { Ok(balances) }).boxed(),
+            None => self.inner.balance_stream(config, address, options),
+        }
+    }
+
+    fn active_endpoint(&self) -> Option<&str> {
+        self.inner.active_endpoint()
+    }
+
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        let key = Self::key(address, None);
+        if let Some(balance) = self.get(&key) {
+            return Ok(balance);
+        }
+        let balance = self.inner.get_native_balance(address).await?;
+        self.put(key, &balance);
+        Ok(balance)
+    }
+
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        let key = Self::key(address, Some(token));
+        if let Some(balance) = self.get(&key) {
+            return Ok(balance);
+        }
+        let balance = self.inner.get_token_balance(address, token).await?;
+        self.put(key, &balance);
+        Ok(balance)
+    }
+
+    /// Answered from the cache only when every balance is fresh, otherwise
+    /// read in one inner call (e.g. one multicall) and cached
+    async fn get_all_balances(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Balance>, BalanceError> {
+        let keys: Vec<_> = std::iter::once(None)
+            .chain(tokens.iter().map(Some))
+            .map(|token| Self::key(address, token))
+            .collect();
+        let cached: Option<Vec<Balance>> = keys.iter().map(|key| self.get(key)).collect();
+        if let Some(balances) = cached {
+            return Ok(balances);
+        }
+
+        let balances = self.inner.get_all_balances(address, tokens).await?;
+        if balances.len() == keys.len() {
+            for (key, balance) in keys.into_iter().zip(&balances) {
+                self.put(key, balance);
+            }
+        }
+        Ok(balances)
+    }
+}
+
+/// Spaces calls to the inner provider evenly, at most `requests_per_second`.
+/// `get_all_balances` and `get_balances` count as one call however many
+/// tokens they read.
+pub struct RateLimited<P> {
+    inner: P,
+    interval: Duration,
+    next_slot: tokio::sync::Mutex<Instant>,
+}
+
+impl<P> RateLimited<P> {
+    pub fn new(inner: P, requests_per_second: u32) -> Self {
+        Self {
+            inner,
+            interval: Duration::from_secs(1) / requests_per_second.max(1),
+            next_slot: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for this call's turn
+    async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+#[async_trait]
+impl<P: ChainProvider> ChainProvider for RateLimited<P> {
+    fn chain_name(&self) -> &str {
+        self.inner.chain_name()
+    }
+
+    fn native_symbol(&self) -> &str {
+        self.inner.native_symbol()
+    }
+
+    async fn get_balances(
+        &self,
+        config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        self.acquire().await;
+        self.inner.get_balances(config, address, options).await
+    }
+
+    /// Counts as one call, made when the stream is first polled
+    fn balance_stream<'a>(
+        &'a self,
+        config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        futures::stream::once(self.acquire())
+            .flat_map(move |()| self.inner.balance_stream(config, address, options))
+            .boxed()
+    }
+
+    fn active_endpoint(&self) -> Option<&str> {
+        self.inner.active_endpoint()
+    }
+
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        self.acquire().await;
+        self.inner.get_native_balance(address).await
+    }
+
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        self.acquire().await;
+        self.inner.get_token_balance(address, token).await
+    }
+
+    async fn get_all_balances(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Balance>, BalanceError> {
+        self.acquire().await;
+        self.inner.get_all_balances(address, tokens).await
+    }
+}
+
+#[async_trait]
+impl ChainProvider for Box<dyn ChainProvider> {
+    fn chain_name(&self) -> &str {
+        (**self).chain_name()
+    }
+
+    fn native_symbol(&self) -> &str {
+        (**self).native_symbol()
+    }
+
+    async fn get_balances(
+        &self,
+        config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        (**self).get_balances(config, address, options).await
+    }
+
+    fn balance_stream<'a>(
+        &'a self,
+        config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        (**self).balance_stream(config, address, options)
+    }
+
+    fn active_endpoint(&self) -> Option<&str> {
+        (**self).active_endpoint()
+    }
+
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        (**self).get_native_balance(address).await
+    }
+
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        (**self).get_token_balance(address, token).await
+    }
+
+    async fn get_all_balances(
+        &self,
+        address: &str,
+        tokens: &[Token],
+    ) -> Result<Vec<Balance>, BalanceError> {
+        (**self).get_all_balances(address, tokens).await
+    }
+}
+
+/// Builder stacking middleware around a provider; each layer wraps the
+/// ones added before it, e.g.
+/// `ProviderStack::new(provider).rate_limit(5).retry(policy).cache(ttl).build()`
+/// checks the cache first and retries calls that each wait for the rate limit.
+pub struct ProviderStack {
+    provider: Box<dyn ChainProvider>,
+}
+
+impl ProviderStack {
+    pub fn new(provider: impl ChainProvider + 'static) -> Self {
+        Self {
+            provider: Box::new(provider),
+        }
+    }
+
+    /// The layers set in `config`: rate limit innermost, so every retry
+    /// attempt is limited too, then retry, then the cache outermost
+    pub fn from_config(provider: impl ChainProvider + 'static, config: &MiddlewareConfig) -> Self {
+        let mut stack = Self::new(provider);
+        if let Some(requests_per_second) = config.requests_per_second {
+            stack = stack.rate_limit(requests_per_second);
+        }
+        if let Some(max_attempts) = config.retry_attempts {
+            stack = stack.retry(RetryPolicy {
+                max_attempts,
+                ..Default::default()
+            });
+        }
+        if let Some(ttl) = config.cache_ttl_secs {
+            stack = stack.cache(Duration::from_secs(ttl));
+        }
+        stack
+    }
+
+    pub fn retry(self, policy: RetryPolicy) -> Self {
+        Self::new(Retry::new(self.provider, policy))
+    }
+
+    pub fn cache(self, ttl: Duration) -> Self {
+        Self::new(Cache::new(self.provider, ttl))
+    }
+
+    pub fn rate_limit(self, requests_per_second: u32) -> Self {
+        Self::new(RateLimited::new(self.provider, requests_per_second))
+    }
+
+    /// Wrap the stack in a custom layer
+    pub fn layer<L: ChainProvider + 'static>(
+        self,
+        layer: impl FnOnce(Box<dyn ChainProvider>) -> L,
+    ) -> Self {
+        Self::new(layer(self.provider))
+    }
+
+    pub fn build(self) -> Box<dyn ChainProvider> {
+        self.provider
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    /// Fails the first `failures` calls with a retryable error, then returns 1 ETH
+    struct Flaky {
+        calls: Arc<AtomicU32>,
+        failures: u32,
+    }
+
+    #[async_trait]
+    impl ChainProvider for Flaky {
+        fn chain_name(&self) -> &str {
+            "Flaky"
+        }
+
+        fn native_symbol(&self) -> &str {
+            "ETH"
+        }
+
+        async fn get_native_balance(&self, _address: &str) -> Result<Balance, BalanceError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            match call <= self.failures {
+                true => Err(BalanceError::Timeout),
+                false => Ok(Balance::new(
+                    "ETH".to_string(),
+                    "1000000000000000000".to_string(),
+                    18,
+                )?),
+            }
+        }
+
+        async fn get_token_balance(
+            &self,
+            _address: &str,
+            _token: &Token,
+        ) -> Result<Balance, BalanceError> {
+            Err(BalanceError::InvalidAddress {
+                address: "0x".to_string(),
+            })
+        }
+    }
+
+    fn flaky(failures: u32) -> (Flaky, Arc<AtomicU32>) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let provider = Flaky {
+            calls: calls.clone(),
+            failures,
+        };
+        (provider, calls)
+    }
+
+    fn quick_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_recovers_from_transient_errors_only() {
+        let (provider, calls) = flaky(2);
+        let provider = ProviderStack::new(provider).retry(quick_retry()).build();
+        assert!(provider.get_native_balance("0xabc").await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let token = Token::Erc20 {
+            address: "0xdef".to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+        assert!(provider.get_token_balance("0xabc", &token).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cache_outside_retry_serves_repeated_reads() {
+        let (provider, calls) = flaky(1);
+        let provider = ProviderStack::new(provider)
+            .retry(quick_retry())
+            .cache(Duration::from_secs(60))
+            .build();
+        for _ in 0..3 {
+            let balance = provider.get_native_balance("0xABC").await.unwrap();
+            assert_eq!(balance.formatted, "1");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_layers_apply_to_get_balances() {
+        let chain: ChainConfig = serde_json::from_value(serde_json::json!({
+            "type": "evm",
+            "name": "Flaky",
+            "rpc": "https://rpc.example.com",
+            "nativeToken": { "symbol": "ETH", "decimals": 18 },
+            "tokens": {}
+        }))
+        .unwrap();
+        let (provider, calls) = flaky(1);
+        let provider = ProviderStack::new(provider)
+            .retry(quick_retry())
+            .cache(Duration::from_secs(60))
+            .build();
+
+        let options = QueryOptions::default();
+        for _ in 0..3 {
+            let balances = provider
+                .get_balances(&chain, "0xabc", &options)
+                .await
+                .unwrap();
+            assert_eq!(balances[0].formatted, "1");
+        }
+        // One retried failure, then every query served from the cache
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_spaces_calls() {
+        let (provider, _) = flaky(0);
+        let config = MiddlewareConfig {
+            requests_per_second: Some(20),
+            ..Default::default()
+        };
+        let provider = ProviderStack::from_config(provider, &config).build();
+        let start = Instant::now();
+        for _ in 0..3 {
+            provider.get_native_balance("0xabc").await.unwrap();
+        }
+        // The first call goes out at once, the next two 50ms apart
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::str::FromStr;
+
+use crate::http::{HttpConfig, RequestId};
+use crate::types::TokenSpec;
+
+/// Block EVM balances are read at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockTag {
+    #[default]
+    Latest,
+    /// Latest block plus the node's pending mempool transactions. Node-dependent:
+    /// some nodes treat it as `latest`, and two reads may differ as the
+    /// mempool changes, so results are not reproducible.
+    Pending,
+    Safe,
+    Finalized,
+    Number(u64),
+}
+
+/// Parses `latest`, `pending`, `safe`, `finalized` or a block number (decimal or 0x hex)
+impl FromStr for BlockTag {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "latest" => Ok(BlockTag::Latest),
+            "pending" => Ok(BlockTag::Pending),
+            "safe" => Ok(BlockTag::Safe),
+            "finalized" => Ok(BlockTag::Finalized),
+            other => {
+                let number = match other.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => other.parse(),
+                };
+                number.map(BlockTag::Number).map_err(|_| {
+                    anyhow!(
+                        "Invalid block tag '{}', expected latest, pending, safe, finalized or a number",
+                        s
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// JSON-RPC form: the tag name or a 0x-prefixed hex block number
+impl std::fmt::Display for BlockTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockTag::Latest => write!(f, "latest"),
+            BlockTag::Pending => write!(f, "pending"),
+            BlockTag::Safe => write!(f, "safe"),
+            BlockTag::Finalized => write!(f, "finalized"),
+            BlockTag::Number(number) => write!(f, "{:#x}", number),
+        }
+    }
+}
+
+/// What batch queries (several chains or blocks) do when one item fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Fail the whole batch on the first error
+    #[default]
+    Abort,
+    /// Keep going and report the failed items next to the successful ones
+    Skip,
+}
+
+impl FromStr for OnError {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "skip" => Ok(Self::Skip),
+            _ => Err(anyhow!(
+                "Unknown error mode '{}' (expected abort or skip)",
+                s
+            )),
+        }
+    }
+}
+
+/// Per-query settings; `QueryOptions::default()` matches plain `get_balances`
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Correlation ID attached as a header to every RPC request (opt-in)
+    pub request_id: Option<RequestId>,
+    /// Generate a fresh correlation ID under this header for each query
+    /// (each chain of a batch, each address, each `ChainReader`) and log it
+    /// at info level. Unused when `request_id` is set.
+    pub request_id_header: Option<String>,
+    /// Also report the wrapped SOL (wSOL) SPL balance on Solana chains
+    pub include_wrapped: bool,
+    /// Don't fall back to the built-in public RPC list when the configured endpoint fails
+    pub disable_fallback_rpc: bool,
+    /// Report a reverted ERC-20 `balanceOf` as zero instead of failing the query.
+    /// Per-token `treatRevertAsZero` in the config takes precedence.
+    pub treat_revert_as_zero: bool,
+    /// Ad-hoc tokens queried in addition to the configured ones
+    pub extra_tokens: Vec<TokenSpec>,
+    /// HTTP client tuning; set fields override the config's `http` section
+    pub http: HttpConfig,
+    /// Query the `mainnet`/`testnet` sibling of the requested chain, see `Config::resolve_chain`
+    pub network: Option<String>,
+    /// Block that EVM native and token balances are read at; queries on other
+    /// chains fail unless it is `latest`
+    pub block_tag: BlockTag,
+    /// Commitment Solana balances are read at (EVM ignores it); `confirmed`
+    /// when unset, see `SolanaProvider::with_commitment`
+    pub solana_commitment: Option<CommitmentConfig>,
+    /// Fail with `BalanceError::StaleNode` when that block (or the current
+    /// Solana slot) is more than this many seconds old
+    pub max_staleness_secs: Option<u64>,
+    /// How `get_balances_for_chains` and `balance_history` handle a failing
+    /// chain or block
+    pub on_error: OnError,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_tag_parse_and_display() {
+        assert_eq!("pending".parse::<BlockTag>().unwrap(), BlockTag::Pending);
+        assert_eq!(
+            "Finalized".parse::<BlockTag>().unwrap(),
+            BlockTag::Finalized
+        );
+        assert_eq!(
+            "7000000".parse::<BlockTag>().unwrap(),
+            BlockTag::Number(7_000_000)
+        );
+        assert_eq!("0x10".parse::<BlockTag>().unwrap(), BlockTag::Number(16));
+        assert!("earliest-ish".parse::<BlockTag>().is_err());
+
+        assert_eq!(BlockTag::Pending.to_string(), "pending");
+        assert_eq!(BlockTag::Number(16).to_string(), "0x10");
+    }
+
+    #[test]
+    fn test_on_error_parse() {
+        assert_eq!("abort".parse::<OnError>().unwrap(), OnError::Abort);
+        assert_eq!("skip".parse::<OnError>().unwrap(), OnError::Skip);
+        assert!("ignore".parse::<OnError>().is_err());
+        assert_eq!(OnError::default(), OnError::Abort);
+    }
+}
@@ -0,0 +1,431 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::ethereum::EthereumProvider;
+use crate::fallback::public_rpcs;
+
+/// Backend used to look up fiat prices
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PriceSource {
+    #[default]
+    #[serde(rename = "coingecko")]
+    CoinGecko,
+    #[serde(rename = "coinmarketcap")]
+    CoinMarketCap,
+    /// Chainlink price feeds read over EVM RPC, no external price API involved
+    #[serde(rename = "chainlink-onchain")]
+    ChainlinkOnchain,
+}
+
+impl FromStr for PriceSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "coingecko" => Ok(Self::CoinGecko),
+            "coinmarketcap" => Ok(Self::CoinMarketCap),
+            "chainlink-onchain" => Ok(Self::ChainlinkOnchain),
+            _ => Err(anyhow!(
+                "Unknown price source '{}' (expected coingecko, coinmarketcap or chainlink-onchain)",
+                s
+            )),
+        }
+    }
+}
+
+/// Price lookup settings from the `price` section of `config.json`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct PriceConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<PriceSource>,
+    /// CoinMarketCap API key; the `CMC_API_KEY` environment variable is used when unset
+    #[serde(
+        rename = "coinmarketcapApiKey",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub coinmarketcap_api_key: Option<String>,
+    /// Ethereum mainnet RPC used to read Chainlink feeds (defaults to a public endpoint)
+    #[serde(rename = "chainlinkRpc", skip_serializing_if = "Option::is_none")]
+    pub chainlink_rpc: Option<String>,
+    /// Extra or overriding symbol -> Chainlink USD feed addresses
+    #[serde(
+        rename = "chainlinkFeeds",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub chainlink_feeds: HashMap<String, String>,
+    /// Extra or overriding symbol -> CoinGecko coin IDs, used to price
+    /// tokens the bundled table doesn't know
+    #[serde(
+        rename = "coingeckoIds",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub coingecko_ids: HashMap<String, String>,
+}
+
+/// Build the price provider selected by `config.source`
+pub fn price_provider(config: &PriceConfig) -> Result<Box<dyn PriceProvider>> {
+    match config.source.unwrap_or_default() {
+        PriceSource::CoinGecko => {
+            let mut provider = CoinGeckoPriceProvider::new();
+            for (symbol, coin_id) in &config.coingecko_ids {
+                provider = provider.with_coin_id(symbol, coin_id);
+            }
+            Ok(Box::new(provider))
+        }
+        PriceSource::CoinMarketCap => {
+            let api_key = match &config.coinmarketcap_api_key {
+                Some(key) => key.clone(),
+                None => std::env::var("CMC_API_KEY").map_err(|_| {
+                    anyhow!("CoinMarketCap needs coinmarketcapApiKey in config or CMC_API_KEY")
+                })?,
+            };
+            Ok(Box::new(CoinMarketCapPriceProvider::new(api_key)))
+        }
+        PriceSource::ChainlinkOnchain => {
+            let rpc = match &config.chainlink_rpc {
+                Some(rpc) => rpc.clone(),
+                None => public_rpcs(1)[0].to_string(),
+            };
+            let mut provider = ChainlinkPriceProvider::new(EthereumProvider::new(rpc));
+            for (symbol, feed) in &config.chainlink_feeds {
+                provider = provider.with_feed(symbol, feed);
+            }
+            Ok(Box::new(provider))
+        }
+    }
+}
+
+/// Source of fiat (USD) unit prices for token symbols
+#[async_trait]
+pub trait PriceProvider: Send + Sync {
+    /// Get the USD price of one unit of `symbol`
+    async fn get_price(&self, symbol: &str) -> Result<f64>;
+
+    /// USD prices of several symbols, keyed by the symbols as given. Symbols
+    /// without a price are left out. Providers with a batch API override this
+    /// to make one request; the default looks each symbol up in turn.
+    async fn get_prices(&self, symbols: &[String]) -> Result<HashMap<String, f64>> {
+        let mut prices = HashMap::new();
+        for symbol in symbols {
+            if prices.contains_key(symbol) {
+                continue;
+            }
+            if let Ok(price) = self.get_price(symbol).await {
+                prices.insert(symbol.clone(), price);
+            }
+        }
+        Ok(prices)
+    }
+}
+
+/// Price provider backed by the public CoinGecko `simple/price` API
+pub struct CoinGeckoPriceProvider {
+    base_url: String,
+    client: reqwest::Client,
+    coin_ids: HashMap<String, String>,
+}
+
+impl CoinGeckoPriceProvider {
+    pub fn new() -> Self {
+        Self::with_base_url("https://api.coingecko.com/api/v3".to_string())
+    }
+
+    pub fn with_base_url(base_url: String) -> Self {
+        Self {
+            base_url,
+            client: reqwest::Client::new(),
+            coin_ids: default_coin_ids(),
+        }
+    }
+
+    /// Map an additional symbol to its CoinGecko coin ID
+    pub fn with_coin_id(mut self, symbol: &str, coin_id: &str) -> Self {
+        self.coin_ids
+            .insert(symbol.to_uppercase(), coin_id.to_string());
+        self
+    }
+
+    fn coin_id(&self, symbol: &str) -> Option<&str> {
+        self.coin_ids
+            .get(&symbol.to_uppercase())
+            .map(String::as_str)
+    }
+
+    /// Map a `simple/price` response keyed by coin ID back to `symbols`
+    fn prices_by_symbol(
+        &self,
+        symbols: &[String],
+        response: &HashMap<String, HashMap<String, f64>>,
+    ) -> HashMap<String, f64> {
+        symbols
+            .iter()
+            .filter_map(|symbol| {
+                let price = response.get(self.coin_id(symbol)?)?.get("usd")?;
+                Some((symbol.clone(), *price))
+            })
+            .collect()
+    }
+}
+
+impl Default for CoinGeckoPriceProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl PriceProvider for CoinGeckoPriceProvider {
+    async fn get_price(&self, symbol: &str) -> Result<f64> {
+        if self.coin_id(symbol).is_none() {
+            return Err(anyhow!("No CoinGecko coin ID known for symbol {}", symbol));
+        }
+        self.get_prices(&[symbol.to_string()])
+            .await?
+            .remove(symbol)
+            .ok_or_else(|| anyhow!("CoinGecko returned no USD price for {}", symbol))
+    }
+
+    /// All known symbols in one `simple/price` request (comma-separated ids)
+    async fn get_prices(&self, symbols: &[String]) -> Result<HashMap<String, f64>> {
+        let mut ids: Vec<&str> = symbols
+            .iter()
+            .filter_map(|symbol| self.coin_id(symbol))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let url = format!("{}/simple/price", self.base_url);
+        let response: HashMap<String, HashMap<String, f64>> = self
+            .client
+            .get(url)
+            .query(&[("ids", ids.join(",").as_str()), ("vs_currencies", "usd")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(self.prices_by_symbol(symbols, &response))
+    }
+}
+
+/// Price provider backed by the CoinMarketCap `quotes/latest` API (requires an API key)
+pub struct CoinMarketCapPriceProvider {
+    base_url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl CoinMarketCapPriceProvider {
+    pub fn new(api_key: String) -> Self {
+        Self::with_base_url("https://pro-api.coinmarketcap.com".to_string(), api_key)
+    }
+
+    pub fn with_base_url(base_url: String, api_key: String) -> Self {
+        Self {
+            base_url,
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for CoinMarketCapPriceProvider {
+    async fn get_price(&self, symbol: &str) -> Result<f64> {
+        // Wrapped SOL has no listing of its own and trades at SOL's price
+        let symbol = match symbol.to_uppercase().as_str() {
+            "WSOL" => "SOL".to_string(),
+            other => other.to_string(),
+        };
+
+        let url = format!("{}/v2/cryptocurrency/quotes/latest", self.base_url);
+        let response: serde_json::Value = self
+            .client
+            .get(url)
+            .header("X-CMC_PRO_API_KEY", &self.api_key)
+            .query(&[("symbol", symbol.as_str()), ("convert", "USD")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        parse_cmc_price(&response, &symbol)
+    }
+}
+
+/// USD price of the first (highest ranked) listing for `symbol`
+fn parse_cmc_price(response: &serde_json::Value, symbol: &str) -> Result<f64> {
+    response["data"][symbol][0]["quote"]["USD"]["price"]
+        .as_f64()
+        .ok_or_else(|| anyhow!("CoinMarketCap returned no USD price for {}", symbol))
+}
+
+/// Price provider reading Chainlink USD price feeds on Ethereum mainnet
+pub struct ChainlinkPriceProvider {
+    provider: EthereumProvider,
+    feeds: HashMap<String, String>,
+}
+
+impl ChainlinkPriceProvider {
+    pub fn new(provider: EthereumProvider) -> Self {
+        Self {
+            provider,
+            feeds: default_chainlink_feeds(),
+        }
+    }
+
+    /// Map an additional symbol to its USD feed address
+    pub fn with_feed(mut self, symbol: &str, feed_address: &str) -> Self {
+        self.feeds
+            .insert(symbol.to_uppercase(), feed_address.to_string());
+        self
+    }
+
+    fn feed(&self, symbol: &str) -> Option<&str> {
+        self.feeds.get(&symbol.to_uppercase()).map(String::as_str)
+    }
+}
+
+#[async_trait]
+impl PriceProvider for ChainlinkPriceProvider {
+    async fn get_price(&self, symbol: &str) -> Result<f64> {
+        let feed = self
+            .feed(symbol)
+            .ok_or_else(|| anyhow!("No Chainlink feed known for symbol {}", symbol))?;
+
+        let (answer, decimals) = self.provider.read_price_feed(feed).await?;
+        let answer: f64 = answer.parse()?;
+        Ok(answer / 10f64.powi(decimals as i32))
+    }
+}
+
+/// Chainlink USD feeds on Ethereum mainnet for the symbols in the bundled config.
+/// EURC has no dedicated feed; the EUR/USD feed would hide a depeg, so it is left out.
+fn default_chainlink_feeds() -> HashMap<String, String> {
+    [
+        ("ETH", "0x5f4eC3Df9cbd43714FE2740f5E3616155c5b8419"),
+        ("SOL", "0x4ffC43a60e009B551865A93d232E33Fce9f01507"),
+        ("WSOL", "0x4ffC43a60e009B551865A93d232E33Fce9f01507"),
+        ("USDC", "0x8fFfFfd4AfB6115b954Bd326cbe7B4BA576818f6"),
+    ]
+    .into_iter()
+    .map(|(symbol, feed)| (symbol.to_string(), feed.to_string()))
+    .collect()
+}
+
+/// CoinGecko coin IDs for the symbols in the bundled config
+fn default_coin_ids() -> HashMap<String, String> {
+    [
+        ("ETH", "ethereum"),
+        ("SOL", "solana"),
+        ("WSOL", "wrapped-solana"),
+        ("USDC", "usd-coin"),
+        ("EURC", "euro-coin"),
+    ]
+    .into_iter()
+    .map(|(symbol, id)| (symbol.to_string(), id.to_string()))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coin_id_lookup_is_case_insensitive() {
+        let provider = CoinGeckoPriceProvider::new().with_coin_id("dai", "dai");
+        assert_eq!(provider.coin_id("usdc"), Some("usd-coin"));
+        assert_eq!(provider.coin_id("wSOL"), Some("wrapped-solana"));
+        assert_eq!(provider.coin_id("DAI"), Some("dai"));
+        assert_eq!(provider.coin_id("UNKNOWN"), None);
+    }
+
+    #[test]
+    fn test_prices_by_symbol_maps_ids_back() {
+        let provider = CoinGeckoPriceProvider::new();
+        let response: HashMap<String, HashMap<String, f64>> = serde_json::from_str(
+            r#"{ "usd-coin": { "usd": 0.9998 }, "ethereum": { "usd": 2500.0 } }"#,
+        )
+        .unwrap();
+        let symbols = ["usdc", "ETH", "EURC", "UNKNOWN"].map(String::from);
+
+        let prices = provider.prices_by_symbol(&symbols, &response);
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices["usdc"], 0.9998);
+        assert_eq!(prices["ETH"], 2500.0);
+    }
+
+    #[test]
+    fn test_price_source_parse() {
+        assert_eq!(
+            "coingecko".parse::<PriceSource>().unwrap(),
+            PriceSource::CoinGecko
+        );
+        assert_eq!(
+            "chainlink-onchain".parse::<PriceSource>().unwrap(),
+            PriceSource::ChainlinkOnchain
+        );
+        assert!("binance".parse::<PriceSource>().is_err());
+
+        let config: PriceConfig =
+            serde_json::from_str(r#"{ "source": "coinmarketcap", "coinmarketcapApiKey": "k" }"#)
+                .unwrap();
+        assert_eq!(config.source, Some(PriceSource::CoinMarketCap));
+    }
+
+    #[test]
+    fn test_parse_cmc_price() {
+        let response = serde_json::json!({
+            "data": { "USDC": [{ "quote": { "USD": { "price": 0.9998 } } }] }
+        });
+        assert_eq!(parse_cmc_price(&response, "USDC").unwrap(), 0.9998);
+        assert!(parse_cmc_price(&response, "EURC").is_err());
+    }
+
+    #[test]
+    fn test_chainlink_feed_lookup_is_case_insensitive() {
+        let provider = ChainlinkPriceProvider::new(EthereumProvider::new(String::new()))
+            .with_feed("link", "0x2c1d072e956AFFC0D435Cb7AC38EF18d24d9127c");
+        assert!(provider.feed("usdc").is_some());
+        assert!(provider.feed("LINK").is_some());
+        assert_eq!(provider.feed("EURC"), None);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_chainlink_usdc_price_near_peg() {
+        let provider = price_provider(&PriceConfig {
+            source: Some(PriceSource::ChainlinkOnchain),
+            ..Default::default()
+        })
+        .unwrap();
+        let price = provider.get_price("USDC").await.unwrap();
+        assert!(
+            price > 0.9 && price < 1.1,
+            "USDC price {} is off-peg",
+            price
+        );
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_coingecko_usdc_price_near_peg() {
+        let provider = CoinGeckoPriceProvider::new();
+        let price = provider.get_price("USDC").await.unwrap();
+        assert!(
+            price > 0.9 && price < 1.1,
+            "USDC price {} is off-peg",
+            price
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::future::Future;
+use std::time::Duration;
+
+/// How transient RPC failures are retried: up to `max_attempts` tries in
+/// total, waiting `base_delay` before the second, twice that before the third
+/// and so on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Try once, never retry
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+        }
+    }
+
+    /// Wait after failed attempt number `attempt` (1-based)
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(1u32 << (attempt - 1).min(16))
+    }
+
+    /// Call `call` until it succeeds, fails with an error `is_transient`
+    /// rejects, or the attempts run out; the last error is returned
+    pub async fn run<T, E>(
+        &self,
+        mut call: impl FnMut() -> Result<T, E>,
+        is_transient: impl Fn(&E) -> bool,
+    ) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match call() {
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    tokio::time::sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// `run` for async calls, e.g. a provider method
+    pub async fn run_async<T, E, F: Future<Output = Result<T, E>>>(
+        &self,
+        mut call: impl FnMut() -> F,
+        is_transient: impl Fn(&E) -> bool,
+    ) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match call().await {
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    tokio::time::sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_delay_doubles() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(800));
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_are_not_retried() {
+        let mut calls = 0;
+        let result: Result<(), &str> = quick(3)
+            .run(
+                || {
+                    calls += 1;
+                    Err("bad address")
+                },
+                |_| false,
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let mut calls = 0;
+        let result: Result<(), &str> = quick(3)
+            .run(
+                || {
+                    calls += 1;
+                    Err("node behind")
+                },
+                |_| true,
+            )
+            .await;
+        assert_eq!(result, Err("node behind"));
+        assert_eq!(calls, 3);
+    }
+}
@@ -0,0 +1,382 @@
+use alloy::primitives::U256;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bitcoin::BitcoinProvider;
+use crate::config::{ChainConfig, Config};
+use crate::ethereum::EthereumProvider;
+use crate::fallback::rpc_candidates;
+use crate::options::QueryOptions;
+use crate::price::{price_provider, PriceProvider};
+use crate::solana::SolanaProvider;
+use crate::types::{Balance, BalanceKind};
+
+/// Everything known about an address on one chain at one point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub chain: String,
+    pub address: String,
+    /// Block number (EVM) or slot (Solana) the balances were read at, the
+    /// newest one if tokens were read at different `confirmations`. Chains
+    /// whose reads don't report one (Bitcoin) get the tip read after them.
+    pub block: u64,
+    /// Unix time in seconds when the snapshot was taken
+    pub timestamp: u64,
+    pub balances: Vec<Balance>,
+    /// USD value of all fungible balances, `None` if any non-zero one has no price
+    #[serde(rename = "fiatTotal")]
+    pub fiat_total: Option<f64>,
+}
+
+/// Raw amounts of one token in two snapshots, rescaled to common decimals
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenDelta {
+    pub token: String,
+    pub decimals: u8,
+    /// Raw amount in the earlier snapshot, `"0"` for an added token
+    pub previous: String,
+    /// Raw amount in the later snapshot, `"0"` for a removed token
+    pub current: String,
+    /// Exact signed raw change, e.g. `"-1500000"`
+    pub delta: String,
+}
+
+/// Per-token differences between two snapshots, each list in token order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    /// Tokens only in the later snapshot
+    pub added: Vec<TokenDelta>,
+    /// Tokens only in the earlier snapshot
+    pub removed: Vec<TokenDelta>,
+    /// Tokens in both whose amount changed
+    pub changed: Vec<TokenDelta>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Snapshot {
+    /// What changed from `self` to the later snapshot `other`. Amounts of a
+    /// token with different decimals on each side are compared at the larger
+    /// decimals, so deltas are exact; fails on an amount that isn't an integer.
+    pub fn diff(&self, other: &Snapshot) -> Result<SnapshotDiff> {
+        diff_balances(&self.balances, &other.balances)
+    }
+}
+
+/// `Snapshot::diff` for plain balance lists
+pub fn diff_balances(previous: &[Balance], current: &[Balance]) -> Result<SnapshotDiff> {
+    let mut tokens: Vec<&str> = previous
+        .iter()
+        .chain(current)
+        .map(|balance| balance.token.as_str())
+        .collect();
+    tokens.sort_unstable();
+    tokens.dedup();
+
+    let mut diff = SnapshotDiff::default();
+    for token in tokens {
+        let before = previous.iter().find(|balance| balance.token == token);
+        let after = current.iter().find(|balance| balance.token == token);
+        let decimals = before
+            .iter()
+            .chain(after.iter())
+            .map(|balance| balance.decimals)
+            .max()
+            .unwrap_or_default();
+        // 256-bit, so no EVM amount is too large to diff
+        let units = |balance: Option<&Balance>| -> Result<U256> {
+            let Some(balance) = balance else {
+                return Ok(U256::ZERO);
+            };
+            let raw = U256::from_str_radix(&balance.amount, 10).map_err(|_| {
+                anyhow!(
+                    "Invalid raw amount '{}' for {}",
+                    balance.amount,
+                    balance.token
+                )
+            })?;
+            U256::from(10)
+                .checked_pow(U256::from(decimals - balance.decimals))
+                .and_then(|factor| raw.checked_mul(factor))
+                .ok_or_else(|| anyhow!("Amount of {} overflows at {} decimals", token, decimals))
+        };
+        let (old, new) = (units(before)?, units(after)?);
+
+        let delta = TokenDelta {
+            token: token.to_string(),
+            decimals,
+            previous: old.to_string(),
+            current: new.to_string(),
+            delta: match new >= old {
+                true => (new - old).to_string(),
+                false => format!("-{}", old - new),
+            },
+        };
+        match (before, after) {
+            (None, Some(_)) => diff.added.push(delta),
+            (Some(_), None) => diff.removed.push(delta),
+            _ if old != new => diff.changed.push(delta),
+            _ => {}
+        }
+    }
+    Ok(diff)
+}
+
+/// Native and all configured token balances of `address` on `chain`, with
+/// the block, time and USD total, using the configured price source
+pub async fn snapshot(chain: &str, address: &str) -> Result<Snapshot> {
+    snapshot_with_options(chain, address, &QueryOptions::default()).await
+}
+
+/// `snapshot` with explicit query options
+pub async fn snapshot_with_options(
+    chain: &str,
+    address: &str,
+    options: &QueryOptions,
+) -> Result<Snapshot> {
+    let balances = crate::get_balances_with_options(chain, address, options).await?;
+    snapshot_of_balances(chain, address, balances, options).await
+}
+
+/// The `Snapshot` of `balances` already read for `address` on `chain` with
+/// `options`, adding the block, time and USD total `snapshot` would
+pub async fn snapshot_of_balances(
+    chain: &str,
+    address: &str,
+    balances: Vec<Balance>,
+    options: &QueryOptions,
+) -> Result<Snapshot> {
+    let config = Config::shared()?;
+    let (chain_name, chain_config) =
+        config.resolve_chain_config(chain, options.network.as_deref())?;
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let block = match read_block(&balances) {
+        Some(block) => block,
+        None => current_block(chain_config, options).await?,
+    };
+    let prices = price_provider(&config.price)?;
+    let fiat_total = fiat_total(&balances, prices.as_ref()).await;
+
+    Ok(Snapshot {
+        chain: chain_name,
+        address: address.to_string(),
+        block,
+        timestamp,
+        balances,
+        fiat_total,
+    })
+}
+
+/// Newest block (or slot) any of `balances` was read at
+fn read_block(balances: &[Balance]) -> Option<u64> {
+    balances.iter().filter_map(|balance| balance.block).max()
+}
+
+/// Latest block (EVM, Bitcoin) or slot (Solana) of `chain`
+pub async fn latest_block(chain: &str, options: &QueryOptions) -> Result<u64> {
+    let config = Config::shared()?;
+    let (_, chain_config) = config.resolve_chain_config(chain, options.network.as_deref())?;
+    current_block(chain_config, options).await
+}
+
+/// Latest block (EVM, Bitcoin) or slot (Solana), through a provider that
+/// falls back from one endpoint to the next when one is down
+async fn current_block(config: &ChainConfig, options: &QueryOptions) -> Result<u64> {
+    let mut rpcs = rpc_candidates(config, !options.disable_fallback_rpc);
+    if rpcs.is_empty() {
+        return Err(anyhow!("No RPC endpoint configured for {}", config.name));
+    }
+    let rpc = rpcs.remove(0);
+    match config.chain_type.as_str() {
+        "evm" => {
+            EthereumProvider::new(rpc)
+                .with_http_config(options.http.clone())
+                .with_rpc_methods(config.rpc_methods.clone())
+                .with_fallback_rpcs(rpcs)
+                .block_number()
+                .await
+        }
+        "solana" => {
+            SolanaProvider::new(rpc)
+                .with_http_config(options.http.clone())?
+                .with_fallback_rpcs(rpcs)?
+                .slot()
+                .await
+        }
+        "icp" => Err(anyhow!("ICP ledgers report no block height to snapshot at")),
+        "bitcoin" => {
+            BitcoinProvider::new(rpc)
+                .with_http_config(options.http.clone())
+                .with_fallback_rpcs(rpcs)
+                .tip_height()
+                .await
+        }
+        other => Err(anyhow!("Unsupported chain type: {}", other)),
+    }
+}
+
+/// USD total of `balances`, pricing every distinct symbol in one batch
+async fn fiat_total(balances: &[Balance], prices: &dyn PriceProvider) -> Option<f64> {
+    let held: Vec<&Balance> = balances
+        .iter()
+        .filter(|balance| balance.kind == BalanceKind::Fungible && balance.amount != "0")
+        .collect();
+    let mut symbols: Vec<String> = held.iter().map(|balance| balance.token.clone()).collect();
+    symbols.sort();
+    symbols.dedup();
+    let unit_prices = prices.get_prices(&symbols).await.ok()?;
+
+    let mut total = 0.0;
+    for balance in held {
+        let price = unit_prices.get(&balance.token)?;
+        total += balance.usd_value(*price);
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct FixedPrices;
+
+    #[async_trait]
+    impl PriceProvider for FixedPrices {
+        async fn get_price(&self, symbol: &str) -> Result<f64> {
+            match symbol {
+                "ETH" => Ok(2000.0),
+                "USDC" => Ok(1.0),
+                _ => Err(anyhow!("no price")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fiat_total() {
+        let balances = vec![
+            Balance::new("ETH".to_string(), "500000000000000000".to_string(), 18).unwrap(),
+            Balance::new("USDC".to_string(), "2500000".to_string(), 6).unwrap(),
+            // Unpriced but empty, so it doesn't matter
+            Balance::new("EURC".to_string(), "0".to_string(), 6).unwrap(),
+        ];
+        assert_eq!(fiat_total(&balances, &FixedPrices).await, Some(1002.5));
+
+        let unpriced = vec![Balance::new("EURC".to_string(), "1".to_string(), 6).unwrap()];
+        assert_eq!(fiat_total(&unpriced, &FixedPrices).await, None);
+    }
+
+    /// Only answers batched lookups, counting the requests
+    struct BatchPrices(AtomicUsize);
+
+    #[async_trait]
+    impl PriceProvider for BatchPrices {
+        async fn get_price(&self, _symbol: &str) -> Result<f64> {
+            Err(anyhow!("single lookups not supported"))
+        }
+
+        async fn get_prices(&self, symbols: &[String]) -> Result<HashMap<String, f64>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(symbols.iter().map(|symbol| (symbol.clone(), 2.0)).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fiat_total_prices_in_one_batch() {
+        let balances = vec![
+            Balance::new("USDC".to_string(), "1000000".to_string(), 6).unwrap(),
+            Balance::new("USDC".to_string(), "3000000".to_string(), 6).unwrap(),
+            Balance::new("EURC".to_string(), "1000000".to_string(), 6).unwrap(),
+        ];
+        let prices = BatchPrices(AtomicUsize::new(0));
+        assert_eq!(fiat_total(&balances, &prices).await, Some(10.0));
+        assert_eq!(prices.0.load(Ordering::SeqCst), 1);
+    }
+
+    fn snapshot_of(balances: Vec<Balance>) -> Snapshot {
+        Snapshot {
+            chain: "sepolia".to_string(),
+            address: "0x78697a9cfc48C1e9d1040172d51833EF78083b10".to_string(),
+            block: 1,
+            timestamp: 0,
+            balances,
+            fiat_total: None,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let before = snapshot_of(vec![
+            Balance::new("ETH".to_string(), "1000".to_string(), 18).unwrap(),
+            Balance::new("EURC".to_string(), "5".to_string(), 6).unwrap(),
+            Balance::new("USDC".to_string(), "2500000".to_string(), 6).unwrap(),
+        ]);
+        let after = snapshot_of(vec![
+            Balance::new("ETH".to_string(), "1000".to_string(), 18).unwrap(),
+            Balance::new("USDC".to_string(), "1000000".to_string(), 6).unwrap(),
+            Balance::new("WETH".to_string(), "7".to_string(), 18).unwrap(),
+        ]);
+
+        let diff = before.diff(&after).unwrap();
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].token, "WETH");
+        assert_eq!(diff.added[0].delta, "7");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].token, "EURC");
+        assert_eq!(diff.removed[0].delta, "-5");
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].previous, "2500000");
+        assert_eq!(diff.changed[0].delta, "-1500000");
+
+        assert!(before.diff(&before).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_diff_across_decimals_is_exact() {
+        let before = snapshot_of(vec![
+            Balance::new("USDC".to_string(), "1".to_string(), 6).unwrap()
+        ]);
+        let after = snapshot_of(vec![Balance::new(
+            "USDC".to_string(),
+            "1000000000001".to_string(),
+            18,
+        )
+        .unwrap()]);
+
+        let diff = before.diff(&after).unwrap();
+        assert_eq!(diff.changed[0].decimals, 18);
+        assert_eq!(diff.changed[0].delta, "1");
+    }
+
+    #[test]
+    fn test_read_block_is_the_newest_balance_block() {
+        let at = |block: Option<u64>| Balance {
+            block,
+            ..Balance::new("ETH".to_string(), "1".to_string(), 18).unwrap()
+        };
+        assert_eq!(
+            read_block(&[at(Some(988)), at(Some(1_000)), at(None)]),
+            Some(1_000)
+        );
+        assert_eq!(read_block(&[at(None)]), None);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_sepolia_snapshot() {
+        let snapshot = snapshot("sepolia", "0x78697a9cfc48C1e9d1040172d51833EF78083b10")
+            .await
+            .unwrap();
+        assert_eq!(snapshot.chain, "sepolia");
+        assert!(snapshot.block > 0);
+        assert_eq!(snapshot.balances[0].token, "ETH");
+    }
+}
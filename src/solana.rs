@@ -1,108 +1,1204 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use solana_client::rpc_client::RpcClient;
-use solana_client::rpc_request::TokenAccountsFilter;
+use base64::Engine;
+use futures::stream::BoxStream;
+use serde::Serialize;
+use solana_account_decoder::UiAccountData;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
+use solana_client::rpc_config::RpcContextConfig;
+use solana_client::rpc_custom_error::{
+    JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE,
+    JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET,
+    JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_UNREACHABLE,
+    JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+};
+use solana_client::rpc_request::{RpcError, RpcRequest, TokenAccountsFilter};
+use solana_client::rpc_response::{Response, RpcKeyedAccount};
+use solana_rpc_client::http_sender::HttpSender;
+use solana_rpc_client::rpc_sender::RpcSender;
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::program_pack::Pack;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::TransactionError;
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
 
 use crate::chain::ChainProvider;
+use crate::config::ChainConfig;
+use crate::error::BalanceError;
+use crate::fallback::RpcEndpoints;
+use crate::graphql::GraphQlProvider;
+use crate::http::{self, HttpConfig, RequestId, DEFAULT_RPC_TIMEOUT};
+use crate::options::QueryOptions;
+use crate::retry::RetryPolicy;
+use crate::telemetry;
 use crate::types::{Balance, Token};
 
+/// Mint of wrapped SOL, the SPL token form of native SOL
+pub const WRAPPED_SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+/// Symbol reported for the wrapped SOL balance
+pub const WRAPPED_SOL_SYMBOL: &str = "wSOL";
+
+/// Token-2022 program; its accounts and mints share the SPL Token layout,
+/// followed by extensions
+pub const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PQnx1L9fTqbM7HN";
+
+/// Most accounts some RPC providers return from `getTokenAccountsByOwner`;
+/// a response this long may be missing accounts
+const TOKEN_ACCOUNTS_LIMIT: usize = 1000;
+
+/// Nominal slot time; real slots run slower and skipped slots produce no block
+const SLOT_TIME_MS: u64 = 400;
+
+/// State of an SPL token account; a frozen account's balance can't be spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountState {
+    Initialized,
+    Frozen,
+}
+
+/// One SPL token account of an owner
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenAccount {
+    pub address: String,
+    pub amount: String,
+    pub formatted: String,
+    pub state: AccountState,
+}
+
+/// An owner's token accounts for one mint and their total
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenAccounts {
+    pub mint: String,
+    pub total: Balance,
+    pub accounts: Vec<TokenAccount>,
+}
+
 /// Solana chain provider using JSON-RPC
 pub struct SolanaProvider {
-    client: RpcClient,
+    endpoints: RpcEndpoints,
+    request_id: Option<RequestId>,
+    http: HttpConfig,
+    retry: RetryPolicy,
+    timeout: Duration,
+    /// Commitment balances and accounts are read at
+    commitment: CommitmentConfig,
+    chain_name: String,
+    /// One client per endpoint, in the same order
+    clients: Vec<RpcClient>,
+    /// Limits requests in flight
+    in_flight: Arc<Semaphore>,
 }
 
+// The retried client calls return solana's own `ClientError`, which is large
+// but only lives until `is_transient` has looked at it
+#[allow(clippy::result_large_err)]
 impl SolanaProvider {
     pub fn new(rpc_url: String) -> Self {
         Self {
-            client: RpcClient::new(rpc_url),
+            clients: vec![RpcClient::new_with_timeout(
+                rpc_url.clone(),
+                DEFAULT_RPC_TIMEOUT,
+            )],
+            endpoints: RpcEndpoints::new(vec![rpc_url]),
+            request_id: None,
+            http: HttpConfig::default(),
+            retry: RetryPolicy::default(),
+            timeout: DEFAULT_RPC_TIMEOUT,
+            commitment: CommitmentConfig::confirmed(),
+            chain_name: "Solana".to_string(),
+            in_flight: HttpConfig::default().in_flight_limiter(),
         }
     }
 
     pub fn new_devnet() -> Self {
         Self::new("https://api.devnet.solana.com".to_string())
+            .with_chain_name("Solana Devnet".to_string())
+    }
+
+    /// Name `chain_name` reports; "Solana" by default
+    pub fn with_chain_name(mut self, name: String) -> Self {
+        self.chain_name = name;
+        self
+    }
+
+    /// Endpoints to move on to, in order, when the current one is down
+    /// (unreachable, erroring, timing out or lagging behind after retries).
+    /// The endpoint that answered stays in use for later calls.
+    pub fn with_fallback_rpcs(mut self, rpcs: Vec<String>) -> Result<Self> {
+        let primary = self.endpoints.urls()[0].clone();
+        self.endpoints = RpcEndpoints::new(std::iter::once(primary).chain(rpcs).collect());
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    /// The RPC URL and fallbacks, in the order they are tried
+    pub fn rpc_urls(&self) -> &[String] {
+        self.endpoints.urls()
+    }
+
+    /// The RPC URL calls currently go to
+    pub fn active_rpc(&self) -> &str {
+        self.endpoints.active()
+    }
+
+    /// Send the given correlation ID header with every RPC request
+    pub fn with_request_id(mut self, request_id: RequestId) -> Result<Self> {
+        self.request_id = Some(request_id);
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    /// Tune the connection pool of the underlying HTTP client
+    pub fn with_http_config(mut self, http: HttpConfig) -> Result<Self> {
+        self.in_flight = http.in_flight_limiter();
+        self.http = http;
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    /// Retry policy for transient Solana errors (node behind, slot skipped,
+    /// block not yet available, ...); three attempts by default
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Commitment native balances, token accounts and mints are read at:
+    /// `processed` for the lowest latency (may be rolled back), `finalized`
+    /// for balances that can no longer change; `confirmed` by default
+    pub fn with_commitment(mut self, commitment: CommitmentConfig) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    /// Give up on an RPC call to an endpoint, retries included, after
+    /// `timeout` with `BalanceError::Timeout` (then moving on to the next
+    /// fallback, if any); `DEFAULT_RPC_TIMEOUT` by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self> {
+        self.timeout = timeout;
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    /// GraphQL indexer on `url`, sending its queries like this provider's
+    /// calls: same request ID, HTTP settings, timeout and in-flight limit
+    pub(crate) fn graphql_indexer(&self, url: String, query: String) -> GraphQlProvider {
+        let mut indexer = GraphQlProvider::new(url, query)
+            .with_http_config(self.http.clone())
+            .with_timeout(self.timeout)
+            .with_in_flight(self.in_flight.clone());
+        if let Some(request_id) = &self.request_id {
+            indexer = indexer.with_request_id(request_id.clone());
+        }
+        indexer
+    }
+
+    /// Send every call of the primary endpoint through `sender` instead of
+    /// HTTP, e.g. to record them; fallbacks are dropped
+    pub(crate) fn with_sender(mut self, sender: impl RpcSender + Send + Sync + 'static) -> Self {
+        self.endpoints = RpcEndpoints::new(vec![self.endpoints.urls()[0].clone()]);
+        self.clients = vec![RpcClient::new_sender(sender, RpcClientConfig::default())];
+        self
+    }
+
+    /// Run a client call under the retry policy and the timeout, on each
+    /// endpoint in turn while they are down
+    async fn call<'a, T, F: Future<Output = Result<T, ClientError>>>(
+        &'a self,
+        mut call: impl FnMut(&'a RpcClient) -> F,
+    ) -> Result<T> {
+        let in_flight = &self.in_flight;
+        let mut last_error = None;
+        for index in self.endpoints.order() {
+            let client = &self.clients[index];
+            let url = &self.endpoints.urls()[index];
+            // Wait for a slot per attempt, so backoff between retries holds none
+            let attempts = self.retry.run_async(
+                || {
+                    let call = call(client);
+                    async move {
+                        let _permit = in_flight.acquire().await;
+                        telemetry::rpc_call(url, call).await
+                    }
+                },
+                is_transient,
+            );
+            let error: anyhow::Error = match tokio::time::timeout(self.timeout, attempts).await {
+                Ok(Ok(value)) => return Ok(value),
+                Ok(Err(error)) if is_timeout(&error) => BalanceError::Timeout.into(),
+                Ok(Err(error)) if is_endpoint_failure(&error) => error.into(),
+                Ok(Err(error)) => return Err(error.into()),
+                Err(_) => BalanceError::Timeout.into(),
+            };
+            self.endpoints.fail_over(index, &error);
+            last_error = Some(error);
+        }
+        Err(last_error.expect("a provider has at least one endpoint"))
+    }
+
+    fn rebuild_client(&mut self) -> Result<()> {
+        let client = http::client_builder(self.request_id.as_ref(), &self.http)?
+            .timeout(self.timeout)
+            .build()?;
+        self.clients = self
+            .endpoints
+            .urls()
+            .iter()
+            .map(|url| {
+                let sender = HttpSender::new_with_client(url.clone(), client.clone());
+                RpcClient::new_sender(sender, RpcClientConfig::default())
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Current slot, used as a connectivity check
+    pub async fn slot(&self) -> Result<u64> {
+        self.call(|client| client.get_slot()).await
+    }
+
+    /// Native balance read at or after `slot`, with the slot it was read at.
+    ///
+    /// Solana nodes don't serve historical account state, so this can't go
+    /// back in time: the node answers from its current state and fails with
+    /// "minimum context slot has not been reached" while it is behind `slot`.
+    pub async fn get_native_balance_at(&self, address: &str, slot: u64) -> Result<(Balance, u64)> {
+        let pubkey = owner_pubkey(address)?;
+        let config = RpcContextConfig {
+            commitment: Some(self.commitment),
+            min_context_slot: Some(slot),
+        };
+        let response: Response<u64> = self
+            .call(|client| {
+                client.send(
+                    RpcRequest::GetBalance,
+                    serde_json::json!([pubkey.to_string(), config]),
+                )
+            })
+            .await?;
+        let slot = response.context.slot;
+        Ok((
+            Balance {
+                block: Some(slot),
+                ..sol_balance(response.value)
+            },
+            slot,
+        ))
+    }
+
+    /// Current slot and its estimated Unix time, from the first block at or
+    /// after it when the slot itself was skipped
+    pub async fn slot_time(&self) -> Result<(u64, i64)> {
+        let slot = self.slot().await?;
+        self.block_time_from(slot)
+            .await?
+            .ok_or_else(|| anyhow!("No block at or after slot {}", slot))
+    }
+
+    /// Estimate the slot that was current at Unix time `timestamp`.
+    ///
+    /// The nominal ~400ms slot time gives a first guess, which is then refined
+    /// by binary search on `getBlockTime`. Block times are stake-weighted
+    /// validator estimates with one second resolution, so the result is the
+    /// last block whose reported time is at or before `timestamp`: typically
+    /// within a couple of slots of the true one, but it can be off by the
+    /// clock drift of the reported block times (a few seconds at worst).
+    /// Timestamps in the future resolve to the latest finalized slot.
+    pub async fn slot_for_timestamp(&self, timestamp: i64) -> Result<u64> {
+        let head = self.slot().await?;
+        let (head, head_time) = self
+            .block_time_from(head)
+            .await?
+            .ok_or_else(|| anyhow!("No block at or after slot {}", head))?;
+        if timestamp >= head_time {
+            return Ok(head);
+        }
+
+        // Step back by the nominal slot time, doubling until we are before `timestamp`
+        let mut gap = estimated_slots(head_time - timestamp);
+        let mut low = head.saturating_sub(gap);
+        loop {
+            match self.block_time_from(low).await? {
+                Some((_, time)) if time <= timestamp => break,
+                _ if low == 0 => {
+                    return Err(anyhow!(
+                        "Timestamp {} is before the first available block",
+                        timestamp
+                    ))
+                }
+                _ => {
+                    gap = gap.saturating_mul(2);
+                    low = head.saturating_sub(gap);
+                }
+            }
+        }
+
+        // Invariant: the first block from `low` is at or before `timestamp`,
+        // the first block from `high` is after it
+        let mut high = head;
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            match self.block_time_from(mid).await? {
+                Some((_, time)) if time <= timestamp => low = mid,
+                _ => high = mid,
+            }
+        }
+
+        self.block_time_from(low)
+            .await?
+            .map(|(slot, _)| slot)
+            .ok_or_else(|| anyhow!("No block at or after slot {}", low))
+    }
+
+    /// First produced block at or after `slot` and its block time; skipped
+    /// slots have no block, so `getBlockTime` alone can't be used on them
+    async fn block_time_from(&self, slot: u64) -> Result<Option<(u64, i64)>> {
+        let blocks = self
+            .call(|client| client.get_blocks_with_limit(slot, 1))
+            .await?;
+        match blocks.first() {
+            Some(&block) => {
+                let time = self.call(|client| client.get_block_time(block)).await?;
+                Ok(Some((block, time)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read an SPL mint's decimals from chain
+    pub async fn fetch_mint_decimals(&self, mint: &str) -> Result<u8> {
+        let mint_pubkey = Pubkey::from_str(mint)?;
+        let data = self
+            .call(|client| client.get_account_with_commitment(&mint_pubkey, self.commitment))
+            .await?
+            .value
+            .ok_or_else(|| anyhow!("Mint {} not found", mint))?
+            .data;
+
+        // Token-2022 mints append extensions after the base mint layout
+        let len = spl_token::state::Mint::LEN.min(data.len());
+        let mint = spl_token::state::Mint::unpack(&data[..len])?;
+        Ok(mint.decimals)
+    }
+
+    /// Mints `address` holds an SPL Token or Token-2022 account for, with
+    /// the raw total per mint (sorted by mint). Mints whose accounts are all
+    /// empty, such as spent airdrops, are left out unless `include_zero`.
+    pub async fn discover_tokens(
+        &self,
+        address: &str,
+        include_zero: bool,
+    ) -> Result<Vec<(String, u128)>> {
+        let owner_pubkey = &Pubkey::from_str(address)?;
+        let accounts_of = |program: Pubkey| {
+            self.call(move |client| {
+                client.get_token_accounts_by_owner_with_commitment(
+                    owner_pubkey,
+                    TokenAccountsFilter::ProgramId(program),
+                    self.commitment,
+                )
+            })
+        };
+        let (legacy, token_2022) = futures::future::try_join(
+            accounts_of(spl_token::id()),
+            accounts_of(Pubkey::from_str(TOKEN_2022_PROGRAM_ID)?),
+        )
+        .await?;
+        let accounts: Vec<_> = legacy.value.into_iter().chain(token_2022.value).collect();
+        discovered(&accounts, include_zero)
+    }
+
+    /// Every token account `address` owns for an SPL token's mint, with its
+    /// amount and state, plus their total. Frozen and empty accounts are
+    /// listed and count towards the total; closed accounts no longer exist.
+    pub async fn token_accounts(&self, address: &str, token: &Token) -> Result<TokenAccounts> {
+        let Token::Spl { mint, .. } = token else {
+            return Err(not_a_solana_token(token).into());
+        };
+        let owner_pubkey = owner_pubkey(address)?;
+        let mint_pubkey = Pubkey::from_str(mint).map_err(|_| BalanceError::Config {
+            message: format!("Invalid mint '{}' of {}", mint, token.symbol()),
+        })?;
+
+        // Get token accounts using the correct filter type
+        let response = self
+            .call(|client| {
+                let filter = TokenAccountsFilter::Mint(mint_pubkey);
+                client.get_token_accounts_by_owner_with_commitment(
+                    &owner_pubkey,
+                    filter,
+                    self.commitment,
+                )
+            })
+            .await?;
+        let keyed_accounts = response.value;
+        if keyed_accounts.len() >= TOKEN_ACCOUNTS_LIMIT {
+            tracing::warn!(
+                accounts = keyed_accounts.len(),
+                token = token.symbol(),
+                address,
+                "RPC returned as many token accounts as some providers' limit, the total may be incomplete"
+            );
+        }
+
+        let mut total: u128 = 0;
+        let mut accounts = Vec::new();
+        let mut seen = HashSet::new();
+        for keyed in &keyed_accounts {
+            // Count each account once, even if the node repeats it
+            if !seen.insert(keyed.pubkey.as_str()) {
+                continue;
+            }
+            let (account_mint, amount, state) = token_account(keyed)?;
+            // Don't rely on the node applying the mint filter
+            if account_mint != mint_pubkey {
+                continue;
+            }
+            total += amount as u128;
+            let balance = Balance::from_minor_units(
+                token.symbol().to_string(),
+                amount.into(),
+                token.decimals(),
+            );
+            accounts.push(TokenAccount {
+                address: keyed.pubkey.clone(),
+                amount: balance.amount,
+                formatted: balance.formatted,
+                state,
+            });
+        }
+
+        let total = Balance::from_minor_units(token.symbol().to_string(), total, token.decimals());
+        Ok(TokenAccounts {
+            mint: mint.clone(),
+            total: Balance {
+                block: Some(response.context.slot),
+                ..total
+            },
+            accounts,
+        })
+    }
+
+    /// Whether an account (e.g. a token mint) exists at `address`
+    pub async fn account_exists(&self, address: &str) -> Result<bool> {
+        let pubkey = Pubkey::from_str(address)?;
+        let response = self
+            .call(|client| client.get_account_with_commitment(&pubkey, self.commitment))
+            .await?;
+        Ok(response.value.is_some())
     }
 }
 
+/// Raw total per mint of token `accounts`, without empty mints unless
+/// `include_zero`
+fn discovered(accounts: &[RpcKeyedAccount], include_zero: bool) -> Result<Vec<(String, u128)>> {
+    let mut totals = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for keyed in accounts {
+        if !seen.insert(keyed.pubkey.as_str()) {
+            continue;
+        }
+        let (mint, amount, _) = token_account(keyed)?;
+        *totals.entry(mint.to_string()).or_insert(0u128) += amount as u128;
+    }
+    Ok(totals
+        .into_iter()
+        .filter(|(_, total)| include_zero || *total > 0)
+        .collect())
+}
+
+/// `decode_token_account` of a `getTokenAccountsByOwner` result. An account
+/// that doesn't decode fails the read rather than being left out of a total.
+fn token_account(keyed: &RpcKeyedAccount) -> Result<(Pubkey, u64, AccountState)> {
+    decode_token_account(&keyed.account.owner, &keyed.account.data).ok_or_else(|| {
+        anyhow!(
+            "Token account {} (owned by {}) doesn't decode as an SPL token account",
+            keyed.pubkey,
+            keyed.account.owner
+        )
+    })
+}
+
+fn not_a_solana_token(token: &Token) -> BalanceError {
+    BalanceError::Config {
+        message: format!(
+            "{} is not a Solana token, expected an SPL token or the native coin",
+            token.symbol()
+        ),
+    }
+}
+
+/// Whether the HTTP request behind a client error ran out of time
+fn is_timeout(error: &ClientError) -> bool {
+    matches!(error.kind(), ClientErrorKind::Reqwest(error) if error.is_timeout())
+}
+
+/// Failures of the endpoint rather than the request, so another endpoint may
+/// answer it: unreachable, erroring over HTTP, or lagging behind
+fn is_endpoint_failure(error: &ClientError) -> bool {
+    matches!(
+        error.kind(),
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_)
+    ) || is_transient(error)
+}
+
+/// Solana RPC errors that go away on their own: the node lagging behind or
+/// not having the requested slot or block yet, or an expired blockhash
+fn is_transient(error: &ClientError) -> bool {
+    if let ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) = error.kind() {
+        return matches!(
+            *code,
+            JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE
+                | JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+                | JSON_RPC_SERVER_ERROR_SLOT_SKIPPED
+                | JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET
+                | JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED
+                | JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_UNREACHABLE
+        );
+    }
+    error.kind().get_transaction_error() == Some(TransactionError::BlockhashNotFound)
+}
+
+/// Mint, amount and state of a token account owned by `program` (SPL Token
+/// or Token-2022), from base64 or `jsonParsed` data
+fn decode_token_account(
+    program: &str,
+    data: &UiAccountData,
+) -> Option<(Pubkey, u64, AccountState)> {
+    match data {
+        UiAccountData::Binary(encoded, _) | UiAccountData::LegacyBinary(encoded) => {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .ok()?;
+            // Token-2022 extensions follow the base layout
+            let base = match program {
+                TOKEN_2022_PROGRAM_ID => decoded.get(..spl_token::state::Account::LEN)?,
+                _ if program == spl_token::id().to_string() => decoded.as_slice(),
+                _ => return None,
+            };
+            let account = spl_token::state::Account::unpack(base).ok()?;
+            let state = match account.state {
+                spl_token::state::AccountState::Frozen => AccountState::Frozen,
+                _ => AccountState::Initialized,
+            };
+            Some((account.mint, account.amount, state))
+        }
+        UiAccountData::Json(parsed) => {
+            if !matches!(parsed.program.as_str(), "spl-token" | "spl-token-2022") {
+                return None;
+            }
+            let info = parsed.parsed.get("info")?;
+            let mint = Pubkey::from_str(info["mint"].as_str()?).ok()?;
+            let amount = info["tokenAmount"]["amount"].as_str()?.parse().ok()?;
+            let state = match info["state"].as_str() {
+                Some("frozen") => AccountState::Frozen,
+                _ => AccountState::Initialized,
+            };
+            Some((mint, amount, state))
+        }
+    }
+}
+
+fn sol_balance(lamports: u64) -> Balance {
+    Balance::from_minor_units(
+        "SOL".to_string(),
+        lamports.into(),
+        9, // SOL has 9 decimals
+    )
+}
+
+/// Slots produced in `seconds` at the nominal slot time
+fn estimated_slots(seconds: i64) -> u64 {
+    (seconds.max(0) as u64).saturating_mul(1000) / SLOT_TIME_MS
+}
+
+/// `address` as a Solana public key, or `BalanceError::InvalidAddress`
+fn owner_pubkey(address: &str) -> Result<Pubkey, BalanceError> {
+    Pubkey::from_str(address).map_err(|_| BalanceError::InvalidAddress {
+        address: address.to_string(),
+    })
+}
+
 #[async_trait]
+#[allow(clippy::result_large_err)]
 impl ChainProvider for SolanaProvider {
-    async fn get_native_balance(&self, address: &str) -> Result<Balance> {
-        let pubkey = Pubkey::from_str(address)?;
-        let lamports = self.client.get_balance(&pubkey)?;
+    fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
 
-        Ok(Balance::new(
-            "SOL".to_string(),
-            lamports.to_string(),
-            9, // SOL has 9 decimals
-        ))
+    fn native_symbol(&self) -> &str {
+        "SOL"
     }
 
-    async fn get_token_balance(&self, address: &str, token: &Token) -> Result<Balance> {
-        let Token::Erc20 {
-            address: token_address,
-            symbol,
-            decimals,
-        } = token;
+    async fn get_balances(
+        &self,
+        config: &ChainConfig,
+        address: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Balance>, BalanceError> {
+        Ok(crate::get_solana_balances(config, self, address, options).await?)
+    }
 
-        let owner_pubkey = Pubkey::from_str(address)?;
-        let mint_pubkey = Pubkey::from_str(token_address)?;
+    fn balance_stream<'a>(
+        &'a self,
+        config: &'a ChainConfig,
+        address: &'a str,
+        options: &'a QueryOptions,
+    ) -> BoxStream<'a, Result<Vec<Balance>, BalanceError>> {
+        crate::stream_reads(crate::solana_balance_reads(config, self, address, options))
+    }
 
-        // Get token accounts using the correct filter type
-        let filter = TokenAccountsFilter::Mint(mint_pubkey);
-        let token_accounts = self
-            .client
-            .get_token_accounts_by_owner(&owner_pubkey, filter)?;
+    fn active_endpoint(&self) -> Option<&str> {
+        Some(self.active_rpc())
+    }
 
-        // Sum up balances from all token accounts
-        let total_balance: u64 = token_accounts
-            .iter()
-            .filter_map(|account_info| {
-                // Decode the token account data - need to handle UiAccountData
-                use solana_account_decoder::UiAccountData;
-                match &account_info.account.data {
-                    UiAccountData::Binary(encoded, _) | UiAccountData::LegacyBinary(encoded) => {
-                        // Decode base64 data
-                        use base64::Engine;
-                        let engine = base64::engine::general_purpose::STANDARD;
-                        if let Ok(decoded) = engine.decode(encoded) {
-                            if let Ok(account_data) = spl_token::state::Account::unpack(&decoded) {
-                                return Some(account_data.amount);
-                            }
-                        }
-                        None
-                    }
-                    UiAccountData::Json(parsed) => {
-                        // Try to extract amount from parsed JSON
-                        if let Some(info) = parsed.parsed.get("info") {
-                            if let Some(token_amount) = info.get("tokenAmount") {
-                                if let Some(amount_str) = token_amount.get("amount") {
-                                    if let Some(amount_val) = amount_str.as_str() {
-                                        if let Ok(amount) = amount_val.parse::<u64>() {
-                                            return Some(amount);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        None
-                    }
-                }
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        let read = async {
+            let pubkey = owner_pubkey(address)?;
+            let response = self
+                .call(|client| client.get_balance_with_commitment(&pubkey, self.commitment))
+                .await?;
+            Ok(Balance {
+                block: Some(response.context.slot),
+                ..sol_balance(response.value)
             })
-            .sum();
+        };
+        telemetry::balance_read(
+            &self.chain_name,
+            address,
+            "SOL",
+            self.request_id.as_ref(),
+            read,
+        )
+        .await
+    }
 
-        Ok(Balance::new(
-            symbol.clone(),
-            total_balance.to_string(),
-            *decimals,
-        ))
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        let read = async {
+            match token {
+                Token::Spl { .. } => Ok(self.token_accounts(address, token).await?.total),
+                Token::Native { .. } => {
+                    let native = self.get_native_balance(address).await?;
+                    Ok(Balance {
+                        block: native.block,
+                        ..Balance::for_token(token, native.amount)?
+                    })
+                }
+                other => Err(not_a_solana_token(other)),
+            }
+        };
+        telemetry::balance_read(
+            &self.chain_name,
+            address,
+            token.symbol(),
+            self.request_id.as_ref(),
+            read,
+        )
+        .await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_server;
+    use futures::TryStreamExt;
+    use solana_account_decoder::parse_account_data::ParsedAccount;
+    use solana_account_decoder::UiAccountEncoding;
+    use solana_client::rpc_request::RpcResponseErrorData;
+    use std::sync::{Arc, Mutex};
+
+    fn rpc_error(code: i64, message: &str) -> ClientError {
+        RpcError::RpcResponseError {
+            code,
+            message: message.to_string(),
+            data: RpcResponseErrorData::Empty,
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_decode_token_account_state() {
+        let account = spl_token::state::Account {
+            amount: 1500,
+            state: spl_token::state::AccountState::Frozen,
+            ..Default::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(account, &mut data).unwrap();
+        let binary = UiAccountData::Binary(
+            base64::engine::general_purpose::STANDARD.encode(&data),
+            UiAccountEncoding::Base64,
+        );
+        let legacy = spl_token::id().to_string();
+        assert_eq!(
+            decode_token_account(&legacy, &binary),
+            Some((Pubkey::default(), 1500, AccountState::Frozen))
+        );
+
+        let parsed = UiAccountData::Json(ParsedAccount {
+            program: "spl-token".to_string(),
+            parsed: serde_json::json!({
+                "info": {
+                    "mint": WRAPPED_SOL_MINT,
+                    "state": "initialized",
+                    "tokenAmount": { "amount": "42" }
+                },
+                "type": "account"
+            }),
+            space: 165,
+        });
+        assert_eq!(
+            decode_token_account(&legacy, &parsed),
+            Some((
+                Pubkey::from_str(WRAPPED_SOL_MINT).unwrap(),
+                42,
+                AccountState::Initialized
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_token_2022_account() {
+        let account = spl_token::state::Account {
+            amount: 2_500_000,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(account, &mut data).unwrap();
+        // Account type byte, then an ImmutableOwner extension (type 7, no data)
+        data.extend_from_slice(&[2, 7, 0, 0, 0]);
+        let binary = UiAccountData::Binary(
+            base64::engine::general_purpose::STANDARD.encode(&data),
+            UiAccountEncoding::Base64,
+        );
+
+        assert_eq!(
+            decode_token_account(TOKEN_2022_PROGRAM_ID, &binary),
+            Some((Pubkey::default(), 2_500_000, AccountState::Initialized))
+        );
+        // The legacy program has no extensions, so the longer data isn't its account
+        assert_eq!(
+            decode_token_account(&spl_token::id().to_string(), &binary),
+            None
+        );
+        assert_eq!(
+            decode_token_account("11111111111111111111111111111111", &binary),
+            None
+        );
+
+        let parsed = UiAccountData::Json(ParsedAccount {
+            program: "spl-token-2022".to_string(),
+            parsed: serde_json::json!({
+                "info": {
+                    "mint": WRAPPED_SOL_MINT,
+                    "state": "frozen",
+                    "tokenAmount": { "amount": "7" }
+                },
+                "type": "account"
+            }),
+            space: 170,
+        });
+        assert_eq!(
+            decode_token_account(TOKEN_2022_PROGRAM_ID, &parsed),
+            Some((
+                Pubkey::from_str(WRAPPED_SOL_MINT).unwrap(),
+                7,
+                AccountState::Frozen
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_evm_tokens() {
+        let provider = SolanaProvider::new_devnet();
+        let usdc = Token::Erc20 {
+            address: "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238".to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+        // Rejected before any request is sent
+        let error = provider
+            .get_token_balance("8vJ1EEeJBSX8UZetuHY7d2SiGjdw2AhfamzfxokPsCF4", &usdc)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("not a Solana token"));
+    }
+
+    #[test]
+    fn test_transient_solana_errors() {
+        assert!(is_transient(&rpc_error(
+            JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+            "Node is behind by 42 slots"
+        )));
+        assert!(is_transient(&rpc_error(
+            JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+            "Slot 1 was skipped"
+        )));
+        assert!(is_transient(&TransactionError::BlockhashNotFound.into()));
+        assert!(!is_transient(&rpc_error(-32602, "Invalid param")));
+    }
+
+    #[tokio::test]
+    #[allow(clippy::result_large_err)]
+    async fn test_node_behind_is_retried() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        };
+        let mut calls = 0;
+        let slot = policy
+            .run(
+                || {
+                    calls += 1;
+                    match calls {
+                        1 => Err(rpc_error(
+                            JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+                            "Node is behind by 42 slots",
+                        )),
+                        _ => Ok(1234u64),
+                    }
+                },
+                is_transient,
+            )
+            .await
+            .unwrap();
+        assert_eq!(slot, 1234);
+        assert_eq!(calls, 2);
+    }
+
+    /// Local JSON-RPC endpoint answering every call with `result`, served
+    /// on the test's own runtime
+    async fn serve(result: serde_json::Value) -> String {
+        serve_recording(result).await.0
+    }
+
+    /// `serve`, also keeping every request it receives
+    async fn serve_recording(
+        result: serde_json::Value,
+    ) -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let url = test_server::serve_json_rpc(move |request| {
+            recorded.lock().unwrap().push(request.clone());
+            result.clone()
+        })
+        .await;
+        (url, requests)
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_concurrent_queries_on_single_threaded_runtime() {
+        let url =
+            serve(serde_json::json!({ "context": { "slot": 1 }, "value": 1_500_000_000 })).await;
+        let provider = std::sync::Arc::new(SolanaProvider::new(url));
+
+        // The endpoint runs on this same thread, so any query blocking it
+        // would never get an answer
+        let mut queries = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            let provider = provider.clone();
+            queries.spawn(async move { provider.get_native_balance(WRAPPED_SOL_MINT).await });
+        }
+        let balances = tokio::time::timeout(Duration::from_secs(5), queries.join_all())
+            .await
+            .expect("queries stalled the runtime");
+        for balance in balances {
+            assert_eq!(balance.unwrap().formatted, "1.5");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_down_endpoint_fails_over_to_fallback() {
+        let down_url = test_server::unreachable_url();
+        let url =
+            serve(serde_json::json!({ "context": { "slot": 1 }, "value": 1_500_000_000 })).await;
+
+        let provider = SolanaProvider::new(down_url)
+            .with_fallback_rpcs(vec![url.clone()])
+            .unwrap();
+        assert_eq!(provider.rpc_urls().len(), 2);
+        let balance = provider.get_native_balance(WRAPPED_SOL_MINT).await.unwrap();
+        assert_eq!(balance.formatted, "1.5");
+        assert_eq!(provider.active_rpc(), url);
+    }
+
+    #[tokio::test]
+    async fn test_native_balance_at_slot() {
+        let url =
+            serve(serde_json::json!({ "context": { "slot": 42 }, "value": 1_500_000_000 })).await;
+        let provider = SolanaProvider::new(url);
+
+        let (balance, slot) = provider
+            .get_native_balance_at(WRAPPED_SOL_MINT, 40)
+            .await
+            .unwrap();
+        assert_eq!(balance.formatted, "1.5");
+        assert_eq!(slot, 42);
+        assert_eq!(balance.block, Some(42));
+
+        let balance = provider.get_native_balance(WRAPPED_SOL_MINT).await.unwrap();
+        assert_eq!(balance.block, Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_commitment_is_sent_with_balance_queries() {
+        let (url, requests) =
+            serve_recording(serde_json::json!({ "context": { "slot": 1 }, "value": 0 })).await;
+
+        SolanaProvider::new(url.clone())
+            .get_native_balance(WRAPPED_SOL_MINT)
+            .await
+            .unwrap();
+        SolanaProvider::new(url)
+            .with_commitment(CommitmentConfig::finalized())
+            .get_native_balance(WRAPPED_SOL_MINT)
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        let commitments: Vec<&serde_json::Value> = requests
+            .iter()
+            .map(|request| &request["params"][1]["commitment"])
+            .collect();
+        assert_eq!(commitments, ["confirmed", "finalized"]);
+    }
+
+    /// `getTokenAccountsByOwner` entry for a legacy SPL token account
+    fn keyed_token_account(pubkey: &str, mint: &str, amount: u64) -> serde_json::Value {
+        let account = spl_token::state::Account {
+            mint: Pubkey::from_str(mint).unwrap(),
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(account, &mut data).unwrap();
+        serde_json::json!({
+            "pubkey": pubkey,
+            "account": {
+                "lamports": 2_039_280,
+                "data": [base64::engine::general_purpose::STANDARD.encode(&data), "base64"],
+                "owner": spl_token::id().to_string(),
+                "executable": false,
+                "rentEpoch": 0,
+                "space": spl_token::state::Account::LEN
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_token_accounts_sums_each_matching_account_once() {
+        let usdc = "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU";
+        let first = Pubkey::new_unique().to_string();
+        let second = Pubkey::new_unique().to_string();
+        let url = serve(serde_json::json!({
+            "context": { "slot": 1 },
+            "value": [
+                keyed_token_account(&first, usdc, 1_500_000),
+                keyed_token_account(&second, usdc, 250_000),
+                // An empty account is listed without changing the total
+                keyed_token_account(&Pubkey::new_unique().to_string(), usdc, 0),
+                // Repeated, and for another mint: neither counts
+                keyed_token_account(&first, usdc, 1_500_000),
+                keyed_token_account(&Pubkey::new_unique().to_string(), WRAPPED_SOL_MINT, 9),
+            ]
+        }))
+        .await;
+        let provider = SolanaProvider::new(url);
+        let token = Token::Spl {
+            mint: usdc.to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+
+        let accounts = provider
+            .token_accounts(WRAPPED_SOL_MINT, &token)
+            .await
+            .unwrap();
+        assert_eq!(accounts.accounts.len(), 3);
+        assert_eq!(accounts.accounts[0].address, first);
+        assert_eq!(accounts.accounts[1].address, second);
+        assert_eq!(accounts.total.amount, "1750000");
+        assert_eq!(accounts.total.formatted, "1.75");
+        assert_eq!(accounts.total.block, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_balance_stream_yields_reads_as_they_complete() {
+        let usdc = "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU";
+        let account = keyed_token_account(&Pubkey::new_unique().to_string(), usdc, 1_500_000);
+        // The native balance is answered well after the token's accounts
+        let url =
+            test_server::serve_json_rpc_after(move |request| match request["method"].as_str() {
+                Some("getBalance") => (
+                    Duration::from_millis(300),
+                    serde_json::json!({ "context": { "slot": 1 }, "value": 1_500_000_000 }),
+                ),
+                _ => (
+                    Duration::ZERO,
+                    serde_json::json!({ "context": { "slot": 1 }, "value": [account] }),
+                ),
+            })
+            .await;
+        let config: ChainConfig = serde_json::from_value(serde_json::json!({
+            "type": "solana",
+            "name": "Local",
+            "rpc": url,
+            "nativeToken": { "symbol": "SOL", "decimals": 9 },
+            "tokens": { "USDC": { "address": usdc, "decimals": 6 } }
+        }))
+        .unwrap();
+        let provider = SolanaProvider::new(url);
+        let options = QueryOptions::default();
+
+        let streamed: Vec<Vec<Balance>> = provider
+            .balance_stream(&config, WRAPPED_SOL_MINT, &options)
+            .try_collect()
+            .await
+            .unwrap();
+        let tokens: Vec<Vec<&str>> = streamed
+            .iter()
+            .map(|read| read.iter().map(|b| b.token.as_str()).collect())
+            .collect();
+        assert_eq!(tokens, [["USDC"], ["SOL"]]);
+
+        // The same reads, in query order
+        let balances = provider
+            .get_balances(&config, WRAPPED_SOL_MINT, &options)
+            .await
+            .unwrap();
+        let tokens: Vec<&str> = balances.iter().map(|b| b.token.as_str()).collect();
+        assert_eq!(tokens, ["SOL", "USDC"]);
+        assert_eq!(balances[1].amount, "1500000");
+    }
+
+    #[test]
+    fn test_estimated_slots() {
+        assert_eq!(estimated_slots(0), 0);
+        assert_eq!(estimated_slots(2), 5);
+        assert_eq!(estimated_slots(3600), 9000);
+        assert_eq!(estimated_slots(-5), 0);
+    }
+
+    /// A `jsonParsed` token account as returned by `getTokenAccountsByOwner`
+    fn parsed_token_account(mint: &str, amount: u64) -> RpcKeyedAccount {
+        serde_json::from_value(serde_json::json!({
+            "pubkey": Pubkey::new_unique().to_string(),
+            "account": {
+                "lamports": 2039280,
+                "owner": spl_token::id().to_string(),
+                "executable": false,
+                "rentEpoch": 0,
+                "space": 165,
+                "data": {
+                    "program": "spl-token",
+                    "parsed": {
+                        "type": "account",
+                        "info": {
+                            "mint": mint,
+                            "tokenAmount": { "amount": amount.to_string() }
+                        }
+                    },
+                    "space": 165
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    /// `parsed_token_account` held under the Token-2022 program
+    fn parsed_token_2022_account(mint: &str, amount: u64) -> RpcKeyedAccount {
+        let mut keyed = parsed_token_account(mint, amount);
+        keyed.account.owner = TOKEN_2022_PROGRAM_ID.to_string();
+        if let UiAccountData::Json(parsed) = &mut keyed.account.data {
+            parsed.program = "spl-token-2022".to_string();
+        }
+        keyed
+    }
+
+    #[test]
+    fn test_discovered_merges_token_2022_accounts_by_mint() {
+        let usdc = "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU";
+        let pyusd = "CXk2AMBfi3TwaEL2468s6zP8xq9NxTXjp9gjMgzeUynM";
+        let accounts = [
+            parsed_token_account(usdc, 1_000_000),
+            parsed_token_2022_account(usdc, 500_000),
+            parsed_token_2022_account(pyusd, 2_000_000),
+        ];
+
+        let mut expected = vec![
+            (usdc.to_string(), 1_500_000),
+            (pyusd.to_string(), 2_000_000),
+        ];
+        expected.sort();
+        assert_eq!(discovered(&accounts, false).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_discovered_skips_empty_mints_by_default() {
+        let usdc = "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU";
+        let accounts = [
+            parsed_token_account(usdc, 1_500_000),
+            parsed_token_account(usdc, 0),
+            // An old airdrop, long since emptied
+            parsed_token_account(WRAPPED_SOL_MINT, 0),
+        ];
+
+        assert_eq!(
+            discovered(&accounts, false).unwrap(),
+            [(usdc.to_string(), 1_500_000)]
+        );
+        assert_eq!(
+            discovered(&accounts, true).unwrap(),
+            [
+                (usdc.to_string(), 1_500_000),
+                (WRAPPED_SOL_MINT.to_string(), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_discovered_fails_on_undecodable_account() {
+        let usdc = "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU";
+        let mut broken = parsed_token_account(usdc, 1_000_000);
+        if let UiAccountData::Json(parsed) = &mut broken.account.data {
+            parsed.parsed["info"]["tokenAmount"]["amount"] = serde_json::json!("not a number");
+        }
+        let pubkey = broken.pubkey.clone();
+        let accounts = [parsed_token_account(usdc, 500_000), broken];
+
+        let error = discovered(&accounts, false).unwrap_err().to_string();
+        assert!(error.contains(&pubkey), "{}", error);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[ignore] // Requires network access
+    async fn test_slot_for_timestamp() {
+        let provider = SolanaProvider::new_devnet();
+        let head = provider.slot().await.unwrap();
+        let head_time = provider.clients[0].get_block_time(head).await.unwrap();
+
+        let slot = provider.slot_for_timestamp(head_time - 600).await.unwrap();
+        assert!(slot < head);
+        let time = provider.clients[0].get_block_time(slot).await.unwrap();
+        assert!(time <= head_time - 600 && time >= head_time - 610);
+    }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     #[ignore] // Requires network access
@@ -133,14 +1229,14 @@ mod tests {
         );
 
         // Define expected tokens
-        let usdc = Token::Erc20 {
-            address: "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU".to_string(),
+        let usdc = Token::Spl {
+            mint: "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU".to_string(),
             symbol: "USDC".to_string(),
             decimals: 6,
         };
 
-        let eurc = Token::Erc20 {
-            address: "HzwqbKZw8HxMN6bF2yFZNrht3c2iXXzpKcFu7uBEDKtr".to_string(),
+        let eurc = Token::Spl {
+            mint: "HzwqbKZw8HxMN6bF2yFZNrht3c2iXXzpKcFu7uBEDKtr".to_string(),
             symbol: "EURC".to_string(),
             decimals: 6,
         };
@@ -0,0 +1,169 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+use crate::snapshot::Snapshot;
+use crate::types::Balance;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS balances (
+        id INTEGER PRIMARY KEY,
+        chain TEXT NOT NULL,
+        address TEXT NOT NULL,
+        token TEXT NOT NULL,
+        raw_amount TEXT NOT NULL,
+        decimals INTEGER NOT NULL,
+        formatted TEXT NOT NULL,
+        block INTEGER,
+        timestamp INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS balances_by_holder
+        ON balances (chain, address, token, timestamp);
+";
+
+/// Balance history in a SQLite `balances` table, one row per token per query.
+///
+/// `raw_amount` is stored as TEXT since on-chain amounts can exceed SQLite's
+/// 64-bit integers; `CAST(raw_amount AS REAL)` is fine for rough analysis.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database at `path`, creating the schema on first use
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Insert one query's balances in a single transaction. Each row gets the
+    /// block its balance was read at, or `block` for balances that don't
+    /// record one.
+    pub fn insert(
+        &mut self,
+        chain: &str,
+        address: &str,
+        block: Option<u64>,
+        timestamp: u64,
+        balances: &[Balance],
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO balances
+                    (chain, address, token, raw_amount, decimals, formatted, block, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for balance in balances {
+                insert.execute(params![
+                    chain,
+                    address,
+                    balance.token,
+                    balance.amount,
+                    balance.decimals,
+                    balance.formatted,
+                    balance.block.or(block).map(i64::try_from).transpose()?,
+                    i64::try_from(timestamp)?,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn insert_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.insert(
+            &snapshot.chain,
+            &snapshot.address,
+            Some(snapshot.block),
+            snapshot.timestamp,
+            &snapshot.balances,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_creates_rows() {
+        let mut store = SqliteStore::open(":memory:").unwrap();
+        let balances = vec![
+            Balance::new(
+                "ETH".to_string(),
+                "1000000000000000000000000".to_string(),
+                18,
+            )
+            .unwrap(),
+            Balance::new("USDC".to_string(), "2500000".to_string(), 6).unwrap(),
+        ];
+        store
+            .insert(
+                "sepolia",
+                "0xabc",
+                Some(7_000_000),
+                1_700_000_000,
+                &balances,
+            )
+            .unwrap();
+        store
+            .insert("sepolia", "0xabc", None, 1_700_000_060, &balances[1..])
+            .unwrap();
+
+        let rows: i64 = store
+            .conn
+            .query_row("SELECT COUNT(*) FROM balances", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 3);
+
+        // Amounts beyond i64 survive as text
+        let (raw, block): (String, Option<i64>) = store
+            .conn
+            .query_row(
+                "SELECT raw_amount, block FROM balances WHERE token = 'ETH'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(raw, "1000000000000000000000000");
+        assert_eq!(block, Some(7_000_000));
+    }
+
+    #[test]
+    fn test_rows_keep_the_block_each_balance_was_read_at() {
+        let mut store = SqliteStore::open(":memory:").unwrap();
+        let balances = vec![
+            Balance {
+                block: Some(988),
+                ..Balance::new("USDC".to_string(), "2500000".to_string(), 6).unwrap()
+            },
+            Balance::new("ETH".to_string(), "1".to_string(), 18).unwrap(),
+        ];
+        store
+            .insert("sepolia", "0xabc", Some(1_000), 1_700_000_000, &balances)
+            .unwrap();
+
+        let block = |token: &str| -> Option<i64> {
+            store
+                .conn
+                .query_row(
+                    "SELECT block FROM balances WHERE token = ?1",
+                    [token],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(block("USDC"), Some(988));
+        assert_eq!(block("ETH"), Some(1_000));
+    }
+
+    #[test]
+    fn test_reopen_keeps_schema() {
+        let dir = std::env::temp_dir().join(format!("balances-{}.db", std::process::id()));
+        SqliteStore::open(&dir).unwrap();
+        SqliteStore::open(&dir).unwrap();
+        std::fs::remove_file(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+//! Local HTTP endpoints for tests, so providers can be exercised end to end
+//! without network access
+
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// A request received by `serve`
+pub(crate) struct Request {
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// Base URL of a local HTTP server answering every request with the status
+/// and body `respond` returns for it. Connections are kept alive, so a
+/// client may send one request after the other on the same connection.
+pub(crate) async fn serve(
+    respond: impl Fn(&Request) -> (u16, String) + Send + Sync + 'static,
+) -> String {
+    listen(move |request| {
+        let (status, body) = respond(request);
+        (Duration::ZERO, status, body)
+    })
+    .await
+}
+
+/// `serve_json_rpc`, answering each request after the delay `respond`
+/// returns with its `result`, e.g. to make one call finish after another
+pub(crate) async fn serve_json_rpc_after(
+    respond: impl Fn(&Value) -> (Duration, Value) + Send + Sync + 'static,
+) -> String {
+    listen(move |request| {
+        let request: Value = serde_json::from_slice(&request.body).unwrap();
+        let (delay, result) = respond(&request);
+        let response = serde_json::json!({
+            "jsonrpc": "2.0", "id": request["id"], "result": result
+        });
+        (delay, 200, response.to_string())
+    })
+    .await
+}
+
+async fn listen(
+    respond: impl Fn(&Request) -> (Duration, u16, String) + Send + Sync + 'static,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let respond = Arc::new(respond);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                while let Some(request) = read_request(&mut stream).await {
+                    let (delay, status, body) = respond(&request);
+                    tokio::time::sleep(delay).await;
+                    let reply = format!(
+                        "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    if stream.get_mut().write_all(reply.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    url
+}
+
+/// `serve` for JSON-RPC: each request gets the `result` `respond` returns
+/// for its parsed body, under the request's `id`
+pub(crate) async fn serve_json_rpc(
+    respond: impl Fn(&Value) -> Value + Send + Sync + 'static,
+) -> String {
+    serve_json_rpc_after(move |request| (Duration::ZERO, respond(request))).await
+}
+
+/// URL of a port nothing listens on, as an endpoint that is down
+pub(crate) fn unreachable_url() -> String {
+    // The port is free again once its listener is dropped
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+/// A listener whose connections are accepted (by the OS) but never answered,
+/// and its URL; the endpoint hangs for as long as the listener is kept
+pub(crate) fn silent() -> (std::net::TcpListener, String) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    (listener, url)
+}
+
+/// The next request on a connection, or `None` once the client closed it
+async fn read_request(stream: &mut BufReader<tokio::net::TcpStream>) -> Option<Request> {
+    let mut request_line = String::new();
+    if stream.read_line(&mut request_line).await.ok()? == 0 {
+        return None;
+    }
+    let path = request_line.split_whitespace().nth(1)?.to_string();
+
+    let mut length = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        if line == "\r\n" {
+            break;
+        }
+        let header = line.to_ascii_lowercase();
+        if let Some(value) = header.strip_prefix("content-length:") {
+            length = value.trim().parse().ok()?;
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.ok()?;
+    Some(Request { path, body })
+}
@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+
+use crate::chain::ChainProvider;
+use crate::error::BalanceError;
+use crate::types::{Balance, Token};
+
+/// `ChainProvider` answering from canned responses, for testing balance
+/// logic without network access. Addresses and tokens without a response
+/// fail, so a test can't silently read a balance it didn't set up. When
+/// several responses match a query, the one set last wins.
+#[derive(Debug, Clone)]
+pub struct MockProvider {
+    chain_name: String,
+    native_symbol: String,
+    native: Vec<(String, Result<Balance, BalanceError>)>,
+    tokens: Vec<(String, Token, Result<Balance, BalanceError>)>,
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self {
+            chain_name: "Mock".to_string(),
+            native_symbol: "ETH".to_string(),
+            native: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report `chain_name` and `native_symbol` as `name` and `native_symbol`
+    /// instead of "Mock" and "ETH"
+    pub fn with_chain(mut self, name: &str, native_symbol: &str) -> Self {
+        self.chain_name = name.to_string();
+        self.native_symbol = native_symbol.to_string();
+        self
+    }
+
+    /// Answer native balance queries for `address` with `balance`
+    pub fn with_native(mut self, address: &str, balance: Balance) -> Self {
+        self.native.push((address.to_lowercase(), Ok(balance)));
+        self
+    }
+
+    /// Fail native balance queries for `address` with `error`
+    pub fn with_native_error(mut self, address: &str, error: BalanceError) -> Self {
+        self.native.push((address.to_lowercase(), Err(error)));
+        self
+    }
+
+    /// Answer `token` balance queries for `address` with `balance`
+    pub fn with_token(mut self, address: &str, token: &Token, balance: Balance) -> Self {
+        self.tokens
+            .push((address.to_lowercase(), token.clone(), Ok(balance)));
+        self
+    }
+
+    /// Fail `token` balance queries for `address` with `error`
+    pub fn with_token_error(mut self, address: &str, token: &Token, error: BalanceError) -> Self {
+        self.tokens
+            .push((address.to_lowercase(), token.clone(), Err(error)));
+        self
+    }
+}
+
+/// Addresses compare case-insensitively, as EVM checksums only change case
+fn same_address(canned: &str, queried: &str) -> bool {
+    canned == queried.to_lowercase()
+}
+
+/// Failure for a query no response was set up for
+fn unset(message: String) -> BalanceError {
+    BalanceError::Config { message }
+}
+
+#[async_trait]
+impl ChainProvider for MockProvider {
+    fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    fn native_symbol(&self) -> &str {
+        &self.native_symbol
+    }
+
+    async fn get_native_balance(&self, address: &str) -> Result<Balance, BalanceError> {
+        let response = self
+            .native
+            .iter()
+            .rev()
+            .find(|(canned, _)| same_address(canned, address))
+            .map(|(_, response)| response);
+        response.cloned().unwrap_or_else(|| {
+            Err(unset(format!(
+                "MockProvider has no native balance for {}",
+                address
+            )))
+        })
+    }
+
+    async fn get_token_balance(
+        &self,
+        address: &str,
+        token: &Token,
+    ) -> Result<Balance, BalanceError> {
+        let response = self
+            .tokens
+            .iter()
+            .rev()
+            .find(|(canned, canned_token, _)| {
+                same_address(canned, address) && canned_token == token
+            })
+            .map(|(_, _, response)| response);
+        response.cloned().unwrap_or_else(|| {
+            Err(unset(format!(
+                "MockProvider has no {} balance for {}",
+                token.symbol(),
+                address
+            )))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLDER: &str = "0x78697a9cfc48C1e9d1040172d51833EF78083b10";
+
+    fn erc20(symbol: &str, address: &str) -> Token {
+        Token::Erc20 {
+            address: address.to_string(),
+            symbol: symbol.to_string(),
+            decimals: 6,
+        }
+    }
+
+    fn balance(symbol: &str, amount: &str, decimals: u8) -> Balance {
+        Balance::new(symbol.to_string(), amount.to_string(), decimals).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_all_balances_native_first_in_token_order() {
+        let usdc = erc20("USDC", "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238");
+        let eurc = erc20("EURC", "0x08210F9170F89Ab7658F0B5E3fF39b0E03C594D4");
+        let provider = MockProvider::new()
+            .with_token(HOLDER, &usdc, balance("USDC", "1500000", 6))
+            .with_token(HOLDER, &eurc, balance("EURC", "250000", 6))
+            .with_native(HOLDER, balance("ETH", "1000000000000000000", 18));
+
+        let balances = provider
+            .get_all_balances(&HOLDER.to_lowercase(), &[eurc, usdc])
+            .await
+            .unwrap();
+        let tokens: Vec<&str> = balances.iter().map(|b| b.token.as_str()).collect();
+        assert_eq!(tokens, ["ETH", "EURC", "USDC"]);
+        assert_eq!(balances[0].formatted, "1");
+        assert_eq!(balances[2].formatted, "1.5");
+    }
+
+    #[tokio::test]
+    async fn test_all_balances_propagates_errors() {
+        let usdc = erc20("USDC", "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238");
+        let provider = MockProvider::new()
+            .with_native(HOLDER, balance("ETH", "0", 18))
+            .with_token_error(HOLDER, &usdc, BalanceError::RateLimited);
+
+        let error = provider
+            .get_all_balances(HOLDER, &[usdc])
+            .await
+            .unwrap_err();
+        assert_eq!(error, BalanceError::RateLimited);
+
+        let provider = MockProvider::new().with_native_error(HOLDER, BalanceError::Timeout);
+        let error = provider.get_all_balances(HOLDER, &[]).await.unwrap_err();
+        assert_eq!(error, BalanceError::Timeout);
+    }
+
+    #[tokio::test]
+    async fn test_unset_responses_fail() {
+        let usdc = erc20("USDC", "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238");
+        let provider = MockProvider::new().with_native(HOLDER, balance("ETH", "0", 18));
+
+        assert!(provider.get_native_balance("0xdef").await.is_err());
+        let error = provider.get_token_balance(HOLDER, &usdc).await.unwrap_err();
+        assert!(error.to_string().contains("no USDC balance"));
+    }
+
+    #[tokio::test]
+    async fn test_later_response_overrides_earlier_one() {
+        let provider = MockProvider::new()
+            .with_native_error(HOLDER, BalanceError::Timeout)
+            .with_native(HOLDER, balance("ETH", "5", 0));
+        assert_eq!(
+            provider.get_native_balance(HOLDER).await.unwrap().amount,
+            "5"
+        );
+    }
+}
@@ -1,4 +1,27 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Whether a balance is a fungible amount or a count of NFTs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BalanceKind {
+    #[default]
+    Fungible,
+    Nft,
+}
+
+/// How `Balance::formatted_rounded` drops digits beyond the decimal cap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Cut the extra digits, so nonzero dust can show as "0"
+    #[default]
+    Truncate,
+    /// Cut the extra digits, but keep a nonzero balance's first significant
+    /// digit even past the cap, e.g. "0.0000001" rather than "0"
+    KeepSignificant,
+}
 
 /// Represents a token balance with amount and decimals
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,42 +30,933 @@ pub struct Balance {
     pub amount: String,
     pub decimals: u8,
     pub formatted: String,
+    /// Snapshots written before NFTs were supported have no kind and are fungible
+    #[serde(default)]
+    pub kind: BalanceKind,
+    /// Config key of the chain the balance was read from, set by `get_balances`
+    /// so balances of several chains stay distinguishable once flattened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+    /// Address the balance was queried for, set by `get_balances` in its
+    /// canonical form: EIP-55 checksummed on EVM chains, Solana base58 (and
+    /// ENS names) as given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Host of the RPC endpoint that served the query (no credentials or
+    /// path), set by `get_balances` to show which fallback answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc: Option<String>,
+    /// Block (EVM) or slot (Solana) the balance was read at, where the node
+    /// reports it, so balances of two queries can be compared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<u64>,
 }
 
 impl Balance {
-    pub fn new(token: String, amount: String, decimals: u8) -> Self {
-        let formatted = format_balance(&amount, decimals);
-        Self {
+    /// Balance with the raw integer `amount` in the token's smallest unit, of
+    /// any size. Fails if `amount` isn't a plain non-negative integer.
+    pub fn new(token: String, amount: String, decimals: u8) -> Result<Self> {
+        let formatted =
+            format_balance(&amount, decimals).map_err(|e| anyhow!("{} for {}", e, token))?;
+        Ok(Self {
             token,
             amount,
             decimals,
             formatted,
+            kind: BalanceKind::Fungible,
+            chain: None,
+            address: None,
+            rpc: None,
+            block: None,
+        })
+    }
+
+    /// Number of NFTs held of an ERC-721 collection or ERC-1155 token ID
+    pub fn nft_count(token: String, count: String) -> Result<Self> {
+        Ok(Self {
+            kind: BalanceKind::Nft,
+            ..Self::new(token, count, 0)?
+        })
+    }
+
+    /// Balance of `token` with the given raw amount, counted as NFTs for NFT tokens
+    pub fn for_token(token: &Token, amount: String) -> Result<Self> {
+        if token.is_nft() {
+            Self::nft_count(token.symbol().to_string(), amount)
+        } else {
+            Self::new(token.symbol().to_string(), amount, token.decimals())
         }
     }
+
+    /// Plain decimal rendering of the balance, computed from the raw amount
+    /// and decimals without going through `f64`, so tiny values never come
+    /// out in scientific notation.
+    pub fn formatted_fixed(&self) -> Result<String> {
+        format_balance(&self.amount, self.decimals)
+    }
+
+    /// Like `formatted_fixed`, with at most `max_decimals` places unless
+    /// `rounding` keeps a significant digit of a smaller balance.
+    /// Digits are cut rather than rounded up, so a balance is never overstated.
+    pub fn formatted_rounded(&self, max_decimals: u8, rounding: Rounding) -> Result<String> {
+        let fixed = self.formatted_fixed()?;
+        let Some((whole, fraction)) = fixed.split_once('.') else {
+            return Ok(fixed);
+        };
+
+        let mut keep = fraction.len().min(max_decimals as usize);
+        if rounding == Rounding::KeepSignificant && whole == "0" {
+            // `formatted_fixed` trims trailing zeros, so a fraction has a nonzero digit
+            let first_significant = fraction.find(|c| c != '0').unwrap_or(0);
+            keep = keep.max(first_significant + 1);
+        }
+
+        let fraction = fraction[..keep].trim_end_matches('0');
+        if fraction.is_empty() {
+            Ok(whole.to_string())
+        } else {
+            Ok(format!("{}.{}", whole, fraction))
+        }
+    }
+
+    /// Decimal rendering keeping every one of `decimals` places, so amounts of
+    /// one token line up in fixed-width output (1.5 USDC is "1.500000").
+    /// Parses back to the same amount with `parse_human`.
+    pub fn formatted_full(&self) -> Result<String> {
+        format_balance_full(&self.amount, self.decimals)
+    }
+
+    /// USD value of the balance at `price` per whole token, e.g. 1.5 USDC at
+    /// $0.999 is 1.4985. Approximate, since it goes through `f64`.
+    pub fn usd_value(&self, price: f64) -> f64 {
+        self.as_f64().unwrap_or(f64::NAN) * price
+    }
+
+    /// The balance in whole tokens as an `f64`, parsed from `formatted`.
+    /// Fine for display, charts and rough math, but an `f64` only holds 15
+    /// to 17 significant digits: large or many-decimal amounts lose their
+    /// low digits. Use `as_decimal` when the result must be exact.
+    pub fn as_f64(&self) -> Result<f64> {
+        self.formatted
+            .parse()
+            .map_err(|_| anyhow!("Invalid amount '{}' for {}", self.formatted, self.token))
+    }
+
+    /// The exact balance in whole tokens, from the raw `amount` and
+    /// `decimals` with nothing rounded and trailing zeros stripped. Fails when
+    /// the raw amount doesn't fit in the `Decimal`'s 96-bit mantissa or
+    /// `decimals` is over 28, which only arises for large 18-decimal amounts
+    /// (above about 79 billion tokens) and unusual tokens.
+    pub fn as_decimal(&self) -> Result<Decimal> {
+        self.amount
+            .parse::<i128>()
+            .ok()
+            .and_then(|units| Decimal::try_from_i128_with_scale(units, self.decimals as u32).ok())
+            .map(|decimal| decimal.normalize())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Amount {} of {} doesn't fit in a Decimal (96-bit mantissa, at most 28 decimals)",
+                    self.formatted,
+                    self.token
+                )
+            })
+    }
+
+    /// Whether the raw amount is zero. Checked on `amount` rather than
+    /// `formatted`, so dust that would display as 0 still counts as held.
+    pub fn is_zero(&self) -> bool {
+        self.amount.bytes().all(|digit| digit == b'0')
+    }
+
+    /// Build a balance from an amount in the token's smallest unit
+    pub fn from_minor_units(token: String, units: u128, decimals: u8) -> Self {
+        Self::new(token, units.to_string(), decimals).expect("an integer is a valid amount")
+    }
+
+    /// The raw amount in the token's smallest unit
+    pub fn to_minor_units(&self) -> Result<u128> {
+        self.amount
+            .parse()
+            .map_err(|_| anyhow!("Invalid raw amount '{}' for {}", self.amount, self.token))
+    }
+
+    /// The same amount expressed with `decimals` places, e.g. to add a
+    /// 6-decimal and an 18-decimal USDC balance. Fails if precision would be lost.
+    pub fn rescale(&self, decimals: u8) -> Result<Self> {
+        let units = rescale(self.to_minor_units()?, self.decimals, decimals)?;
+        let rescaled = Self::from_minor_units(self.token.clone(), units, decimals);
+        Ok(Self {
+            amount: rescaled.amount,
+            decimals,
+            formatted: rescaled.formatted,
+            ..self.clone()
+        })
+    }
+
+    /// Build a balance from a human decimal amount, e.g.
+    /// `Balance::parse_human("1.5", "USDC", 6)` has a raw amount of 1500000.
+    /// Fails instead of rounding when `value` has more than `decimals` places.
+    pub fn parse_human(value: &str, token: &str, decimals: u8) -> Result<Self> {
+        let units = parse_units(value, decimals)?;
+        Ok(Self::from_minor_units(token.to_string(), units, decimals))
+    }
 }
 
 /// Represents different tokens that can be queried
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Erc20 {
         address: String,
         symbol: String,
         decimals: u8,
     },
+    /// SPL token on Solana, legacy SPL Token or Token-2022
+    Spl {
+        mint: String,
+        symbol: String,
+        decimals: u8,
+    },
+    /// ICRC-1 token on the Internet Computer, identified by its ledger canister
+    Icrc1 {
+        ledger: String,
+        symbol: String,
+        decimals: u8,
+    },
+    /// The chain's native coin (ETH, SOL, ...), read as the native balance
+    Native { symbol: String, decimals: u8 },
+    /// Uniswap V2 LP token, reported as the holder's share of both reserves
+    UniV2Lp { pair: String, symbol: String },
+    /// ERC-721 collection, reported as the number of NFTs held
+    Erc721 { address: String, symbol: String },
+    /// One token ID of an ERC-1155 contract; `id` is a decimal uint256
+    Erc1155 {
+        address: String,
+        id: String,
+        symbol: String,
+    },
 }
 
-/// Format balance with proper decimal places
-fn format_balance(amount: &str, decimals: u8) -> String {
-    let value = amount.parse::<u128>().unwrap_or(0);
-    let divisor = 10u128.pow(decimals as u32);
-    let whole = value / divisor;
-    let fractional = value % divisor;
+impl Token {
+    /// Contract address (ERC-20/721/1155), mint (SPL), ledger canister
+    /// (ICRC-1) or pair contract (LP); empty for the native coin
+    pub fn address(&self) -> &str {
+        match self {
+            Token::Erc20 { address, .. }
+            | Token::Erc721 { address, .. }
+            | Token::Erc1155 { address, .. } => address,
+            Token::Spl { mint, .. } => mint,
+            Token::Icrc1 { ledger, .. } => ledger,
+            Token::UniV2Lp { pair, .. } => pair,
+            Token::Native { .. } => "",
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        match self {
+            Token::Erc20 { symbol, .. }
+            | Token::Spl { symbol, .. }
+            | Token::Icrc1 { symbol, .. }
+            | Token::Native { symbol, .. }
+            | Token::UniV2Lp { symbol, .. }
+            | Token::Erc721 { symbol, .. }
+            | Token::Erc1155 { symbol, .. } => symbol,
+        }
+    }
+
+    pub fn decimals(&self) -> u8 {
+        match self {
+            Token::Erc20 { decimals, .. }
+            | Token::Spl { decimals, .. }
+            | Token::Icrc1 { decimals, .. }
+            | Token::Native { decimals, .. } => *decimals,
+            // Uniswap V2 pair tokens always have 18 decimals
+            Token::UniV2Lp { .. } => 18,
+            Token::Erc721 { .. } | Token::Erc1155 { .. } => 0,
+        }
+    }
+
+    /// Whether balances of this token count NFTs rather than fungible units
+    pub fn is_nft(&self) -> bool {
+        matches!(self, Token::Erc721 { .. } | Token::Erc1155 { .. })
+    }
+}
+
+/// Parses `erc20:ADDRESS:SYMBOL:DECIMALS`, `spl:MINT:SYMBOL:DECIMALS`,
+/// `icrc1:LEDGER:SYMBOL:DECIMALS`, `native:SYMBOL:DECIMALS`, `univ2lp:PAIR:SYMBOL`,
+/// `erc721:ADDRESS:SYMBOL` or `erc1155:ADDRESS:ID:SYMBOL`
+impl FromStr for Token {
+    type Err = anyhow::Error;
 
-    if fractional == 0 {
-        format!("{}", whole)
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split(':').map(str::trim).collect();
+        let Some((kind, fields)) = parts.split_first() else {
+            return Err(anyhow!("Invalid token '{}'", s));
+        };
+        if fields.iter().any(|field| field.is_empty()) {
+            return Err(anyhow!("Invalid token '{}', empty field", s));
+        }
+
+        let kind = kind.to_ascii_lowercase();
+        let parse_decimals = |decimals: &str| -> Result<u8> {
+            decimals
+                .parse()
+                .map_err(|_| anyhow!("Invalid decimals '{}' in token '{}'", decimals, s))
+        };
+
+        match (kind.as_str(), fields) {
+            ("erc20", [address, symbol, decimals]) => Ok(Token::Erc20 {
+                address: address.to_string(),
+                symbol: symbol.to_string(),
+                decimals: parse_decimals(decimals)?,
+            }),
+            ("spl", [mint, symbol, decimals]) => Ok(Token::Spl {
+                mint: mint.to_string(),
+                symbol: symbol.to_string(),
+                decimals: parse_decimals(decimals)?,
+            }),
+            ("icrc1", [ledger, symbol, decimals]) => Ok(Token::Icrc1 {
+                ledger: ledger.to_string(),
+                symbol: symbol.to_string(),
+                decimals: parse_decimals(decimals)?,
+            }),
+            ("native", [symbol, decimals]) => Ok(Token::Native {
+                symbol: symbol.to_string(),
+                decimals: parse_decimals(decimals)?,
+            }),
+            ("univ2lp", [pair, symbol]) => Ok(Token::UniV2Lp {
+                pair: pair.to_string(),
+                symbol: symbol.to_string(),
+            }),
+            ("erc721", [address, symbol]) => Ok(Token::Erc721 {
+                address: address.to_string(),
+                symbol: symbol.to_string(),
+            }),
+            ("erc1155", [address, id, symbol]) => {
+                if !id.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(anyhow!("Invalid token ID '{}' in token '{}'", id, s));
+                }
+                Ok(Token::Erc1155 {
+                    address: address.to_string(),
+                    id: id.to_string(),
+                    symbol: symbol.to_string(),
+                })
+            }
+            ("erc20" | "spl" | "icrc1", _) => Err(anyhow!(
+                "Invalid token '{}', expected {}:ADDRESS:SYMBOL:DECIMALS",
+                s,
+                kind
+            )),
+            ("univ2lp" | "erc721", _) => Err(anyhow!(
+                "Invalid token '{}', expected {}:ADDRESS:SYMBOL",
+                s,
+                kind
+            )),
+            ("native", _) => Err(anyhow!(
+                "Invalid token '{}', expected native:SYMBOL:DECIMALS",
+                s
+            )),
+            ("erc1155", _) => Err(anyhow!(
+                "Invalid token '{}', expected erc1155:ADDRESS:ID:SYMBOL",
+                s
+            )),
+            (other, _) => Err(anyhow!(
+                "Unknown token kind '{}', expected erc20, spl, icrc1, native, univ2lp, erc721 or erc1155",
+                other
+            )),
+        }
+    }
+}
+
+/// Ad-hoc token given as `ADDRESS[:SYMBOL[:DECIMALS]]`; missing parts are
+/// read from chain before querying
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenSpec {
+    pub address: String,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+impl From<Token> for TokenSpec {
+    fn from(token: Token) -> Self {
+        Self {
+            address: token.address().to_string(),
+            symbol: Some(token.symbol().to_string()),
+            decimals: Some(token.decimals()),
+        }
+    }
+}
+
+impl FromStr for TokenSpec {
+    type Err = anyhow::Error;
+
+    /// Also accepts the fully specified `Token` forms (`erc20:...`, `spl:...`,
+    /// `icrc1:...`)
+    fn from_str(s: &str) -> Result<Self> {
+        let lower = s.to_ascii_lowercase();
+        if ["erc20:", "spl:", "icrc1:"]
+            .iter()
+            .any(|kind| lower.starts_with(kind))
+        {
+            return s.parse::<Token>().map(TokenSpec::from);
+        }
+
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() > 3 || parts.iter().any(|part| part.trim().is_empty()) {
+            return Err(anyhow!(
+                "Invalid token '{}', expected ADDRESS[:SYMBOL[:DECIMALS]]",
+                s
+            ));
+        }
+
+        let decimals = match parts.get(2) {
+            Some(decimals) => Some(
+                decimals
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid decimals '{}' in token '{}'", decimals, s))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            address: parts[0].trim().to_string(),
+            symbol: parts.get(1).map(|symbol| symbol.trim().to_string()),
+            decimals,
+        })
+    }
+}
+
+/// Drop zero token balances from a list as returned by `get_balances`,
+/// keeping the native balance (the first entry) even when it is zero
+pub fn filter_nonzero(balances: Vec<Balance>) -> Vec<Balance> {
+    balances
+        .into_iter()
+        .enumerate()
+        .filter(|(index, balance)| *index == 0 || !balance.is_zero())
+        .map(|(_, balance)| balance)
+        .collect()
+}
+
+/// Convert an amount in smallest units from `from` to `to` decimal places,
+/// failing instead of rounding when digits would be dropped (or on overflow)
+pub fn rescale(units: u128, from: u8, to: u8) -> Result<u128> {
+    let shift = |places: u8| {
+        10u128
+            .checked_pow(places as u32)
+            .ok_or_else(|| anyhow!("Cannot rescale by {} decimal places", places))
+    };
+
+    if to >= from {
+        units.checked_mul(shift(to - from)?).ok_or_else(|| {
+            anyhow!(
+                "Amount {} overflows when rescaled from {} to {} decimals",
+                units,
+                from,
+                to
+            )
+        })
     } else {
-        let frac_str = format!("{:0width$}", fractional, width = decimals as usize);
-        let trimmed = frac_str.trim_end_matches('0');
-        format!("{}.{}", whole, trimmed)
+        let divisor = shift(from - to)?;
+        if !units.is_multiple_of(divisor) {
+            return Err(anyhow!(
+                "Amount {} loses precision when rescaled from {} to {} decimals",
+                units,
+                from,
+                to
+            ));
+        }
+        Ok(units / divisor)
+    }
+}
+
+/// Parse a human decimal string (e.g. "1.5") into smallest units for a token
+/// with `decimals` places, failing rather than rounding if precision would be lost
+pub(crate) fn parse_units(value: &str, decimals: u8) -> Result<u128> {
+    let value = value.trim();
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+        return Err(anyhow!("Invalid decimal amount: '{}'", value));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(anyhow!(
+            "Amount '{}' has more than {} decimal places",
+            value,
+            decimals
+        ));
+    }
+
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals as usize);
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    digits
+        .parse::<u128>()
+        .map_err(|_| anyhow!("Amount '{}' is too large", value))
+}
+
+/// The digits of a raw amount without leading zeros ("0" for zero). Works on
+/// the string so amounts of any size, e.g. a full 256-bit value, stay exact.
+fn amount_digits(amount: &str) -> Result<&str> {
+    if amount.is_empty() || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(anyhow!("Invalid raw amount '{}'", amount));
+    }
+    let digits = amount.trim_start_matches('0');
+    Ok(if digits.is_empty() { "0" } else { digits })
+}
+
+/// Format balance with proper decimal places, trailing zeros trimmed
+fn format_balance(amount: &str, decimals: u8) -> Result<String> {
+    let full = format_balance_full(amount, decimals)?;
+    match full.split_once('.') {
+        Some((whole, fractional)) => match fractional.trim_end_matches('0') {
+            "" => Ok(whole.to_string()),
+            fractional => Ok(format!("{}.{}", whole, fractional)),
+        },
+        None => Ok(full),
+    }
+}
+
+/// Format balance with all `decimals` places, e.g. "1.500000" for 1500000
+/// with 6 decimals
+pub(crate) fn format_balance_full(amount: &str, decimals: u8) -> Result<String> {
+    let digits = amount_digits(amount)?;
+    let decimals = decimals as usize;
+    let padded = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fractional) = padded.split_at(padded.len() - decimals);
+    match fractional.is_empty() {
+        true => Ok(whole.to_string()),
+        false => Ok(format!("{}.{}", whole, fractional)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_spec_address_only() {
+        let spec: TokenSpec = "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238"
+            .parse()
+            .unwrap();
+        assert_eq!(spec.address, "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238");
+        assert_eq!(spec.symbol, None);
+        assert_eq!(spec.decimals, None);
+    }
+
+    #[test]
+    fn test_token_spec_fully_specified() {
+        let spec: TokenSpec = "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU:USDC:6"
+            .parse()
+            .unwrap();
+        assert_eq!(spec.address, "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU");
+        assert_eq!(spec.symbol.as_deref(), Some("USDC"));
+        assert_eq!(spec.decimals, Some(6));
+    }
+
+    #[test]
+    fn test_token_spec_rejects_malformed_input() {
+        assert!("0xabc:USDC:six".parse::<TokenSpec>().is_err());
+        assert!("0xabc::6".parse::<TokenSpec>().is_err());
+        assert!("0xabc:USDC:6:extra".parse::<TokenSpec>().is_err());
+        assert!("0xabc:USDC:300".parse::<TokenSpec>().is_err());
+    }
+
+    #[test]
+    fn test_token_from_str() {
+        let erc20: Token = "erc20:0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238:USDC:6"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            erc20,
+            Token::Erc20 {
+                address: "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238".to_string(),
+                symbol: "USDC".to_string(),
+                decimals: 6,
+            }
+        );
+
+        let spl: Token = "spl:HzwqbKZw8HxMN6bF2yFZNrht3c2iXXzpKcFu7uBEDKtr:EURC:6"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            spl.address(),
+            "HzwqbKZw8HxMN6bF2yFZNrht3c2iXXzpKcFu7uBEDKtr"
+        );
+        assert_eq!(spl.symbol(), "EURC");
+        assert!(matches!(spl, Token::Spl { decimals: 6, .. }));
+
+        let ckbtc: Token = "icrc1:mxzaz-hqaaa-aaaar-qaada-cai:ckBTC:8".parse().unwrap();
+        assert_eq!(ckbtc.address(), "mxzaz-hqaaa-aaaar-qaada-cai");
+        assert!(matches!(ckbtc, Token::Icrc1 { decimals: 8, .. }));
+
+        let native: Token = "native:SOL:9".parse().unwrap();
+        assert_eq!(
+            native,
+            Token::Native {
+                symbol: "SOL".to_string(),
+                decimals: 9,
+            }
+        );
+        assert_eq!(native.address(), "");
+        assert!("native:SOL".parse::<Token>().is_err());
+
+        let lp: Token = "univ2lp:0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc:UNI-V2"
+            .parse()
+            .unwrap();
+        assert!(matches!(lp, Token::UniV2Lp { .. }));
+        assert_eq!(lp.symbol(), "UNI-V2");
+        assert_eq!(lp.decimals(), 18);
+
+        let badge: Token = "erc1155:0x76BE3b62873462d2142405439777e971754E8E77:10144:BADGE"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            badge,
+            Token::Erc1155 {
+                address: "0x76BE3b62873462d2142405439777e971754E8E77".to_string(),
+                id: "10144".to_string(),
+                symbol: "BADGE".to_string(),
+            }
+        );
+        assert!(badge.is_nft());
+        assert_eq!(badge.decimals(), 0);
+    }
+
+    #[test]
+    fn test_balance_kind() {
+        let punks = Token::Erc721 {
+            address: "0xb47e3cd837dDF8e4c57F05d70Ab865de6e193BBB".to_string(),
+            symbol: "PUNK".to_string(),
+        };
+        let nft = Balance::for_token(&punks, "3".to_string()).unwrap();
+        assert_eq!(nft.kind, BalanceKind::Nft);
+        assert_eq!(nft.formatted, "3");
+
+        // Snapshots from before `kind` existed still load, as fungible
+        let old: Balance = serde_json::from_str(
+            r#"{ "token": "USDC", "amount": "1", "decimals": 6, "formatted": "0.000001" }"#,
+        )
+        .unwrap();
+        assert_eq!(old.kind, BalanceKind::Fungible);
+    }
+
+    #[test]
+    fn test_balance_chain_omitted_when_unset() {
+        let balance = Balance::new("ETH".to_string(), "1".to_string(), 18).unwrap();
+        let json = serde_json::to_value(&balance).unwrap();
+        assert!(json.get("chain").is_none());
+
+        let tagged = Balance {
+            chain: Some("sepolia".to_string()),
+            ..balance
+        };
+        assert_eq!(serde_json::to_value(&tagged).unwrap()["chain"], "sepolia");
+    }
+
+    #[test]
+    fn test_balance_address_omitted_when_unset() {
+        let balance = Balance::new("ETH".to_string(), "1".to_string(), 18).unwrap();
+        let json = serde_json::to_value(&balance).unwrap();
+        assert!(json.get("address").is_none());
+
+        let tagged = Balance {
+            address: Some("0x78697a9cfc48C1e9d1040172d51833EF78083b10".to_string()),
+            ..balance
+        };
+        assert_eq!(
+            serde_json::to_value(&tagged).unwrap()["address"],
+            "0x78697a9cfc48C1e9d1040172d51833EF78083b10"
+        );
+    }
+
+    #[test]
+    fn test_token_from_str_rejects_malformed_input() {
+        assert!("erc20:0xabc:USDC".parse::<Token>().is_err());
+        assert!("bep20:0xabc:USDC:6".parse::<Token>().is_err());
+        assert!("spl::USDC:6".parse::<Token>().is_err());
+        assert!("spl:mint:USDC:x".parse::<Token>().is_err());
+        assert!("univ2lp::UNI-V2".parse::<Token>().is_err());
+        assert!("erc1155:0xabc:one:BADGE".parse::<Token>().is_err());
+        assert!("erc721:0xabc:PUNK:0".parse::<Token>().is_err());
+    }
+
+    #[test]
+    fn test_token_spec_accepts_token_form() {
+        let spec: TokenSpec = "spl:HzwqbKZw8HxMN6bF2yFZNrht3c2iXXzpKcFu7uBEDKtr:EURC:6"
+            .parse()
+            .unwrap();
+        assert_eq!(spec.address, "HzwqbKZw8HxMN6bF2yFZNrht3c2iXXzpKcFu7uBEDKtr");
+        assert_eq!(spec.symbol.as_deref(), Some("EURC"));
+        assert_eq!(spec.decimals, Some(6));
+    }
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(parse_units("1.5", 6).unwrap(), 1_500_000);
+        assert_eq!(parse_units("0.000001", 6).unwrap(), 1);
+        assert_eq!(parse_units("42", 0).unwrap(), 42);
+        assert_eq!(parse_units(".5", 1).unwrap(), 5);
+        assert_eq!(parse_units("0", 18).unwrap(), 0);
+        assert!(parse_units("0.0000001", 6).is_err());
+        assert!(parse_units("1e-6", 6).is_err());
+        assert!(parse_units("-1", 6).is_err());
+        assert!(parse_units(".", 6).is_err());
+    }
+
+    #[test]
+    fn test_minor_units_round_trip() {
+        let balance = Balance::from_minor_units("USDC".to_string(), 1_500_000, 6);
+        assert_eq!(balance.formatted, "1.5");
+        assert_eq!(balance.to_minor_units().unwrap(), 1_500_000);
+    }
+
+    #[test]
+    fn test_rescale() {
+        assert_eq!(
+            rescale(1_500_000, 6, 18).unwrap(),
+            1_500_000_000_000_000_000
+        );
+        assert_eq!(
+            rescale(1_500_000_000_000_000_000, 18, 6).unwrap(),
+            1_500_000
+        );
+        assert_eq!(rescale(42, 6, 6).unwrap(), 42);
+        assert!(rescale(1_500_000_000_000_000_001, 18, 6).is_err());
+        assert!(rescale(u128::MAX, 0, 1).is_err());
+        assert!(rescale(1, 0, 40).is_err());
+
+        let usdc = Balance::new("USDC".to_string(), "1500000".to_string(), 6).unwrap();
+        let wide = usdc.rescale(18).unwrap();
+        assert_eq!(wide.amount, "1500000000000000000");
+        assert_eq!(wide.formatted, usdc.formatted);
+    }
+
+    #[test]
+    fn test_rescale_keeps_read_metadata() {
+        let usdc = Balance {
+            chain: Some("sepolia".to_string()),
+            rpc: Some("rpc.example.com".to_string()),
+            block: Some(7_000_000),
+            ..Balance::new("USDC".to_string(), "1500000".to_string(), 6).unwrap()
+        };
+        let wide = usdc.rescale(18).unwrap();
+        assert_eq!(wide.decimals, 18);
+        assert_eq!(wide.block, Some(7_000_000));
+        assert_eq!(wide.chain, usdc.chain);
+        assert_eq!(wide.rpc, usdc.rpc);
+    }
+
+    #[test]
+    fn test_usd_value() {
+        let usdc = Balance::new("USDC".to_string(), "1500000".to_string(), 6).unwrap();
+        assert!((usdc.usd_value(0.999) - 1.4985).abs() < 1e-9);
+
+        let dust = Balance::new("ETH".to_string(), "1".to_string(), 18).unwrap();
+        assert!(dust.usd_value(3000.0) > 0.0);
+    }
+
+    #[test]
+    fn test_numeric_accessors() {
+        let usdc = Balance::new("USDC".to_string(), "1500000".to_string(), 6).unwrap();
+        assert_eq!(usdc.as_f64().unwrap(), 1.5);
+        let decimal = usdc.as_decimal().unwrap();
+        assert_eq!(decimal.to_string(), "1.5");
+        assert_eq!(decimal, Decimal::new(15, 1));
+
+        // One wei over 1000 ETH: exact as a Decimal, rounded away as an f64
+        let eth =
+            Balance::new("ETH".to_string(), "1000000000000000000001".to_string(), 18).unwrap();
+        assert_eq!(
+            eth.as_decimal().unwrap().to_string(),
+            "1000.000000000000000001"
+        );
+        assert_eq!(eth.as_f64().unwrap(), 1000.0);
+
+        // The largest 96-bit mantissa fits, one more doesn't
+        let max = (1u128 << 96) - 1;
+        let fits = Balance::new("ETH".to_string(), max.to_string(), 18).unwrap();
+        assert_eq!(fits.as_decimal().unwrap().to_string(), fits.formatted);
+        let over = Balance::new("ETH".to_string(), (max + 1).to_string(), 18).unwrap();
+        assert!(over.as_f64().is_ok());
+        assert!(over.as_decimal().is_err());
+
+        let huge = Balance::new("ETH".to_string(), format!("{}0", u128::MAX), 18).unwrap();
+        assert!(huge.as_decimal().is_err());
+    }
+
+    #[test]
+    fn test_filter_nonzero() {
+        let balance = |token: &str, amount: &str| {
+            Balance::new(token.to_string(), amount.to_string(), 18).unwrap()
+        };
+        let balances = vec![
+            balance("ETH", "0"),
+            balance("DAI", "0"),
+            // Displays as 0 once rounded, but is still held
+            balance("DUST", "1"),
+            balance("USDC", "000"),
+        ];
+
+        let kept: Vec<String> = filter_nonzero(balances)
+            .into_iter()
+            .map(|balance| balance.token)
+            .collect();
+        assert_eq!(kept, ["ETH", "DUST"]);
+        assert!(filter_nonzero(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_parse_human() {
+        let balance = Balance::parse_human("1.5", "USDC", 6).unwrap();
+        assert_eq!(balance.token, "USDC");
+        assert_eq!(balance.amount, "1500000");
+        assert_eq!(balance.formatted, "1.5");
+
+        let wei = Balance::parse_human("0.000000000000000001", "ETH", 18).unwrap();
+        assert_eq!(wei.to_minor_units().unwrap(), 1);
+
+        assert!(Balance::parse_human("1.0000001", "USDC", 6).is_err());
+        assert!(Balance::parse_human("abc", "USDC", 6).is_err());
+    }
+
+    #[test]
+    fn test_formatted_fixed_one_wei() {
+        let balance = Balance::new("ETH".to_string(), "1".to_string(), 18).unwrap();
+        assert_eq!(balance.formatted_fixed().unwrap(), "0.000000000000000001");
+        assert!(!balance.formatted_fixed().unwrap().contains('e'));
+    }
+
+    #[test]
+    fn test_formatted_fixed_matches_formatted() {
+        let balance = Balance::new("USDC".to_string(), "1500000".to_string(), 6).unwrap();
+        assert_eq!(balance.formatted_fixed().unwrap(), "1.5");
+        assert_eq!(balance.formatted_fixed().unwrap(), balance.formatted);
+    }
+
+    #[test]
+    fn test_formatted_fixed_zero_decimals() {
+        let balance = Balance::new("NFT".to_string(), "42".to_string(), 0).unwrap();
+        assert_eq!(balance.formatted_fixed().unwrap(), "42");
+    }
+
+    #[test]
+    fn test_formatted_full_keeps_trailing_zeros() {
+        let cases = [
+            ("1000000", 6, "1", "1.000000"),
+            ("1500000", 6, "1.5", "1.500000"),
+            ("1050000", 6, "1.05", "1.050000"),
+            ("50000", 6, "0.05", "0.050000"),
+            ("0", 6, "0", "0.000000"),
+            ("42", 0, "42", "42"),
+        ];
+        for (amount, decimals, trimmed, full) in cases {
+            let balance = Balance::new("X".to_string(), amount.to_string(), decimals).unwrap();
+            assert_eq!(balance.formatted, trimmed);
+            assert_eq!(balance.formatted_full().unwrap(), full);
+
+            let parsed = Balance::parse_human(full, "X", decimals).unwrap();
+            assert_eq!(parsed.amount, amount);
+        }
+    }
+
+    #[test]
+    fn test_format_256_bit_max() {
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        let balance = Balance::new("ETH".to_string(), max.to_string(), 18).unwrap();
+        assert_eq!(
+            balance.formatted,
+            "115792089237316195423570985008687907853269984665640564039457.584007913129639935"
+        );
+
+        let raw = Balance::new("X".to_string(), max.to_string(), 0).unwrap();
+        assert_eq!(raw.formatted, max);
+        assert!(raw.to_minor_units().is_err());
+    }
+
+    #[test]
+    fn test_invalid_amount_is_an_error_not_zero() {
+        for amount in ["", "abc", "-1", "1.5", "0x10"] {
+            assert!(
+                Balance::new("USDC".to_string(), amount.to_string(), 6).is_err(),
+                "{:?} should be rejected",
+                amount
+            );
+        }
+        // More decimals than u128 can scale by
+        let tiny = Balance::new("X".to_string(), "1".to_string(), 40).unwrap();
+        assert_eq!(tiny.formatted, format!("0.{}1", "0".repeat(39)));
+    }
+
+    #[test]
+    fn test_formatted_rounded_caps_decimals() {
+        let balance = Balance::new("USDC".to_string(), "1234567".to_string(), 6).unwrap();
+        assert_eq!(
+            balance.formatted_rounded(2, Rounding::Truncate).unwrap(),
+            "1.23"
+        );
+        assert_eq!(
+            balance
+                .formatted_rounded(2, Rounding::KeepSignificant)
+                .unwrap(),
+            "1.23"
+        );
+        assert_eq!(
+            balance
+                .formatted_rounded(0, Rounding::KeepSignificant)
+                .unwrap(),
+            "1"
+        );
+        assert_eq!(
+            balance.formatted_rounded(18, Rounding::Truncate).unwrap(),
+            "1.234567"
+        );
+    }
+
+    #[test]
+    fn test_formatted_rounded_truncates_dust_to_zero() {
+        let balance = Balance::new("ETH".to_string(), "100000000000".to_string(), 18).unwrap();
+        assert_eq!(
+            balance.formatted_rounded(2, Rounding::Truncate).unwrap(),
+            "0"
+        );
+    }
+
+    #[test]
+    fn test_formatted_rounded_keeps_significant_figure_of_dust() {
+        let balance = Balance::new("ETH".to_string(), "100000000000".to_string(), 18).unwrap();
+        assert_eq!(
+            balance
+                .formatted_rounded(2, Rounding::KeepSignificant)
+                .unwrap(),
+            "0.0000001"
+        );
+
+        let wei = Balance::new("ETH".to_string(), "1".to_string(), 18).unwrap();
+        assert_eq!(
+            wei.formatted_rounded(0, Rounding::KeepSignificant).unwrap(),
+            "0.000000000000000001"
+        );
+
+        // Only the first significant digit is kept past the cap
+        let dust = Balance::new("USDC".to_string(), "1999".to_string(), 6).unwrap();
+        assert_eq!(
+            dust.formatted_rounded(2, Rounding::KeepSignificant)
+                .unwrap(),
+            "0.001"
+        );
+    }
+
+    #[test]
+    fn test_formatted_rounded_zero_stays_zero() {
+        let balance = Balance::new("ETH".to_string(), "0".to_string(), 18).unwrap();
+        assert_eq!(
+            balance
+                .formatted_rounded(2, Rounding::KeepSignificant)
+                .unwrap(),
+            "0"
+        );
     }
 }
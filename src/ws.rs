@@ -0,0 +1,167 @@
+use alloy::providers::WsConnect;
+use alloy::pubsub::{PubSubConnect, PubSubFrontend};
+use alloy::rpc::json_rpc::{Request, RequestPacket, ResponsePacket, SerializedRequest};
+use alloy::transports::{TransportError, TransportErrorKind, TransportFut, TransportResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tower::Service;
+
+use crate::error::BalanceError;
+use crate::etag::{is_transient, retry_error};
+use crate::retry::RetryPolicy;
+use crate::telemetry;
+
+/// JSON-RPC over one WebSocket connection, as alloy's pubsub client, with the
+/// limits `EtagTransport` puts on HTTP calls: standard method names found in
+/// `methods` are replaced by the chain's own, transient failures are retried
+/// under `retry`, a call still unanswered after `timeout` (retries included)
+/// fails with `BalanceError::Timeout`, and each attempt waits for a permit of
+/// `in_flight`. Subscriptions need the bare pubsub client, so `eth_subscribe`
+/// doesn't work through this transport.
+#[derive(Debug, Clone)]
+pub(crate) struct WsTransport {
+    url: String,
+    frontend: PubSubFrontend,
+    methods: Arc<HashMap<String, String>>,
+    retry: RetryPolicy,
+    timeout: Duration,
+    in_flight: Arc<Semaphore>,
+}
+
+impl WsTransport {
+    /// Open the connection to `url`, which also fails after `timeout`
+    pub(crate) async fn connect(
+        url: &str,
+        methods: Arc<HashMap<String, String>>,
+        retry: RetryPolicy,
+        timeout: Duration,
+        in_flight: Arc<Semaphore>,
+    ) -> TransportResult<Self> {
+        let frontend = tokio::time::timeout(timeout, WsConnect::new(url).into_service())
+            .await
+            .map_err(|_| TransportErrorKind::custom(BalanceError::Timeout))??;
+        Ok(Self {
+            url: url.to_string(),
+            frontend,
+            methods,
+            retry,
+            timeout,
+            in_flight,
+        })
+    }
+
+    fn request(&self, packet: RequestPacket) -> TransportFut<'static> {
+        let this = self.clone();
+        Box::pin(async move {
+            let packet = rename_methods(packet, &this.methods)?;
+            let attempts = this
+                .retry
+                .run_async(|| this.send(packet.clone()), is_transient);
+            match tokio::time::timeout(this.timeout, attempts).await {
+                Ok(result) => result,
+                Err(_) => Err(TransportErrorKind::custom(BalanceError::Timeout)),
+            }
+        })
+    }
+
+    /// One attempt at `packet` once a request slot is free; JSON-RPC errors
+    /// that ask for a retry are returned as errors so the retry policy sees them
+    async fn send(&self, packet: RequestPacket) -> TransportResult<ResponsePacket> {
+        let _permit = self.in_flight.acquire().await;
+        let packet = telemetry::rpc_call(&self.url, self.frontend.clone().call(packet)).await?;
+        match retry_error(&packet) {
+            Some(error) => Err(error),
+            None => Ok(packet),
+        }
+    }
+}
+
+impl Service<RequestPacket> for WsTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, packet: RequestPacket) -> Self::Future {
+        self.request(packet)
+    }
+}
+
+/// Replace the method of each call by its configured alias, if any
+fn rename_methods(
+    packet: RequestPacket,
+    methods: &HashMap<String, String>,
+) -> TransportResult<RequestPacket> {
+    let rename = |request: SerializedRequest| {
+        let Some(alias) = methods.get(request.method()) else {
+            return Ok(request);
+        };
+        let params = request.params().map(ToOwned::to_owned);
+        let mut renamed = Request::new(alias.clone(), request.id().clone(), params)
+            .serialize()
+            .map_err(TransportError::ser_err)?;
+        if request.is_subscription() {
+            renamed.set_is_subscription();
+        }
+        Ok(renamed)
+    };
+    match packet {
+        RequestPacket::Single(request) => Ok(RequestPacket::Single(rename(request)?)),
+        RequestPacket::Batch(requests) => requests
+            .into_iter()
+            .map(rename)
+            .collect::<TransportResult<_>>()
+            .map(RequestPacket::Batch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use alloy::rpc::json_rpc::Id;
+    use serde_json::json;
+
+    #[test]
+    fn test_rename_methods() {
+        let methods =
+            HashMap::from([("eth_getBalance".to_string(), "klay_getBalance".to_string())]);
+        let packet: RequestPacket = [
+            Request::new("eth_getBalance", Id::Number(1), json!(["0xabc", "latest"])),
+            Request::new("eth_call", Id::Number(2), json!([])),
+        ]
+        .into_iter()
+        .map(|request| request.serialize().unwrap())
+        .collect();
+
+        let renamed = serde_json::to_value(rename_methods(packet, &methods).unwrap()).unwrap();
+        assert_eq!(renamed[0]["method"], "klay_getBalance");
+        assert_eq!(renamed[0]["id"], 1);
+        assert_eq!(renamed[0]["params"], json!(["0xabc", "latest"]));
+        assert_eq!(renamed[1]["method"], "eth_call");
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_handshake_times_out() {
+        let (_listener, url) = test_server::silent();
+        let error = WsTransport::connect(
+            &url.replace("http://", "ws://"),
+            Arc::default(),
+            RetryPolicy::none(),
+            Duration::from_millis(100),
+            Arc::new(Semaphore::new(1)),
+        )
+        .await
+        .unwrap_err();
+        let error = anyhow::Error::from(error);
+        assert!(error
+            .chain()
+            .any(|cause| cause.downcast_ref::<BalanceError>() == Some(&BalanceError::Timeout)));
+    }
+}